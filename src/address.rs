@@ -1,3 +1,5 @@
+use crate::process_id::validate_segment;
+use crate::ProcessIdParseError;
 pub use crate::{Address, PackageId, ProcessId};
 use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
@@ -42,51 +44,207 @@ impl Address {
     pub fn package_id(&self) -> PackageId {
         PackageId::new(self.package(), self.publisher())
     }
+    /// Start building an `Address` segment by segment with an [`AddressBuilder`].
+    pub fn builder() -> AddressBuilder {
+        AddressBuilder::default()
+    }
+    /// Check whether this `Address` points at the given process, on any node.
+    ///
+    /// Example:
+    /// ```
+    /// # use kinode_process_lib::Address;
+    /// let source: Address = "our@kv:distro:sys".parse().unwrap();
+    /// assert!(source.is_process("kv", "distro", "sys"));
+    /// ```
+    pub fn is_process(&self, process: &str, package: &str, publisher: &str) -> bool {
+        self.process() == process && self.package() == package && self.publisher() == publisher
+    }
+    /// Check whether this `Address` points at any process in the given package, on any node.
+    pub fn is_package(&self, package: &str, publisher: &str) -> bool {
+        self.package() == package && self.publisher() == publisher
+    }
+    /// Check whether this `Address` is on the given node.
+    pub fn is_node(&self, node: &str) -> bool {
+        self.node() == node
+    }
+    /// Check whether this `Address` matches an [`AddressPattern`].
+    pub fn matches(&self, pattern: &AddressPattern) -> bool {
+        pattern.matches(self)
+    }
+}
+
+/// Builder for an [`Address`]. Every segment must be set before calling
+/// [`AddressBuilder::build()`], which validates the segments the same way
+/// parsing an `Address` from a string does.
+#[derive(Clone, Debug, Default)]
+pub struct AddressBuilder {
+    node: Option<String>,
+    process: Option<String>,
+    package: Option<String>,
+    publisher: Option<String>,
+}
+
+impl AddressBuilder {
+    /// Set the node ID.
+    pub fn node<T: Into<String>>(mut self, node: T) -> Self {
+        self.node = Some(node.into());
+        self
+    }
+    /// Set the process name.
+    pub fn process<T: Into<String>>(mut self, process: T) -> Self {
+        self.process = Some(process.into());
+        self
+    }
+    /// Set the package name.
+    pub fn package<T: Into<String>>(mut self, package: T) -> Self {
+        self.package = Some(package.into());
+        self
+    }
+    /// Set the publisher node ID.
+    pub fn publisher<T: Into<String>>(mut self, publisher: T) -> Self {
+        self.publisher = Some(publisher.into());
+        self
+    }
+    /// Set the package name and publisher node ID from a [`PackageId`].
+    pub fn package_id(mut self, package_id: &PackageId) -> Self {
+        self.package = Some(package_id.package().to_string());
+        self.publisher = Some(package_id.publisher().to_string());
+        self
+    }
+    /// Build the `Address`, failing if a segment is missing or invalid.
+    pub fn build(self) -> Result<Address, AddressParseError> {
+        let node = self.node.ok_or(AddressParseError::MissingNodeId)?;
+        let process_name = self.process.ok_or(AddressParseError::MissingField)?;
+        let package_name = self.package.ok_or(AddressParseError::MissingField)?;
+        let publisher_node = self.publisher.ok_or(AddressParseError::MissingField)?;
+        validate_segment(&node)?;
+        validate_segment(&process_name)?;
+        validate_segment(&package_name)?;
+        validate_segment(&publisher_node)?;
+        Ok(Address {
+            node,
+            process: ProcessId {
+                process_name,
+                package_name,
+                publisher_node,
+            },
+        })
+    }
+}
+
+/// A glob-style pattern over the four segments of an [`Address`], written
+/// like an address string: `node@process:package:publisher`. A segment of `*`
+/// matches anything, and a segment ending in `*` matches by prefix.
+///
+/// Example:
+/// ```
+/// # use kinode_process_lib::{Address, AddressPattern};
+/// let pattern: AddressPattern = "*@*:my_app:my-publisher.os".parse().unwrap();
+/// let source: Address = "alice.os@chat:my_app:my-publisher.os".parse().unwrap();
+/// assert!(source.matches(&pattern));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AddressPattern {
+    node: String,
+    process: String,
+    package: String,
+    publisher: String,
+}
+
+impl AddressPattern {
+    /// Create a pattern from its segments.
+    pub fn new(node: &str, process: &str, package: &str, publisher: &str) -> Self {
+        AddressPattern {
+            node: node.to_string(),
+            process: process.to_string(),
+            package: package.to_string(),
+            publisher: publisher.to_string(),
+        }
+    }
+    /// Check whether an `Address` matches this pattern.
+    pub fn matches(&self, address: &Address) -> bool {
+        segment_matches(&self.node, address.node())
+            && segment_matches(&self.process, address.process())
+            && segment_matches(&self.package, address.package())
+            && segment_matches(&self.publisher, address.publisher())
+    }
+}
+
+fn segment_matches(pattern: &str, segment: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => segment.starts_with(prefix),
+        None => pattern == segment,
+    }
+}
+
+impl std::str::FromStr for AddressPattern {
+    type Err = AddressParseError;
+    /// Attempt to parse an `AddressPattern` from a string. The string follows the
+    /// same structure as an `Address`, except segments may contain `*` wildcards.
+    fn from_str(input: &str) -> Result<Self, AddressParseError> {
+        let (node, process) = input
+            .split_once('@')
+            .ok_or(AddressParseError::MissingNodeId)?;
+        if process.contains('@') {
+            return Err(AddressParseError::TooManyAts);
+        }
+        let mut segments = process.split(':');
+        let process = segments.next().ok_or(AddressParseError::MissingField)?;
+        let package = segments.next().ok_or(AddressParseError::MissingField)?;
+        let publisher = segments.next().ok_or(AddressParseError::MissingField)?;
+        if segments.next().is_some() {
+            return Err(AddressParseError::TooManyColons);
+        }
+        for segment in [node, process, package, publisher] {
+            if segment.is_empty() {
+                return Err(AddressParseError::EmptyField);
+            }
+        }
+        Ok(AddressPattern::new(node, process, package, publisher))
+    }
+}
+
+impl std::fmt::Display for AddressPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}@{}:{}:{}",
+            self.node, self.process, self.package, self.publisher
+        )
+    }
 }
 
 impl std::str::FromStr for Address {
     type Err = AddressParseError;
     /// Attempt to parse an `Address` from a string. The formatting structure for
     /// an Address is `node@process_name:package_name:publisher_node`.
-    ///
-    /// TODO: clarify if `@` can be present in process name / package name / publisher name
-    ///
-    /// TODO: ensure `:` cannot sneak into first segment
+    /// The string must contain exactly one `@`, and each segment must be non-empty
+    /// and free of separators, whitespace, and control characters.
     fn from_str(input: &str) -> Result<Self, AddressParseError> {
-        // split string on colons into 4 segments,
-        // first one with @, next 3 with :
+        // split string on @ into node and process ID,
+        // then the process ID on colons into 3 segments
         let mut name_rest = input.split('@');
         let node = name_rest
             .next()
             .ok_or(AddressParseError::MissingField)?
             .to_string();
-        let mut segments = name_rest
-            .next()
-            .ok_or(AddressParseError::MissingNodeId)?
-            .split(':');
-        let process_name = segments
-            .next()
-            .ok_or(AddressParseError::MissingField)?
-            .to_string();
-        let package_name = segments
-            .next()
-            .ok_or(AddressParseError::MissingField)?
-            .to_string();
-        let publisher_node = segments
-            .next()
-            .ok_or(AddressParseError::MissingField)?
-            .to_string();
-        if segments.next().is_some() {
-            return Err(AddressParseError::TooManyColons);
+        let process = name_rest.next().ok_or(AddressParseError::MissingNodeId)?;
+        if name_rest.next().is_some() {
+            return Err(AddressParseError::TooManyAts);
         }
-        Ok(Address {
-            node,
-            process: ProcessId {
-                process_name,
-                package_name,
-                publisher_node,
-            },
-        })
+        if node.is_empty() {
+            return Err(AddressParseError::MissingNodeId);
+        }
+        validate_segment(&node)?;
+        let process: ProcessId = process.parse()?;
+        Ok(Address { node, process })
+    }
+}
+
+impl TryFrom<&str> for Address {
+    type Error = AddressParseError;
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        input.parse()
     }
 }
 
@@ -161,24 +319,39 @@ impl std::fmt::Display for Address {
 }
 
 /// Error type for parsing an `Address` from a string.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AddressParseError {
     TooManyColons,
+    TooManyAts,
     MissingNodeId,
     MissingField,
+    EmptyField,
+    InvalidCharacter(char),
+}
+
+impl From<ProcessIdParseError> for AddressParseError {
+    fn from(error: ProcessIdParseError) -> Self {
+        match error {
+            ProcessIdParseError::TooManyColons => AddressParseError::TooManyColons,
+            ProcessIdParseError::MissingField => AddressParseError::MissingField,
+            ProcessIdParseError::EmptyField => AddressParseError::EmptyField,
+            ProcessIdParseError::InvalidCharacter(c) => AddressParseError::InvalidCharacter(c),
+        }
+    }
 }
 
 impl std::fmt::Display for AddressParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                AddressParseError::TooManyColons => "Too many colons in ProcessId string",
-                AddressParseError::MissingNodeId => "Node ID missing",
-                AddressParseError::MissingField => "Missing field in ProcessId string",
+        match self {
+            AddressParseError::TooManyColons => write!(f, "Too many colons in ProcessId string"),
+            AddressParseError::TooManyAts => write!(f, "Too many @ in Address string"),
+            AddressParseError::MissingNodeId => write!(f, "Node ID missing"),
+            AddressParseError::MissingField => write!(f, "Missing field in ProcessId string"),
+            AddressParseError::EmptyField => write!(f, "Empty field in Address string"),
+            AddressParseError::InvalidCharacter(c) => {
+                write!(f, "Invalid character {:?} in Address string", c)
             }
-        )
+        }
     }
 }
 
@@ -186,8 +359,67 @@ impl std::error::Error for AddressParseError {
     fn description(&self) -> &str {
         match self {
             AddressParseError::TooManyColons => "Too many colons in ProcessId string",
+            AddressParseError::TooManyAts => "Too many @ in Address string",
             AddressParseError::MissingNodeId => "Node ID missing",
             AddressParseError::MissingField => "Missing field in ProcessId string",
+            AddressParseError::EmptyField => "Empty field in Address string",
+            AddressParseError::InvalidCharacter(_) => "Invalid character in Address string",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const SEGMENT: &str = "[a-zA-Z0-9_.-]{1,16}";
+
+    proptest! {
+        #[test]
+        fn round_trips(
+            node in SEGMENT,
+            process in SEGMENT,
+            package in SEGMENT,
+            publisher in SEGMENT,
+        ) {
+            let address = Address::new(&node, (process.as_str(), package.as_str(), publisher.as_str()));
+            prop_assert_eq!(address.to_string().parse::<Address>(), Ok(address.clone()));
+            prop_assert_eq!(Address::try_from(address.to_string().as_str()), Ok(address));
+        }
+
+        #[test]
+        fn rejects_extra_ats(node in SEGMENT, other in SEGMENT, process in SEGMENT) {
+            let input = format!("{}@{}@{}:pkg:pub", node, other, process);
+            prop_assert_eq!(input.parse::<Address>(), Err(AddressParseError::TooManyAts));
+        }
+
+        #[test]
+        fn rejects_missing_node(process in SEGMENT, package in SEGMENT, publisher in SEGMENT) {
+            let id = format!("{}:{}:{}", process, package, publisher);
+            prop_assert_eq!(id.parse::<Address>(), Err(AddressParseError::MissingNodeId));
+            let input = format!("@{}", id);
+            prop_assert_eq!(input.parse::<Address>(), Err(AddressParseError::MissingNodeId));
+        }
+
+        #[test]
+        fn rejects_bad_process_ids(node in SEGMENT, a in SEGMENT, b in SEGMENT) {
+            let input = format!("{}@{}:{}", node, a, b);
+            prop_assert_eq!(input.parse::<Address>(), Err(AddressParseError::MissingField));
+            let input = format!("{}@{}:{}:{}:{}", node, a, b, a, b);
+            prop_assert_eq!(input.parse::<Address>(), Err(AddressParseError::TooManyColons));
+        }
+
+        #[test]
+        fn rejects_invalid_characters(
+            node in SEGMENT,
+            bad in prop::sample::select(vec![' ', ':', '\n']),
+            at in 0usize..16,
+        ) {
+            let mut node = node;
+            node.insert(at.min(node.len()), bad);
+            let input = format!("{}@proc:pkg:pub", node);
+            prop_assert_eq!(input.parse::<Address>(), Err(AddressParseError::InvalidCharacter(bad)));
         }
     }
 }
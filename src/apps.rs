@@ -0,0 +1,126 @@
+use crate::kernel_types::PackageVersion;
+use crate::{Message, PackageId, ProcessLibError, Request};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Actions are sent to the app store process, `our@main:app_store:sys`.
+/// Sending them requires the messaging capability for the app store, which
+/// the package must request in its manifest.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum AppsRequest {
+    /// List every installed package.
+    ListInstalled,
+    /// Download, if needed, and install a package. With no version, the
+    /// newest one listed is installed.
+    Install {
+        package_id: PackageId,
+        version: Option<String>,
+    },
+    Uninstall {
+        package_id: PackageId,
+    },
+    /// List the installed packages that have a newer version listed.
+    CheckUpdates,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AppsResponse {
+    Ok,
+    Installed { packages: Vec<InstalledPackage> },
+    Updates { updates: Vec<AvailableUpdate> },
+    Err { error: AppsError },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub package_id: PackageId,
+    pub version: PackageVersion,
+    /// Whether the app store installs new versions of this package itself.
+    pub auto_update: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvailableUpdate {
+    pub package_id: PackageId,
+    pub installed: PackageVersion,
+    pub available: PackageVersion,
+}
+
+#[derive(Debug, Serialize, Deserialize, Error)]
+pub enum AppsError {
+    #[error("apps: package not found: {package_id}")]
+    PackageNotFound { package_id: PackageId },
+    #[error("apps: package {package_id} has no version {version}")]
+    VersionNotFound {
+        package_id: PackageId,
+        version: String,
+    },
+    #[error("apps: package not installed: {package_id}")]
+    NotInstalled { package_id: PackageId },
+    #[error("apps: download failed: {error}")]
+    DownloadFailed { error: String },
+    #[error("apps: install failed: {error}")]
+    InstallFailed { error: String },
+    #[error("apps: No capability: {error}")]
+    NoCap { error: String },
+}
+
+/// List every installed package.
+pub fn list_installed() -> Result<Vec<InstalledPackage>, ProcessLibError> {
+    match send(AppsRequest::ListInstalled, 5)? {
+        AppsResponse::Installed { packages } => Ok(packages),
+        AppsResponse::Err { error } => Err(error.into()),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+/// Install a package, at `version` or else its newest version. The package
+/// may need to be downloaded first, so this waits up to `timeout` seconds.
+pub fn install(
+    package_id: &PackageId,
+    version: Option<&str>,
+    timeout: u64,
+) -> Result<(), ProcessLibError> {
+    let request = AppsRequest::Install {
+        package_id: package_id.clone(),
+        version: version.map(|version| version.to_string()),
+    };
+    match send(request, timeout)? {
+        AppsResponse::Ok => Ok(()),
+        AppsResponse::Err { error } => Err(error.into()),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+/// Uninstall a package, stopping its processes.
+pub fn uninstall(package_id: &PackageId) -> Result<(), ProcessLibError> {
+    let request = AppsRequest::Uninstall {
+        package_id: package_id.clone(),
+    };
+    match send(request, 5)? {
+        AppsResponse::Ok => Ok(()),
+        AppsResponse::Err { error } => Err(error.into()),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+/// List the installed packages that have a newer version available.
+pub fn check_updates() -> Result<Vec<AvailableUpdate>, ProcessLibError> {
+    match send(AppsRequest::CheckUpdates, 30)? {
+        AppsResponse::Updates { updates } => Ok(updates),
+        AppsResponse::Err { error } => Err(error.into()),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+fn send(request: AppsRequest, timeout: u64) -> Result<AppsResponse, ProcessLibError> {
+    let res = Request::new()
+        .target(("our", "main", "app_store", "sys"))
+        .body(serde_json::to_vec(&request)?)
+        .send_and_await_response(timeout)??;
+
+    match res {
+        Message::Response { body, .. } => Ok(serde_json::from_slice::<AppsResponse>(&body)?),
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
@@ -0,0 +1,71 @@
+pub use crate::LazyLoadBlob;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Shorthand for [`LazyLoadBlob`], the wit type that carries a message's blob.
+pub type Blob = LazyLoadBlob;
+
+/// `LazyLoadBlob` is defined in the wit bindings, but constructors and methods here.
+/// These constructors set the MIME type to match the bytes they produce, so the
+/// receiver can tell how to interpret a blob without out-of-band agreement.
+impl LazyLoadBlob {
+    /// Create a new `LazyLoadBlob`. Takes an optional MIME type and the bytes.
+    pub fn new<T, U>(mime: Option<T>, bytes: U) -> LazyLoadBlob
+    where
+        T: Into<String>,
+        U: Into<Vec<u8>>,
+    {
+        LazyLoadBlob {
+            mime: mime.map(|mime| mime.into()),
+            bytes: bytes.into(),
+        }
+    }
+    /// Create a `LazyLoadBlob` holding a type serialized to JSON,
+    /// with MIME type `application/json`.
+    pub fn json<T: Serialize>(value: &T) -> serde_json::Result<LazyLoadBlob> {
+        Ok(LazyLoadBlob {
+            mime: Some("application/json".to_string()),
+            bytes: serde_json::to_vec(value)?,
+        })
+    }
+    /// Create a `LazyLoadBlob` holding a UTF-8 string, with MIME type `text/plain`.
+    pub fn text<T: Into<String>>(text: T) -> LazyLoadBlob {
+        LazyLoadBlob {
+            mime: Some("text/plain".to_string()),
+            bytes: text.into().into_bytes(),
+        }
+    }
+    /// Read a whole file from the vfs into a `LazyLoadBlob`, with the MIME
+    /// type guessed from the file extension.
+    #[cfg(feature = "http")]
+    pub fn from_vfs_read(path: &str) -> anyhow::Result<LazyLoadBlob> {
+        let bytes = crate::vfs::File {
+            path: path.to_string(),
+        }
+        .read()?;
+        Ok(LazyLoadBlob {
+            mime: Some(crate::http::get_mime_type(path)),
+            bytes,
+        })
+    }
+    /// Read the MIME type of a `LazyLoadBlob`, if it has one.
+    pub fn mime(&self) -> Option<&str> {
+        self.mime.as_deref()
+    }
+    /// Read the bytes of a `LazyLoadBlob`.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+    /// Check whether the MIME type of this `LazyLoadBlob` is JSON.
+    pub fn is_json(&self) -> bool {
+        self.mime() == Some("application/json")
+    }
+    /// Attempt to deserialize the bytes of this `LazyLoadBlob` from JSON.
+    /// The MIME type is not checked, since many senders do not set one.
+    pub fn as_json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.bytes)
+    }
+    /// Attempt to read the bytes of this `LazyLoadBlob` as a UTF-8 string.
+    pub fn as_text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.bytes)
+    }
+}
@@ -0,0 +1,133 @@
+//! Keep a kv prefix and a graphdb table in step, so an app can serve fast
+//! lookups by key from kv while querying the same records in graphdb,
+//! without writing every change twice itself.
+//!
+//! ```ignore
+//! let users = KvGraphBridge::new(kv, "user:", graph, "user")?;
+//! users.set("alice", &User { name: "Alice".into(), age: 30 })?;
+//! let alice: Option<User> = users.get("alice")?;
+//! let adults: Vec<User> = graph.read_as("SELECT * FROM user WHERE age >= 18;".into())?;
+//! ```
+//!
+//! Only writes made through the bridge are mirrored. Each record is stored
+//! in kv as JSON under the prefix and its key, and in graphdb as
+//! `table:key` with the same contents, which must be a JSON object.
+use crate::graphdb::{check_table_name, GraphDb, GraphDbError};
+use crate::kv::{Kv, KvError};
+use crate::{ModuleError, ProcessLibError};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Field a resync reads each graphdb record's key into.
+const KEY_FIELD: &str = "_bridge_key";
+
+/// Mirrors records written through it to both a kv prefix and a graphdb table.
+pub struct KvGraphBridge {
+    kv: Kv,
+    prefix: Vec<u8>,
+    graph: GraphDb,
+    table: String,
+}
+
+impl KvGraphBridge {
+    pub fn new(kv: Kv, prefix: &str, graph: GraphDb, table: &str) -> Result<Self, ProcessLibError> {
+        check_table_name(table)?;
+        Ok(KvGraphBridge {
+            kv,
+            prefix: prefix.as_bytes().to_vec(),
+            graph,
+            table: table.to_string(),
+        })
+    }
+
+    /// Read a record from kv.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ProcessLibError> {
+        match self.kv.get(self.kv_key(key)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a record to kv, then to graphdb. If the graphdb write fails,
+    /// the kv write stands; [`KvGraphBridge::resync_to_graph()`] repairs it.
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<(), ProcessLibError> {
+        let value = serde_json::to_value(value)?;
+        if !value.is_object() {
+            return Err(GraphDbError::InputError {
+                error: format!("bridge: record {:?} is not a JSON object", key),
+            }
+            .into());
+        }
+        self.kv
+            .set(self.kv_key(key), serde_json::to_vec(&value)?, None)?;
+        self.write_graph(key, value)
+    }
+
+    /// Delete a record from kv, then from graphdb.
+    pub fn delete(&self, key: &str) -> Result<(), ProcessLibError> {
+        self.kv.delete(self.kv_key(key), None)?;
+        self.graph.write(
+            "DELETE type::thing($table, $key);".to_string(),
+            Some(serde_json::json!({ "table": self.table, "key": key })),
+        )
+    }
+
+    /// Copy every record under the kv prefix into graphdb, for when kv is
+    /// the source of truth. Records only in graphdb are left alone.
+    /// Returns the number copied.
+    pub fn resync_to_graph(&self) -> Result<u64, ProcessLibError> {
+        let mut copied = 0;
+        for entry in self.kv.scan_prefix(&self.prefix) {
+            let (key, value) = entry?;
+            let key = String::from_utf8_lossy(&key[self.prefix.len()..]).to_string();
+            self.write_graph(&key, serde_json::from_slice(&value)?)?;
+            copied += 1;
+        }
+        Ok(copied)
+    }
+
+    /// Copy every record of the graphdb table into kv, for when graphdb is
+    /// the source of truth. Keys only in kv are left alone. Returns the
+    /// number copied.
+    pub fn resync_to_kv(&self) -> Result<u64, ProcessLibError> {
+        let rows = self.graph.read_as::<serde_json::Value>(format!(
+            "SELECT *, meta::id(id) AS {} FROM {};",
+            KEY_FIELD, self.table,
+        ))?;
+        let mut tx = self.kv.buffered_tx()?;
+        for row in rows {
+            let serde_json::Value::Object(mut record) = row else {
+                continue;
+            };
+            let key = match record.remove(KEY_FIELD) {
+                Some(serde_json::Value::String(key)) => key,
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+            record.remove("id");
+            tx.set(self.kv_key(&key), serde_json::to_vec(&record)?);
+        }
+        let copied = tx.len() as u64;
+        tx.commit()?;
+        Ok(copied)
+    }
+
+    pub fn kv(&self) -> &Kv {
+        &self.kv
+    }
+
+    pub fn graph(&self) -> &GraphDb {
+        &self.graph
+    }
+
+    fn kv_key(&self, key: &str) -> Vec<u8> {
+        [self.prefix.as_slice(), key.as_bytes()].concat()
+    }
+
+    fn write_graph(&self, key: &str, content: serde_json::Value) -> Result<(), ProcessLibError> {
+        self.graph.write(
+            "UPDATE type::thing($table, $key) CONTENT $content;".to_string(),
+            Some(serde_json::json!({ "table": self.table, "key": key, "content": content })),
+        )
+    }
+}
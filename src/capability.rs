@@ -1,4 +1,4 @@
-pub use crate::{Address, Capability, ProcessId};
+pub use crate::{Address, Capability, PackageId, ProcessId};
 use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
 
@@ -27,6 +27,79 @@ impl Capability {
     pub fn params(&self) -> &str {
         &self.params
     }
+    /// Read the params from a `Capability` as JSON.
+    pub fn params_json(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::from_str(&self.params)
+    }
+    /// The capability to message a process.
+    pub fn messaging<T>(address: T) -> Capability
+    where
+        T: Into<Address>,
+    {
+        Capability::new(address, "\"messaging\"")
+    }
+    /// The capability to read from a kv db on our node.
+    /// Takes our address, since the capability is issued by our node's runtime module.
+    pub fn kv_read(our: &Address, db: &str) -> Capability {
+        runtime_db_capability(our, "kv", "read", db)
+    }
+    /// The capability to write to a kv db on our node.
+    pub fn kv_write(our: &Address, db: &str) -> Capability {
+        runtime_db_capability(our, "kv", "write", db)
+    }
+    /// The capability to read from a sqlite db on our node.
+    pub fn sqlite_read(our: &Address, db: &str) -> Capability {
+        runtime_db_capability(our, "sqlite", "read", db)
+    }
+    /// The capability to write to a sqlite db on our node.
+    pub fn sqlite_write(our: &Address, db: &str) -> Capability {
+        runtime_db_capability(our, "sqlite", "write", db)
+    }
+    /// The capability to read from a graphdb db on our node.
+    pub fn graphdb_read(our: &Address, db: &str) -> Capability {
+        runtime_db_capability(our, "graphdb", "read", db)
+    }
+    /// The capability to write to a graphdb db on our node.
+    pub fn graphdb_write(our: &Address, db: &str) -> Capability {
+        runtime_db_capability(our, "graphdb", "write", db)
+    }
+    /// The capability to read from a vfs drive on our node. Takes our address,
+    /// the package that owns the drive, and the name of the drive.
+    pub fn vfs_read(our: &Address, package_id: &PackageId, drive: &str) -> Capability {
+        vfs_capability(our, "read", package_id, drive)
+    }
+    /// The capability to write to a vfs drive on our node. Takes our address,
+    /// the package that owns the drive, and the name of the drive.
+    pub fn vfs_write(our: &Address, package_id: &PackageId, drive: &str) -> Capability {
+        vfs_capability(our, "write", package_id, drive)
+    }
+    /// The capability to bind `path`, e.g. `/api`, on our node's http_server.
+    /// Takes our address, since the capability is issued by our node's http_server.
+    pub fn http_bind(our: &Address, path: &str) -> Capability {
+        Capability::new(
+            Address::new(our.node(), ("http_server", "distro", "sys")),
+            serde_json::json!({ "kind": "bind", "path": path }).to_string(),
+        )
+    }
+}
+
+/// Build the capability a kv, sqlite, or graphdb runtime module checks
+/// before allowing access to a db. The params must match the runtime exactly.
+fn runtime_db_capability(our: &Address, module: &str, kind: &str, db: &str) -> Capability {
+    Capability::new(
+        Address::new(our.node(), (module, "distro", "sys")),
+        serde_json::json!({ "kind": kind, "db": db }).to_string(),
+    )
+}
+
+/// Build the capability the vfs runtime module checks before allowing access
+/// to a drive. The params must match the runtime exactly.
+fn vfs_capability(our: &Address, kind: &str, package_id: &PackageId, drive: &str) -> Capability {
+    Capability::new(
+        Address::new(our.node(), ("vfs", "distro", "sys")),
+        serde_json::json!({ "kind": kind, "drive": format!("/{}/{}", package_id, drive) })
+            .to_string(),
+    )
 }
 
 impl std::str::FromStr for Capability {
@@ -172,3 +245,83 @@ impl std::error::Error for CapabilityParseError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn our() -> Address {
+        Address::new("our.os", ("app", "pkg", "pub.os"))
+    }
+
+    fn standard_capabilities() -> Vec<Capability> {
+        let our = our();
+        let package_id = PackageId::new("pkg", "pub.os");
+        vec![
+            Capability::messaging(our.clone()),
+            Capability::kv_read(&our, "db"),
+            Capability::kv_write(&our, "db"),
+            Capability::sqlite_read(&our, "db"),
+            Capability::sqlite_write(&our, "db"),
+            Capability::graphdb_read(&our, "db"),
+            Capability::graphdb_write(&our, "db"),
+            Capability::vfs_read(&our, &package_id, "files"),
+            Capability::vfs_write(&our, &package_id, "files"),
+            Capability::http_bind(&our, "/api"),
+        ]
+    }
+
+    #[test]
+    fn round_trips_through_strings() {
+        for capability in standard_capabilities() {
+            let parsed: Capability = capability.to_string().parse().unwrap();
+            assert_eq!(parsed, capability);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_serde() {
+        for capability in standard_capabilities() {
+            let json = serde_json::to_string(&capability).unwrap();
+            let parsed: Capability = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, capability);
+        }
+    }
+
+    #[test]
+    fn emits_runtime_params() {
+        let our = our();
+        let cases = [
+            (
+                Capability::kv_read(&our, "db"),
+                "our.os@kv:distro:sys",
+                serde_json::json!({ "kind": "read", "db": "db" }),
+            ),
+            (
+                Capability::sqlite_write(&our, "db"),
+                "our.os@sqlite:distro:sys",
+                serde_json::json!({ "kind": "write", "db": "db" }),
+            ),
+            (
+                Capability::graphdb_read(&our, "db"),
+                "our.os@graphdb:distro:sys",
+                serde_json::json!({ "kind": "read", "db": "db" }),
+            ),
+            (
+                Capability::vfs_write(&our, &PackageId::new("pkg", "pub.os"), "files"),
+                "our.os@vfs:distro:sys",
+                serde_json::json!({ "kind": "write", "drive": "/pkg:pub.os/files" }),
+            ),
+            (
+                Capability::http_bind(&our, "/api"),
+                "our.os@http_server:distro:sys",
+                serde_json::json!({ "kind": "bind", "path": "/api" }),
+            ),
+        ];
+        for (capability, issuer, params) in cases {
+            assert_eq!(capability.issuer().to_string(), issuer);
+            assert_eq!(capability.params_json().unwrap(), params);
+        }
+        assert_eq!(Capability::messaging(our).params(), "\"messaging\"");
+    }
+}
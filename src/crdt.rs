@@ -0,0 +1,425 @@
+//! Conflict-free replicated data types, for state that several nodes edit
+//! independently and exchange: any two replicas that have merged each
+//! other's state hold the same value, whatever order edits and merges
+//! happened in.
+//!
+//! ```ignore
+//! let mut likes = Counter::default();
+//! likes.increment(our.node(), 1);
+//! // on a timer, or when a peer comes online:
+//! crdt::sync_with(&peer, "likes", &mut likes, 5)?;
+//! // and in the message loop, to answer peers' syncs:
+//! let is_peer = |source: &Address| peers.contains(source.node());
+//! if let Some(result) = crdt::handle_sync(&message, "likes", &mut likes, is_peer) {
+//!     result?;
+//!     continue;
+//! }
+//! ```
+//!
+//! States travel as bincode in the blob of a [`CrdtSyncRequest`].
+use crate::ids::Ulid;
+use crate::{get_blob, Address, Message, ProcessLibError, Request, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CrdtError {
+    /// The peer could not merge our state, and said why.
+    #[error("crdt: peer failed to sync: {error}")]
+    Peer { error: String },
+    /// A state could not be encoded or decoded as bincode.
+    #[error("crdt: bad state: {error}")]
+    Encoding { error: String },
+    /// A sync from a process the authorization predicate rejected.
+    #[error("crdt: sync from {address} not allowed")]
+    NotAllowed { address: String },
+    #[error("crdt: {0}")]
+    Net(#[from] ProcessLibError),
+    #[error("crdt: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A replicated value that merges deterministically.
+pub trait Crdt: Serialize + DeserializeOwned {
+    /// Fold another replica's state into this one. Merging is commutative,
+    /// associative and idempotent, so replicas may merge in any order, any
+    /// number of times.
+    fn merge(&mut self, other: &Self);
+}
+
+/// A last-writer-wins register: the value set at the latest timestamp wins,
+/// with ties broken by node name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    value: T,
+    /// Milliseconds since the epoch at which the value was set.
+    timestamp: u64,
+    node: String,
+}
+
+impl<T> LwwRegister<T> {
+    pub fn new(value: T, node: &str) -> Self {
+        LwwRegister {
+            value,
+            timestamp: crate::time::now_millis(),
+            node: node.to_string(),
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Set the value as of now. Clocks across nodes are not in sync, so a
+    /// write may lose to one made slightly earlier on a node whose clock
+    /// runs ahead; a write never loses to an earlier one from the same node.
+    pub fn set(&mut self, value: T, node: &str) {
+        let timestamp = crate::time::now_millis().max(self.timestamp.saturating_add(1));
+        self.value = value;
+        self.timestamp = timestamp;
+        self.node = node.to_string();
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> Crdt for LwwRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if (other.timestamp, &other.node) > (self.timestamp, &self.node) {
+            *self = other.clone();
+        }
+    }
+}
+
+/// A counter that each node may increment and decrement.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counter {
+    increments: BTreeMap<String, u64>,
+    decrements: BTreeMap<String, u64>,
+}
+
+impl Counter {
+    pub fn increment(&mut self, node: &str, by: u64) {
+        let count = self.increments.entry(node.to_string()).or_default();
+        *count = count.saturating_add(by);
+    }
+
+    pub fn decrement(&mut self, node: &str, by: u64) {
+        let count = self.decrements.entry(node.to_string()).or_default();
+        *count = count.saturating_add(by);
+    }
+
+    /// The increments minus the decrements, saturating at the bounds of `i64`.
+    pub fn value(&self) -> i64 {
+        let sum = |counts: &BTreeMap<String, u64>| {
+            counts
+                .values()
+                .fold(0i128, |sum, count| sum + i128::from(*count))
+        };
+        let value = sum(&self.increments) - sum(&self.decrements);
+        value.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+    }
+}
+
+impl Crdt for Counter {
+    fn merge(&mut self, other: &Self) {
+        for (mine, theirs) in [
+            (&mut self.increments, &other.increments),
+            (&mut self.decrements, &other.decrements),
+        ] {
+            for (node, count) in theirs {
+                let mine = mine.entry(node.clone()).or_default();
+                *mine = (*mine).max(*count);
+            }
+        }
+    }
+}
+
+/// An observed-remove set: a remove takes out the adds it has seen, so an
+/// add and a remove of the same element made concurrently on different
+/// nodes leave the element in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrSet<T: Ord> {
+    /// Each element with the tags of the adds not yet removed.
+    entries: BTreeMap<T, BTreeSet<Ulid>>,
+    /// Tags of removed adds.
+    removed: BTreeSet<Ulid>,
+}
+
+impl<T: Ord> Default for OrSet<T> {
+    fn default() -> Self {
+        OrSet {
+            entries: BTreeMap::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> OrSet<T> {
+    pub fn insert(&mut self, value: T) {
+        self.entries.entry(value).or_default().insert(Ulid::new());
+    }
+
+    /// Remove an element, as far as this replica has seen it added.
+    pub fn remove(&mut self, value: &T) -> bool {
+        match self.entries.remove(value) {
+            Some(tags) => {
+                self.removed.extend(tags);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.entries.contains_key(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> Crdt for OrSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.removed.extend(other.removed.iter().copied());
+        for (value, tags) in &other.entries {
+            self.entries
+                .entry(value.clone())
+                .or_default()
+                .extend(tags.iter().copied());
+        }
+        let removed = &self.removed;
+        self.entries.retain(|_, tags| {
+            tags.retain(|tag| !removed.contains(tag));
+            !tags.is_empty()
+        });
+    }
+}
+
+/// Sent by [`sync_with()`], with the sender's state as bincode in the blob,
+/// and answered with a [`CrdtSyncResponse`] carrying the merged state.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CrdtSyncRequest {
+    Sync { name: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CrdtSyncResponse {
+    /// The receiver's state, after merging the sender's, is in the blob.
+    Synced,
+    Err {
+        error: String,
+    },
+}
+
+/// Exchange states of the CRDT `name` with `peer`, which answers with
+/// [`handle_sync()`], so both hold the merged state.
+pub fn sync_with<C: Crdt>(
+    peer: &Address,
+    name: &str,
+    state: &mut C,
+    timeout: u64,
+) -> Result<(), CrdtError> {
+    let res = Request::new()
+        .target(peer.clone())
+        .body(serde_json::to_vec(&CrdtSyncRequest::Sync {
+            name: name.to_string(),
+        })?)
+        .blob_bytes(encode(state)?)
+        .send_and_await_response(timeout)
+        .map_err(ProcessLibError::from)?
+        .map_err(ProcessLibError::from)?;
+
+    match serde_json::from_slice::<CrdtSyncResponse>(res.body())? {
+        CrdtSyncResponse::Synced => {
+            let blob = get_blob().ok_or(ProcessLibError::NoBlob)?;
+            state.merge(&decode(&blob.bytes)?);
+            Ok(())
+        }
+        CrdtSyncResponse::Err { error } => Err(CrdtError::Peer { error }),
+    }
+}
+
+/// If `message` is a [`CrdtSyncRequest`] for the CRDT `name`, merge the
+/// peer's state into `state`, answer with the result, and return `Some`.
+/// Returns `None` for any other message.
+///
+/// Any process that can message us could otherwise write to `state`, so
+/// the sync is only merged if `allowed` accepts its source, e.g. the nodes
+/// the CRDT is shared with. Others get an error response.
+pub fn handle_sync<C, F>(
+    message: &Message,
+    name: &str,
+    state: &mut C,
+    allowed: F,
+) -> Option<Result<(), CrdtError>>
+where
+    C: Crdt,
+    F: Fn(&Address) -> bool,
+{
+    if !message.is_request() {
+        return None;
+    }
+    let Ok(CrdtSyncRequest::Sync { name: requested }) = serde_json::from_slice(message.body())
+    else {
+        return None;
+    };
+    if requested != name {
+        return None;
+    }
+    let merged = if allowed(message.source()) {
+        get_blob()
+            .ok_or_else(|| ProcessLibError::NoBlob.into())
+            .and_then(|blob| decode::<C>(&blob.bytes))
+            .and_then(|theirs| {
+                state.merge(&theirs);
+                encode(state)
+            })
+    } else {
+        Err(CrdtError::NotAllowed {
+            address: message.source().to_string(),
+        })
+    };
+    Some(match merged {
+        Ok(bytes) => Response::new()
+            .body(serde_json::to_vec(&CrdtSyncResponse::Synced).unwrap())
+            .blob_bytes(bytes)
+            .send()
+            .map_err(|e| ProcessLibError::from(e).into()),
+        Err(e) => {
+            let _ = Response::new()
+                .body(
+                    serde_json::to_vec(&CrdtSyncResponse::Err {
+                        error: e.to_string(),
+                    })
+                    .unwrap(),
+                )
+                .send();
+            Err(e)
+        }
+    })
+}
+
+fn encode<C: Crdt>(state: &C) -> Result<Vec<u8>, CrdtError> {
+    bincode::serialize(state).map_err(|e| CrdtError::Encoding {
+        error: e.to_string(),
+    })
+}
+
+fn decode<C: Crdt>(bytes: &[u8]) -> Result<C, CrdtError> {
+    bincode::deserialize(bytes).map_err(|e| CrdtError::Encoding {
+        error: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const NODES: [&str; 3] = ["a.os", "b.os", "c.os"];
+
+    fn merged<C: Crdt + Clone>(a: &C, b: &C) -> C {
+        let mut a = a.clone();
+        a.merge(b);
+        a
+    }
+
+    fn counter() -> impl Strategy<Value = Counter> {
+        prop::collection::vec((0..NODES.len(), any::<bool>(), 0..1000u64), 0..8).prop_map(|ops| {
+            let mut counter = Counter::default();
+            for (node, up, by) in ops {
+                if up {
+                    counter.increment(NODES[node], by);
+                } else {
+                    counter.decrement(NODES[node], by);
+                }
+            }
+            counter
+        })
+    }
+
+    /// Registers whose value follows from when and where it was set, as it
+    /// does for real writes.
+    fn register() -> impl Strategy<Value = LwwRegister<u64>> {
+        (0..5u64, 0..NODES.len()).prop_map(|(timestamp, node)| LwwRegister {
+            value: timestamp * 10 + node as u64,
+            timestamp,
+            node: NODES[node].to_string(),
+        })
+    }
+
+    fn or_set() -> impl Strategy<Value = OrSet<u8>> {
+        prop::collection::vec((any::<bool>(), 0..4u8), 0..8).prop_map(|ops| {
+            let mut set = OrSet::default();
+            for (insert, value) in ops {
+                if insert {
+                    set.insert(value);
+                } else {
+                    set.remove(&value);
+                }
+            }
+            set
+        })
+    }
+
+    macro_rules! merge_laws {
+        ($name:ident, $strategy:expr) => {
+            proptest! {
+                #[test]
+                fn $name(a in $strategy, b in $strategy, c in $strategy) {
+                    prop_assert_eq!(merged(&a, &b), merged(&b, &a));
+                    prop_assert_eq!(
+                        merged(&merged(&a, &b), &c),
+                        merged(&a, &merged(&b, &c))
+                    );
+                    prop_assert_eq!(merged(&a, &a), a);
+                }
+            }
+        };
+    }
+
+    merge_laws!(counter_merge_laws, counter());
+    merge_laws!(register_merge_laws, register());
+    merge_laws!(or_set_merge_laws, or_set());
+
+    #[test]
+    fn counter_value_saturates() {
+        let mut counter = Counter::default();
+        counter.increment("a.os", u64::MAX);
+        counter.increment("a.os", 1);
+        counter.increment("b.os", u64::MAX);
+        assert_eq!(counter.value(), i64::MAX);
+        let mut counter = Counter::default();
+        counter.decrement("a.os", u64::MAX);
+        assert_eq!(counter.value(), i64::MIN);
+        counter.increment("b.os", 5);
+        counter.decrement("b.os", 2);
+        assert_eq!(counter.value(), i64::MIN);
+    }
+
+    #[test]
+    fn concurrent_add_wins_over_remove() {
+        let mut a = OrSet::default();
+        a.insert(1u8);
+        let mut b = a.clone();
+        b.remove(&1);
+        a.insert(1);
+        a.merge(&b);
+        assert!(a.contains(&1));
+        b.merge(&a);
+        assert_eq!(a, b);
+    }
+}
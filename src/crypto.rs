@@ -0,0 +1,179 @@
+//! Symmetric encryption and key derivation for data a process stores, built
+//! on XChaCha20-Poly1305 and HMAC-SHA256, and signing with the node's
+//! networking key. Enabled with the `crypto` feature.
+use crate::net::{net_request, NetAction, NetResponse};
+use crate::{get_blob, Address, ProcessLibError};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::marker::PhantomData;
+use thiserror::Error;
+
+/// Length of a symmetric key, in bytes.
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+pub type Key = [u8; KEY_LEN];
+
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("crypto: ciphertext is too short")]
+    Truncated,
+    #[error("crypto: decryption failed; wrong key or tampered ciphertext")]
+    Decrypt,
+    #[error("crypto: signature does not match the message and signer")]
+    BadSignature,
+    #[error("crypto: message signed by {signer}, expected a process on {expected}")]
+    WrongSigner { expected: String, signer: String },
+    #[error("crypto: {0}")]
+    Net(#[from] ProcessLibError),
+    #[error("crypto: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Generate a random key.
+pub fn generate_key() -> Key {
+    rand::random()
+}
+
+/// Derive an independent subkey from a master key for one purpose, named by
+/// `context` (HKDF-SHA256 with a single output block). Different contexts
+/// give unrelated keys, so one master key can protect many dbs.
+pub fn derive_key(master: &[u8], context: &str) -> Key {
+    // extract, with an all-zero salt as in RFC 5869
+    let mut extract = <Hmac<Sha256> as Mac>::new_from_slice(&[0u8; 32]).unwrap();
+    extract.update(master);
+    let prk = extract.finalize().into_bytes();
+    // expand to a single block
+    let mut expand = <Hmac<Sha256> as Mac>::new_from_slice(&prk).unwrap();
+    expand.update(context.as_bytes());
+    expand.update(&[1]);
+    expand.finalize().into_bytes().into()
+}
+
+/// A keyed hash of `data`, for looking values up by a secret-derived name
+/// without revealing the name.
+pub fn keyed_hash(key: &Key, data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Encrypt and authenticate `plaintext`. `aad` is authenticated but not
+/// encrypted: decryption fails unless the same `aad` is given, which binds
+/// the ciphertext to e.g. the key it is stored under.
+///
+/// The output is a random nonce followed by the ciphertext and tag.
+pub fn encrypt(key: &Key, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        // only fails for plaintexts larger than 256GiB
+        .expect("crypto: plaintext too large");
+    [nonce.as_slice(), &ciphertext].concat()
+}
+
+/// Decrypt the output of [`encrypt()`], checking that it was encrypted under
+/// `key` with the same `aad` and has not been altered.
+pub fn decrypt(key: &Key, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    if ciphertext.len() < NONCE_LEN {
+        return Err(CryptoError::Truncated);
+    }
+    let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::Decrypt)
+}
+
+/// Sign `data` with this node's networking key. The signature also covers
+/// this process's address, so it only verifies as coming from this process.
+pub fn sign(data: &[u8]) -> Result<Vec<u8>, ProcessLibError> {
+    match net_request(NetAction::Sign, Some(data))? {
+        NetResponse::Signed => Ok(get_blob().ok_or(ProcessLibError::NoBlob)?.bytes),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+/// Check that `signature` is a signature over `data` made with [`sign()`] by
+/// the process at `from`.
+pub fn verify(from: &Address, data: &[u8], signature: &[u8]) -> Result<bool, ProcessLibError> {
+    let action = NetAction::Verify {
+        from: from.clone(),
+        signature: signature.to_vec(),
+    };
+    match net_request(action, Some(data))? {
+        NetResponse::Verified(valid) => Ok(valid),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+/// A JSON-serialized `T` signed by the process that sent it, for app
+/// protocols between nodes that need to know a message was not altered and
+/// came from who it says. The signer seals a value into bytes to send:
+///
+/// ```ignore
+/// let bytes = SignedMessage::seal(&our, &bid)?;
+/// ```
+///
+/// and the receiver opens them, checking that the signer is on the node it
+/// expects:
+///
+/// ```ignore
+/// let bid: Bid = SignedMessage::open(&bytes, "auctioneer.os")?;
+/// ```
+///
+/// A sealed message can be replayed as is, so include a nonce or timestamp
+/// in `T` if that matters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedMessage<T> {
+    pub from: Address,
+    pub body: Vec<u8>,
+    pub signature: Vec<u8>,
+    #[serde(skip)]
+    _type: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> SignedMessage<T> {
+    /// Serialize and sign `value` as the process at `our`, and return the
+    /// envelope as bytes.
+    pub fn seal(our: &Address, value: &T) -> Result<Vec<u8>, CryptoError> {
+        let body = serde_json::to_vec(value)?;
+        let signature = sign(&body)?;
+        Ok(serde_json::to_vec(&SignedMessage::<T> {
+            from: our.clone(),
+            body,
+            signature,
+            _type: PhantomData,
+        })?)
+    }
+
+    /// Check that `bytes` is an envelope from [`SignedMessage::seal()`],
+    /// signed by a process on `expected_node`, and return the value in it.
+    pub fn open(bytes: &[u8], expected_node: &str) -> Result<T, CryptoError> {
+        let envelope: SignedMessage<T> = serde_json::from_slice(bytes)?;
+        if envelope.from.node() != expected_node {
+            return Err(CryptoError::WrongSigner {
+                expected: expected_node.to_string(),
+                signer: envelope.from.to_string(),
+            });
+        }
+        if !verify(&envelope.from, &envelope.body, &envelope.signature)? {
+            return Err(CryptoError::BadSignature);
+        }
+        Ok(serde_json::from_slice(&envelope.body)?)
+    }
+}
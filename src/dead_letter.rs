@@ -0,0 +1,141 @@
+use crate::ids::Ulid;
+use crate::kv::{Kv, KvError};
+use crate::time::Timestamp;
+use crate::{Address, Message, ModuleError, ProcessLibError, Request, SendError};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of every key a [`DeadLetter`] queue writes.
+const KEY_PREFIX: &[u8] = b"dead_letter:";
+
+/// A request that failed to get a response, kept so it can be replayed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterEntry {
+    pub id: Ulid,
+    pub target: Address,
+    pub body: Vec<u8>,
+    pub metadata: Option<String>,
+    pub blob: Option<Vec<u8>>,
+    /// How many times the request has been sent.
+    pub attempts: u32,
+    /// Why the last attempt failed.
+    pub error: String,
+    pub failed_at: Timestamp,
+}
+
+/// A queue, kept in kv, of outgoing requests whose responses timed out or
+/// that could not be delivered, so that failed work is kept for a later
+/// [`DeadLetter::replay()`] instead of being silently dropped.
+///
+/// ```ignore
+/// let dead_letters = DeadLetter::new(kv::open(our.package_id(), "dead_letters")?);
+/// match Request::to(&target).body(body).send_and_await_response(5)? {
+///     Ok(response) => handle(response),
+///     Err(e) => { dead_letters.record(&target, &e)?; }
+/// }
+/// // later, e.g. on a timer
+/// dead_letters.replay_all(5)?;
+/// ```
+///
+/// Entries are kept in the order they failed. Give the queue a db of its
+/// own, or at least no other keys starting with `dead_letter:`.
+pub struct DeadLetter {
+    kv: Kv,
+}
+
+impl DeadLetter {
+    pub fn new(kv: Kv) -> Self {
+        DeadLetter { kv }
+    }
+
+    /// Add the request a [`SendError`] reports to the queue. The error does
+    /// not say where the request was going, so pass its `target`.
+    pub fn record(&self, target: &Address, error: &SendError) -> Result<Ulid, ProcessLibError> {
+        let entry = DeadLetterEntry {
+            id: Ulid::new(),
+            target: target.clone(),
+            body: error.message().body().to_vec(),
+            metadata: error.message().metadata().map(|s| s.to_string()),
+            blob: error.blob().map(|blob| blob.bytes.clone()),
+            attempts: 1,
+            error: error.to_string(),
+            failed_at: Timestamp::now(),
+        };
+        self.push(&entry)?;
+        Ok(entry.id)
+    }
+
+    /// Add an entry to the queue, replacing any with the same id.
+    pub fn push(&self, entry: &DeadLetterEntry) -> Result<(), ProcessLibError> {
+        self.kv
+            .set(entry_key(&entry.id), serde_json::to_vec(entry)?, None)
+    }
+
+    pub fn get(&self, id: &Ulid) -> Result<Option<DeadLetterEntry>, ProcessLibError> {
+        match self.kv.get(entry_key(id)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every entry in the queue, oldest first.
+    pub fn list(&self) -> Result<Vec<DeadLetterEntry>, ProcessLibError> {
+        self.kv
+            .scan_prefix(KEY_PREFIX)
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect()
+    }
+
+    pub fn remove(&self, id: &Ulid) -> Result<(), ProcessLibError> {
+        self.kv.delete(entry_key(id), None)
+    }
+
+    /// Send an entry's request again, waiting up to `timeout` seconds. If it
+    /// gets a response, the entry is removed; if not, its attempt count and
+    /// error are updated and it stays queued.
+    pub fn replay(
+        &self,
+        entry: &DeadLetterEntry,
+        timeout: u64,
+    ) -> Result<Result<Message, SendError>, ProcessLibError> {
+        let mut request = Request::new()
+            .target(entry.target.clone())
+            .body(entry.body.clone());
+        if let Some(metadata) = &entry.metadata {
+            request = request.metadata(metadata);
+        }
+        if let Some(blob) = &entry.blob {
+            request = request.blob_bytes(blob.clone());
+        }
+        let result = request.send_and_await_response(timeout)?;
+        match &result {
+            Ok(_) => self.remove(&entry.id)?,
+            Err(e) => self.push(&DeadLetterEntry {
+                attempts: entry.attempts + 1,
+                error: e.to_string(),
+                failed_at: Timestamp::now(),
+                ..entry.clone()
+            })?,
+        }
+        Ok(result)
+    }
+
+    /// Replay every entry, oldest first. Returns the responses to the ones
+    /// that succeeded, which are removed from the queue.
+    pub fn replay_all(
+        &self,
+        timeout: u64,
+    ) -> Result<Vec<(DeadLetterEntry, Message)>, ProcessLibError> {
+        let mut delivered = vec![];
+        for entry in self.list()? {
+            if let Ok(response) = self.replay(&entry, timeout)? {
+                delivered.push((entry, response));
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+fn entry_key(id: &Ulid) -> Vec<u8> {
+    [KEY_PREFIX, &id.to_bytes()].concat()
+}
@@ -0,0 +1,159 @@
+use crate::timer::{self, TimerEvent};
+use crate::Message;
+use std::time::Duration;
+
+/// Runs something once changes stop: each [`Debouncer::trigger()`] restarts
+/// the delay, and only when a full delay passes without one does
+/// [`Debouncer::handle()`] hand back the latest value. For example, to
+/// recompute an index 500ms after the last change:
+///
+/// ```ignore
+/// let mut reindex = Debouncer::new("reindex", Duration::from_millis(500));
+/// loop {
+///     let message = await_message()?;
+///     if let Some(fired) = reindex.handle(&message) {
+///         if let Some(changed) = fired {
+///             recompute_index(changed);
+///         }
+///         continue;
+///     }
+///     let change = apply(message)?;
+///     reindex.trigger(change);
+/// }
+/// ```
+///
+/// The timer is set with [`timer::set_timer_with_id()`] under `id`, which
+/// must be unique among the process's timers.
+pub struct Debouncer<C> {
+    id: String,
+    delay: Duration,
+    pending: Option<C>,
+}
+
+impl<C> Debouncer<C> {
+    pub fn new(id: &str, delay: Duration) -> Self {
+        Debouncer {
+            id: id.to_string(),
+            delay,
+            pending: None,
+        }
+    }
+
+    /// Record a change, replacing any value not yet handed back, and restart
+    /// the delay.
+    pub fn trigger(&mut self, value: C) {
+        self.pending = Some(value);
+        timer::set_timer_with_id(self.delay.as_millis() as u64, &self.id, None);
+    }
+
+    /// Drop the pending value, if any, so nothing fires.
+    pub fn cancel(&mut self) -> Option<C> {
+        timer::cancel_timer(&self.id);
+        self.pending.take()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// If `message` is this debouncer's timer, return `Some`, holding the
+    /// latest value if the delay has passed, or `None` if the timer was
+    /// stale. Returns `None` for any other message.
+    pub fn handle(&mut self, message: &Message) -> Option<Option<C>> {
+        if timer::timer_id(message)? != self.id {
+            return None;
+        }
+        match timer::handle_timer(message)? {
+            TimerEvent::Fired { .. } => Some(self.pending.take()),
+            TimerEvent::Stale { .. } => Some(None),
+        }
+    }
+}
+
+/// Runs something at most once per interval. A value submitted within the
+/// interval of the last run is held, replacing any already held, and handed
+/// back by [`Throttle::handle()`] once the interval is up, so the last
+/// change is never lost. For example, to persist state at most once a second:
+///
+/// ```ignore
+/// let mut persist = Throttle::new("persist", Duration::from_secs(1));
+/// loop {
+///     let message = await_message()?;
+///     if let Some(Some(state)) = persist.handle(&message) {
+///         set_state(&state);
+///         continue;
+///     }
+///     let state = apply(message)?;
+///     if let Some(state) = persist.submit(state) {
+///         set_state(&state);
+///     }
+/// }
+/// ```
+///
+/// As with [`Debouncer`], `id` must be unique among the process's timers.
+pub struct Throttle<C> {
+    id: String,
+    interval: Duration,
+    last_run: Option<u64>,
+    pending: Option<C>,
+}
+
+impl<C> Throttle<C> {
+    pub fn new(id: &str, interval: Duration) -> Self {
+        Throttle {
+            id: id.to_string(),
+            interval,
+            last_run: None,
+            pending: None,
+        }
+    }
+
+    /// Return `value` if it may run now, counting that as a run. Otherwise
+    /// hold it until the interval is up and return `None`.
+    pub fn submit(&mut self, value: C) -> Option<C> {
+        let now = crate::time::now_millis();
+        let interval = self.interval.as_millis() as u64;
+        match self.last_run {
+            Some(last_run) if now < last_run + interval => {
+                if self.pending.is_none() {
+                    timer::set_timer_with_id(last_run + interval - now, &self.id, None);
+                }
+                self.pending = Some(value);
+                None
+            }
+            _ => {
+                self.last_run = Some(now);
+                Some(value)
+            }
+        }
+    }
+
+    /// Drop the held value, if any.
+    pub fn cancel(&mut self) -> Option<C> {
+        timer::cancel_timer(&self.id);
+        self.pending.take()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// If `message` is this throttle's timer, return `Some`, holding the
+    /// held value if it may now run, counting that as a run. Returns `None`
+    /// for any other message.
+    pub fn handle(&mut self, message: &Message) -> Option<Option<C>> {
+        if timer::timer_id(message)? != self.id {
+            return None;
+        }
+        match timer::handle_timer(message)? {
+            TimerEvent::Fired { .. } => {
+                let value = self.pending.take();
+                if value.is_some() {
+                    self.last_run = Some(crate::time::now_millis());
+                }
+                Some(value)
+            }
+            TimerEvent::Stale { .. } => Some(None),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+#[cfg(feature = "kv")]
+use crate::kv::Kv;
+use crate::Message;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+
+/// Discards repeats of messages already handled, for protocols that retry
+/// on timeout and so may deliver the same request more than once.
+///
+/// Messages are identified by a SHA-256 hash of their source, body and
+/// metadata, truncated to 128 bits, or
+/// by an id the protocol provides with [`Dedup::seen_id()`]. The most recent
+/// `capacity` ids are remembered in memory. With [`Dedup::with_kv()`], ids
+/// are also persisted, so retries are caught across restarts.
+///
+/// ```ignore
+/// let mut dedup = Dedup::new(1024);
+/// let message = await_message()?;
+/// if dedup.seen(&message) {
+///     continue;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Dedup {
+    capacity: usize,
+    order: VecDeque<u128>,
+    ids: HashSet<u128>,
+    #[cfg(feature = "kv")]
+    kv: Option<Kv>,
+}
+
+impl Dedup {
+    /// Remember up to `capacity` ids in memory.
+    pub fn new(capacity: usize) -> Self {
+        Dedup {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            ids: HashSet::with_capacity(capacity),
+            #[cfg(feature = "kv")]
+            kv: None,
+        }
+    }
+
+    /// Also persist every id to `kv`, and check it for ids not in memory.
+    /// Entries in the db are never removed, so give it a db of its own that
+    /// can be cleared when old retries can no longer arrive.
+    #[cfg(feature = "kv")]
+    pub fn with_kv(capacity: usize, kv: Kv) -> Self {
+        Dedup {
+            kv: Some(kv),
+            ..Dedup::new(capacity)
+        }
+    }
+
+    /// Record `message`, returning `true` if it has been seen before.
+    pub fn seen(&mut self, message: &Message) -> bool {
+        let metadata = match message.metadata() {
+            Some(metadata) => [b"\x01", metadata.as_bytes()].concat(),
+            None => vec![0],
+        };
+        self.seen_hash(stable_hash(&[
+            message.source().to_string().as_bytes(),
+            message.body(),
+            &metadata,
+        ]))
+    }
+
+    /// Record a protocol-level message id, returning `true` if it has been
+    /// seen before.
+    pub fn seen_id(&mut self, id: &[u8]) -> bool {
+        self.seen_hash(stable_hash(&[id]))
+    }
+
+    fn seen_hash(&mut self, hash: u128) -> bool {
+        if self.ids.contains(&hash) {
+            return true;
+        }
+        #[cfg(feature = "kv")]
+        if let Some(kv) = &self.kv {
+            let key = dedup_key(hash);
+            // a missing key means unseen; if the db is unreachable, fall back
+            // to memory alone rather than drop a message we may never have handled
+            if kv.get(key.clone()).is_ok() {
+                self.remember(hash);
+                return true;
+            }
+            let _ = kv.set(key, vec![], None);
+        }
+        self.remember(hash);
+        false
+    }
+
+    fn remember(&mut self, hash: u128) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(hash);
+        self.ids.insert(hash);
+    }
+}
+
+/// A hash that stays the same across builds and toolchains, since ids are
+/// persisted. Each part is prefixed with its length, so parts cannot run
+/// into each other.
+fn stable_hash(parts: &[&[u8]]) -> u128 {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    u128::from_be_bytes(hasher.finalize()[..16].try_into().unwrap())
+}
+
+#[cfg(feature = "kv")]
+fn dedup_key(hash: u128) -> Vec<u8> {
+    [b"dedup:".as_slice(), &hash.to_be_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Address;
+
+    fn request(source: &str, body: &[u8], metadata: Option<&str>) -> Message {
+        Message::Request {
+            source: source.parse::<Address>().unwrap(),
+            expects_response: Some(5),
+            body: body.to_vec(),
+            metadata: metadata.map(|m| m.to_string()),
+            capabilities: vec![],
+        }
+    }
+
+    #[test]
+    fn detects_duplicates() {
+        let mut dedup = Dedup::new(8);
+        assert!(!dedup.seen_id(b"a"));
+        assert!(dedup.seen_id(b"a"));
+        assert!(!dedup.seen_id(b"b"));
+        assert!(dedup.seen_id(b"a"));
+    }
+
+    #[test]
+    fn identifies_messages_by_source_body_and_metadata() {
+        let mut dedup = Dedup::new(8);
+        assert!(!dedup.seen(&request("a.os@p:pkg:pub.os", b"x", None)));
+        assert!(dedup.seen(&request("a.os@p:pkg:pub.os", b"x", None)));
+        assert!(!dedup.seen(&request("b.os@p:pkg:pub.os", b"x", None)));
+        assert!(!dedup.seen(&request("a.os@p:pkg:pub.os", b"y", None)));
+        assert!(!dedup.seen(&request("a.os@p:pkg:pub.os", b"x", Some(""))));
+    }
+
+    #[test]
+    fn evicts_the_oldest_beyond_the_window() {
+        let mut dedup = Dedup::new(2);
+        assert!(!dedup.seen_id(b"a"));
+        assert!(!dedup.seen_id(b"b"));
+        assert!(!dedup.seen_id(b"c"));
+        // "a" fell out of the window of two, and seeing it again evicts "b"
+        assert!(!dedup.seen_id(b"a"));
+        assert!(dedup.seen_id(b"c"));
+        assert!(!dedup.seen_id(b"b"));
+    }
+
+    #[test]
+    fn remembers_nothing_with_no_capacity() {
+        let mut dedup = Dedup::new(0);
+        assert!(!dedup.seen_id(b"a"));
+        assert!(!dedup.seen_id(b"a"));
+    }
+
+    #[test]
+    fn parts_cannot_run_into_each_other() {
+        assert_ne!(
+            stable_hash(&[b"ab".as_slice(), b"c"]),
+            stable_hash(&[b"a".as_slice(), b"bc"])
+        );
+    }
+}
@@ -0,0 +1,205 @@
+#[cfg(feature = "apps")]
+use crate::apps::AppsError;
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoError;
+#[cfg(feature = "eth")]
+use crate::eth::EthError;
+#[cfg(feature = "graphdb")]
+use crate::graphdb::GraphDbError;
+#[cfg(feature = "kv")]
+use crate::kv::KvError;
+#[cfg(feature = "llm")]
+use crate::llm::LlmError;
+#[cfg(feature = "python")]
+use crate::python::PythonError;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteError;
+#[cfg(feature = "vfs")]
+use crate::vfs::VfsError;
+use crate::{SendError, SendErrorKind};
+use thiserror::Error;
+
+/// Crate-wide error type. Functions that talk to runtime modules return this,
+/// so callers can match on the kind of failure rather than on error strings.
+/// It implements [`std::error::Error`], so `?` still converts it into an
+/// `anyhow::Error` for callers that don't care.
+#[derive(Debug, Error)]
+pub enum ProcessLibError {
+    /// The target did not respond within the timeout.
+    #[error("timed out waiting for response")]
+    Timeout,
+    /// The target's node could not be reached.
+    #[error("target node is offline")]
+    Offline,
+    /// A body or blob could not be serialized or deserialized.
+    #[error("serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// The target responded, but not with a message we expected.
+    #[error("unexpected response: {body}")]
+    UnexpectedResponse { body: String },
+    /// The response was expected to carry a blob, but did not.
+    #[error("response is missing its blob")]
+    NoBlob,
+    /// An argument was rejected before anything was sent.
+    #[error("invalid input: {error}")]
+    InvalidInput { error: String },
+    /// A runtime module returned an error of its own.
+    #[error(transparent)]
+    Module(#[from] ModuleError),
+    /// A request to a module on another node was not delivered, or got no
+    /// response in time.
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+    /// Data failed to decrypt or verify. Boxed, since a [`CryptoError`] may
+    /// itself hold a `ProcessLibError`.
+    #[cfg(feature = "crypto")]
+    #[error(transparent)]
+    Crypto(Box<CryptoError>),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Errors returned by the runtime modules process_lib wraps.
+#[derive(Debug, Error)]
+pub enum ModuleError {
+    #[cfg(feature = "kv")]
+    #[error(transparent)]
+    Kv(#[from] KvError),
+    #[cfg(feature = "graphdb")]
+    #[error(transparent)]
+    GraphDb(#[from] GraphDbError),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] SqliteError),
+    #[cfg(feature = "vfs")]
+    #[error(transparent)]
+    Vfs(#[from] VfsError),
+    #[cfg(feature = "llm")]
+    #[error(transparent)]
+    Llm(#[from] LlmError),
+    #[cfg(feature = "apps")]
+    #[error(transparent)]
+    Apps(#[from] AppsError),
+    #[cfg(feature = "eth")]
+    #[error(transparent)]
+    Eth(#[from] EthError),
+    #[cfg(feature = "python")]
+    #[error(transparent)]
+    Python(#[from] PythonError),
+}
+
+/// A request to another node's runtime module that failed in the network
+/// rather than in the module.
+#[derive(Debug, Error)]
+#[error("request to node {node} failed: {kind:?}")]
+pub struct RemoteError {
+    pub node: String,
+    pub kind: SendErrorKind,
+}
+
+impl RemoteError {
+    pub fn new(node: &str, error: SendError) -> Self {
+        RemoteError {
+            node: node.to_string(),
+            kind: error.kind,
+        }
+    }
+}
+
+impl ProcessLibError {
+    /// Build an `UnexpectedResponse` from anything printable.
+    pub fn unexpected<T: std::fmt::Debug>(response: T) -> Self {
+        ProcessLibError::UnexpectedResponse {
+            body: format!("{:?}", response),
+        }
+    }
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ProcessLibError::Timeout => true,
+            ProcessLibError::Remote(error) => error.kind.is_timeout(),
+            _ => false,
+        }
+    }
+    pub fn is_offline(&self) -> bool {
+        match self {
+            ProcessLibError::Offline => true,
+            ProcessLibError::Remote(error) => error.kind.is_offline(),
+            _ => false,
+        }
+    }
+}
+
+impl From<SendError> for ProcessLibError {
+    fn from(error: SendError) -> Self {
+        match error.kind {
+            SendErrorKind::Offline => ProcessLibError::Offline,
+            SendErrorKind::Timeout => ProcessLibError::Timeout,
+        }
+    }
+}
+
+#[cfg(feature = "kv")]
+impl From<KvError> for ProcessLibError {
+    fn from(error: KvError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "graphdb")]
+impl From<GraphDbError> for ProcessLibError {
+    fn from(error: GraphDbError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<SqliteError> for ProcessLibError {
+    fn from(error: SqliteError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "vfs")]
+impl From<VfsError> for ProcessLibError {
+    fn from(error: VfsError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "llm")]
+impl From<LlmError> for ProcessLibError {
+    fn from(error: LlmError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "crypto")]
+impl From<CryptoError> for ProcessLibError {
+    fn from(error: CryptoError) -> Self {
+        match error {
+            CryptoError::Net(error) => error,
+            error => ProcessLibError::Crypto(Box::new(error)),
+        }
+    }
+}
+
+#[cfg(feature = "apps")]
+impl From<AppsError> for ProcessLibError {
+    fn from(error: AppsError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "eth")]
+impl From<EthError> for ProcessLibError {
+    fn from(error: EthError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<PythonError> for ProcessLibError {
+    fn from(error: PythonError) -> Self {
+        ProcessLibError::Module(error.into())
+    }
+}
@@ -1,10 +1,12 @@
 use crate::*;
 use crate::{Address as KiAddress, Request as KiRequest};
 use alloy_rpc_types::Log;
+pub use ethers_core::types::transaction::eip2718::TypedTransaction;
 pub use ethers_core::types::{
-    Address as EthAddress, BlockNumber, Filter, FilterBlockOption, Topic, ValueOrArray, H256, U64,
+    Address as EthAddress, BlockNumber, Bytes, Filter, FilterBlockOption, Signature, Topic,
+    TransactionReceipt, ValueOrArray, H256, U256, U64,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 /// The Request type that can be made to eth:distro:sys. Currently primitive, this
 /// enum will expand to support more actions in the future.
@@ -16,20 +18,35 @@ pub enum EthAction {
     SubscribeLogs { sub_id: u64, filter: Filter },
     /// Kill a SubscribeLogs subscription of a given ID, to stop getting updates.
     UnsubscribeLogs(u64),
+    /// Make a JSON-RPC call, e.g. `eth_getTransactionCount`, to the provider.
+    /// Answered with a `Result<serde_json::Value, EthError>` holding the
+    /// call's result.
+    Request {
+        method: String,
+        params: serde_json::Value,
+        /// The RPC endpoint URL or configured provider alias to use, or
+        /// `None` for the module's default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provider: Option<String>,
+    },
 }
 
 /// The Response type which a process will get from requesting with an [`EthAction`] will be
 /// of the form `Result<(), EthError>`, serialized and deserialized using `serde_json::to_vec`
 /// and `serde_json::from_slice`.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, thiserror::Error)]
 pub enum EthError {
     /// The subscription ID already existed
+    #[error("eth: subscription id already exists")]
     SubscriptionIdCollision,
     /// The ethers provider threw an error when trying to subscribe
     /// (contains ProviderError serialized to debug string)
+    #[error("eth: provider error: {0}")]
     ProviderError(String),
+    #[error("eth: subscription closed")]
     SubscriptionClosed,
     /// The subscription ID was not found, so we couldn't unsubscribe.
+    #[error("eth: subscription not found")]
     SubscriptionNotFound,
 }
 
@@ -233,3 +250,331 @@ impl SubscribeLogsRequest {
         self.filter.get_to_block()
     }
 }
+
+/// Makes JSON-RPC calls through the eth module, typed for the calls that
+/// sending a transaction needs. Any other call can be made with
+/// [`EthProvider::request()`].
+///
+/// Given several providers with [`EthProvider::with_providers()`], each
+/// call goes to the first healthy one, in order. A provider that times out
+/// or rate-limits a call is benched for a while, doubling each time it
+/// fails in a row, and the call is retried on the next. Clones of a
+/// provider share its health.
+#[derive(Debug, Clone)]
+pub struct EthProvider {
+    /// Timeout, in seconds, for each call.
+    pub timeout: u64,
+    providers: Vec<String>,
+    health: std::rc::Rc<std::cell::RefCell<Vec<ProviderHealth>>>,
+}
+
+/// How a provider of an [`EthProvider`] has been doing.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderHealth {
+    /// Calls that have failed over in a row.
+    pub failures: u32,
+    /// Until when, in milliseconds since the epoch, the provider is skipped.
+    pub benched_until: u64,
+    pub last_error: Option<String>,
+}
+
+/// How long a provider is first benched after failing.
+const BENCH_MILLIS: u64 = 5_000;
+/// The longest a provider is benched for.
+const MAX_BENCH_MILLIS: u64 = 5 * 60_000;
+
+/// Why a call failed.
+enum CallError {
+    /// The provider timed out or rate-limited us: try another.
+    Unavailable(ProcessLibError),
+    Failed(ProcessLibError),
+}
+
+impl Default for EthProvider {
+    fn default() -> Self {
+        EthProvider {
+            timeout: 10,
+            providers: vec![],
+            health: Default::default(),
+        }
+    }
+}
+
+impl EthProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use these RPC endpoint URLs or provider aliases, in order of
+    /// preference, instead of the eth module's default.
+    pub fn with_providers(mut self, providers: &[&str]) -> Self {
+        self.providers = providers.iter().map(|p| p.to_string()).collect();
+        self.health = std::rc::Rc::new(std::cell::RefCell::new(vec![
+            ProviderHealth::default();
+            providers.len()
+        ]));
+        self
+    }
+
+    /// Each provider with its health, in order of preference.
+    pub fn health(&self) -> Vec<(String, ProviderHealth)> {
+        self.providers
+            .iter()
+            .cloned()
+            .zip(self.health.borrow().iter().cloned())
+            .collect()
+    }
+
+    /// Make a JSON-RPC call and deserialize its result. If every provider
+    /// is unavailable, the last one's error is returned.
+    pub fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<T, ProcessLibError> {
+        if self.providers.is_empty() {
+            return match self.call(None, method, &params) {
+                Ok(value) => Ok(serde_json::from_value(value)?),
+                Err(CallError::Unavailable(e) | CallError::Failed(e)) => Err(e),
+            };
+        }
+        let mut last_error = None;
+        for i in self.provider_order() {
+            match self.call(Some(&self.providers[i]), method, &params) {
+                Ok(value) => {
+                    self.health.borrow_mut()[i] = ProviderHealth::default();
+                    return Ok(serde_json::from_value(value)?);
+                }
+                Err(CallError::Unavailable(e)) => {
+                    let mut health = self.health.borrow_mut();
+                    let health = &mut health[i];
+                    health.failures += 1;
+                    let bench = BENCH_MILLIS
+                        .saturating_mul(1 << (health.failures - 1).min(16))
+                        .min(MAX_BENCH_MILLIS);
+                    health.benched_until = crate::time::now_millis() + bench;
+                    health.last_error = Some(e.to_string());
+                    last_error = Some(e);
+                }
+                Err(CallError::Failed(e)) => return Err(e),
+            }
+        }
+        // the provider list is not empty, so at least one was tried
+        Err(last_error.unwrap())
+    }
+
+    /// Indices of the providers to try: those not benched, in order, then
+    /// the benched ones, soonest back first, as a last resort.
+    fn provider_order(&self) -> Vec<usize> {
+        let now = crate::time::now_millis();
+        let health = self.health.borrow();
+        let (mut ready, mut benched): (Vec<usize>, Vec<usize>) =
+            (0..self.providers.len()).partition(|&i| health[i].benched_until <= now);
+        benched.sort_by_key(|&i| health[i].benched_until);
+        ready.extend(benched);
+        ready
+    }
+
+    fn call(
+        &self,
+        provider: Option<&str>,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, CallError> {
+        let body = serde_json::to_vec(&EthAction::Request {
+            method: method.to_string(),
+            params: params.clone(),
+            provider: provider.map(|p| p.to_string()),
+        })
+        .map_err(|e| CallError::Failed(e.into()))?;
+        let response = KiRequest::new()
+            .target(("our", "eth", "distro", "sys"))
+            .body(body)
+            .send_and_await_response(self.timeout)
+            .map_err(|e| CallError::Failed(e.into()))?
+            .map_err(|e| CallError::Unavailable(e.into()))?;
+        match serde_json::from_slice::<Result<serde_json::Value, EthError>>(response.body()) {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(EthError::ProviderError(e))) if is_unavailable(&e) => {
+                Err(CallError::Unavailable(EthError::ProviderError(e).into()))
+            }
+            Ok(Err(e)) => Err(CallError::Failed(e.into())),
+            Err(e) => Err(CallError::Failed(e.into())),
+        }
+    }
+
+    pub fn chain_id(&self) -> Result<U256, ProcessLibError> {
+        self.request("eth_chainId", serde_json::json!([]))
+    }
+
+    /// The number of transactions `address` has sent, counting pending
+    /// ones, which is the nonce its next transaction should use.
+    pub fn get_transaction_count(&self, address: EthAddress) -> Result<U256, ProcessLibError> {
+        self.request(
+            "eth_getTransactionCount",
+            serde_json::json!([address, "pending"]),
+        )
+    }
+
+    pub fn gas_price(&self) -> Result<U256, ProcessLibError> {
+        self.request("eth_gasPrice", serde_json::json!([]))
+    }
+
+    pub fn estimate_gas(&self, tx: &TypedTransaction) -> Result<U256, ProcessLibError> {
+        self.request("eth_estimateGas", serde_json::json!([tx]))
+    }
+
+    /// Submit a signed transaction and return its hash.
+    pub fn send_raw_transaction(&self, raw: &Bytes) -> Result<H256, ProcessLibError> {
+        self.request("eth_sendRawTransaction", serde_json::json!([raw]))
+    }
+
+    /// The receipt of a mined transaction, or `None` if it is still pending.
+    pub fn get_transaction_receipt(
+        &self,
+        hash: H256,
+    ) -> Result<Option<TransactionReceipt>, ProcessLibError> {
+        self.request("eth_getTransactionReceipt", serde_json::json!([hash]))
+    }
+}
+
+/// Whether a provider error means the provider is down or rate-limiting,
+/// rather than that the call itself was bad.
+fn is_unavailable(error: &str) -> bool {
+    let error = error.to_lowercase();
+    [
+        "429",
+        "rate limit",
+        "too many requests",
+        "timeout",
+        "timed out",
+        "503",
+        "502",
+    ]
+    .iter()
+    .any(|pattern| error.contains(pattern))
+}
+
+/// Signs transactions for a [`TxManager`], e.g. with a local wallet key or
+/// by asking another process that holds one.
+pub trait TxSigner {
+    /// The account that sends the transactions.
+    fn address(&self) -> EthAddress;
+    /// Sign a transaction whose nonce, gas, gas price and chain id are set.
+    fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, ProcessLibError>;
+}
+
+/// Sends transactions from one account: fills in the nonce, gas limit and
+/// gas price, signs, submits, and waits for receipts.
+///
+/// ```ignore
+/// let manager = TxManager::new(kv, provider, signer)?;
+/// let hash = manager.send(TransactionRequest::new().to(contract).data(calldata).into())?;
+/// let receipt = manager.wait_for_receipt(hash, Duration::from_secs(2), Duration::from_secs(120))?;
+/// ```
+///
+/// The next nonce is kept in kv, so transactions sent in quick succession,
+/// or across a restart, do not reuse one while earlier ones are pending.
+/// If transactions are also sent from the account elsewhere, call
+/// [`TxManager::reset_nonce()`] after they are.
+#[cfg(all(feature = "kv", feature = "timer"))]
+pub struct TxManager<S: TxSigner> {
+    kv: crate::kv::Kv,
+    provider: EthProvider,
+    signer: S,
+    chain_id: U256,
+}
+
+#[cfg(all(feature = "kv", feature = "timer"))]
+impl<S: TxSigner> TxManager<S> {
+    pub fn new(
+        kv: crate::kv::Kv,
+        provider: EthProvider,
+        signer: S,
+    ) -> Result<Self, ProcessLibError> {
+        let chain_id = provider.chain_id()?;
+        Ok(TxManager {
+            kv,
+            provider,
+            signer,
+            chain_id,
+        })
+    }
+
+    /// Fill in whatever of `tx`'s sender, nonce, chain id, gas limit and gas
+    /// price it does not set, sign it, submit it, and return its hash. The
+    /// nonce is only used up if the provider accepts the transaction.
+    pub fn send(&self, mut tx: TypedTransaction) -> Result<H256, ProcessLibError> {
+        tx.set_from(self.signer.address());
+        tx.set_chain_id(self.chain_id.as_u64());
+        let nonce = match tx.nonce() {
+            Some(nonce) => *nonce,
+            None => self.next_nonce()?,
+        };
+        tx.set_nonce(nonce);
+        if tx.gas_price().is_none() {
+            tx.set_gas_price(self.provider.gas_price()?);
+        }
+        if tx.gas().is_none() {
+            tx.set_gas(self.provider.estimate_gas(&tx)?);
+        }
+        let signature = self.signer.sign_transaction(&tx)?;
+        let hash = self
+            .provider
+            .send_raw_transaction(&tx.rlp_signed(&signature))?;
+        self.store_nonce(nonce + 1)?;
+        Ok(hash)
+    }
+
+    /// Poll for a transaction's receipt every `poll` until it is mined, or
+    /// fail with [`ProcessLibError::Timeout`] once `timeout` has passed.
+    pub fn wait_for_receipt(
+        &self,
+        hash: H256,
+        poll: std::time::Duration,
+        timeout: std::time::Duration,
+    ) -> Result<TransactionReceipt, ProcessLibError> {
+        let deadline = crate::time::now_millis() + timeout.as_millis() as u64;
+        loop {
+            if let Some(receipt) = self.provider.get_transaction_receipt(hash)? {
+                return Ok(receipt);
+            }
+            if crate::time::now_millis() >= deadline {
+                return Err(ProcessLibError::Timeout);
+            }
+            crate::timer::set_and_await_timer(poll.as_millis() as u64)?;
+        }
+    }
+
+    /// The nonce the next transaction will use: the stored one, unless the
+    /// chain has seen more transactions from the account.
+    pub fn next_nonce(&self) -> Result<U256, ProcessLibError> {
+        let chain = self.provider.get_transaction_count(self.signer.address())?;
+        let stored = match self.kv.get(self.nonce_key()) {
+            Ok(bytes) => Some(serde_json::from_slice::<U256>(&bytes)?),
+            Err(ProcessLibError::Module(ModuleError::Kv(crate::kv::KvError::KeyNotFound {
+                ..
+            }))) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(stored.map_or(chain, |stored| stored.max(chain)))
+    }
+
+    /// Forget the stored nonce, so the next one is read from the chain.
+    pub fn reset_nonce(&self) -> Result<(), ProcessLibError> {
+        self.kv.delete(self.nonce_key(), None)
+    }
+
+    pub fn provider(&self) -> &EthProvider {
+        &self.provider
+    }
+
+    fn store_nonce(&self, nonce: U256) -> Result<(), ProcessLibError> {
+        self.kv
+            .set(self.nonce_key(), serde_json::to_vec(&nonce)?, None)
+    }
+
+    fn nonce_key(&self) -> Vec<u8> {
+        format!("eth_nonce:{}:{:?}", self.chain_id, self.signer.address()).into_bytes()
+    }
+}
@@ -0,0 +1,163 @@
+use crate::kv::{Kv, KvError, LogPosition};
+use crate::{ModuleError, ProcessLibError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// State built by applying events in order, for an [`EventStore`].
+pub trait EventSourced: Default + Serialize + DeserializeOwned {
+    type Event: Serialize + DeserializeOwned;
+
+    fn apply(&mut self, event: &Self::Event);
+}
+
+/// Where an [`EventStore`] keeps its snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotStore {
+    /// In kv, next to the log.
+    Kv,
+    /// In the process's saved state, with `set_state()`. Use this only if
+    /// the process keeps nothing else there.
+    ProcessState,
+}
+
+/// A snapshot of the state and the last event it includes.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T> {
+    position: Option<LogPosition>,
+    state: T,
+}
+
+/// Event-sourced state: every change is appended to a kv event log as an
+/// event, and the state is what applying them all in order gives. Every so
+/// many events the state is snapshotted and the events it includes are
+/// pruned, so that loading replays only the events since the snapshot.
+///
+/// ```ignore
+/// #[derive(Default, Serialize, Deserialize)]
+/// struct Balance(i64);
+/// impl EventSourced for Balance {
+///     type Event = i64;
+///     fn apply(&mut self, delta: &i64) { self.0 += delta; }
+/// }
+///
+/// let mut store = EventStore::<Balance>::load(kv, "balance", SnapshotStore::Kv)?;
+/// store.append(&-25)?;
+/// println!("balance: {}", store.state().0);
+/// ```
+///
+/// An event is in the log before it is applied, so a crash between the two
+/// is made good by the replay on the next load. Events are JSON, and the
+/// log and snapshot are named by `log`, so several stores can share a db.
+pub struct EventStore<T: EventSourced> {
+    kv: Kv,
+    log: String,
+    snapshots: SnapshotStore,
+    /// Snapshot after this many events; 0 never snapshots on its own.
+    snapshot_every: usize,
+    state: T,
+    /// The last event applied to `state`.
+    position: Option<LogPosition>,
+    since_snapshot: usize,
+}
+
+impl<T: EventSourced> EventStore<T> {
+    /// Load the latest snapshot, or the default state if there is none, and
+    /// replay the events logged since.
+    pub fn load(kv: Kv, log: &str, snapshots: SnapshotStore) -> Result<Self, ProcessLibError> {
+        let snapshot = match snapshots {
+            SnapshotStore::Kv => match kv.get(snapshot_key(log)) {
+                Ok(bytes) => Some(serde_json::from_slice::<Snapshot<T>>(&bytes)?),
+                Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => None,
+                Err(e) => return Err(e),
+            },
+            SnapshotStore::ProcessState => match crate::get_state() {
+                Some(bytes) => Some(serde_json::from_slice::<Snapshot<T>>(&bytes)?),
+                None => None,
+            },
+        };
+        let (mut state, mut position) = match snapshot {
+            Some(snapshot) => (snapshot.state, snapshot.position),
+            None => (T::default(), None),
+        };
+        let events = kv.read_log_after(log, position)?;
+        let since_snapshot = events.len();
+        for entry in events {
+            state.apply(&serde_json::from_slice(&entry.value)?);
+            position = Some(entry.position);
+        }
+        Ok(EventStore {
+            kv,
+            log: log.to_string(),
+            snapshots,
+            snapshot_every: 100,
+            state,
+            position,
+            since_snapshot,
+        })
+    }
+
+    /// Snapshot after every `events` events, instead of every 100. With 0,
+    /// snapshots are only taken by [`EventStore::snapshot()`].
+    pub fn snapshot_every(mut self, events: usize) -> Self {
+        self.snapshot_every = events;
+        self
+    }
+
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// The position of the last event applied.
+    pub fn position(&self) -> Option<LogPosition> {
+        self.position
+    }
+
+    /// Log an event and apply it to the state, snapshotting if due.
+    pub fn append(&mut self, event: &T::Event) -> Result<LogPosition, ProcessLibError> {
+        let bytes = serde_json::to_vec(event)?;
+        let position = self.kv.append_log_after(&self.log, self.position, bytes)?;
+        // replay reads only what comes after `self.position`, so an event
+        // logged at or before it would be lost on the next load
+        if Some(position) <= self.position {
+            return Err(ProcessLibError::InvalidInput {
+                error: format!(
+                    "event log {:?} appended at {:?}, not after {:?}",
+                    self.log, position, self.position
+                ),
+            });
+        }
+        self.state.apply(event);
+        self.position = Some(position);
+        self.since_snapshot += 1;
+        if self.snapshot_every > 0 && self.since_snapshot >= self.snapshot_every {
+            self.snapshot()?;
+        }
+        Ok(position)
+    }
+
+    /// Save the state now and prune the events it includes.
+    pub fn snapshot(&mut self) -> Result<(), ProcessLibError> {
+        let bytes = serde_json::to_vec(&Snapshot {
+            position: self.position,
+            state: &self.state,
+        })?;
+        match self.snapshots {
+            SnapshotStore::Kv => self.kv.set(snapshot_key(&self.log), bytes, None)?,
+            SnapshotStore::ProcessState => crate::set_state(&bytes),
+        }
+        // only once the snapshot is saved are its events safe to drop
+        if let Some(position) = self.position {
+            self.kv.compact_log(&self.log, position.successor())?;
+        }
+        self.since_snapshot = 0;
+        Ok(())
+    }
+
+    /// The underlying db.
+    pub fn kv(&self) -> &Kv {
+        &self.kv
+    }
+}
+
+fn snapshot_key(log: &str) -> Vec<u8> {
+    format!("event_store:{}:snapshot", log).into_bytes()
+}
@@ -1,4 +1,6 @@
-use crate::{get_blob, Message, PackageId, Request};
+use crate::{
+    get_blob, Address, Capability, Message, PackageId, ProcessLibError, RemoteError, Request,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -14,30 +16,469 @@ pub struct GraphDbRequest {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum DefineResourceType {
-    Namespace { name: String },
-    Database { name: String },
-    Table { name: String },
+    Namespace {
+        name: String,
+    },
+    Database {
+        name: String,
+    },
+    Table {
+        name: String,
+        /// Row-level permissions for the table. `None` leaves SurrealDB's default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        permissions: Option<Permissions>,
+    },
+    /// A function, `DEFINE FUNCTION fn::name($arg: type, ...) { body }`,
+    /// callable in queries as `fn::name(...)` or with [`GraphDb::call_function()`].
+    Function {
+        /// The name without its `fn::` prefix. May contain `::`, e.g. `score::post`.
+        name: String,
+        /// Argument names, without `$`, and their SurrealQL types, e.g. `("post", "object")`.
+        args: Vec<(String, String)>,
+        /// The function body, without the surrounding braces.
+        body: String,
+    },
+    /// A full-text search analyzer, for `SEARCH ANALYZER` indexes.
+    Analyzer {
+        name: String,
+        /// e.g. `blank`, `class`, `camel`, `punct`.
+        tokenizers: Vec<String>,
+        /// e.g. `lowercase`, `ascii`, `snowball(english)`.
+        filters: Vec<String>,
+    },
+}
+
+/// Who may perform one kind of operation on a table's rows.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Permission {
+    /// Nobody.
+    None,
+    /// Everybody.
+    Full,
+    /// Rows for which this SurrealQL condition holds, e.g. `user = $auth.id`.
+    Where(String),
+}
+
+impl From<&str> for Permission {
+    fn from(condition: &str) -> Self {
+        Permission::Where(condition.to_string())
+    }
+}
+
+impl From<String> for Permission {
+    fn from(condition: String) -> Self {
+        Permission::Where(condition)
+    }
+}
+
+/// The `PERMISSIONS` clause of a table definition, built per operation:
+///
+/// ```ignore
+/// let permissions = Permissions::new()
+///     .select(Permission::Full)
+///     .create("user = $auth.id")
+///     .update("user = $auth.id")
+///     .delete(Permission::None);
+/// db.define(DefineResourceType::Table { name: "post".into(), permissions: Some(permissions) })?;
+/// ```
+///
+/// Operations left unset keep SurrealDB's default for the table.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Permissions {
+    pub select: Option<Permission>,
+    pub create: Option<Permission>,
+    pub update: Option<Permission>,
+    pub delete: Option<Permission>,
+}
+
+impl Permissions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn select<P: Into<Permission>>(mut self, permission: P) -> Self {
+        self.select = Some(permission.into());
+        self
+    }
+    pub fn create<P: Into<Permission>>(mut self, permission: P) -> Self {
+        self.create = Some(permission.into());
+        self
+    }
+    pub fn update<P: Into<Permission>>(mut self, permission: P) -> Self {
+        self.update = Some(permission.into());
+        self
+    }
+    pub fn delete<P: Into<Permission>>(mut self, permission: P) -> Self {
+        self.delete = Some(permission.into());
+        self
+    }
+    /// Render as a SurrealQL `PERMISSIONS` clause, or an empty string if no
+    /// operation is set.
+    pub fn to_surql(&self) -> String {
+        let clauses: Vec<String> = [
+            ("select", &self.select),
+            ("create", &self.create),
+            ("update", &self.update),
+            ("delete", &self.delete),
+        ]
+        .into_iter()
+        .filter_map(|(operation, permission)| {
+            Some(match permission.as_ref()? {
+                Permission::None => format!("FOR {} NONE", operation),
+                Permission::Full => format!("FOR {} FULL", operation),
+                Permission::Where(condition) => {
+                    format!("FOR {} WHERE {}", operation, condition)
+                }
+            })
+        })
+        .collect();
+        if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("PERMISSIONS {}", clauses.join(" "))
+        }
+    }
 }
 
 pub type GraphDbRequestParams = serde_json::Value;
 
+/// A typed query parameter. Convert a set of them into [`GraphDbRequestParams`]
+/// with [`params()`].
+///
+/// Params travel as JSON, so datetimes, durations and record ids arrive in the
+/// query as strings: cast them with `<datetime>$param`, `<duration>$param` and
+/// `type::thing($param)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    String(String),
+    Number(serde_json::Number),
+    Bool(bool),
+    /// An RFC 3339 datetime, e.g. `2024-01-01T00:00:00Z`. Build one with
+    /// [`Param::datetime()`].
+    Datetime(String),
+    /// A SurrealQL duration, e.g. `1h30m`. Build one with [`Param::duration()`].
+    Duration(String),
+    /// A record id, built with [`record_id()`].
+    RecordId(String),
+    Array(Vec<Param>),
+    Object(std::collections::BTreeMap<String, Param>),
+    /// A geometry, sent as GeoJSON, which SurrealDB reads as a geometry.
+    /// Build one with [`Param::point()`] or [`Param::polygon()`].
+    Geometry(Geometry),
+}
+
+/// A GeoJSON geometry, with positions as `[longitude, latitude]`, which is
+/// also how SurrealDB returns geometry fields in query results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point([f64; 2]),
+    /// An outer ring followed by any holes, each closed: its last position
+    /// repeats its first.
+    Polygon(Vec<Vec<[f64; 2]>>),
+}
+
+impl From<Param> for serde_json::Value {
+    fn from(param: Param) -> Self {
+        match param {
+            Param::String(s) | Param::Datetime(s) | Param::Duration(s) | Param::RecordId(s) => {
+                serde_json::Value::String(s)
+            }
+            Param::Number(n) => serde_json::Value::Number(n),
+            Param::Bool(b) => serde_json::Value::Bool(b),
+            Param::Array(items) => {
+                serde_json::Value::Array(items.into_iter().map(Into::into).collect())
+            }
+            Param::Object(fields) => {
+                serde_json::Value::Object(fields.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+            Param::Geometry(geometry) => serde_json::to_value(geometry).unwrap(),
+        }
+    }
+}
+
+impl Param {
+    /// A datetime, in UTC with as many fractional digits as it needs, which
+    /// is how SurrealDB itself writes datetimes.
+    pub fn datetime<Tz: chrono::TimeZone>(datetime: chrono::DateTime<Tz>) -> Self {
+        Param::Datetime(
+            datetime
+                .with_timezone(&chrono::Utc)
+                .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+        )
+    }
+
+    /// A duration, written in SurrealQL duration syntax.
+    pub fn duration(duration: std::time::Duration) -> Self {
+        Param::Duration(format_duration(duration))
+    }
+
+    /// A point, from its longitude and latitude in degrees. Note the order,
+    /// which is GeoJSON's.
+    pub fn point(longitude: f64, latitude: f64) -> Self {
+        Param::Geometry(Geometry::Point([longitude, latitude]))
+    }
+
+    /// A polygon without holes, from the `(longitude, latitude)` corners of
+    /// its outline. The outline is closed if its last corner is not its first.
+    pub fn polygon<I: IntoIterator<Item = (f64, f64)>>(outline: I) -> Self {
+        let mut ring: Vec<[f64; 2]> = outline
+            .into_iter()
+            .map(|(longitude, latitude)| [longitude, latitude])
+            .collect();
+        if let (Some(first), Some(last)) = (ring.first().copied(), ring.last()) {
+            if first != *last {
+                ring.push(first);
+            }
+        }
+        Param::Geometry(Geometry::Polygon(vec![ring]))
+    }
+
+    /// `$name`, cast back to this param's type if it travels as a string.
+    fn placeholder(&self, name: &str) -> String {
+        match self {
+            Param::Datetime(_) => format!("<datetime>${}", name),
+            Param::Duration(_) => format!("<duration>${}", name),
+            Param::RecordId(_) => format!("type::thing(${})", name),
+            _ => format!("${}", name),
+        }
+    }
+}
+
+/// Write a duration the way SurrealQL expects, e.g. `1d2h30m` or `1s500ms`.
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    let nanos = duration.subsec_nanos();
+    let parts = [
+        (secs / 86_400, "d"),
+        (secs / 3_600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+        (u64::from(nanos / 1_000_000), "ms"),
+        (u64::from(nanos / 1_000 % 1_000), "us"),
+        (u64::from(nanos % 1_000), "ns"),
+    ];
+    let formatted: String = parts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if formatted.is_empty() {
+        "0ns".to_string()
+    } else {
+        formatted
+    }
+}
+
+/// Parse a datetime as read back from a query, in UTC.
+pub fn parse_datetime(s: &str) -> Result<chrono::DateTime<chrono::Utc>, GraphDbError> {
+    // SurrealDB may wrap values it prints in `d'...'`
+    let s = s
+        .strip_prefix("d'")
+        .and_then(|s| s.strip_suffix('\''))
+        .unwrap_or(s);
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|datetime| datetime.with_timezone(&chrono::Utc))
+        .map_err(|e| GraphDbError::InputError {
+            error: format!("bad datetime {:?}: {}", s, e),
+        })
+}
+
+/// Parse a duration as read back from a query, e.g. `1w2d3h`: a sequence of
+/// whole numbers with units, from `y` (365 days) and `w` down to `ns`.
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, GraphDbError> {
+    let bad = || GraphDbError::InputError {
+        error: format!("bad duration {:?}", s),
+    };
+    let mut total = std::time::Duration::ZERO;
+    let mut rest = s;
+    if rest.is_empty() {
+        return Err(bad());
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(bad)?;
+        let n: u64 = rest[..digits].parse().map_err(|_| bad())?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ns" => std::time::Duration::from_nanos(1),
+            "us" | "µs" => std::time::Duration::from_micros(1),
+            "ms" => std::time::Duration::from_millis(1),
+            "s" => std::time::Duration::from_secs(1),
+            "m" => std::time::Duration::from_secs(60),
+            "h" => std::time::Duration::from_secs(3_600),
+            "d" => std::time::Duration::from_secs(86_400),
+            "w" => std::time::Duration::from_secs(604_800),
+            "y" => std::time::Duration::from_secs(31_536_000),
+            _ => return Err(bad()),
+        };
+        rest = &rest[unit_len..];
+        // counts may exceed u32, e.g. `5000000000ns`, so multiply in nanoseconds
+        let nanos = unit.as_nanos().checked_mul(u128::from(n)).ok_or_else(bad)?;
+        let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| bad())?;
+        let part = std::time::Duration::new(secs, (nanos % 1_000_000_000) as u32);
+        total = total.checked_add(part).ok_or_else(bad)?;
+    }
+    Ok(total)
+}
+
+impl From<&str> for Param {
+    fn from(s: &str) -> Self {
+        Param::String(s.to_string())
+    }
+}
+
+impl From<String> for Param {
+    fn from(s: String) -> Self {
+        Param::String(s)
+    }
+}
+
+impl From<bool> for Param {
+    fn from(b: bool) -> Self {
+        Param::Bool(b)
+    }
+}
+
+/// Also lets an unsuffixed literal, which defaults to `i32`, be a param.
+impl From<i32> for Param {
+    fn from(n: i32) -> Self {
+        Param::Number(n.into())
+    }
+}
+
+impl From<u32> for Param {
+    fn from(n: u32) -> Self {
+        Param::Number(n.into())
+    }
+}
+
+impl From<i64> for Param {
+    fn from(n: i64) -> Self {
+        Param::Number(n.into())
+    }
+}
+
+impl From<u64> for Param {
+    fn from(n: u64) -> Self {
+        Param::Number(n.into())
+    }
+}
+
+impl TryFrom<f64> for Param {
+    type Error = GraphDbError;
+    /// Fails for NaN and infinities, which have no JSON representation.
+    fn try_from(n: f64) -> Result<Self, GraphDbError> {
+        serde_json::Number::from_f64(n)
+            .map(Param::Number)
+            .ok_or_else(|| GraphDbError::InputError {
+                error: format!("{} cannot be a param", n),
+            })
+    }
+}
+
+impl From<Geometry> for Param {
+    fn from(geometry: Geometry) -> Self {
+        Param::Geometry(geometry)
+    }
+}
+
+impl<T: Into<Param>> From<Vec<T>> for Param {
+    fn from(items: Vec<T>) -> Self {
+        Param::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Build a [`Param::RecordId`] for the record `id` in `table`. The table
+/// name must be a plain identifier (ASCII letters, digits and `_`); the id
+/// may be anything, and is escaped so it cannot break out of the record id.
+pub fn record_id(table: &str, id: &str) -> Result<Param, GraphDbError> {
+    check_table_name(table)?;
+    let id = id.replace('\\', "\\\\").replace('⟩', "\\⟩");
+    Ok(Param::RecordId(format!("{}:⟨{}⟩", table, id)))
+}
+
+/// Collect named [`Param`]s into [`GraphDbRequestParams`] for
+/// [`GraphDb::write()`]. Each name is bound as `$name` in the statement.
+pub fn params<I, K>(params: I) -> GraphDbRequestParams
+where
+    I: IntoIterator<Item = (K, Param)>,
+    K: Into<String>,
+{
+    serde_json::Value::Object(
+        params
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect(),
+    )
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum GraphDbAction {
     Open,
-    Define { resource: DefineResourceType },
-    Write { statement: String },
-    Read { statement: String },
+    Define {
+        resource: DefineResourceType,
+    },
+    Write {
+        statement: String,
+    },
+    /// Like `Write`, but responds with `Data`, with the records the
+    /// statement returned as JSON in the blob.
+    WriteReturning {
+        statement: String,
+    },
+    Read {
+        statement: String,
+    },
     Backup,
     RemoveDb,
+    /// Start a `LIVE SELECT` statement. Its changes are sent to the
+    /// requesting process as [`LiveNotification`] requests until killed.
+    Live {
+        statement: String,
+    },
+    Kill {
+        live_id: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum GraphDbResponse {
     Ok,
     Data,
+    Live { live_id: String },
     Err { error: GraphDbError },
 }
 
+/// Pushed, as a request from `graphdb:distro:sys`, for each change matching
+/// a live query started with [`GraphDb::live()`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveNotification {
+    pub package_id: PackageId,
+    pub db: String,
+    pub live_id: String,
+    pub action: LiveAction,
+    /// The record as the query selects it, or, for a `LIVE SELECT DIFF`, a
+    /// JSON Patch (RFC 6902) from its previous state.
+    pub result: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LiveAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// Parse a message as a [`LiveNotification`], or return `None` if it is not one.
+pub fn parse_live_notification(message: &Message) -> Option<LiveNotification> {
+    if !message.is_request() || !message.source().is_process("graphdb", "distro", "sys") {
+        return None;
+    }
+    serde_json::from_slice(message.body()).ok()
+}
+
 #[derive(Debug, Serialize, Deserialize, Error)]
 pub enum GraphDbError {
     #[error("graphdb: DbDoesNotExist")]
@@ -54,15 +495,129 @@ pub enum GraphDbError {
     InputError { error: String },
     #[error("graphdb: IO error: {error}")]
     IOError { error: String },
+    #[error("graphdb: expected at most one row, got {count}")]
+    MultipleRows { count: usize },
+    /// The statement did not parse. `line` and `col` are 1-based.
+    #[error("graphdb: parse error at line {line}, column {col}: {error}")]
+    ParseError { line: u32, col: u32, error: String },
+    /// A write would have broken a unique index.
+    #[error("graphdb: unique index {index} already contains the value: {error}")]
+    IndexExists { index: String, error: String },
+    #[error("graphdb: permission denied: {error}")]
+    PermissionDenied { error: String },
+    /// A value failed a field's `ASSERT` clause.
+    #[error("graphdb: value for field {field} failed its assertion: {error}")]
+    FieldAssertFailed { field: String, error: String },
+    /// [`GraphDb::update_if_version()`] found the record at a different
+    /// version than expected: someone else updated it first. `actual` is
+    /// `None` if the record no longer exists.
+    #[error("graphdb: {thing} is at version {actual:?}, expected {expected}")]
+    Conflict {
+        thing: String,
+        expected: u64,
+        actual: Option<u64>,
+    },
+    /// [`GraphDb::patch()`], [`GraphDb::soft_delete()`] or
+    /// [`GraphDb::restore()`] found no record to change.
+    #[error("graphdb: no record {thing}")]
+    RecordNotFound { thing: String },
+}
+
+impl GraphDbError {
+    /// Turn a [`GraphDbError::SurrealDBError`] into a more specific variant
+    /// when its message is one we recognize. Every response from the graphdb
+    /// module is decoded this way, so callers can match on the failure.
+    pub fn decode(self) -> Self {
+        let GraphDbError::SurrealDBError { action, error } = self else {
+            return self;
+        };
+        if let Some((line, col)) = parse_error_position(&error) {
+            return GraphDbError::ParseError { line, col, error };
+        }
+        if let Some(index) = text_between(&error, "Database index `", "`") {
+            if error.contains("already contains") {
+                return GraphDbError::IndexExists {
+                    index: index.to_string(),
+                    error,
+                };
+            }
+        }
+        if error.contains("Not enough permissions") || error.contains("not allowed to") {
+            return GraphDbError::PermissionDenied { error };
+        }
+        if error.contains("but field must conform to") {
+            if let Some(field) = text_between(&error, "for field `", "`") {
+                return GraphDbError::FieldAssertFailed {
+                    field: field.to_string(),
+                    error,
+                };
+            }
+        }
+        GraphDbError::SurrealDBError { action, error }
+    }
+}
+
+/// The text in `s` between the first `start` and the next `end` after it.
+fn text_between<'a>(s: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let rest = &s[s.find(start)? + start.len()..];
+    Some(&rest[..rest.find(end)?])
+}
+
+/// The line and column of a SurrealDB parse error, which depending on the
+/// version reads `line 1 column 8`, `line 1 at character 8` or `--> [1:8]`.
+fn parse_error_position(error: &str) -> Option<(u32, u32)> {
+    if !error.contains("Parse error") && !error.contains("parse error") {
+        return None;
+    }
+    let number_after = |s: &str, prefix: &str| -> Option<u32> {
+        let rest = &s[s.find(prefix)? + prefix.len()..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..end].parse().ok()
+    };
+    if let Some(position) = text_between(error, "--> [", "]") {
+        let (line, col) = position.split_once(':')?;
+        return Some((line.parse().ok()?, col.parse().ok()?));
+    }
+    let line = number_after(error, "line ")?;
+    let col = number_after(error, "column ").or_else(|| number_after(error, "at character "))?;
+    Some((line, col))
+}
+
+/// Table that [`GraphDb::soft_delete()`] and [`GraphDb::restore()`] record
+/// an entry in for every change, read back with [`GraphDb::history()`].
+pub const HISTORY_TABLE: &str = "_history";
+/// Field that [`GraphDb::soft_delete()`] sets on a record. Queries that should
+/// skip deleted records filter on `deleted_at IS NONE`.
+pub const DELETED_AT_FIELD: &str = "deleted_at";
+/// Field that [`GraphDb::update_if_version()`] keeps a record's version in.
+/// A record without it is at version 0.
+pub const VERSION_FIELD: &str = "_v";
+/// The most bytes of records [`GraphDb::insert_many()`] sends in one
+/// statement, to stay well under the size limit for a message's blob.
+pub const INSERT_BATCH_BYTES: usize = 1024 * 1024;
+
+/// The timeout of a handle serialized before handles had one.
+fn default_timeout() -> u64 {
+    5
+}
+
+/// The timeout of a remote handle serialized without one.
+fn default_remote_timeout() -> u64 {
+    30
 }
 
 /// GraphDb helper struct for a db.
 /// Opening or creating a db will give you a Result<GraphDb>.
 /// You can call it's impl functions to interact with it.
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GraphDb {
     pub package_id: PackageId,
     pub db: String,
+    /// Timeout, in seconds, for every request made through this handle.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
 }
 
 /// Process lib for graphdb.
@@ -70,14 +625,65 @@ pub struct GraphDb {
 ///
 /// Functions:
 ///     open()
+///     assume_open(package_id: PackageId, db: &str)
 ///     define(resource: Resource)
 ///     write(resource: Resource, params: Option<serde_json::Value>)
 ///     read(resource: Resource, params: Option<serde_json::Value>)
+///     read_with_timeout(statement: String, timeout: u64)
+///     read_with_options(statement: String, params: Option<serde_json::Value>, options: &QueryOptions)
+///     write_with_options(statement: String, params: Option<serde_json::Value>, options: &QueryOptions)
+///     query_one::<T>(statement: String, params: Option<serde_json::Value>)
+///     read_as::<T>(statement: String)
+///     count(table: &str)
+///     exists(thing: &str)
+///     table_exists(name: &str)
+///     soft_delete(thing: &str)
+///     restore(thing: &str)
+///     history(thing: &str)
+///     define_ttl(table: &str, field: &str, ttl: Duration)
+///     remove_ttl(table: &str, field: &str)
+///     expire(table: &str, field: &str, ttl: Duration)
+///     patch(thing: &str, ops: Vec<PatchOp>)
+///     update_if_version(thing: &str, expected_version: u64, content: serde_json::Value)
+///     call_function(name: &str, args: Vec<Param>)
+///     select_where::<T>(table: &str)
+///     insert_many<T>(table: &str, records: impl IntoIterator<Item = T>)
+///     aggregate_paged(table: &str, group_by: &str, aggregates: &[Aggregate], chunk_size: usize)
+///     live(statement: String)
+///     live_table(table: &str, diff: bool)
+///     kill(live_id: &str)
+///     timed(threshold: Duration)
 ///     backup()
 ///     remove_db()
 impl GraphDb {
+    /// A handle to a db this process has already opened, e.g. one recorded
+    /// in its saved state, without the [`open()`] round trip to the graphdb
+    /// module. If the db does not exist, requests through the handle fail.
+    pub fn assume_open(package_id: PackageId, db: &str) -> Self {
+        GraphDb {
+            package_id,
+            db: db.to_string(),
+            timeout: 5,
+        }
+    }
+
+    /// A read-only handle to the same db, to hand to code that should not write.
+    pub fn read_only(&self) -> GraphDbRead {
+        GraphDbRead {
+            package_id: self.package_id.clone(),
+            db: self.db.clone(),
+            timeout: self.timeout,
+        }
+    }
+
+    /// Set the default timeout, in seconds, for requests made through this handle.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     /// Define a resource (table, database, namespace).
-    pub fn define(&self, resource: DefineResourceType) -> anyhow::Result<()> {
+    pub fn define(&self, resource: DefineResourceType) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "graphdb", "distro", "sys"))
             .body(serde_json::to_vec(&GraphDbRequest {
@@ -85,11 +691,9 @@ impl GraphDb {
                 db: self.db.clone(),
                 action: GraphDbAction::Define { resource },
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(self.timeout)??;
 
-        self.handle_response(
-            res.map_err(|e| anyhow::anyhow!("graphdb: define() - response error: {:?}", e)),
-        )
+        self.handle_response(res)
     }
 
     /// Execute a write query.
@@ -97,7 +701,7 @@ impl GraphDb {
         &self,
         statement: String,
         params: Option<serde_json::Value>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), ProcessLibError> {
         let res = match params {
             Some(params) => Request::new()
                 .target(("our", "graphdb", "distro", "sys"))
@@ -107,7 +711,7 @@ impl GraphDb {
                     action: GraphDbAction::Write { statement },
                 })?)
                 .blob_bytes(serde_json::to_vec(&params)?)
-                .send_and_await_response(5)?,
+                .send_and_await_response(self.timeout)??,
             // if params is None, we don't send a blob
             None => Request::new()
                 .target(("our", "graphdb", "distro", "sys"))
@@ -116,72 +720,1452 @@ impl GraphDb {
                     db: self.db.clone(),
                     action: GraphDbAction::Write { statement },
                 })?)
-                .send_and_await_response(5)?,
+                .send_and_await_response(self.timeout)??,
         };
 
-        self.handle_response(
-            res.map_err(|e| anyhow::anyhow!("graphdb: define() - response error: {:?}", e)),
-        )
+        self.handle_response(res)
+    }
+
+    /// Execute a write query and return how many records it created,
+    /// updated or deleted, so a no-op update can be detected without a
+    /// follow-up read. Counts the records the statement returns, so a
+    /// `DELETE` must end in `RETURN BEFORE` to be counted, and a statement
+    /// ending in `RETURN NONE` always counts zero.
+    pub fn write_counted(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<u64, ProcessLibError> {
+        let mut request = Request::new()
+            .target(("our", "graphdb", "distro", "sys"))
+            .body(serde_json::to_vec(&GraphDbRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: GraphDbAction::WriteReturning { statement },
+            })?);
+        if let Some(params) = params {
+            request = request.blob_bytes(serde_json::to_vec(&params)?);
+        }
+        let res = request.send_and_await_response(self.timeout)??;
+        Ok(count_records(&data_from(res)?))
     }
 
     /// Execute a read query.
-    pub fn read(&self, statement: String) -> anyhow::Result<serde_json::Value> {
+    pub fn read(&self, statement: String) -> Result<serde_json::Value, ProcessLibError> {
+        self.read_with_timeout(statement, self.timeout)
+    }
+
+    /// Execute a read query, waiting up to `timeout` seconds for the result
+    /// instead of the handle's default. Useful for one-off analytical queries.
+    pub fn read_with_timeout(
+        &self,
+        statement: String,
+        timeout: u64,
+    ) -> Result<serde_json::Value, ProcessLibError> {
+        self.read_with_params(statement, None, timeout)
+    }
+
+    /// Execute a read query tuned with [`QueryOptions`].
+    pub fn read_with_options(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+        options: &QueryOptions,
+    ) -> Result<serde_json::Value, ProcessLibError> {
+        let statement = options.apply(&statement)?;
+        self.read_with_params(statement, params, options.request_timeout(self.timeout))
+    }
+
+    /// Execute a write query tuned with [`QueryOptions`].
+    pub fn write_with_options(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+        options: &QueryOptions,
+    ) -> Result<(), ProcessLibError> {
+        let statement = options.apply(&statement)?;
+        self.clone()
+            .timeout(options.request_timeout(self.timeout))
+            .write(statement, params)
+    }
+
+    /// Execute a read query that should match at most one row, and
+    /// deserialize that row. Errors if more than one row comes back.
+    pub fn query_one<T>(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<Option<T>, ProcessLibError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut rows = match self.read_with_params(statement, params, self.timeout)? {
+            serde_json::Value::Array(rows) => rows,
+            serde_json::Value::Null => vec![],
+            row => vec![row],
+        };
+        match rows.len() {
+            0 => Ok(None),
+            1 => Ok(Some(serde_json::from_value(rows.remove(0))?)),
+            count => Err(GraphDbError::MultipleRows { count }.into()),
+        }
+    }
+
+    /// Execute a read query and deserialize each returned row as `T`.
+    pub fn read_as<T>(&self, statement: String) -> Result<Vec<T>, ProcessLibError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        rows_as(self.read(statement)?)
+    }
+
+    /// Count the records in a table.
+    pub fn count(&self, table: &str) -> Result<u64, ProcessLibError> {
+        count_from(self.read(count_statement(table)?)?)
+    }
+
+    /// Check whether a record, e.g. `"user:alice"`, exists.
+    pub fn exists(&self, thing: &str) -> Result<bool, ProcessLibError> {
+        let rows = self.read_with_params(
+            "SELECT id FROM type::thing($thing) LIMIT 1;".to_string(),
+            Some(serde_json::json!({ "thing": thing })),
+            self.timeout,
+        )?;
+        Ok(!rows_as::<serde_json::Value>(rows)?.is_empty())
+    }
+
+    /// Check whether a table has been defined in this db.
+    pub fn table_exists(&self, name: &str) -> Result<bool, ProcessLibError> {
+        let info = self.read("INFO FOR DB;".to_string())?;
+        let info = match info {
+            serde_json::Value::Array(mut rows) if !rows.is_empty() => rows.remove(0),
+            info => info,
+        };
+        // older surrealdb versions abbreviate the key to "tb"
+        let tables = match info.get("tables") {
+            Some(tables) => tables,
+            None => &info["tb"],
+        };
+        Ok(tables.get(name).is_some())
+    }
+
+    fn read_with_params(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+        timeout: u64,
+    ) -> Result<serde_json::Value, ProcessLibError> {
+        read(&self.package_id, &self.db, statement, params, timeout)
+    }
+
+    /// Mark a record, e.g. `"user:alice"`, as deleted by setting its
+    /// [`DELETED_AT_FIELD`], and record the change along with the record's
+    /// previous contents in the [`HISTORY_TABLE`]. A record that does not
+    /// exist is a [`GraphDbError::RecordNotFound`].
+    pub fn soft_delete(&self, thing: &str) -> Result<(), ProcessLibError> {
+        self.write_with_history(thing, "soft_delete", "time::now()")
+    }
+
+    /// Undo a [`GraphDb::soft_delete()`], recording the change in the [`HISTORY_TABLE`].
+    /// A record that does not exist is a [`GraphDbError::RecordNotFound`].
+    pub fn restore(&self, thing: &str) -> Result<(), ProcessLibError> {
+        self.write_with_history(thing, "restore", "NONE")
+    }
+
+    /// Every change recorded for a record in the [`HISTORY_TABLE`], oldest first.
+    pub fn history(&self, thing: &str) -> Result<serde_json::Value, ProcessLibError> {
+        self.read_with_params(
+            format!(
+                "SELECT * FROM {} WHERE thing = type::thing($thing) ORDER BY at ASC;",
+                HISTORY_TABLE,
+            ),
+            Some(serde_json::json!({ "thing": thing })),
+            self.timeout,
+        )
+    }
+
+    /// Expire records of `table` once their datetime `field`, e.g.
+    /// `created_at` or `last_seen`, is more than `ttl` old, for session
+    /// stores and ephemeral feeds. Defines an event that deletes the
+    /// expired records whenever a record is created in the table, so a
+    /// table that is written to keeps itself trimmed. Defining it again
+    /// replaces the ttl. A table that may go quiet should also call
+    /// [`GraphDb::expire()`] now and then, e.g. on a timer. Records whose
+    /// `field` is missing or not a datetime never expire.
+    ///
+    /// The delete scans the whole table on every insert, so this suits small
+    /// tables such as sessions. For large or busy tables, skip this and only
+    /// call [`GraphDb::expire()`] on a timer.
+    pub fn define_ttl(
+        &self,
+        table: &str,
+        field: &str,
+        ttl: std::time::Duration,
+    ) -> Result<(), ProcessLibError> {
+        check_table_name(table)?;
+        check_field_name(field)?;
+        let statement = format!(
+            "DEFINE EVENT {name} ON TABLE {table} WHEN $event = \"CREATE\" THEN ({delete});",
+            name = ttl_event_name(field),
+            table = table,
+            delete = expire_statement(table, field, ttl),
+        );
+        self.write(statement, None)
+    }
+
+    /// Remove the expiry set with [`GraphDb::define_ttl()`].
+    pub fn remove_ttl(&self, table: &str, field: &str) -> Result<(), ProcessLibError> {
+        check_table_name(table)?;
+        check_field_name(field)?;
+        self.write(
+            format!("REMOVE EVENT {} ON TABLE {};", ttl_event_name(field), table),
+            None,
+        )
+    }
+
+    /// Delete the records of `table` whose datetime `field` is more than
+    /// `ttl` old, and return how many were deleted. Scans the whole table.
+    pub fn expire(
+        &self,
+        table: &str,
+        field: &str,
+        ttl: std::time::Duration,
+    ) -> Result<u64, ProcessLibError> {
+        check_table_name(table)?;
+        check_field_name(field)?;
+        self.write_counted(
+            format!("{} RETURN BEFORE;", expire_statement(table, field, ttl)),
+            None,
+        )
+    }
+
+    fn write_with_history(
+        &self,
+        thing: &str,
+        action: &str,
+        deleted_at: &str,
+    ) -> Result<(), ProcessLibError> {
+        // updating a selection matches nothing when the record is missing,
+        // where updating the id would create it; only the updated rows are
+        // returned, so the count says whether there was a record
+        let statement = format!(
+            "BEGIN TRANSACTION;\
+             LET $before = (SELECT * FROM ONLY type::thing($thing));\
+             LET $updated = (UPDATE (SELECT id FROM type::thing($thing)) SET {field} = {deleted_at} RETURN AFTER);\
+             IF array::len($updated) > 0 {{ CREATE {table} SET thing = type::thing($thing), action = $action, at = time::now(), before = $before RETURN NONE; }};\
+             RETURN $updated;\
+             COMMIT TRANSACTION;",
+            field = DELETED_AT_FIELD,
+            deleted_at = deleted_at,
+            table = HISTORY_TABLE,
+        );
+        let updated = self.write_counted(
+            statement,
+            Some(serde_json::json!({ "thing": thing, "action": action })),
+        )?;
+        if updated == 0 {
+            return Err(GraphDbError::RecordNotFound {
+                thing: thing.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of a record, e.g. `"doc:readme"`, only if it is
+    /// still at `expected_version`, and return its new version. Read the
+    /// version along with the record, from its [`VERSION_FIELD`]; if another
+    /// writer has updated the record since, this fails with
+    /// [`GraphDbError::Conflict`] and the caller should re-read and retry.
+    ///
+    /// `content` must be an object, and is stored with the version added.
+    /// A record that does not exist is at version 0, so an expected version
+    /// of 0 creates it.
+    pub fn update_if_version(
+        &self,
+        thing: &str,
+        expected_version: u64,
+        content: serde_json::Value,
+    ) -> Result<u64, ProcessLibError> {
+        let serde_json::Value::Object(mut content) = content else {
+            return Err(GraphDbError::InputError {
+                error: "content must be an object".to_string(),
+            }
+            .into());
+        };
+        let version = expected_version + 1;
+        content.insert(VERSION_FIELD.to_string(), version.into());
+        // only version 0 may create the record: any other updates it only
+        // if it exists, by selecting it first
+        let target = if expected_version == 0 {
+            "type::thing($thing)"
+        } else {
+            "(SELECT id FROM type::thing($thing))"
+        };
+        let statement = format!(
+            "UPDATE {target} CONTENT $content WHERE ({field} ?? 0) = $expected RETURN AFTER;",
+            target = target,
+            field = VERSION_FIELD,
+        );
+        let updated = self.write_counted(
+            statement,
+            Some(serde_json::json!({
+                "thing": thing,
+                "content": content,
+                "expected": expected_version,
+            })),
+        )?;
+        if updated > 0 {
+            return Ok(version);
+        }
+        let current = self.read_with_params(
+            format!("SELECT {} FROM type::thing($thing);", VERSION_FIELD),
+            Some(serde_json::json!({ "thing": thing })),
+            self.timeout,
+        )?;
+        let actual = rows_as::<serde_json::Value>(current)?
+            .first()
+            .map(|row| row[VERSION_FIELD].as_u64().unwrap_or(0));
+        Err(GraphDbError::Conflict {
+            thing: thing.to_string(),
+            expected: expected_version,
+            actual,
+        }
+        .into())
+    }
+
+    /// Change parts of a record, e.g. `"user:alice"`, with JSON Patch
+    /// operations, without sending its whole content:
+    ///
+    /// ```ignore
+    /// db.patch("user:alice", vec![
+    ///     PatchOp::replace("/email", "alice@example.com".into()),
+    ///     PatchOp::add("/tags/-", "admin".into()),
+    ///     PatchOp::remove("/legacy_id"),
+    /// ])?;
+    /// ```
+    ///
+    /// The operations apply in order, and all or none take effect. A record
+    /// that does not exist is not created: that is a
+    /// [`GraphDbError::RecordNotFound`].
+    pub fn patch(&self, thing: &str, ops: Vec<PatchOp>) -> Result<(), ProcessLibError> {
+        // updating a selection rather than the record id itself matches
+        // nothing when the record is missing, where `UPDATE` would create it
+        let patched = self.write_counted(
+            "UPDATE (SELECT id FROM type::thing($thing)) PATCH $ops RETURN AFTER;".to_string(),
+            Some(serde_json::json!({
+                "thing": thing,
+                "ops": ops,
+            })),
+        )?;
+        if patched == 0 {
+            return Err(GraphDbError::RecordNotFound {
+                thing: thing.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Call a function defined with [`DefineResourceType::Function`] and
+    /// return its result. `name` is without its `fn::` prefix, and each
+    /// argument is passed as a param, so it needs no escaping.
+    pub fn call_function(
+        &self,
+        name: &str,
+        args: Vec<Param>,
+    ) -> Result<serde_json::Value, ProcessLibError> {
+        check_function_name(name)?;
+        let placeholders: Vec<String> = (0..args.len()).map(|i| format!("$arg{}", i)).collect();
+        let statement = format!("RETURN fn::{}({});", name, placeholders.join(", "));
+        let params = params(
+            args.into_iter()
+                .enumerate()
+                .map(|(i, arg)| (format!("arg{}", i), arg)),
+        );
+        // functions may write, so this cannot go through a read
         let res = Request::new()
             .target(("our", "graphdb", "distro", "sys"))
             .body(serde_json::to_vec(&GraphDbRequest {
                 package_id: self.package_id.clone(),
                 db: self.db.clone(),
-                action: GraphDbAction::Read { statement },
+                action: GraphDbAction::WriteReturning { statement },
             })?)
-            .send_and_await_response(5)?;
+            .blob_bytes(serde_json::to_vec(&params)?)
+            .send_and_await_response(self.timeout)??;
+        data_from(res)
+    }
 
-        match res {
-            Ok(Message::Response { body, .. }) => {
-                let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
+    /// Wrap this handle to log queries that take longer than `threshold`.
+    pub fn timed(self, threshold: std::time::Duration) -> TimedGraphDb {
+        TimedGraphDb {
+            db: self,
+            threshold,
+            verbosity: 1,
+        }
+    }
 
-                match response {
-                    GraphDbResponse::Data => {
-                        let blob = get_blob().ok_or_else(|| GraphDbError::InputError {
-                            error: "no blob".to_string(),
-                        })?;
-                        let values = serde_json::from_slice::<serde_json::Value>(&blob.bytes)
-                            .map_err(|e| GraphDbError::InputError {
-                                error: format!("gave unparsable response: {}", e),
-                            })?;
-                        Ok(values)
-                    }
-                    GraphDbResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!(
-                        "graphdb: select() - unexpected response {:?}",
-                        response
-                    )),
+    /// Start building a `SELECT * FROM table WHERE ...` whose conditions are
+    /// all and-ed together, with every value bound as a param:
+    ///
+    /// ```ignore
+    /// let issues: Vec<Issue> = db
+    ///     .select_where("issue")
+    ///     .eq("status", "open")
+    ///     .gt("score", 10)
+    ///     .order_by_desc("created_at")
+    ///     .limit(20)
+    ///     .fetch()?;
+    /// ```
+    pub fn select_where<T>(&self, table: &str) -> SelectWhere<'_, T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        SelectWhere {
+            db: self,
+            table: table.to_string(),
+            conditions: vec![],
+            params: serde_json::Map::new(),
+            order_by: vec![],
+            limit: None,
+            options: QueryOptions::default(),
+            error: check_table_name(table).err(),
+            _type: std::marker::PhantomData,
+        }
+    }
+
+    /// Insert many records into `table`, in as few `INSERT` statements as
+    /// fit under [`INSERT_BATCH_BYTES`] each, and return how many were
+    /// inserted. Each statement is atomic, but the whole insert is not: if
+    /// one fails, the batches before it stay inserted.
+    pub fn insert_many<T, I>(&self, table: &str, records: I) -> Result<u64, ProcessLibError>
+    where
+        T: Serialize,
+        I: IntoIterator<Item = T>,
+    {
+        self.insert_many_with_progress(table, records, |_| {})
+    }
+
+    /// [`GraphDb::insert_many()`], calling `progress` with the number of
+    /// records inserted so far after each batch, e.g. to report how far a
+    /// long seeding has got.
+    pub fn insert_many_with_progress<T, I, F>(
+        &self,
+        table: &str,
+        records: I,
+        mut progress: F,
+    ) -> Result<u64, ProcessLibError>
+    where
+        T: Serialize,
+        I: IntoIterator<Item = T>,
+        F: FnMut(u64),
+    {
+        check_table_name(table)?;
+        let statement = format!("INSERT INTO {} $records RETURN NONE;", table);
+        let mut inserted = 0;
+        let mut batch: Vec<serde_json::Value> = vec![];
+        let mut batch_bytes = 0;
+        let mut send = |batch: Vec<serde_json::Value>| -> Result<(), ProcessLibError> {
+            let count = batch.len() as u64;
+            self.write(
+                statement.clone(),
+                Some(serde_json::json!({ "records": batch })),
+            )?;
+            inserted += count;
+            progress(inserted);
+            Ok(())
+        };
+        for record in records {
+            let record = serde_json::to_value(record)?;
+            // the serialized length, plus a comma between records
+            let len = serde_json::to_vec(&record)?.len() + 1;
+            if !batch.is_empty() && batch_bytes + len > INSERT_BATCH_BYTES {
+                send(std::mem::take(&mut batch))?;
+                batch_bytes = 0;
+            }
+            batch.push(record);
+            batch_bytes += len;
+        }
+        if !batch.is_empty() {
+            send(batch)?;
+        }
+        Ok(inserted)
+    }
+
+    /// Run `SELECT group_by, aggregates... FROM table GROUP BY group_by` over
+    /// a table too big to aggregate in one request. The table is read in
+    /// chunks of `chunk_size` records, in record id order, each chunk is
+    /// grouped by the db, and the partial results are merged here, so no
+    /// single request has to hold or return more than one chunk.
+    ///
+    /// This bounds the size of each request, not the db's work: the db does
+    /// not use the id order to skip ahead, so each chunk takes two queries
+    /// that each scan the whole table, and the total work grows with the
+    /// number of chunks times the table's size. Use chunks as large as the
+    /// timeout allows.
+    ///
+    /// ```ignore
+    /// let by_country = db.aggregate_paged(
+    ///     "order",
+    ///     "country",
+    ///     &[Aggregate::Count, Aggregate::Sum("total".into())],
+    ///     10_000,
+    /// )?;
+    /// ```
+    ///
+    /// Groups come back in the order they were first seen. Records written
+    /// while this runs may or may not be counted.
+    pub fn aggregate_paged(
+        &self,
+        table: &str,
+        group_by: &str,
+        aggregates: &[Aggregate],
+        chunk_size: usize,
+    ) -> Result<Vec<AggregateGroup>, ProcessLibError> {
+        check_table_name(table)?;
+        check_field_name(group_by)?;
+        let mut columns = vec![format!("{} AS key", group_by)];
+        for (i, aggregate) in aggregates.iter().enumerate() {
+            columns.extend(aggregate.partial_columns(i)?);
+        }
+        let chunk_size = chunk_size.max(1);
+
+        let mut groups: Vec<(serde_json::Value, Vec<PartialAggregate>)> = vec![];
+        let mut index: std::collections::HashMap<String, usize> = Default::default();
+        let mut after: Option<serde_json::Value> = None;
+        loop {
+            let filter = if after.is_some() {
+                " WHERE id > type::thing($after)"
+            } else {
+                ""
+            };
+            let params = after
+                .as_ref()
+                .map(|after| serde_json::json!({ "after": after }));
+            let rows = self.read_with_params(
+                format!(
+                    "SELECT {} FROM (SELECT * FROM {}{} ORDER BY id LIMIT {}) GROUP BY key;",
+                    columns.join(", "),
+                    table,
+                    filter,
+                    chunk_size,
+                ),
+                params.clone(),
+                self.timeout,
+            )?;
+            for row in rows_as::<serde_json::Value>(rows)? {
+                let key = row["key"].clone();
+                let slot = *index.entry(key.to_string()).or_insert_with(|| {
+                    groups.push((key, aggregates.iter().map(PartialAggregate::new).collect()));
+                    groups.len() - 1
+                });
+                for (i, partial) in groups[slot].1.iter_mut().enumerate() {
+                    partial.merge(i, &row);
                 }
             }
-            _ => Err(anyhow::anyhow!("graphdb: unexpected message: {:?}", res)),
+
+            // the last id of this chunk, or nothing if it was the last chunk
+            let last = self.read_with_params(
+                format!(
+                    "SELECT VALUE id FROM {}{} ORDER BY id LIMIT 1 START {};",
+                    table,
+                    filter,
+                    chunk_size - 1,
+                ),
+                params,
+                self.timeout,
+            )?;
+            match rows_as::<serde_json::Value>(last)?.into_iter().next() {
+                Some(last) => after = Some(last),
+                None => break,
+            }
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|(key, partials)| AggregateGroup {
+                key,
+                values: partials.iter().map(PartialAggregate::value).collect(),
+            })
+            .collect())
+    }
+
+    /// Start building a transaction: statements run all together, in one
+    /// request, or not at all.
+    pub fn tx(&self) -> GraphDbTx<'_> {
+        GraphDbTx {
+            db: self,
+            statements: vec![],
+            params: serde_json::Map::new(),
         }
     }
 
-    fn handle_response(&self, res: Result<Message, anyhow::Error>) -> anyhow::Result<()> {
+    /// Start a live query, e.g. `LIVE SELECT * FROM task WHERE done = false`,
+    /// and return its id. Each change it matches arrives as a
+    /// [`LiveNotification`]; pick them out with [`parse_live_notification()`].
+    pub fn live(&self, statement: String) -> Result<String, ProcessLibError> {
+        let res = Request::new()
+            .target(("our", "graphdb", "distro", "sys"))
+            .body(serde_json::to_vec(&GraphDbRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: GraphDbAction::Live { statement },
+            })?)
+            .send_and_await_response(self.timeout)??;
+
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
 
                 match response {
-                    GraphDbResponse::Ok => Ok(()),
-                    GraphDbResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!(
-                        "graphdb: unexpected response {:?}",
-                        response
-                    )),
+                    GraphDbResponse::Live { live_id } => Ok(live_id),
+                    GraphDbResponse::Err { error } => Err(error.decode().into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("graphdb: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
-}
 
-/// Open or create graphdb database.
-pub fn open(package_id: PackageId, db: &str) -> anyhow::Result<GraphDb> {
+    /// Start a live query on every record of a table. With `diff`, changes
+    /// arrive as JSON Patches rather than whole records.
+    pub fn live_table(&self, table: &str, diff: bool) -> Result<String, ProcessLibError> {
+        check_table_name(table)?;
+        let fields = if diff { "DIFF" } else { "*" };
+        self.live(format!("LIVE SELECT {} FROM {};", fields, table))
+    }
+
+    /// Stop a live query.
+    pub fn kill(&self, live_id: &str) -> Result<(), ProcessLibError> {
+        let res = Request::new()
+            .target(("our", "graphdb", "distro", "sys"))
+            .body(serde_json::to_vec(&GraphDbRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: GraphDbAction::Kill {
+                    live_id: live_id.to_string(),
+                },
+            })?)
+            .send_and_await_response(self.timeout)??;
+
+        self.handle_response(res)
+    }
+
+    fn handle_response(&self, res: Message) -> Result<(), ProcessLibError> {
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
+
+                match response {
+                    GraphDbResponse::Ok => Ok(()),
+                    GraphDbResponse::Err { error } => Err(error.decode().into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+}
+
+/// A transaction built with [`GraphDb::tx()`]. Its statements are sent in a
+/// single write wrapped in `BEGIN TRANSACTION` and `COMMIT TRANSACTION`, so
+/// variables set with [`GraphDbTx::let_var()`] can be used by the statements
+/// after them:
+///
+/// ```ignore
+/// db.tx()
+///     .param("name", "alice".into())
+///     .let_var("user", "(CREATE ONLY user SET name = $name)")?
+///     .statement("CREATE post SET author = $user.id, title = 'hello'")
+///     .commit()?;
+/// ```
+pub struct GraphDbTx<'a> {
+    db: &'a GraphDb,
+    statements: Vec<String>,
+    params: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> GraphDbTx<'a> {
+    /// Add a statement. A trailing `;` is optional.
+    pub fn statement(mut self, statement: &str) -> Self {
+        self.statements
+            .push(statement.trim().trim_end_matches(';').to_string());
+        self
+    }
+
+    /// Set `$name` to the result of a SurrealQL expression, such as a
+    /// subquery in parentheses, for the statements after it.
+    pub fn let_var(self, name: &str, expression: &str) -> Result<Self, GraphDbError> {
+        check_table_name(name).map_err(|_| GraphDbError::InputError {
+            error: format!("invalid variable name {:?}", name),
+        })?;
+        Ok(self.statement(&format!("LET ${} = {}", name, expression)))
+    }
+
+    /// Bind `$name` to a value for every statement in the transaction.
+    pub fn param(mut self, name: &str, value: Param) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The transaction's statements, as sent.
+    pub fn to_surql(&self) -> String {
+        let mut surql = String::from("BEGIN TRANSACTION;\n");
+        for statement in &self.statements {
+            surql.push_str(statement);
+            surql.push_str(";\n");
+        }
+        surql.push_str("COMMIT TRANSACTION;");
+        surql
+    }
+
+    /// Run the transaction.
+    pub fn commit(self) -> Result<(), ProcessLibError> {
+        let surql = self.to_surql();
+        let params = (!self.params.is_empty()).then_some(serde_json::Value::Object(self.params));
+        self.db.write(surql, params)
+    }
+}
+
+/// A simple `SELECT` built with [`GraphDb::select_where()`]. Field names
+/// must be identifiers, optionally with `.` for nested fields; a bad one
+/// makes [`SelectWhere::fetch()`] fail rather than reach the db.
+///
+/// Datetime, duration and record id values are cast back from strings in
+/// the query, so they compare as their own types. Inside an array they stay
+/// strings.
+pub struct SelectWhere<'a, T> {
+    db: &'a GraphDb,
+    table: String,
+    conditions: Vec<String>,
+    params: serde_json::Map<String, serde_json::Value>,
+    order_by: Vec<String>,
+    limit: Option<usize>,
+    options: QueryOptions,
+    error: Option<GraphDbError>,
+    _type: std::marker::PhantomData<T>,
+}
+
+impl<'a, T> SelectWhere<'a, T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    pub fn eq<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, "=", value.into())
+    }
+    pub fn ne<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, "!=", value.into())
+    }
+    pub fn gt<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, ">", value.into())
+    }
+    pub fn gte<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, ">=", value.into())
+    }
+    pub fn lt<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, "<", value.into())
+    }
+    pub fn lte<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, "<=", value.into())
+    }
+    /// The field, an array, contains `value`.
+    pub fn contains<P: Into<Param>>(self, field: &str, value: P) -> Self {
+        self.condition(field, "CONTAINS", value.into())
+    }
+    /// The field's value is one of `values`.
+    pub fn one_of<P: Into<Param>>(self, field: &str, values: Vec<P>) -> Self {
+        self.condition(field, "INSIDE", values.into())
+    }
+    /// The field, a geometry, is inside `area`, e.g. a [`Param::polygon()`].
+    pub fn inside<P: Into<Param>>(self, field: &str, area: P) -> Self {
+        self.condition(field, "INSIDE", area.into())
+    }
+    /// The field, a geometry, intersects `area`.
+    pub fn intersects<P: Into<Param>>(self, field: &str, area: P) -> Self {
+        self.condition(field, "INTERSECTS", area.into())
+    }
+    /// The field, a point, is at most `meters` from `point`, as measured by
+    /// `geo::distance`, along the earth's surface. `meters` must be finite.
+    pub fn within_distance<P: Into<Param>>(mut self, field: &str, point: P, meters: f64) -> Self {
+        let meters = match Param::try_from(meters) {
+            Ok(meters) => meters,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                return self;
+            }
+        };
+        if self.check_field(field) {
+            let point_name = format!("p{}", self.params.len());
+            self.params.insert(point_name.clone(), point.into().into());
+            let meters_name = format!("p{}", self.params.len());
+            self.params.insert(meters_name.clone(), meters.into());
+            self.conditions.push(format!(
+                "geo::distance({}, ${}) <= ${}",
+                field, point_name, meters_name
+            ));
+        }
+        self
+    }
+    /// The field is not set.
+    pub fn is_none(mut self, field: &str) -> Self {
+        if self.check_field(field) {
+            self.conditions.push(format!("{} IS NONE", field));
+        }
+        self
+    }
+
+    /// Sort by `field`, ascending. Call again to break ties by another field.
+    pub fn order_by(mut self, field: &str) -> Self {
+        if self.check_field(field) {
+            self.order_by.push(format!("{} ASC", field));
+        }
+        self
+    }
+    /// Sort by `field`, descending.
+    pub fn order_by_desc(mut self, field: &str) -> Self {
+        if self.check_field(field) {
+            self.order_by.push(format!("{} DESC", field));
+        }
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Tune the query with optimizer hints.
+    pub fn options(mut self, options: QueryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The statement, as sent, with values as `$p0`, `$p1`, ...
+    pub fn to_surql(&self) -> String {
+        let mut surql = format!("SELECT * FROM {}", self.table);
+        if !self.conditions.is_empty() {
+            surql.push_str(" WHERE ");
+            surql.push_str(&self.conditions.join(" AND "));
+        }
+        if !self.order_by.is_empty() {
+            surql.push_str(" ORDER BY ");
+            surql.push_str(&self.order_by.join(", "));
+        }
+        if let Some(limit) = self.limit {
+            surql.push_str(&format!(" LIMIT {}", limit));
+        }
+        surql.push(';');
+        surql
+    }
+
+    /// Run the query and deserialize each row as `T`.
+    pub fn fetch(self) -> Result<Vec<T>, ProcessLibError> {
+        if let Some(error) = self.error {
+            return Err(error.into());
+        }
+        let surql = self.to_surql();
+        let params = (!self.params.is_empty()).then_some(serde_json::Value::Object(self.params));
+        rows_as(self.db.read_with_options(surql, params, &self.options)?)
+    }
+
+    /// Run the query with a limit of one, and deserialize the row, if any.
+    pub fn fetch_first(self) -> Result<Option<T>, ProcessLibError> {
+        Ok(self.limit(1).fetch()?.into_iter().next())
+    }
+
+    fn condition(mut self, field: &str, operator: &str, value: Param) -> Self {
+        if self.check_field(field) {
+            let name = format!("p{}", self.params.len());
+            self.conditions.push(format!(
+                "{} {} {}",
+                field,
+                operator,
+                value.placeholder(&name)
+            ));
+            self.params.insert(name, value.into());
+        }
+        self
+    }
+
+    /// Record an error for a bad field name, and return whether it was good.
+    fn check_field(&mut self, field: &str) -> bool {
+        match check_field_name(field) {
+            Ok(()) => true,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                false
+            }
+        }
+    }
+}
+
+/// One aggregate for [`GraphDb::aggregate_paged()`]. Each but `Count` takes
+/// a numeric field, whose missing values are skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    /// The number of records in the group.
+    Count,
+    Sum(String),
+    Min(String),
+    Max(String),
+    Mean(String),
+}
+
+impl Aggregate {
+    /// The columns that compute this aggregate over one chunk, named
+    /// `a{i}`, plus `a{i}_n` for the count a mean needs to be merged.
+    fn partial_columns(&self, i: usize) -> Result<Vec<String>, GraphDbError> {
+        Ok(match self {
+            Aggregate::Count => vec![format!("count() AS a{}", i)],
+            Aggregate::Sum(field) => {
+                check_field_name(field)?;
+                vec![format!("math::sum({}) AS a{}", field, i)]
+            }
+            Aggregate::Min(field) => {
+                check_field_name(field)?;
+                vec![format!("math::min({}) AS a{}", field, i)]
+            }
+            Aggregate::Max(field) => {
+                check_field_name(field)?;
+                vec![format!("math::max({}) AS a{}", field, i)]
+            }
+            Aggregate::Mean(field) => {
+                check_field_name(field)?;
+                vec![
+                    format!("math::sum({}) AS a{}", field, i),
+                    format!("count({} != NONE) AS a{}_n", field, i),
+                ]
+            }
+        })
+    }
+}
+
+/// One group from [`GraphDb::aggregate_paged()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateGroup {
+    /// The group's value of the `group_by` field.
+    pub key: serde_json::Value,
+    /// One value per requested [`Aggregate`], in order. `None` for a min,
+    /// max or mean over a group with no values for the field.
+    pub values: Vec<Option<f64>>,
+}
+
+/// An aggregate merged over the chunks seen so far.
+enum PartialAggregate {
+    Count(f64),
+    Sum(f64),
+    Min(Option<f64>),
+    Max(Option<f64>),
+    Mean { sum: f64, count: f64 },
+}
+
+impl PartialAggregate {
+    fn new(aggregate: &Aggregate) -> Self {
+        match aggregate {
+            Aggregate::Count => PartialAggregate::Count(0.0),
+            Aggregate::Sum(_) => PartialAggregate::Sum(0.0),
+            Aggregate::Min(_) => PartialAggregate::Min(None),
+            Aggregate::Max(_) => PartialAggregate::Max(None),
+            Aggregate::Mean(_) => PartialAggregate::Mean {
+                sum: 0.0,
+                count: 0.0,
+            },
+        }
+    }
+
+    /// Merge in aggregate `i` of a chunk's row.
+    fn merge(&mut self, i: usize, row: &serde_json::Value) {
+        let value = row[format!("a{}", i)].as_f64();
+        match self {
+            PartialAggregate::Count(total) | PartialAggregate::Sum(total) => {
+                *total += value.unwrap_or(0.0)
+            }
+            PartialAggregate::Min(min) => {
+                *min = match (*min, value) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+            PartialAggregate::Max(max) => {
+                *max = match (*max, value) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+            PartialAggregate::Mean { sum, count } => {
+                *sum += value.unwrap_or(0.0);
+                *count += row[format!("a{}_n", i)].as_f64().unwrap_or(0.0);
+            }
+        }
+    }
+
+    fn value(&self) -> Option<f64> {
+        match *self {
+            PartialAggregate::Count(total) | PartialAggregate::Sum(total) => Some(total),
+            PartialAggregate::Min(value) | PartialAggregate::Max(value) => value,
+            PartialAggregate::Mean { sum, count } => (count > 0.0).then(|| sum / count),
+        }
+    }
+}
+
+/// One operation of a [`GraphDb::patch()`], as in JSON Patch (RFC 6902).
+/// `path` is a JSON Pointer into the record, e.g. `/address/city`, where
+/// `-` as the last segment of an add means the end of an array.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    /// Set a field, or insert into an array.
+    Add {
+        path: String,
+        value: serde_json::Value,
+    },
+    /// Remove a field or array element.
+    Remove { path: String },
+    /// Set a field that must already exist.
+    Replace {
+        path: String,
+        value: serde_json::Value,
+    },
+}
+
+impl PatchOp {
+    pub fn add(path: &str, value: serde_json::Value) -> Self {
+        PatchOp::Add {
+            path: path.to_string(),
+            value,
+        }
+    }
+
+    pub fn remove(path: &str) -> Self {
+        PatchOp::Remove {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn replace(path: &str, value: serde_json::Value) -> Self {
+        PatchOp::Replace {
+            path: path.to_string(),
+            value,
+        }
+    }
+}
+
+/// Optimizer hints for a single statement, added to it as SurrealQL clauses
+/// by [`GraphDb::read_with_options()`], [`GraphDb::write_with_options()`]
+/// and [`SelectWhere::options()`]:
+///
+/// ```ignore
+/// let options = QueryOptions {
+///     parallel: true,
+///     with_index: Some("idx_status".into()),
+///     timeout: Some(Duration::from_secs(20)),
+/// };
+/// db.read_with_options("SELECT * FROM issue WHERE status = 'open';".into(), None, &options)?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryOptions {
+    /// Add `PARALLEL`, fetching records from the table in parallel.
+    pub parallel: bool,
+    /// Add `WITH INDEX name` after the `FROM` targets of a `SELECT`, so the
+    /// query uses that index. Only valid on a single `SELECT`.
+    pub with_index: Option<String>,
+    /// Add `TIMEOUT`, so the db gives up on the statement after this long.
+    /// The request waits at least this long, plus a second, for it.
+    pub timeout: Option<std::time::Duration>,
+}
+
+impl QueryOptions {
+    /// The statement with these options' clauses added, in the order the db
+    /// expects, before any `PARALLEL` or `EXPLAIN` the statement already
+    /// ends with. A statement that already has a `TIMEOUT` is rejected if
+    /// these options set one. Only a single statement is supported.
+    pub fn apply(&self, statement: &str) -> Result<String, GraphDbError> {
+        let mut statement = statement.trim().trim_end_matches(';').to_string();
+        if let Some(index) = &self.with_index {
+            check_table_name(index).map_err(|_| GraphDbError::InputError {
+                error: format!("invalid index name {:?}", index),
+            })?;
+            let from = match statement.get(..6) {
+                Some(select) if select.eq_ignore_ascii_case("SELECT") => {
+                    find_top_level_keyword(&statement, &["FROM"], 0)
+                }
+                _ => None,
+            }
+            .ok_or_else(|| GraphDbError::InputError {
+                error: "WITH INDEX needs a SELECT ... FROM statement".to_string(),
+            })?;
+            let at = find_top_level_keyword(&statement, SELECT_CLAUSES_AFTER_WITH, from + 4)
+                .unwrap_or(statement.len());
+            statement.insert_str(at, &format!(" WITH INDEX {} ", index));
+        }
+        // the trailing clauses come after the targets, so a field named
+        // like one of them is not mistaken for it
+        let clauses = find_top_level_keyword(&statement, &["FROM"], 0).map_or(0, |from| from + 4);
+        if let Some(timeout) = self.timeout {
+            if find_top_level_keyword(&statement, &["TIMEOUT"], clauses).is_some() {
+                return Err(GraphDbError::InputError {
+                    error: "statement already has a TIMEOUT".to_string(),
+                });
+            }
+            let clause = format!("TIMEOUT {}", format_duration(timeout));
+            insert_clause(&mut statement, &clause, &["PARALLEL", "EXPLAIN"], clauses);
+        }
+        if self.parallel && find_top_level_keyword(&statement, &["PARALLEL"], clauses).is_none() {
+            insert_clause(&mut statement, "PARALLEL", &["EXPLAIN"], clauses);
+        }
+        statement.push(';');
+        Ok(statement)
+    }
+
+    /// The request timeout, in seconds, for a statement with these options
+    /// through a handle whose timeout is `default`.
+    fn request_timeout(&self, default: u64) -> u64 {
+        match self.timeout {
+            Some(timeout) => default.max(timeout.as_secs() + 1),
+            None => default,
+        }
+    }
+}
+
+/// The clauses of a `SELECT` that come after its `WITH` clause.
+const SELECT_CLAUSES_AFTER_WITH: &[&str] = &[
+    "WHERE", "SPLIT", "GROUP", "ORDER", "LIMIT", "START", "FETCH", "TIMEOUT", "PARALLEL", "EXPLAIN",
+];
+
+/// The byte offset of the first of `keywords` in `statement`, at or after
+/// `from`, that is a whole word outside any string, brackets or braces.
+fn find_top_level_keyword(statement: &str, keywords: &[&str], from: usize) -> Option<usize> {
+    let bytes = statement.as_bytes();
+    let mut depth = 0usize;
+    let mut quote: Option<u8> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if let Some(q) = quote {
+            if byte == b'\\' {
+                i += 1;
+            } else if byte == q {
+                quote = None;
+            }
+            i += 1;
+            continue;
+        }
+        match byte {
+            b'\'' | b'"' | b'`' => quote = Some(byte),
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            _ if depth == 0 && i >= from => {
+                let starts_word = i == 0 || !is_word_byte(bytes[i - 1]);
+                if starts_word {
+                    for keyword in keywords {
+                        let end = i + keyword.len();
+                        let matches = statement
+                            .get(i..end)
+                            .is_some_and(|word| word.eq_ignore_ascii_case(keyword));
+                        if matches && bytes.get(end).is_none_or(|b| !is_word_byte(*b)) {
+                            return Some(i);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Insert `clause` before the first of the top-level `before` keywords at or
+/// after `from`, or at the end if there is none.
+fn insert_clause(statement: &mut String, clause: &str, before: &[&str], from: usize) {
+    match find_top_level_keyword(statement, before, from) {
+        Some(at) => statement.insert_str(at, &format!("{} ", clause)),
+        None => {
+            statement.push(' ');
+            statement.push_str(clause);
+        }
+    }
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// A read-only handle to a graphdb db. It has no write methods, so code
+/// holding one cannot modify the db. Get one from [`GraphDb::read_only()`],
+/// or from a read capability another package has granted with
+/// [`GraphDbRead::from_capability()`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphDbRead {
+    pub package_id: PackageId,
+    pub db: String,
+    /// Timeout, in seconds, for every request made through this handle.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+impl GraphDbRead {
+    /// Build a handle to the db a graphdb read capability refers to.
+    /// `package_id` is the package that owns the db. Errors if `capability`
+    /// is not a graphdb read capability.
+    pub fn from_capability(
+        package_id: PackageId,
+        capability: &Capability,
+    ) -> Result<Self, GraphDbError> {
+        let bad_cap = || GraphDbError::NoCap {
+            error: format!("not a graphdb read capability: {}", capability),
+        };
+        if !capability.issuer().is_process("graphdb", "distro", "sys") {
+            return Err(bad_cap());
+        }
+        let params = capability.params_json().map_err(|_| bad_cap())?;
+        if params["kind"] != "read" {
+            return Err(bad_cap());
+        }
+        let db = params["db"].as_str().ok_or_else(bad_cap)?;
+        Ok(GraphDbRead {
+            package_id,
+            db: db.to_string(),
+            timeout: 5,
+        })
+    }
+
+    /// Execute a read query.
+    pub fn read(&self, statement: String) -> Result<serde_json::Value, ProcessLibError> {
+        read(&self.package_id, &self.db, statement, None, self.timeout)
+    }
+
+    /// Execute a read query and deserialize each returned row as `T`.
+    pub fn read_as<T>(&self, statement: String) -> Result<Vec<T>, ProcessLibError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        rows_as(self.read(statement)?)
+    }
+
+    /// Count the records in a table.
+    pub fn count(&self, table: &str) -> Result<u64, ProcessLibError> {
+        count_from(self.read(count_statement(table)?)?)
+    }
+
+    /// The capability a process on our node needs to read this db.
+    pub fn capability(&self, our: &Address) -> Capability {
+        Capability::graphdb_read(our, &self.db)
+    }
+}
+
+/// The longest statement, in characters, that a [`TimedGraphDb`] logs.
+const LOGGED_STATEMENT_LEN: usize = 200;
+
+/// A [`GraphDb`] that times every query and logs the ones slower than a
+/// threshold, from [`GraphDb::timed()`]. Only the statement is logged, never
+/// the values of its params, so queries that bind user data as params can be
+/// logged from production nodes.
+///
+/// ```ignore
+/// let db = graphdb::open(our.package_id(), "app")?.timed(Duration::from_millis(250));
+/// db.read("SELECT * FROM post ORDER BY created DESC;".to_string())?;
+/// // graphdb: slow query (412ms, 0 params): SELECT * FROM post ORDER BY created DESC;
+/// ```
+#[derive(Clone, Debug)]
+pub struct TimedGraphDb {
+    db: GraphDb,
+    threshold: std::time::Duration,
+    verbosity: u8,
+}
+
+impl TimedGraphDb {
+    /// The terminal verbosity level slow queries are logged at. Defaults to 1.
+    pub fn verbosity(mut self, verbosity: u8) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// The wrapped handle, for calls that should not be timed.
+    pub fn inner(&self) -> &GraphDb {
+        &self.db
+    }
+
+    pub fn write(
+        &self,
+        statement: String,
+        params: Option<serde_json::Value>,
+    ) -> Result<(), ProcessLibError> {
+        let logged = self.describe(&statement, params.as_ref());
+        self.time(logged, || self.db.write(statement, params))
+    }
+
+    pub fn write_counted(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<u64, ProcessLibError> {
+        let logged = self.describe(&statement, params.as_ref());
+        self.time(logged, || self.db.write_counted(statement, params))
+    }
+
+    pub fn read(&self, statement: String) -> Result<serde_json::Value, ProcessLibError> {
+        let logged = self.describe(&statement, None);
+        self.time(logged, || self.db.read(statement))
+    }
+
+    pub fn query_one<T>(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<Option<T>, ProcessLibError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let logged = self.describe(&statement, params.as_ref());
+        self.time(logged, || self.db.query_one(statement, params))
+    }
+
+    pub fn read_as<T>(&self, statement: String) -> Result<Vec<T>, ProcessLibError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let logged = self.describe(&statement, None);
+        self.time(logged, || self.db.read_as(statement))
+    }
+
+    fn time<T>(
+        &self,
+        (statement, param_count): (String, usize),
+        query: impl FnOnce() -> Result<T, ProcessLibError>,
+    ) -> Result<T, ProcessLibError> {
+        let start = std::time::Instant::now();
+        let result = query();
+        let elapsed = start.elapsed();
+        if elapsed >= self.threshold {
+            crate::print_to_terminal(
+                self.verbosity,
+                &format!(
+                    "graphdb: slow query ({}ms, {} params{}): {}",
+                    elapsed.as_millis(),
+                    param_count,
+                    if result.is_err() { ", failed" } else { "" },
+                    statement,
+                ),
+            );
+        }
+        result
+    }
+
+    /// The statement, truncated, and how many params it was given.
+    fn describe(&self, statement: &str, params: Option<&serde_json::Value>) -> (String, usize) {
+        let statement = match statement.char_indices().nth(LOGGED_STATEMENT_LEN) {
+            Some((end, _)) => format!("{}…", &statement[..end]),
+            None => statement.to_string(),
+        };
+        let param_count = match params {
+            Some(serde_json::Value::Object(params)) => params.len(),
+            Some(_) => 1,
+            None => 0,
+        };
+        (statement, param_count)
+    }
+}
+
+/// A handle to a graphdb db on another node, which that node's owner has
+/// shared with us by capability. Requests go over the network, so the
+/// default timeout is longer than a local db's, and a request that is not
+/// delivered fails with [`ProcessLibError::Remote`] rather than a
+/// [`GraphDbError`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RemoteGraphDb {
+    pub node: String,
+    pub package_id: PackageId,
+    pub db: String,
+    /// Timeout, in seconds, for every request made through this handle.
+    #[serde(default = "default_remote_timeout")]
+    pub timeout: u64,
+}
+
+impl RemoteGraphDb {
+    /// A handle to the db `db` of package `package_id` on `node`. Nothing is
+    /// sent until the first request.
+    pub fn new(node: &str, package_id: PackageId, db: &str) -> Self {
+        RemoteGraphDb {
+            node: node.to_string(),
+            package_id,
+            db: db.to_string(),
+            timeout: 30,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Execute a read query, with optional params.
+    pub fn read(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<serde_json::Value, ProcessLibError> {
+        let res = self.send(GraphDbAction::Read { statement }, params)?;
+        data_from(res)
+    }
+
+    /// Execute a read query and deserialize each returned row as `T`.
+    pub fn read_as<T>(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<Vec<T>, ProcessLibError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        rows_as(self.read(statement, params)?)
+    }
+
+    /// Execute a write query, with optional params. Needs a write capability
+    /// for the db, not just a read one.
+    pub fn write(
+        &self,
+        statement: String,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<(), ProcessLibError> {
+        let res = self.send(GraphDbAction::Write { statement }, params)?;
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
+
+                match response {
+                    GraphDbResponse::Ok => Ok(()),
+                    GraphDbResponse::Err { error } => Err(error.decode().into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+
+    fn send(
+        &self,
+        action: GraphDbAction,
+        params: Option<GraphDbRequestParams>,
+    ) -> Result<Message, ProcessLibError> {
+        let mut request = Request::new()
+            .target((self.node.as_str(), "graphdb", "distro", "sys"))
+            .body(serde_json::to_vec(&GraphDbRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action,
+            })?);
+        if let Some(params) = params {
+            request = request.blob_bytes(serde_json::to_vec(&params)?);
+        }
+        Ok(request
+            .send_and_await_response(self.timeout)?
+            .map_err(|e| RemoteError::new(&self.node, e))?)
+    }
+}
+
+/// Open or create graphdb database.
+pub fn open(package_id: PackageId, db: &str) -> Result<GraphDb, ProcessLibError> {
     let res = Request::new()
         .target(("our", "graphdb", "distro", "sys"))
         .body(serde_json::to_vec(&GraphDbRequest {
@@ -189,30 +2173,28 @@ pub fn open(package_id: PackageId, db: &str) -> anyhow::Result<GraphDb> {
             db: db.to_string(),
             action: GraphDbAction::Open,
         })?)
-        .send_and_await_response(5)?;
+        .send_and_await_response(5)??;
 
     match res {
-        Ok(Message::Response { body, .. }) => {
+        Message::Response { body, .. } => {
             let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
 
             match response {
                 GraphDbResponse::Ok => Ok(GraphDb {
                     package_id,
                     db: db.to_string(),
+                    timeout: 5,
                 }),
-                GraphDbResponse::Err { error } => Err(error.into()),
-                _ => Err(anyhow::anyhow!(
-                    "graphdb: unexpected response {:?}",
-                    response
-                )),
+                GraphDbResponse::Err { error } => Err(error.decode().into()),
+                _ => Err(ProcessLibError::unexpected(response)),
             }
         }
-        _ => Err(anyhow::anyhow!("graphdb: unexpected message: {:?}", res)),
+        _ => Err(ProcessLibError::unexpected(res)),
     }
 }
 
 /// Remove and delete graphdb database.
-pub fn remove_db(package_id: PackageId, db: &str) -> anyhow::Result<()> {
+pub fn remove_db(package_id: PackageId, db: &str) -> Result<(), ProcessLibError> {
     let res = Request::new()
         .target(("our", "graphdb", "distro", "sys"))
         .body(serde_json::to_vec(&GraphDbRequest {
@@ -220,21 +2202,161 @@ pub fn remove_db(package_id: PackageId, db: &str) -> anyhow::Result<()> {
             db: db.to_string(),
             action: GraphDbAction::RemoveDb,
         })?)
-        .send_and_await_response(5)?;
+        .send_and_await_response(5)??;
 
     match res {
-        Ok(Message::Response { body, .. }) => {
+        Message::Response { body, .. } => {
             let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
 
             match response {
                 GraphDbResponse::Ok => Ok(()),
-                GraphDbResponse::Err { error } => Err(error.into()),
-                _ => Err(anyhow::anyhow!(
-                    "graphdb: unexpected response {:?}",
-                    response
-                )),
+                GraphDbResponse::Err { error } => Err(error.decode().into()),
+                _ => Err(ProcessLibError::unexpected(response)),
             }
         }
-        _ => Err(anyhow::anyhow!("graphdb: unexpected message: {:?}", res)),
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
+
+fn read(
+    package_id: &PackageId,
+    db: &str,
+    statement: String,
+    params: Option<GraphDbRequestParams>,
+    timeout: u64,
+) -> Result<serde_json::Value, ProcessLibError> {
+    let mut request = Request::new()
+        .target(("our", "graphdb", "distro", "sys"))
+        .body(serde_json::to_vec(&GraphDbRequest {
+            package_id: package_id.clone(),
+            db: db.to_string(),
+            action: GraphDbAction::Read { statement },
+        })?);
+    // as with writes, params travel in the blob
+    if let Some(params) = params {
+        request = request.blob_bytes(serde_json::to_vec(&params)?);
+    }
+    let res = request.send_and_await_response(timeout)??;
+    data_from(res)
+}
+
+/// The JSON in the blob of a `Data` response.
+fn data_from(res: Message) -> Result<serde_json::Value, ProcessLibError> {
+    match res {
+        Message::Response { body, .. } => {
+            let response = serde_json::from_slice::<GraphDbResponse>(&body)?;
+
+            match response {
+                GraphDbResponse::Data => {
+                    let blob = get_blob().ok_or_else(|| GraphDbError::InputError {
+                        error: "no blob".to_string(),
+                    })?;
+                    let values =
+                        serde_json::from_slice::<serde_json::Value>(&blob.bytes).map_err(|e| {
+                            GraphDbError::InputError {
+                                error: format!("gave unparsable response: {}", e),
+                            }
+                        })?;
+                    Ok(values)
+                }
+                GraphDbResponse::Err { error } => Err(error.decode().into()),
+                _ => Err(ProcessLibError::unexpected(response)),
+            }
+        }
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
+
+fn rows_as<T>(value: serde_json::Value) -> Result<Vec<T>, ProcessLibError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let rows = match value {
+        serde_json::Value::Array(rows) => rows,
+        serde_json::Value::Null => vec![],
+        row => vec![row],
+    };
+    rows.into_iter()
+        .map(|row| Ok(serde_json::from_value(row)?))
+        .collect()
+}
+
+/// The number of records in a statement's result: one per row, summed over
+/// each statement's rows when there are several statements.
+fn count_records(value: &serde_json::Value) -> u64 {
+    match value {
+        serde_json::Value::Null => 0,
+        serde_json::Value::Array(rows) => rows
+            .iter()
+            .map(|row| match row {
+                serde_json::Value::Array(rows) => rows.len() as u64,
+                serde_json::Value::Null => 0,
+                _ => 1,
+            })
+            .sum(),
+        _ => 1,
+    }
+}
+
+pub(crate) fn check_table_name(table: &str) -> Result<(), GraphDbError> {
+    if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(GraphDbError::InputError {
+            error: format!("invalid table name {:?}", table),
+        });
+    }
+    Ok(())
+}
+
+/// Function names are identifiers, optionally namespaced with `::`.
+fn check_function_name(name: &str) -> Result<(), GraphDbError> {
+    if name
+        .split("::")
+        .any(|segment| check_table_name(segment).is_err())
+    {
+        return Err(GraphDbError::InputError {
+            error: format!("invalid function name {:?}", name),
+        });
+    }
+    Ok(())
+}
+
+/// Field names are identifiers, optionally with `.` for nested fields.
+fn check_field_name(field: &str) -> Result<(), GraphDbError> {
+    if field.split('.').any(|part| check_table_name(part).is_err()) {
+        return Err(GraphDbError::InputError {
+            error: format!("invalid field name {:?}", field),
+        });
+    }
+    Ok(())
+}
+
+/// The name of the event [`GraphDb::define_ttl()`] defines for `field`.
+fn ttl_event_name(field: &str) -> String {
+    format!("ttl_{}", field.replace('.', "_"))
+}
+
+fn expire_statement(table: &str, field: &str, ttl: std::time::Duration) -> String {
+    format!(
+        "DELETE {table} WHERE type::is::datetime({field}) AND {field} < time::now() - {ttl}",
+        table = table,
+        field = field,
+        ttl = format_duration(ttl),
+    )
+}
+
+fn count_statement(table: &str) -> Result<String, GraphDbError> {
+    check_table_name(table)?;
+    Ok(format!("SELECT count() FROM {} GROUP ALL;", table))
+}
+
+fn count_from(value: serde_json::Value) -> Result<u64, ProcessLibError> {
+    #[derive(Deserialize)]
+    struct Count {
+        count: u64,
     }
+    // an empty table returns no rows rather than a count of zero
+    Ok(rows_as::<Count>(value)?
+        .first()
+        .map(|row| row.count)
+        .unwrap_or(0))
 }
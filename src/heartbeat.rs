@@ -0,0 +1,187 @@
+//! Uniform liveness checks between processes. A worker sends a periodic
+//! [`HeartbeatBody::Beat`] to its supervisor with a [`Heartbeat`], and any
+//! process can answer [`ping()`]s with [`respond_to_ping()`]. A supervisor
+//! tracks which of its workers are alive with a [`HeartbeatMonitor`].
+use crate::time::Timestamp;
+use crate::{timer, Address, Message, Request, Response, SendError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Context of the timer a [`Heartbeat`] sets, so its timer responses can be
+/// told apart from the process's own.
+const HEARTBEAT_CONTEXT: &[u8] = b"process_lib:heartbeat";
+
+/// The body of every heartbeat message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HeartbeatBody {
+    /// Sent periodically by a worker to its supervisor. Not responded to.
+    Beat {
+        seq: u64,
+        sent_at: Timestamp,
+    },
+    /// A liveness check, answered with a [`HeartbeatBody::Pong`].
+    Ping {
+        sent_at: Timestamp,
+    },
+    Pong {
+        sent_at: Timestamp,
+    },
+}
+
+/// Sends a [`HeartbeatBody::Beat`] to a supervisor every `interval`, driven
+/// by the timer module. Call [`Heartbeat::start()`] once, then pass every
+/// incoming message to [`Heartbeat::handle()`]:
+///
+/// ```ignore
+/// let mut heartbeat = Heartbeat::new(supervisor, Duration::from_secs(10));
+/// heartbeat.start();
+/// loop {
+///     let message = await_message()?;
+///     if heartbeat.handle(&message) || respond_to_ping(&message) {
+///         continue;
+///     }
+///     ...
+/// }
+/// ```
+pub struct Heartbeat {
+    supervisor: Address,
+    interval: Duration,
+    seq: u64,
+}
+
+impl Heartbeat {
+    pub fn new(supervisor: Address, interval: Duration) -> Self {
+        Heartbeat {
+            supervisor,
+            interval,
+            seq: 0,
+        }
+    }
+
+    /// Send the first beat now, and schedule the next.
+    pub fn start(&mut self) {
+        self.beat();
+    }
+
+    /// Number of beats sent so far.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// If `message` is this heartbeat's timer firing, send a beat, schedule
+    /// the next, and return `true`. Otherwise return `false`.
+    pub fn handle(&mut self, message: &Message) -> bool {
+        if message.is_request()
+            || !message.source().is_process("timer", "distro", "sys")
+            || message.context() != Some(HEARTBEAT_CONTEXT)
+        {
+            return false;
+        }
+        self.beat();
+        true
+    }
+
+    fn beat(&mut self) {
+        self.seq += 1;
+        let body = HeartbeatBody::Beat {
+            seq: self.seq,
+            sent_at: Timestamp::now(),
+        };
+        // a beat that fails to send is just a missed beat; the supervisor
+        // notices through the monitor's timeout
+        let _ = Request::new()
+            .target(self.supervisor.clone())
+            .body(serde_json::to_vec(&body).unwrap())
+            .send();
+        timer::set_timer_for(self.interval, Some(HEARTBEAT_CONTEXT.to_vec()));
+    }
+}
+
+/// Check that `target` is alive and responding, returning the round-trip time.
+pub fn ping(target: &Address, timeout: u64) -> Result<Duration, SendError> {
+    let sent_at = Timestamp::now();
+    Request::new()
+        .target(target.clone())
+        .body(serde_json::to_vec(&HeartbeatBody::Ping { sent_at }).unwrap())
+        // safe to unwrap this call when we know we've set both target and body
+        .send_and_await_response(timeout)
+        .unwrap()?;
+    Ok(sent_at.elapsed())
+}
+
+/// If `message` is a [`HeartbeatBody::Ping`], answer it and return `true`.
+/// Otherwise return `false`.
+pub fn respond_to_ping(message: &Message) -> bool {
+    if !message.is_request() {
+        return false;
+    }
+    let Ok(HeartbeatBody::Ping { sent_at }) = serde_json::from_slice(message.body()) else {
+        return false;
+    };
+    let _ = Response::new()
+        .body(serde_json::to_vec(&HeartbeatBody::Pong { sent_at }).unwrap())
+        .send();
+    true
+}
+
+/// Tracks the beats a supervisor receives from its workers. A worker that
+/// has not beaten within `timeout` of its last beat is considered dead.
+pub struct HeartbeatMonitor {
+    timeout: Duration,
+    last_seen: HashMap<Address, Timestamp>,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(timeout: Duration) -> Self {
+        HeartbeatMonitor {
+            timeout,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// If `message` is a [`HeartbeatBody::Beat`], record it and return
+    /// `true`. Otherwise return `false`.
+    pub fn handle(&mut self, message: &Message) -> bool {
+        if !message.is_request() {
+            return false;
+        }
+        let Ok(HeartbeatBody::Beat { .. }) = serde_json::from_slice(message.body()) else {
+            return false;
+        };
+        self.last_seen
+            .insert(message.source().clone(), Timestamp::now());
+        true
+    }
+
+    /// Start expecting beats from `worker`, counting it alive until `timeout`
+    /// passes without one.
+    pub fn watch(&mut self, worker: Address) {
+        self.last_seen.insert(worker, Timestamp::now());
+    }
+
+    /// Stop tracking `worker`.
+    pub fn forget(&mut self, worker: &Address) {
+        self.last_seen.remove(worker);
+    }
+
+    /// When `worker` last beat, if it ever has.
+    pub fn last_seen(&self, worker: &Address) -> Option<Timestamp> {
+        self.last_seen.get(worker).copied()
+    }
+
+    pub fn is_alive(&self, worker: &Address) -> bool {
+        self.last_seen
+            .get(worker)
+            .is_some_and(|seen| seen.elapsed() < self.timeout)
+    }
+
+    /// Every tracked worker that has missed its beats.
+    pub fn dead(&self) -> Vec<&Address> {
+        self.last_seen
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() >= self.timeout)
+            .map(|(worker, _)| worker)
+            .collect()
+    }
+}
@@ -0,0 +1,208 @@
+use super::client::{JsonApi, JsonApiError};
+use super::{send_request_await_response, HttpClientError, Method, StatusCode};
+use crate::kv::{Kv, KvError};
+use crate::time::Timestamp;
+use crate::{ModuleError, ProcessLibError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+
+/// An OAuth2 access token, with the refresh token used to renew it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OAuth2Token {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// When the access token expires, if the provider said.
+    pub expires_at: Option<Timestamp>,
+}
+
+impl OAuth2Token {
+    /// Whether the token has expired, or will within the next 30 seconds.
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => (expires_at - Duration::from_secs(30)).has_passed(),
+            None => false,
+        }
+    }
+}
+
+/// The token endpoint's response to a refresh request, as in RFC 6749.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("auth: no token stored; call OAuth2::set_token() first")]
+    NoToken,
+    #[error("auth: token expired and there is no refresh token")]
+    NoRefreshToken,
+    #[error("auth: token refresh got {status}: {body}")]
+    RefreshFailed { status: StatusCode, body: String },
+    #[error("auth: {0}")]
+    Client(#[from] HttpClientError),
+    #[error(transparent)]
+    Api(#[from] JsonApiError),
+    #[error("auth: failed to store token: {0}")]
+    Store(#[from] ProcessLibError),
+    #[error("auth: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Bearer-token authorization for outgoing HTTP requests, using the OAuth2
+/// refresh-token flow. The token is kept in kv, so it survives restarts.
+///
+/// ```ignore
+/// let mut auth = OAuth2::load(kv, "github", "https://github.com/login/oauth/access_token", &client_id)?
+///     .client_secret(&client_secret);
+/// if auth.token().is_none() {
+///     auth.set_token(token_from_authorization_code_flow)?;
+/// }
+/// let api = http::client::json_api("https://api.github.com")?;
+/// let user: User = auth.send(&api, Method::GET, "/user", None)?;
+/// ```
+pub struct OAuth2 {
+    kv: Kv,
+    key: Vec<u8>,
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    token: Option<OAuth2Token>,
+}
+
+impl OAuth2 {
+    /// Load the token stored under `name` in `kv`, if there is one. Tokens are
+    /// renewed by posting to `token_url` as `client_id`.
+    pub fn load(kv: Kv, name: &str, token_url: &str, client_id: &str) -> Result<Self, AuthError> {
+        let key = format!("oauth2:{}", name).into_bytes();
+        let token = match kv.get(key.clone()) {
+            Ok(bytes) => Some(serde_json::from_slice(&bytes)?),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(OAuth2 {
+            kv,
+            key,
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: None,
+            token,
+        })
+    }
+
+    /// Send a client secret when refreshing, for confidential clients.
+    pub fn client_secret(mut self, client_secret: &str) -> Self {
+        self.client_secret = Some(client_secret.to_string());
+        self
+    }
+
+    pub fn token(&self) -> Option<&OAuth2Token> {
+        self.token.as_ref()
+    }
+
+    /// Store a new token, e.g. one from the authorization-code flow.
+    pub fn set_token(&mut self, token: OAuth2Token) -> Result<(), AuthError> {
+        self.kv
+            .set(self.key.clone(), serde_json::to_vec(&token)?, None)?;
+        self.token = Some(token);
+        Ok(())
+    }
+
+    /// Get a current access token, refreshing it first if it has expired.
+    pub fn access_token(&mut self) -> Result<String, AuthError> {
+        let token = self.token.as_ref().ok_or(AuthError::NoToken)?;
+        if token.is_expired() {
+            self.refresh()?;
+        }
+        Ok(self.token.as_ref().unwrap().access_token.clone())
+    }
+
+    /// Add `Authorization: Bearer <token>` to a set of headers, refreshing the
+    /// token first if it has expired. For use with [`super::send_request()`].
+    pub fn authorize(&mut self, headers: &mut HashMap<String, String>) -> Result<(), AuthError> {
+        let access_token = self.access_token()?;
+        headers.insert(
+            "Authorization".to_string(),
+            format!("Bearer {}", access_token),
+        );
+        Ok(())
+    }
+
+    /// Send a request through `api` with the access token attached. If the
+    /// server responds 401, the token is refreshed and the request retried once.
+    pub fn send<T: DeserializeOwned>(
+        &mut self,
+        api: &JsonApi,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<T, AuthError> {
+        let authorized = api
+            .clone()
+            .header("Authorization", &format!("Bearer {}", self.access_token()?));
+        match authorized.send(method.clone(), path, body.clone()) {
+            Err(e) if e.status() == Some(StatusCode::UNAUTHORIZED) => {
+                self.refresh()?;
+                let authorized = api
+                    .clone()
+                    .header("Authorization", &format!("Bearer {}", self.access_token()?));
+                Ok(authorized.send(method, path, body)?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Exchange the refresh token for a new access token, and store it.
+    pub fn refresh(&mut self) -> Result<(), AuthError> {
+        let refresh_token = self
+            .token
+            .as_ref()
+            .ok_or(AuthError::NoToken)?
+            .refresh_token
+            .clone()
+            .ok_or(AuthError::NoRefreshToken)?;
+
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "refresh_token")
+            .append_pair("refresh_token", &refresh_token)
+            .append_pair("client_id", &self.client_id);
+        if let Some(client_secret) = &self.client_secret {
+            form.append_pair("client_secret", client_secret);
+        }
+        let url = url::Url::parse(&self.token_url).map_err(|_| HttpClientError::BadUrl {
+            url: self.token_url.clone(),
+        })?;
+        let response = send_request_await_response(
+            Method::POST,
+            url,
+            Some(HashMap::from([
+                (
+                    "Content-Type".to_string(),
+                    "application/x-www-form-urlencoded".to_string(),
+                ),
+                ("Accept".to_string(), "application/json".to_string()),
+            ])),
+            30,
+            form.finish().into_bytes(),
+        )?;
+        if !response.status().is_success() {
+            return Err(AuthError::RefreshFailed {
+                status: response.status(),
+                body: String::from_utf8_lossy(response.body()).to_string(),
+            });
+        }
+        let refreshed = serde_json::from_slice::<TokenResponse>(response.body())?;
+        self.set_token(OAuth2Token {
+            access_token: refreshed.access_token,
+            // providers may omit the refresh token if it did not change
+            refresh_token: refreshed.refresh_token.or(Some(refresh_token)),
+            expires_at: refreshed
+                .expires_in
+                .map(|secs| Timestamp::now() + Duration::from_secs(secs)),
+        })
+    }
+}
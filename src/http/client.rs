@@ -0,0 +1,550 @@
+use super::{
+    send_request_await_response, HttpClientAction, HttpClientError, HttpClientRequest,
+    HttpStreamPart, Method, OutgoingHttpRequest, StatusCode,
+};
+pub use crate::RetryPolicy;
+use crate::{get_blob, Address, Message, Request};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// The header that marks a POST or PATCH as safe to retry. The server must
+/// treat requests with the same key as one request.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// A client for a JSON REST API rooted at one base URL. Build one with
+/// [`json_api()`], then call [`JsonApi::get()`], [`JsonApi::post()`] and so
+/// on with paths relative to the base:
+///
+/// ```ignore
+/// let github = http::client::json_api("https://api.github.com")?
+///     .header("User-Agent", "my-process");
+/// let repo: Repo = github.get("/repos/kinode-dao/process_lib")?;
+/// ```
+///
+/// Requests that fail to send, or that come back with a 429 or 5xx status,
+/// are retried as set by the client's [`RetryPolicy`]. Only GET, HEAD, PUT,
+/// DELETE and OPTIONS requests are retried, since repeating them is safe;
+/// POST and PATCH requests are retried only if they carry an
+/// [`IDEMPOTENCY_KEY_HEADER`], e.g. from [`JsonApi::post_idempotent()`].
+/// Other non-2xx statuses are returned as [`JsonApiError::Status`].
+#[derive(Clone, Debug)]
+pub struct JsonApi {
+    base_url: String,
+    headers: HashMap<String, String>,
+    timeout: u64,
+    retry: RetryPolicy,
+}
+
+#[derive(Debug, Error)]
+pub enum JsonApiError {
+    #[error("json_api: invalid url {url}")]
+    BadUrl { url: String },
+    #[error("json_api: {status} response: {body}")]
+    Status { status: StatusCode, body: String },
+    #[error("json_api: {0}")]
+    Client(#[from] HttpClientError),
+    #[error("json_api: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl JsonApiError {
+    /// The status code of the response, if the request got one.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            JsonApiError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// Start building a [`JsonApi`] client. Sends and accepts JSON, times out
+/// after 30 seconds and retries with the default [`RetryPolicy`].
+pub fn json_api(base_url: &str) -> Result<JsonApi, JsonApiError> {
+    let base_url = base_url.trim_end_matches('/').to_string();
+    if url::Url::parse(&base_url).is_err() {
+        return Err(JsonApiError::BadUrl { url: base_url });
+    }
+    Ok(JsonApi {
+        base_url,
+        headers: HashMap::from([
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Accept".to_string(), "application/json".to_string()),
+        ]),
+        timeout: 30,
+        retry: RetryPolicy::default(),
+    })
+}
+
+impl JsonApi {
+    /// Set a header sent with every request.
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+    /// Send `Authorization: Bearer <token>` with every request. For tokens
+    /// that expire, use [`super::auth::OAuth2`] instead.
+    pub fn bearer_token(self, token: &str) -> Self {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+    /// Set the timeout, in seconds, for each attempt.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Set how many times a failed request is retried.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retry.retries = retries;
+        self
+    }
+    /// Set how failed requests are retried.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, JsonApiError> {
+        self.send(Method::GET, path, None)
+    }
+    pub fn delete<T: DeserializeOwned>(&self, path: &str) -> Result<T, JsonApiError> {
+        self.send(Method::DELETE, path, None)
+    }
+    pub fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, JsonApiError> {
+        self.send(Method::POST, path, Some(serde_json::to_vec(body)?))
+    }
+    /// POST with an [`IDEMPOTENCY_KEY_HEADER`], so that the request is
+    /// retried like a PUT. Use a fresh key, such as a [`crate::ids::Ulid`],
+    /// for each logical request, and the same key if it is sent again.
+    pub fn post_idempotent<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        idempotency_key: &str,
+        body: &B,
+    ) -> Result<T, JsonApiError> {
+        let mut headers = self.headers.clone();
+        headers.insert(
+            IDEMPOTENCY_KEY_HEADER.to_string(),
+            idempotency_key.to_string(),
+        );
+        self.send_with_headers(Method::POST, path, headers, Some(serde_json::to_vec(body)?))
+    }
+    pub fn put<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, JsonApiError> {
+        self.send(Method::PUT, path, Some(serde_json::to_vec(body)?))
+    }
+    pub fn patch<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, JsonApiError> {
+        self.send(Method::PATCH, path, Some(serde_json::to_vec(body)?))
+    }
+
+    /// Send a request with any method. A response with an empty body
+    /// deserializes as JSON `null`, so `T` may be `()` or an `Option`.
+    pub fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<T, JsonApiError> {
+        self.send_with_headers(method, path, self.headers.clone(), body)
+    }
+
+    fn send_with_headers<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        headers: HashMap<String, String>,
+        body: Option<Vec<u8>>,
+    ) -> Result<T, JsonApiError> {
+        let url = format!("{}/{}", self.base_url, path.trim_start_matches('/'));
+        let url = url::Url::parse(&url).map_err(|_| JsonApiError::BadUrl { url })?;
+        let body = body.unwrap_or_default();
+        let retries = if is_retry_safe(&method, &headers) {
+            self.retry.retries
+        } else {
+            0
+        };
+
+        let mut attempt = 0;
+        loop {
+            let result = send_request_await_response(
+                method.clone(),
+                url.clone(),
+                Some(headers.clone()),
+                self.timeout,
+                body.clone(),
+            );
+            let retryable = match &result {
+                Ok(response) => {
+                    response.status() == StatusCode::TOO_MANY_REQUESTS
+                        || response.status().is_server_error()
+                }
+                Err(HttpClientError::RequestFailed { .. }) => true,
+                Err(_) => false,
+            };
+            if retryable && attempt < retries {
+                attempt += 1;
+                let delay = self.retry.delay(attempt);
+                let _ = crate::timer::set_and_await_timer(delay.as_millis() as u64);
+                continue;
+            }
+            let response = result?;
+            if !response.status().is_success() {
+                return Err(JsonApiError::Status {
+                    status: response.status(),
+                    body: String::from_utf8_lossy(response.body()).to_string(),
+                });
+            }
+            let bytes = response.body();
+            return Ok(serde_json::from_slice(if bytes.is_empty() {
+                b"null"
+            } else {
+                bytes
+            })?);
+        }
+    }
+}
+
+/// Whether a request can be sent again without risk of doing its work twice.
+fn is_retry_safe(method: &Method, headers: &HashMap<String, String>) -> bool {
+    match *method {
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS => true,
+        _ => headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case(IDEMPOTENCY_KEY_HEADER)),
+    }
+}
+
+/// Start a GET request whose response is streamed back into the message
+/// loop as it arrives, rather than buffered whole. Returns the stream's id;
+/// each piece of the response arrives as a request from `http_client`,
+/// which [`HttpChunk::from_message()`] parses, carrying the id and `context`.
+///
+/// ```ignore
+/// let stream_id = http::client::get_streaming("https://example.com/big.json", None)?;
+/// loop {
+///     let message = await_message()?;
+///     match HttpChunk::from_message(&our, &message) {
+///         Some(HttpChunk { kind: HttpChunkKind::Data(bytes), .. }) => relay(bytes),
+///         Some(HttpChunk { kind: HttpChunkKind::End, .. }) => break,
+///         ...
+///     }
+/// }
+/// ```
+pub fn get_streaming(url: &str, context: Option<Vec<u8>>) -> Result<u32, HttpClientError> {
+    send_streaming(Method::GET, url, HashMap::new(), vec![], context)
+}
+
+/// Send a request of any method whose response is streamed back, as with
+/// [`get_streaming()`].
+pub fn send_streaming(
+    method: Method,
+    url: &str,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+    context: Option<Vec<u8>>,
+) -> Result<u32, HttpClientError> {
+    if url::Url::parse(url).is_err() {
+        return Err(HttpClientError::BadUrl {
+            url: url.to_string(),
+        });
+    }
+    let stream_id = rand::random();
+    Request::new()
+        .target(("our", "http_client", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&HttpClientAction::HttpStream {
+                request: OutgoingHttpRequest {
+                    method: method.to_string(),
+                    version: None,
+                    url: url.to_string(),
+                    headers,
+                },
+                stream_id,
+                context,
+            })
+            .map_err(|e| HttpClientError::BadRequest {
+                req: format!("{e:?}"),
+            })?,
+        )
+        .blob_bytes(body)
+        .send()
+        .map_err(|e| HttpClientError::RequestFailed {
+            error: e.to_string(),
+        })?;
+    Ok(stream_id)
+}
+
+/// A piece of a streamed HTTP response, started with [`get_streaming()`].
+#[derive(Debug)]
+pub struct HttpChunk {
+    pub stream_id: u32,
+    /// The context the stream was started with.
+    pub context: Option<Vec<u8>>,
+    pub kind: HttpChunkKind,
+}
+
+#[derive(Debug)]
+pub enum HttpChunkKind {
+    /// The response's status and headers, which arrive first.
+    Head {
+        status: StatusCode,
+        headers: HashMap<String, String>,
+    },
+    /// The next bytes of the body.
+    Data(Vec<u8>),
+    /// The body is complete.
+    End,
+    /// The request failed. No more chunks will arrive.
+    Error(HttpClientError),
+}
+
+impl HttpChunk {
+    /// Parse a message as a piece of a streamed response, or return `None`
+    /// if it is not one. Only messages from `http_client` on our own node,
+    /// `our`, are accepted.
+    pub fn from_message(our: &Address, message: &Message) -> Option<Self> {
+        let source = message.source();
+        if !message.is_request()
+            || source.node != our.node
+            || !source.is_process("http_client", "distro", "sys")
+        {
+            return None;
+        }
+        let Ok(HttpClientRequest::Stream {
+            stream_id,
+            context,
+            part,
+        }) = serde_json::from_slice(message.body())
+        else {
+            return None;
+        };
+        let kind = match part {
+            HttpStreamPart::Head { status, headers } => match StatusCode::from_u16(status) {
+                Ok(status) => HttpChunkKind::Head { status, headers },
+                Err(_) => HttpChunkKind::Error(HttpClientError::RequestFailed {
+                    error: format!("invalid status code {status}"),
+                }),
+            },
+            HttpStreamPart::Body => HttpChunkKind::Data(get_blob().unwrap_or_default().bytes),
+            HttpStreamPart::End => HttpChunkKind::End,
+            HttpStreamPart::Error { error } => HttpChunkKind::Error(error),
+        };
+        Some(HttpChunk {
+            stream_id,
+            context,
+            kind,
+        })
+    }
+
+    /// Whether this is the last chunk of its stream.
+    pub fn is_last(&self) -> bool {
+        matches!(self.kind, HttpChunkKind::End | HttpChunkKind::Error(_))
+    }
+}
+
+/// How many bytes [`download_to_resumable()`] asks for per request, to stay
+/// under the size limit for a message's blob.
+pub const DOWNLOAD_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Timeout, in seconds, for each request of a download.
+const DOWNLOAD_CHUNK_TIMEOUT: u64 = 60;
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("download: {0}")]
+    Client(#[from] HttpClientError),
+    #[error("download: server responded {status}")]
+    Status { status: StatusCode },
+    #[error("download: server sent a range that does not start at byte {expected}")]
+    BadRange { expected: u64 },
+    /// The server sent an empty range before the end of the resource.
+    #[error("download: server sent no bytes at {offset} of {total}")]
+    EmptyRange { offset: u64, total: u64 },
+    #[error("download: vfs error: {error}")]
+    Vfs { error: String },
+}
+
+fn vfs_error(error: anyhow::Error) -> DownloadError {
+    DownloadError::Vfs {
+        error: error.to_string(),
+    }
+}
+
+/// What [`download_to_resumable()`] keeps next to a download in progress.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct DownloadProgress {
+    url: String,
+    /// The resource's version, so a resumed download notices if it changed.
+    etag: Option<String>,
+    total: Option<u64>,
+}
+
+/// Download `url` to the file at `vfs_path` in chunks, using Range requests,
+/// and return its size. Progress is kept in the vfs, at `vfs_path` with
+/// `.download` appended, so if the download is interrupted, by an error or
+/// a restart, calling this again with the same arguments picks up where it
+/// stopped instead of starting over:
+///
+/// ```ignore
+/// let path = "/my_app:my_app:publisher.os/models/weights.bin";
+/// while let Err(e) = http::client::download_to_resumable(url, path) {
+///     println!("download interrupted, resuming: {e}");
+///     timer::set_and_await_timer(5_000)?;
+/// }
+/// ```
+///
+/// Failed requests are retried with the default [`RetryPolicy`]. If the
+/// resource changes between attempts, per its ETag, or the server does not
+/// support Range requests, the whole file is fetched again. Without a
+/// progress file, any file already at `vfs_path` is overwritten.
+pub fn download_to_resumable(url: &str, vfs_path: &str) -> Result<u64, DownloadError> {
+    let parsed = url::Url::parse(url).map_err(|_| HttpClientError::BadUrl {
+        url: url.to_string(),
+    })?;
+    let progress_path = format!("{}.download", vfs_path);
+    let saved = crate::vfs::open_file(&progress_path, false)
+        .and_then(|file| file.read())
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<DownloadProgress>(&bytes).ok())
+        .filter(|progress| progress.url == url);
+    let mut file = crate::vfs::open_file(vfs_path, true).map_err(vfs_error)?;
+    let mut offset = match saved {
+        Some(_) => file.metadata().map_err(vfs_error)?.len,
+        None => {
+            file.set_len(0).map_err(vfs_error)?;
+            0
+        }
+    };
+    let mut progress = saved.unwrap_or(DownloadProgress {
+        url: url.to_string(),
+        etag: None,
+        total: None,
+    });
+    save_download_progress(&progress_path, &progress).map_err(vfs_error)?;
+
+    let retry = RetryPolicy::default();
+    let mut attempt = 0;
+    loop {
+        if progress.total.is_some_and(|total| offset >= total) {
+            break;
+        }
+        let mut headers = HashMap::from([(
+            "Range".to_string(),
+            format!("bytes={}-{}", offset, offset + DOWNLOAD_CHUNK_SIZE - 1),
+        )]);
+        if let Some(etag) = &progress.etag {
+            headers.insert("If-Range".to_string(), etag.clone());
+        }
+        let result = send_request_await_response(
+            Method::GET,
+            parsed.clone(),
+            Some(headers),
+            DOWNLOAD_CHUNK_TIMEOUT,
+            vec![],
+        );
+        let retryable = match &result {
+            Ok(response) => {
+                response.status() == StatusCode::TOO_MANY_REQUESTS
+                    || response.status().is_server_error()
+            }
+            Err(HttpClientError::RequestFailed { .. }) => true,
+            Err(_) => false,
+        };
+        if retryable && attempt < retry.retries {
+            attempt += 1;
+            let _ = crate::timer::set_and_await_timer(retry.delay(attempt).as_millis() as u64);
+            continue;
+        }
+        attempt = 0;
+        let response = result?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let before = (progress.etag.clone(), progress.total);
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let (start, total) = header("Content-Range")
+                    .as_deref()
+                    .and_then(parse_content_range)
+                    .ok_or(DownloadError::BadRange { expected: offset })?;
+                if start != offset {
+                    return Err(DownloadError::BadRange { expected: offset });
+                }
+                let chunk = response.body();
+                if let Some(total) = total.filter(|&total| chunk.is_empty() && offset < total) {
+                    return Err(DownloadError::EmptyRange { offset, total });
+                }
+                file.append(chunk).map_err(vfs_error)?;
+                offset += chunk.len() as u64;
+                progress.etag = progress.etag.take().or_else(|| header("ETag"));
+                progress.total = total;
+                // with no total given, a short chunk is the last one
+                if total.is_none() && (chunk.len() as u64) < DOWNLOAD_CHUNK_SIZE {
+                    progress.total = Some(offset);
+                }
+            }
+            // the server ignored the range, or the resource changed since
+            // the download started: this is the whole of it
+            StatusCode::OK => {
+                let body = response.body();
+                file.write(body).map_err(vfs_error)?;
+                offset = body.len() as u64;
+                progress.etag = header("ETag");
+                progress.total = Some(offset);
+            }
+            // past the end: done, including for an empty resource, which has
+            // no first byte to ask for
+            StatusCode::RANGE_NOT_SATISFIABLE
+                if offset > 0
+                    || header("Content-Range")
+                        .as_deref()
+                        .and_then(parse_unsatisfied_range)
+                        == Some(0) =>
+            {
+                progress.total = Some(offset);
+            }
+            status => return Err(DownloadError::Status { status }),
+        }
+        if (progress.etag.clone(), progress.total) != before {
+            save_download_progress(&progress_path, &progress).map_err(vfs_error)?;
+        }
+    }
+    crate::vfs::remove_file(&progress_path).map_err(vfs_error)?;
+    Ok(offset)
+}
+
+fn save_download_progress(path: &str, progress: &DownloadProgress) -> anyhow::Result<()> {
+    crate::vfs::open_file(path, true)?.write(&serde_json::to_vec(progress)?)
+}
+
+/// The total length from the `Content-Range` header of a 416 response,
+/// such as `bytes */4096`.
+fn parse_unsatisfied_range(value: &str) -> Option<u64> {
+    value.strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+/// The first byte and total length, if known, from a `Content-Range` header
+/// such as `bytes 0-1023/4096` or `bytes 0-1023/*`.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-')?.0.trim().parse().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start, total))
+}
@@ -0,0 +1,265 @@
+//! Bind paths by who may call them, and find out who did. A path is bound
+//! with an [`HttpAuth`] mode, and each request to it is checked against the
+//! same mode to get the caller's [`HttpIdentity`]:
+//!
+//! ```ignore
+//! let auth = HttpAuth::SignedBy(vec!["alice.os".into(), "bob.os".into()]);
+//! identity::bind("/api/sync", &auth)?;
+//! // for each request to the path
+//! let body = get_blob().map(|blob| blob.bytes).unwrap_or_default();
+//! match request.identity(&our, &auth, &body) {
+//!     Ok(HttpIdentity::Node(address)) => sync_with(address.node(), &body),
+//!     _ => send_response(StatusCode::UNAUTHORIZED, None, vec![]),
+//! }
+//! ```
+//!
+//! The calling process signs its request with [`signed_headers()`], using its
+//! node's networking key, so only processes on the listed nodes can call the
+//! path:
+//!
+//! ```ignore
+//! let url = url::Url::parse("https://bob.example.com/sync:sync:alice.os/api/sync")?;
+//! let headers = identity::signed_headers(&our, "bob.os", &Method::POST, &url, &body)?;
+//! http::send_request_await_response(Method::POST, url, Some(headers), 30, body)?;
+//! ```
+//!
+//! A signature is bound to the node it was made for, so a request captured
+//! on its way to one node cannot be sent to another. It can be replayed to
+//! the same node until [`MAX_SIGNATURE_SKEW`] has passed, though. Paths
+//! where a repeat matters should drop repeated signatures:
+//!
+//! ```ignore
+//! let mut seen = Dedup::new(4096);
+//! if let Ok(HttpIdentity::Node(address)) = request.identity(&our, &auth, &body) {
+//!     let signature = request.headers().get(SIGNATURE_HEADER).unwrap();
+//!     if seen.seen_id(signature.as_bytes()) {
+//!         return send_response(StatusCode::CONFLICT, None, vec![]);
+//!     }
+//!     ...
+//! }
+//! ```
+use super::{bind_http_path, bind_ws_path, HttpServerError, IncomingHttpRequest, Method};
+use crate::kernel_types::NodeId;
+use crate::net::{net_request, NetAction, NetResponse};
+use crate::{get_blob, Address, ProcessLibError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Header carrying the address of the process that signed a request.
+pub const SIGNER_HEADER: &str = "x-kinode-signer";
+/// Header carrying when a request was signed, in milliseconds since the
+/// unix epoch.
+pub const SIGNED_AT_HEADER: &str = "x-kinode-signed-at";
+/// Header carrying the signature, in unpadded URL-safe base64.
+pub const SIGNATURE_HEADER: &str = "x-kinode-signature";
+/// How far, in milliseconds, a signed request's time may be from ours. A
+/// captured request can be replayed within this window, but not after it.
+pub const MAX_SIGNATURE_SKEW: u64 = 5 * 60 * 1000;
+
+/// Who may call a bound path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpAuth {
+    /// Anyone who can reach the node.
+    Public,
+    /// The node's owner, logged in to the node in their browser.
+    Authenticated,
+    /// Only clients on the node's own machine, over the loopback address.
+    LocalOnly,
+    /// Processes on these nodes, signing each request with
+    /// [`signed_headers()`].
+    SignedBy(Vec<NodeId>),
+}
+
+/// Who made a request, as established by the path's [`HttpAuth`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpIdentity {
+    /// Unknown; the path is public.
+    Anonymous,
+    /// The node's owner, through a logged-in browser session.
+    Owner,
+    /// A client on the node's own machine.
+    Local,
+    /// The process that signed the request.
+    Node(Address),
+}
+
+#[derive(Debug, Error)]
+pub enum HttpAuthError {
+    #[error("http auth: request did not come from the loopback address")]
+    NotLocal,
+    #[error("http auth: request is not signed")]
+    Unsigned,
+    #[error("http auth: signature headers are malformed")]
+    Malformed,
+    #[error("http auth: request was signed too long ago, or in the future")]
+    Stale,
+    #[error("http auth: signature does not match the request and signer")]
+    BadSignature,
+    #[error("http auth: {node} may not call this path")]
+    NotAllowed { node: NodeId },
+    #[error("http auth: websocket paths cannot be bound {mode}")]
+    Unsupported { mode: &'static str },
+    #[error("http auth: {0}")]
+    Bind(#[from] HttpServerError),
+    #[error("http auth: {0}")]
+    Net(#[from] ProcessLibError),
+}
+
+impl HttpAuth {
+    /// The `authenticated` and `local_only` flags of the bind. A signed
+    /// path is public to the server; its requests are checked here.
+    fn bind_flags(&self) -> (bool, bool) {
+        match self {
+            HttpAuth::Public | HttpAuth::SignedBy(_) => (false, false),
+            HttpAuth::Authenticated => (true, false),
+            HttpAuth::LocalOnly => (false, true),
+        }
+    }
+
+    /// The identity of whoever opened a websocket on a path bound with
+    /// [`bind_ws()`]. A connection carries no headers, so it is whatever
+    /// the bind guarantees.
+    pub fn websocket_identity(&self) -> HttpIdentity {
+        match self {
+            HttpAuth::Authenticated => HttpIdentity::Owner,
+            _ => HttpIdentity::Anonymous,
+        }
+    }
+}
+
+/// Bind an HTTP path so that only callers allowed by `auth` reach it.
+pub fn bind(path: &str, auth: &HttpAuth) -> Result<(), HttpAuthError> {
+    let (authenticated, local_only) = auth.bind_flags();
+    Ok(bind_http_path(path, authenticated, local_only)?)
+}
+
+/// Bind a websocket path. Only [`HttpAuth::Public`] and
+/// [`HttpAuth::Authenticated`] are possible, since the server cannot limit
+/// websockets to loopback and connections carry no signature.
+pub fn bind_ws(path: &str, auth: &HttpAuth, encrypted: bool) -> Result<(), HttpAuthError> {
+    match auth {
+        HttpAuth::Public => Ok(bind_ws_path(path, false, encrypted)?),
+        HttpAuth::Authenticated => Ok(bind_ws_path(path, true, encrypted)?),
+        HttpAuth::LocalOnly => Err(HttpAuthError::Unsupported { mode: "local only" }),
+        HttpAuth::SignedBy(_) => Err(HttpAuthError::Unsupported { mode: "signed" }),
+    }
+}
+
+impl IncomingHttpRequest {
+    /// Who made this request to `our` process, checked against the `auth`
+    /// its path was bound with. `body` is the request's body, from the blob,
+    /// which a signature covers.
+    pub fn identity(
+        &self,
+        our: &Address,
+        auth: &HttpAuth,
+        body: &[u8],
+    ) -> Result<HttpIdentity, HttpAuthError> {
+        match auth {
+            HttpAuth::Public => Ok(HttpIdentity::Anonymous),
+            HttpAuth::Authenticated => Ok(HttpIdentity::Owner),
+            HttpAuth::LocalOnly => match self.source_socket_addr() {
+                Ok(addr) if addr.ip().is_loopback() => Ok(HttpIdentity::Local),
+                _ => Err(HttpAuthError::NotLocal),
+            },
+            HttpAuth::SignedBy(nodes) => {
+                let signer = self.verify_signature(our, body)?;
+                if !nodes.iter().any(|node| node == signer.node()) {
+                    return Err(HttpAuthError::NotAllowed {
+                        node: signer.node().to_string(),
+                    });
+                }
+                Ok(HttpIdentity::Node(signer))
+            }
+        }
+    }
+
+    /// Check the signature headers from [`signed_headers()`], made for the
+    /// node of `our` process, and return the signer, whatever node it is on.
+    pub fn verify_signature(&self, our: &Address, body: &[u8]) -> Result<Address, HttpAuthError> {
+        let header = |name: &str| {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let (Some(signer), Some(signed_at), Some(signature)) = (
+            header(SIGNER_HEADER),
+            header(SIGNED_AT_HEADER),
+            header(SIGNATURE_HEADER),
+        ) else {
+            return Err(HttpAuthError::Unsigned);
+        };
+        let signer: Address = signer.parse().map_err(|_| HttpAuthError::Malformed)?;
+        let signed_at: u64 = signed_at.parse().map_err(|_| HttpAuthError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| HttpAuthError::Malformed)?;
+        if crate::time::now_millis().abs_diff(signed_at) > MAX_SIGNATURE_SKEW {
+            return Err(HttpAuthError::Stale);
+        }
+        let method = self.method().map_err(|_| HttpAuthError::Malformed)?;
+        let url = self.url().map_err(|_| HttpAuthError::Malformed)?;
+        let data = signing_data(our.node(), &method, &url, signed_at, body);
+        let action = NetAction::Verify {
+            from: signer.clone(),
+            signature,
+        };
+        match net_request(action, Some(&data))? {
+            NetResponse::Verified(true) => Ok(signer),
+            NetResponse::Verified(false) => Err(HttpAuthError::BadSignature),
+            response => Err(ProcessLibError::unexpected(response).into()),
+        }
+    }
+}
+
+/// Headers that sign an outgoing request as coming from `our` process, for a
+/// path bound with [`HttpAuth::SignedBy`] on the node `target`. Send the
+/// request with the same method, URL and body, within [`MAX_SIGNATURE_SKEW`].
+pub fn signed_headers(
+    our: &Address,
+    target: &str,
+    method: &Method,
+    url: &url::Url,
+    body: &[u8],
+) -> Result<HashMap<String, String>, ProcessLibError> {
+    let signed_at = crate::time::now_millis();
+    let data = signing_data(target, method, url, signed_at, body);
+    let signature = match net_request(NetAction::Sign, Some(&data))? {
+        NetResponse::Signed => get_blob().ok_or(ProcessLibError::NoBlob)?.bytes,
+        response => return Err(ProcessLibError::unexpected(response)),
+    };
+    Ok(HashMap::from([
+        (SIGNER_HEADER.to_string(), our.to_string()),
+        (SIGNED_AT_HEADER.to_string(), signed_at.to_string()),
+        (
+            SIGNATURE_HEADER.to_string(),
+            URL_SAFE_NO_PAD.encode(signature),
+        ),
+    ]))
+}
+
+/// What a request's signature covers: the node it is for, its method, path
+/// and query, when it was signed, and a hash of its body. The host is left
+/// out, since the caller may reach the node through a proxy.
+fn signing_data(
+    node: &str,
+    method: &Method,
+    url: &url::Url,
+    signed_at: u64,
+    body: &[u8],
+) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}?{}\n{}\n{:x}",
+        node,
+        method,
+        url.path(),
+        url.query().unwrap_or(""),
+        signed_at,
+        Sha256::digest(body),
+    )
+    .into_bytes()
+}
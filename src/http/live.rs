@@ -0,0 +1,225 @@
+//! Push graphdb changes to web clients over websockets. A [`LiveFanout`]
+//! runs one `LIVE SELECT DIFF` per table that any client watches, and
+//! forwards each change, as a JSON Patch, to every websocket channel
+//! subscribed to that table.
+//!
+//! ```ignore
+//! http::bind_ws_path("/updates", true, false)?;
+//! let mut fanout = LiveFanout::new(db, &["task", "project"]);
+//! loop {
+//!     let message = await_message()?;
+//!     if fanout.handle(&message)? {
+//!         continue;
+//!     }
+//!     ...
+//! }
+//! ```
+//!
+//! Clients subscribe by sending `{"Subscribe":{"table":"task"}}` as a text
+//! message on the socket, and receive a [`LiveUpdate`] for each change.
+//! Clients may only subscribe to the tables the fanout was created with; a
+//! subscription to any other table, or one that fails, is answered with a
+//! [`LiveSubscribeError`]. A server that decides itself what each client
+//! sees can call [`LiveFanout::subscribe()`] instead, for any table.
+use super::{send_ws_push, HttpServerRequest, WsMessageType};
+use crate::graphdb::{parse_live_notification, GraphDb, LiveAction};
+use crate::{get_blob, LazyLoadBlob, Message, ProcessLibError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// What a client sends on its websocket to choose the tables it watches.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum LiveClientMessage {
+    Subscribe { table: String },
+    Unsubscribe { table: String },
+}
+
+/// What a client receives, as a JSON text message, for each change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveUpdate {
+    pub table: String,
+    pub action: LiveAction,
+    /// A JSON Patch (RFC 6902) from the record's previous state.
+    pub patch: serde_json::Value,
+}
+
+/// What a client receives, as a JSON text message, when its subscription
+/// is refused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveSubscribeError {
+    pub table: String,
+    pub error: String,
+}
+
+/// The live query on one table and the channels watching it.
+#[derive(Debug)]
+struct TableFeed {
+    live_id: String,
+    channels: BTreeSet<u32>,
+}
+
+/// Fans graphdb live-query changes out to websocket channels. See the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct LiveFanout {
+    db: GraphDb,
+    feeds: HashMap<String, TableFeed>,
+    /// The tables clients may subscribe to themselves.
+    allowed: BTreeSet<String>,
+}
+
+impl LiveFanout {
+    /// A fanout whose websocket clients may subscribe to `tables`.
+    pub fn new(db: GraphDb, tables: &[&str]) -> Self {
+        LiveFanout {
+            db,
+            feeds: HashMap::new(),
+            allowed: tables.iter().map(|table| table.to_string()).collect(),
+        }
+    }
+
+    /// Send changes to `table` to a channel, starting a live query on the
+    /// table if none is running.
+    pub fn subscribe(&mut self, channel_id: u32, table: &str) -> Result<(), ProcessLibError> {
+        if let Some(feed) = self.feeds.get_mut(table) {
+            feed.channels.insert(channel_id);
+            return Ok(());
+        }
+        let live_id = self.db.live_table(table, true)?;
+        self.feeds.insert(
+            table.to_string(),
+            TableFeed {
+                live_id,
+                channels: BTreeSet::from([channel_id]),
+            },
+        );
+        Ok(())
+    }
+
+    /// Stop sending changes to `table` to a channel, killing the table's
+    /// live query if no channel is left watching it.
+    pub fn unsubscribe(&mut self, channel_id: u32, table: &str) -> Result<(), ProcessLibError> {
+        let Some(feed) = self.feeds.get_mut(table) else {
+            return Ok(());
+        };
+        feed.channels.remove(&channel_id);
+        if feed.channels.is_empty() {
+            let feed = self.feeds.remove(table).unwrap();
+            self.db.kill(&feed.live_id)?;
+        }
+        Ok(())
+    }
+
+    /// Unsubscribe a channel from every table, e.g. once its socket closes.
+    pub fn remove_channel(&mut self, channel_id: u32) -> Result<(), ProcessLibError> {
+        let tables: Vec<String> = self
+            .feeds
+            .iter()
+            .filter(|(_, feed)| feed.channels.contains(&channel_id))
+            .map(|(table, _)| table.clone())
+            .collect();
+        for table in tables {
+            self.unsubscribe(channel_id, &table)?;
+        }
+        Ok(())
+    }
+
+    /// The channels watching a table.
+    pub fn channels(&self, table: &str) -> impl Iterator<Item = u32> + '_ {
+        self.feeds
+            .get(table)
+            .into_iter()
+            .flat_map(|feed| feed.channels.iter().copied())
+    }
+
+    /// Stop every live query.
+    pub fn close(&mut self) -> Result<(), ProcessLibError> {
+        for (_, feed) in self.feeds.drain() {
+            self.db.kill(&feed.live_id)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a message if it is one of ours, and return whether it was:
+    /// either a live notification for one of our tables, which is pushed to
+    /// the table's channels, or a [`LiveClientMessage`] on a websocket.
+    /// A websocket closing is unsubscribed from everything, but returns
+    /// `false` so the caller can forget the channel too.
+    pub fn handle(&mut self, message: &Message) -> Result<bool, ProcessLibError> {
+        if let Some(notification) = parse_live_notification(message) {
+            if notification.package_id != self.db.package_id || notification.db != self.db.db {
+                return Ok(false);
+            }
+            let Some((table, feed)) = self
+                .feeds
+                .iter()
+                .find(|(_, feed)| feed.live_id == notification.live_id)
+            else {
+                return Ok(false);
+            };
+            let update = LiveUpdate {
+                table: table.clone(),
+                action: notification.action,
+                patch: notification.result,
+            };
+            let bytes = serde_json::to_vec(&update)?;
+            for channel_id in &feed.channels {
+                send_ws_push(
+                    *channel_id,
+                    WsMessageType::Text,
+                    LazyLoadBlob {
+                        mime: Some("application/json".to_string()),
+                        bytes: bytes.clone(),
+                    },
+                );
+            }
+            return Ok(true);
+        }
+        if !message.is_request() || !message.source().is_process("http_server", "distro", "sys") {
+            return Ok(false);
+        }
+        match HttpServerRequest::from_bytes(message.body()) {
+            Ok(HttpServerRequest::WebSocketPush { channel_id, .. }) => {
+                let Some(blob) = get_blob() else {
+                    return Ok(false);
+                };
+                match serde_json::from_slice(&blob.bytes) {
+                    Ok(LiveClientMessage::Subscribe { table }) => {
+                        let result = if self.allowed.contains(&table) {
+                            self.subscribe(channel_id, &table)
+                                .map_err(|e| e.to_string())
+                        } else {
+                            Err("not a table clients may subscribe to".to_string())
+                        };
+                        if let Err(error) = result {
+                            refuse(channel_id, table, error)?;
+                        }
+                    }
+                    Ok(LiveClientMessage::Unsubscribe { table }) => {
+                        self.unsubscribe(channel_id, &table)?
+                    }
+                    Err(_) => return Ok(false),
+                }
+                Ok(true)
+            }
+            Ok(HttpServerRequest::WebSocketClose(channel_id)) => {
+                self.remove_channel(channel_id)?;
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Tell a client its subscription to `table` was refused.
+fn refuse(channel_id: u32, table: String, error: String) -> Result<(), ProcessLibError> {
+    send_ws_push(
+        channel_id,
+        WsMessageType::Text,
+        LazyLoadBlob {
+            mime: Some("application/json".to_string()),
+            bytes: serde_json::to_vec(&LiveSubscribeError { table, error })?,
+        },
+    );
+    Ok(())
+}
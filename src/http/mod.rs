@@ -11,6 +11,25 @@ use std::path::Path;
 use std::str::FromStr;
 use thiserror::Error;
 
+/// OAuth2 bearer tokens for the HTTP client, stored in kv.
+#[cfg(feature = "kv")]
+pub mod auth;
+/// Typed clients for JSON APIs, responses streamed in chunks, and resumable
+/// downloads.
+pub mod client;
+/// Bind paths by who may call them, and verify callers' identities.
+pub mod identity;
+/// Push graphdb live-query changes to websocket clients.
+#[cfg(feature = "graphdb")]
+pub mod live;
+/// Route bound HTTP paths to handlers by method and path pattern.
+pub mod router;
+/// Signed session cookies for web users, keyed by a secret stored in kv.
+#[cfg(feature = "kv")]
+pub mod session;
+/// One-way live updates to web clients with server-sent events.
+pub mod sse;
+
 //
 // these types are a copy of the types used in http module of runtime.
 //
@@ -58,6 +77,18 @@ pub struct HttpResponse {
     // BODY is stored in the lazy_load_blob, as bytes
 }
 
+/// Respond to an [`IncomingHttpRequest`] with this instead of an
+/// [`HttpResponse`] to send the status and headers but keep the connection
+/// open: the process writes the body in chunks with
+/// [`type@HttpServerAction::StreamPush`] and ends it with
+/// [`type@HttpServerAction::StreamClose`], using `stream_channel`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamingHttpResponse {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub stream_channel: u32,
+}
+
 /// Request type sent to `http_server:distro:sys` in order to configure it.
 /// You can also send [`type@HttpServerAction::WebSocketPush`], which
 /// allows you to push messages across an existing open WebSocket connection.
@@ -142,6 +173,11 @@ pub enum HttpServerAction {
     },
     /// Sending will close a socket the process controls.
     WebSocketClose(u32),
+    /// Write the lazy_load_blob bytes to a streaming response opened by
+    /// responding with a [`StreamingHttpResponse`].
+    StreamPush { channel_id: u32 },
+    /// End a streaming response, closing the connection.
+    StreamClose(u32),
 }
 
 /// The possible message types for WebSocketPush. Ping and Pong are limited to 125 bytes
@@ -270,6 +306,14 @@ impl IncomingHttpRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum HttpClientAction {
     Http(OutgoingHttpRequest),
+    /// Like `Http`, but the response is sent back in pieces, as
+    /// [`HttpClientRequest::Stream`] requests carrying `stream_id` and
+    /// `context`, as it arrives. Send it without expecting a response.
+    HttpStream {
+        request: OutgoingHttpRequest,
+        stream_id: u32,
+        context: Option<Vec<u8>>,
+    },
     WebSocketOpen {
         url: String,
         headers: HashMap<String, String>,
@@ -307,6 +351,28 @@ pub enum HttpClientRequest {
     WebSocketClose {
         channel_id: u32,
     },
+    /// A piece of a response to [`HttpClientAction::HttpStream`].
+    Stream {
+        stream_id: u32,
+        context: Option<Vec<u8>>,
+        part: HttpStreamPart,
+    },
+}
+
+/// The pieces a streamed HTTP response arrives in: one `Head`, any number of
+/// `Body` chunks, then `End`, or `Error` at any point.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum HttpStreamPart {
+    Head {
+        status: u16,
+        headers: HashMap<String, String>,
+    },
+    /// The chunk's bytes are in the lazy_load_blob.
+    Body,
+    End,
+    Error {
+        error: HttpClientError,
+    },
 }
 
 /// HTTP Client Response type that can be shared over WASM boundary to apps.
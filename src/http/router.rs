@@ -0,0 +1,637 @@
+use super::{
+    send_response, HttpServerError, HttpServerRequest, IncomingHttpRequest, Method, StatusCode,
+};
+use crate::{get_blob, Message};
+use serde::de::{
+    self, value::MapDeserializer, DeserializeOwned, Deserializer, IntoDeserializer, Visitor,
+};
+use std::collections::HashMap;
+
+/// What a route handler sends back: a status, optional headers, and a body.
+pub type RouteResponse = (StatusCode, Option<HashMap<String, String>>, Vec<u8>);
+
+type Handler<S> = Box<dyn Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError>>;
+
+/// A routing table for HTTP requests arriving on paths this process has
+/// bound. Register a handler per method and path pattern, then pass every
+/// incoming message to [`Router::handle()`]:
+///
+/// ```ignore
+/// let mut router = Router::new()
+///     .get("/api/items", list_items)
+///     .get("/api/items/:id", get_item)
+///     .post("/api/items", create_item);
+///
+/// loop {
+///     let message = await_message()?;
+///     if router.handle(&mut state, &message)? {
+///         continue;
+///     }
+///     // not an HTTP request: handle other messages here
+/// }
+///
+/// fn get_item(state: &mut State, req: &RouteRequest) -> Result<RouteResponse, RouteError> {
+///     let id: u64 = req.param("id").unwrap().parse()?;
+///     ...
+/// }
+/// ```
+///
+/// Patterns are matched segment by segment: `:name` matches any one segment
+/// and captures it, and a final `*` matches the rest of the path. A path that
+/// matches no pattern gets a 404, and one that matches with the wrong method
+/// gets a 405. A handler that returns an error gets the error's status,
+/// see [`RouteError`].
+///
+/// For a web frontend served from another origin, add [`Router::with_cors()`].
+/// To keep handlers of public endpoints from working on huge bodies, set
+/// [`Router::max_body_bytes()`] and [`Router::limit_body()`].
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+    cors: Option<Cors>,
+    max_body_bytes: Option<usize>,
+    body_limits: Vec<(Vec<String>, usize)>,
+}
+
+/// Why a route handler failed, sent back as the response's status and body.
+/// Any error converts into one with `?` and gets a 500, except a
+/// [`PayloadTooLarge`], which gets a 413. Use [`RouteError::new()`] to
+/// answer with another status, e.g. a 400 for a malformed body.
+#[derive(Debug)]
+pub struct RouteError {
+    pub status: StatusCode,
+    error: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl RouteError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        RouteError {
+            status,
+            error: message.into().into(),
+        }
+    }
+}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<E> for RouteError {
+    fn from(error: E) -> Self {
+        let error: Box<dyn std::error::Error + Send + Sync> = Box::new(error);
+        let status = match error.downcast_ref::<PayloadTooLarge>() {
+            Some(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        RouteError { status, error }
+    }
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+/// A request body over a size limit. Requests over a [`Router`]'s limits
+/// get a 413 without reaching a handler; a handler with its own limit can
+/// return this as its error to get the same response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadTooLarge {
+    pub limit: usize,
+    /// The size of the body, or the size its `Content-Length` claimed.
+    pub size: usize,
+}
+
+impl std::fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "request body of {} bytes is over the limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
+impl PayloadTooLarge {
+    /// Check a body's size against a limit.
+    pub fn check(body: &[u8], limit: usize) -> Result<(), Self> {
+        if body.len() > limit {
+            return Err(PayloadTooLarge {
+                limit,
+                size: body.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The 413 response for this rejection.
+    pub fn response(&self) -> RouteResponse {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            None,
+            self.to_string().into_bytes(),
+        )
+    }
+}
+
+/// Which cross-origin requests a [`Router`] allows, set with [`Router::with_cors()`].
+#[derive(Debug, Clone)]
+pub struct Cors {
+    /// Allowed origins, e.g. `https://app.example.com`, or `*` for any.
+    pub origins: Vec<String>,
+    pub methods: Vec<Method>,
+    /// Request headers the frontend may send, e.g. `content-type`.
+    pub headers: Vec<String>,
+    /// How long, in seconds, a browser may cache a preflight's answer.
+    pub max_age: u64,
+}
+
+impl Cors {
+    /// The origin to allow for a request from `origin`, if it is allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            Some("*".to_string())
+        } else if self.origins.iter().any(|allowed| allowed == origin) {
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Headers to add to an ordinary response to a request from `origin`.
+    fn response_headers(&self, origin: &str) -> Option<HashMap<String, String>> {
+        let allow_origin = self.allow_origin(origin)?;
+        let mut headers = HashMap::from([(
+            "Access-Control-Allow-Origin".to_string(),
+            allow_origin.clone(),
+        )]);
+        if allow_origin != "*" {
+            headers.insert("Vary".to_string(), "Origin".to_string());
+        }
+        Some(headers)
+    }
+
+    /// Headers answering a preflight from `origin`.
+    fn preflight_headers(&self, origin: &str) -> Option<HashMap<String, String>> {
+        let mut headers = self.response_headers(origin)?;
+        let methods: Vec<&str> = self.methods.iter().map(|method| method.as_str()).collect();
+        headers.insert(
+            "Access-Control-Allow-Methods".to_string(),
+            methods.join(", "),
+        );
+        if !self.headers.is_empty() {
+            headers.insert(
+                "Access-Control-Allow-Headers".to_string(),
+                self.headers.join(", "),
+            );
+        }
+        headers.insert(
+            "Access-Control-Max-Age".to_string(),
+            self.max_age.to_string(),
+        );
+        Some(headers)
+    }
+}
+
+struct Route<S> {
+    method: Method,
+    pattern: Vec<String>,
+    handler: Handler<S>,
+}
+
+/// An incoming HTTP request, with the parameters its route captured.
+pub struct RouteRequest {
+    pub request: IncomingHttpRequest,
+    /// The request body, taken from the message blob.
+    pub body: Vec<u8>,
+    params: HashMap<String, String>,
+}
+
+impl RouteRequest {
+    /// A path parameter captured by a `:name` segment, or `*` for the rest of the path.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|s| s.as_str())
+    }
+    /// Deserialize the path parameters into a struct. Each parameter is
+    /// parsed as its field's type, so `7` fills a `u64` or a `String` field.
+    pub fn path_params<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        from_string_map(&self.params)
+    }
+    /// Deserialize the query parameters into a struct, in the same way as
+    /// [`RouteRequest::path_params()`].
+    pub fn query<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        from_string_map(self.request.query_params())
+    }
+    /// Deserialize the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Self {
+        Router {
+            routes: vec![],
+            cors: None,
+            max_body_bytes: None,
+            body_limits: vec![],
+        }
+    }
+
+    /// Reject request bodies over `max_bytes` on every route without a
+    /// limit of its own from [`Router::limit_body()`].
+    ///
+    /// The limit is advisory: `http_server` has no per-path limit, so it
+    /// still accepts the whole body from the client and passes it to this
+    /// process. The limit only keeps the body from reaching a handler, and,
+    /// when `Content-Length` is over it, from being read out of the blob.
+    pub fn max_body_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_body_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Reject request bodies over `max_bytes` on routes registered with
+    /// exactly `pattern`, whatever their method.
+    pub fn limit_body(mut self, pattern: &str, max_bytes: usize) -> Self {
+        self.body_limits.push((split_path(pattern), max_bytes));
+        self
+    }
+
+    /// Allow cross-origin requests from `origins` (or `"*"` for any). OPTIONS
+    /// preflights are answered without reaching a handler, and every response
+    /// to an allowed origin gets its CORS headers added.
+    pub fn with_cors(mut self, origins: &[&str], methods: &[Method], headers: &[&str]) -> Self {
+        self.cors = Some(Cors {
+            origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            methods: methods.to_vec(),
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            max_age: 600,
+        });
+        self
+    }
+
+    /// Register a handler for `method` requests to paths matching `pattern`.
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError> + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: split_path(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+    pub fn get<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError> + 'static,
+    {
+        self.route(Method::GET, pattern, handler)
+    }
+    pub fn post<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError> + 'static,
+    {
+        self.route(Method::POST, pattern, handler)
+    }
+    pub fn put<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError> + 'static,
+    {
+        self.route(Method::PUT, pattern, handler)
+    }
+    pub fn patch<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError> + 'static,
+    {
+        self.route(Method::PATCH, pattern, handler)
+    }
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut S, &RouteRequest) -> Result<RouteResponse, RouteError> + 'static,
+    {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Route an incoming message. Returns `false`, sending nothing, if it is
+    /// not an HTTP request from `http_server`; otherwise responds to it and
+    /// returns `true`.
+    pub fn handle(&self, state: &mut S, message: &Message) -> Result<bool, HttpServerError> {
+        if !message.is_request() || !message.source().is_process("http_server", "distro", "sys") {
+            return Ok(false);
+        }
+        let Ok(HttpServerRequest::Http(request)) = HttpServerRequest::from_bytes(message.body())
+        else {
+            return Ok(false);
+        };
+        let bad_request = |e: anyhow::Error| HttpServerError::BadRequest { req: e.to_string() };
+        let method = request.method().map_err(bad_request)?;
+        let path = split_path(&request.path().map_err(bad_request)?);
+
+        let origin = request
+            .headers()
+            .get("origin")
+            .and_then(|origin| origin.to_str().ok())
+            .map(|origin| origin.to_string());
+        let cors = self.cors.as_ref().zip(origin.as_deref());
+        if method == Method::OPTIONS {
+            if let Some((cors, origin)) = cors {
+                send_response(
+                    StatusCode::NO_CONTENT,
+                    cors.preflight_headers(origin),
+                    vec![],
+                );
+                return Ok(true);
+            }
+        }
+        let cors_headers = cors.and_then(|(cors, origin)| cors.response_headers(origin));
+        let respond = |status, headers: Option<HashMap<String, String>>, body| {
+            let headers = match (headers, &cors_headers) {
+                (Some(mut headers), Some(cors_headers)) => {
+                    headers.extend(cors_headers.clone());
+                    Some(headers)
+                }
+                (headers, cors_headers) => headers.or_else(|| cors_headers.clone()),
+            };
+            send_response(status, headers, body)
+        };
+
+        let mut allowed = vec![];
+        for route in &self.routes {
+            let Some(params) = match_path(&route.pattern, &path) else {
+                continue;
+            };
+            if route.method != method {
+                allowed.push(route.method.to_string());
+                continue;
+            }
+            let limit = self
+                .body_limits
+                .iter()
+                .find(|(pattern, _)| *pattern == route.pattern)
+                .map(|(_, limit)| *limit)
+                .or(self.max_body_bytes);
+            // a claimed length over the limit is rejected without fetching the blob
+            let claimed = request
+                .headers()
+                .get("content-length")
+                .and_then(|length| length.to_str().ok()?.parse::<usize>().ok());
+            if let (Some(limit), Some(size)) = (limit, claimed) {
+                if size > limit {
+                    let (status, headers, body) = PayloadTooLarge { limit, size }.response();
+                    respond(status, headers, body);
+                    return Ok(true);
+                }
+            }
+            let body = get_blob().unwrap_or_default().bytes;
+            if let Some(limit) = limit {
+                if let Err(too_large) = PayloadTooLarge::check(&body, limit) {
+                    let (status, headers, body) = too_large.response();
+                    respond(status, headers, body);
+                    return Ok(true);
+                }
+            }
+            let request = RouteRequest {
+                request,
+                body,
+                params,
+            };
+            match (route.handler)(state, &request) {
+                Ok((status, headers, body)) => respond(status, headers, body),
+                Err(e) => respond(e.status, None, e.to_string().into_bytes()),
+            }
+            return Ok(true);
+        }
+
+        if allowed.is_empty() {
+            respond(StatusCode::NOT_FOUND, None, vec![]);
+        } else {
+            respond(
+                StatusCode::METHOD_NOT_ALLOWED,
+                Some(HashMap::from([("Allow".to_string(), allowed.join(", "))])),
+                vec![],
+            );
+        }
+        Ok(true)
+    }
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_string())
+        .collect()
+}
+
+fn match_path(pattern: &[String], path: &[String]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    for (i, segment) in pattern.iter().enumerate() {
+        if segment == "*" && i == pattern.len() - 1 {
+            params.insert("*".to_string(), path.get(i..)?.join("/"));
+            return Some(params);
+        }
+        let part = path.get(i)?;
+        match segment.strip_prefix(':') {
+            Some(name) => {
+                params.insert(name.to_string(), part.clone());
+            }
+            None if segment == part => {}
+            None => return None,
+        }
+    }
+    (pattern.len() == path.len()).then_some(params)
+}
+
+/// Deserialize a map of strings, parsing each value as the type its field
+/// asks for.
+fn from_string_map<T: DeserializeOwned>(map: &HashMap<String, String>) -> serde_json::Result<T> {
+    T::deserialize(MapDeserializer::<_, serde_json::Error>::new(
+        map.iter().map(|(k, v)| (k.as_str(), StrValue(v.as_str()))),
+    ))
+}
+
+/// A value from a map of strings: a string, or a number or boolean parsed
+/// from it if that is what the field is.
+struct StrValue<'a>(&'a str);
+
+impl StrValue<'_> {
+    fn parse<T>(&self) -> serde_json::Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.0
+            .parse()
+            .map_err(|e| de::Error::custom(format!("{:?}: {}", self.0, e)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for StrValue<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> serde_json::Result<V::Value> {
+            visitor.$visit(self.parse()?)
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for StrValue<'de> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> serde_json::Result<V::Value> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    deserialize_parsed!(
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> serde_json::Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> serde_json::Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> serde_json::Result<V::Value> {
+        visitor.visit_enum(IntoDeserializer::<serde_json::Error>::into_deserializer(
+            self.0,
+        ))
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+        match_path(&split_path(pattern), &split_path(path))
+    }
+
+    #[test]
+    fn splits_paths_ignoring_empty_segments() {
+        assert_eq!(split_path("/api//items/"), ["api", "items"]);
+        assert!(split_path("/").is_empty());
+    }
+
+    #[test]
+    fn matches_literal_segments() {
+        assert_eq!(matches("/api/items", "/api/items"), Some(HashMap::new()));
+        assert_eq!(matches("/api/items", "/api/items/"), Some(HashMap::new()));
+        assert_eq!(matches("/api/items", "/api/other"), None);
+        assert_eq!(matches("/api/items", "/api"), None);
+        assert_eq!(matches("/api/items", "/api/items/1"), None);
+        assert_eq!(matches("/", "/"), Some(HashMap::new()));
+    }
+
+    #[test]
+    fn captures_params() {
+        let params = matches("/api/items/:id/tags/:tag", "/api/items/7/tags/red").unwrap();
+        assert_eq!(params["id"], "7");
+        assert_eq!(params["tag"], "red");
+        assert_eq!(matches("/api/items/:id", "/api/items"), None);
+    }
+
+    #[test]
+    fn wildcard_captures_the_rest() {
+        let params = matches("/static/*", "/static/css/site.css").unwrap();
+        assert_eq!(params["*"], "css/site.css");
+        assert_eq!(matches("/static/*", "/static").unwrap()["*"], "");
+        assert_eq!(matches("/static/*", "/other/site.css"), None);
+        // only a final `*` is a wildcard
+        assert_eq!(matches("/*/x", "/a/x"), None);
+        assert!(matches("/*/x", "/*/x").is_some());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Params {
+        id: u64,
+        name: String,
+    }
+
+    #[test]
+    fn deserializes_string_maps() {
+        let map = HashMap::from([
+            ("id".to_string(), "7".to_string()),
+            ("name".to_string(), "7".to_string()),
+        ]);
+        let params: Params = from_string_map(&map).unwrap();
+        assert_eq!((params.id, params.name.as_str()), (7, "7"));
+        let map = HashMap::from([
+            ("id".to_string(), "7".to_string()),
+            ("name".to_string(), "bob".to_string()),
+        ]);
+        let params: Params = from_string_map(&map).unwrap();
+        assert_eq!((params.id, params.name.as_str()), (7, "bob"));
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Query {
+        limit: Option<u32>,
+        verbose: bool,
+    }
+
+    #[test]
+    fn parses_string_map_values_per_field() {
+        let map = HashMap::from([("verbose".to_string(), "true".to_string())]);
+        let query: Query = from_string_map(&map).unwrap();
+        assert_eq!((query.limit, query.verbose), (None, true));
+        let map = HashMap::from([
+            ("limit".to_string(), "10".to_string()),
+            ("verbose".to_string(), "false".to_string()),
+        ]);
+        let query: Query = from_string_map(&map).unwrap();
+        assert_eq!((query.limit, query.verbose), (Some(10), false));
+        let map = HashMap::from([
+            ("limit".to_string(), "ten".to_string()),
+            ("verbose".to_string(), "false".to_string()),
+        ]);
+        assert!(from_string_map::<Query>(&map).is_err());
+    }
+
+    #[test]
+    fn handler_errors_carry_a_status() {
+        let too_large: RouteError = PayloadTooLarge { limit: 1, size: 2 }.into();
+        assert_eq!(too_large.status, StatusCode::PAYLOAD_TOO_LARGE);
+        let parse: RouteError = "x".parse::<u64>().unwrap_err().into();
+        assert_eq!(parse.status, StatusCode::INTERNAL_SERVER_ERROR);
+        let bad = RouteError::new(StatusCode::BAD_REQUEST, "no name");
+        assert_eq!(bad.to_string(), "no name");
+    }
+}
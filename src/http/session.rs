@@ -0,0 +1,237 @@
+use super::IncomingHttpRequest;
+use crate::kv::{Kv, KvError};
+use crate::time::Timestamp;
+use crate::{ModuleError, ProcessLibError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The cookie [`IncomingHttpRequest::session()`] reads by default.
+pub const SESSION_COOKIE: &str = "session";
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("session: no session cookie")]
+    Missing,
+    #[error("session: cookie is malformed")]
+    Malformed,
+    #[error("session: cookie signature is invalid")]
+    BadSignature,
+    #[error("session: session has expired")]
+    Expired,
+    #[error("session: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// What a session cookie carries, before signing.
+#[derive(Serialize, Deserialize)]
+struct SessionPayload<T> {
+    data: T,
+    expires_at: Timestamp,
+}
+
+/// Issues and validates signed session cookies. A cookie holds any
+/// serializable session data and an expiry, signed with HMAC-SHA256 under a
+/// secret only this process knows, so users can read their session but not
+/// forge or alter it. Do not put anything secret in session data.
+///
+/// ```ignore
+/// let sessions = SessionSigner::load_or_create(&kv)?;
+/// // after a user logs in
+/// let cookie = sessions.set_cookie(SESSION_COOKIE, &User { name }, Duration::from_secs(86400))?;
+/// send_response(StatusCode::OK, Some(HashMap::from([("Set-Cookie".into(), cookie)])), vec![]);
+/// // on later requests
+/// let user: User = request.session(&sessions)?;
+/// ```
+#[derive(Clone)]
+pub struct SessionSigner {
+    secret: Vec<u8>,
+}
+
+impl SessionSigner {
+    /// Sign with an app-provided secret. It should be at least 32 random bytes.
+    pub fn new(secret: Vec<u8>) -> Self {
+        SessionSigner { secret }
+    }
+
+    /// Sign with a secret stored in `kv`, generating and storing one if
+    /// there is none yet. Removing the secret invalidates every session.
+    pub fn load_or_create(kv: &Kv) -> Result<Self, ProcessLibError> {
+        let key = b"session:secret".to_vec();
+        let secret = match kv.get(key.clone()) {
+            Ok(secret) => secret,
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {
+                let secret = rand::random::<[u8; 32]>().to_vec();
+                kv.set(key, secret.clone(), None)?;
+                secret
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(SessionSigner::new(secret))
+    }
+
+    /// Sign session data into a cookie value that expires after `max_age`.
+    pub fn issue<T: Serialize>(&self, data: &T, max_age: Duration) -> serde_json::Result<String> {
+        let payload = serde_json::to_vec(&SessionPayload {
+            data,
+            expires_at: Timestamp::now() + max_age,
+        })?;
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    /// Check a cookie value's signature and expiry, and return its session data.
+    pub fn validate<T: DeserializeOwned>(&self, value: &str) -> Result<T, SessionError> {
+        let (payload, signature) = value.split_once('.').ok_or(SessionError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| SessionError::Malformed)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| SessionError::BadSignature)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| SessionError::Malformed)?;
+        let payload = serde_json::from_slice::<SessionPayload<T>>(&payload)?;
+        if payload.expires_at.has_passed() {
+            return Err(SessionError::Expired);
+        }
+        Ok(payload.data)
+    }
+
+    /// A `Set-Cookie` header value that stores a new session in cookie `name`.
+    pub fn set_cookie<T: Serialize>(
+        &self,
+        name: &str,
+        data: &T,
+        max_age: Duration,
+    ) -> serde_json::Result<String> {
+        Ok(format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+            name,
+            self.issue(data, max_age)?,
+            max_age.as_secs(),
+        ))
+    }
+
+    /// A `Set-Cookie` header value that removes cookie `name`, logging the user out.
+    pub fn clear_cookie(name: &str) -> String {
+        format!(
+            "{}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax",
+            name
+        )
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        // HMAC accepts keys of any length, so this cannot fail
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+impl IncomingHttpRequest {
+    /// Get the value of a cookie sent with this request.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    /// Validate the [`SESSION_COOKIE`] sent with this request and return its
+    /// session data.
+    pub fn session<T: DeserializeOwned>(&self, signer: &SessionSigner) -> Result<T, SessionError> {
+        let cookie = self.cookie(SESSION_COOKIE).ok_or(SessionError::Missing)?;
+        signer.validate(&cookie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(secret: &[u8]) -> SessionSigner {
+        SessionSigner::new(secret.to_vec())
+    }
+
+    #[test]
+    fn validates_what_it_issued() {
+        let sessions = signer(b"secret");
+        let cookie = sessions.issue(&"alice", Duration::from_secs(60)).unwrap();
+        assert_eq!(sessions.validate::<String>(&cookie).unwrap(), "alice");
+    }
+
+    #[test]
+    fn rejects_expired_sessions() {
+        let sessions = signer(b"secret");
+        let cookie = sessions.issue(&"alice", Duration::ZERO).unwrap();
+        assert!(matches!(
+            sessions.validate::<String>(&cookie),
+            Err(SessionError::Expired)
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_payloads() {
+        let sessions = signer(b"secret");
+        let cookie = sessions.issue(&"alice", Duration::from_secs(60)).unwrap();
+        let (_, signature) = cookie.split_once('.').unwrap();
+        let forged = sessions.issue(&"admin", Duration::from_secs(60)).unwrap();
+        let (payload, _) = forged.split_once('.').unwrap();
+        assert!(matches!(
+            sessions.validate::<String>(&format!("{}.{}", payload, signature)),
+            Err(SessionError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_sessions_signed_with_another_key() {
+        let cookie = signer(b"secret")
+            .issue(&"alice", Duration::from_secs(60))
+            .unwrap();
+        assert!(matches!(
+            signer(b"other").validate::<String>(&cookie),
+            Err(SessionError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_cookies() {
+        let sessions = signer(b"secret");
+        for value in ["", "no-dot", "payload.not base64!"] {
+            assert!(
+                matches!(
+                    sessions.validate::<String>(value),
+                    Err(SessionError::Malformed)
+                ),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn cookies_carry_the_session_and_its_age() {
+        let sessions = signer(b"secret");
+        let header = sessions
+            .set_cookie(SESSION_COOKIE, &"alice", Duration::from_secs(60))
+            .unwrap();
+        let value = header
+            .strip_prefix("session=")
+            .and_then(|rest| rest.split_once(';'))
+            .map(|(value, _)| value)
+            .unwrap();
+        assert_eq!(sessions.validate::<String>(value).unwrap(), "alice");
+        assert!(header.contains("Max-Age=60;"));
+        assert!(SessionSigner::clear_cookie("session").starts_with("session=;"));
+    }
+}
@@ -0,0 +1,97 @@
+use super::{HttpServerAction, StreamingHttpResponse};
+use crate::{ProcessLibError, Request as KiRequest, Response as KiResponse};
+use std::collections::HashMap;
+
+/// Respond to the HTTP request currently being handled by opening a stream
+/// of server-sent events, and return a handle to send events on it.
+///
+/// `channel_id` identifies the stream to `http_server` and must not be
+/// shared with any other stream this process has open.
+///
+/// ```ignore
+/// // in the handler for a GET on a bound path
+/// let stream = http::sse::start_sse(next_channel_id)?;
+/// streams.push(stream);
+/// // later, whenever something changes
+/// for stream in &streams {
+///     stream.send_json(Some("update"), &dashboard)?;
+/// }
+/// ```
+pub fn start_sse(channel_id: u32) -> Result<SseStream, ProcessLibError> {
+    KiResponse::new()
+        .body(serde_json::to_vec(&StreamingHttpResponse {
+            status: 200,
+            headers: HashMap::from([
+                ("Content-Type".to_string(), "text/event-stream".to_string()),
+                ("Cache-Control".to_string(), "no-cache".to_string()),
+            ]),
+            stream_channel: channel_id,
+        })?)
+        .send()?;
+    Ok(SseStream { channel_id })
+}
+
+/// An open server-sent events stream, from [`start_sse()`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SseStream {
+    pub channel_id: u32,
+}
+
+impl SseStream {
+    /// Send an event. Clients listening with `EventSource.addEventListener(name)`
+    /// receive named events; events with no name go to `onmessage`. A name
+    /// may not contain a line break, which would end the `event:` field.
+    pub fn send_event(&self, name: Option<&str>, data: &str) -> Result<(), ProcessLibError> {
+        let mut event = String::new();
+        if let Some(name) = name {
+            if name.contains(['\n', '\r']) {
+                return Err(ProcessLibError::InvalidInput {
+                    error: format!("sse event name {:?} contains a line break", name),
+                });
+            }
+            event.push_str(&format!("event: {}\n", name));
+        }
+        // a multi-line payload must be split across data fields, and a bare
+        // `\r` ends a line too
+        for line in data.split(['\n', '\r']) {
+            event.push_str(&format!("data: {}\n", line));
+        }
+        event.push('\n');
+        self.push(event)
+    }
+
+    /// Send an event whose data is `data` serialized as JSON.
+    pub fn send_json<T: serde::Serialize>(
+        &self,
+        name: Option<&str>,
+        data: &T,
+    ) -> Result<(), ProcessLibError> {
+        self.send_event(name, &serde_json::to_string(data)?)
+    }
+
+    /// Send a comment line, which clients ignore. Sending one every few
+    /// seconds keeps proxies from closing an idle stream.
+    pub fn keep_alive(&self) -> Result<(), ProcessLibError> {
+        self.push(":\n\n".to_string())
+    }
+
+    /// End the stream.
+    pub fn close(self) -> Result<(), ProcessLibError> {
+        KiRequest::to(("our", "http_server", "distro", "sys"))
+            .body(serde_json::to_vec(&HttpServerAction::StreamClose(
+                self.channel_id,
+            ))?)
+            .send()?;
+        Ok(())
+    }
+
+    fn push(&self, event: String) -> Result<(), ProcessLibError> {
+        KiRequest::to(("our", "http_server", "distro", "sys"))
+            .body(serde_json::to_vec(&HttpServerAction::StreamPush {
+                channel_id: self.channel_id,
+            })?)
+            .blob_bytes(event)
+            .send()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use crate::time::now_millis;
+use serde::{Deserialize, Serialize};
+
+/// Crockford's base32 alphabet, as used by ULIDs.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A ULID: 48 bits of millisecond timestamp followed by 80 random bits.
+/// ULIDs sort by creation millisecond both as bytes and as strings, which makes
+/// them good keys for kv and graphdb records that are read back in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ulid(u128);
+
+impl Ulid {
+    /// Generate a new ULID from the current time.
+    pub fn new() -> Self {
+        Self::from_parts(now_millis(), rand::random())
+    }
+    /// Build a ULID from a millisecond timestamp and random bits.
+    /// Only the low 48 bits of `millis` and the low 80 bits of `random` are used.
+    pub fn from_parts(millis: u64, random: u128) -> Self {
+        let millis = (millis as u128) & ((1 << 48) - 1);
+        let random = random & ((1 << 80) - 1);
+        Ulid((millis << 80) | random)
+    }
+    /// The millisecond timestamp this ULID was generated at.
+    pub fn timestamp_millis(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+    /// The 16 big-endian bytes of this ULID, for use as a compact kv key.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Ulid(u128::from_be_bytes(bytes))
+    }
+    /// This ULID as a kv key. Keys generated in a later millisecond sort after
+    /// keys generated earlier; within the same millisecond their order is random.
+    pub fn as_kv_key(&self) -> Vec<u8> {
+        self.to_bytes().to_vec()
+    }
+    /// This ULID as a SurrealDB record id in the given table, e.g. `user:⟨01HN...⟩`.
+    pub fn as_record_id(&self, table: &str) -> String {
+        format!("{}:⟨{}⟩", table, self)
+    }
+}
+
+impl Default for Ulid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for Ulid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = [0u8; 26];
+        for (i, c) in out.iter_mut().enumerate() {
+            let shift = 5 * (25 - i);
+            *c = CROCKFORD[((self.0 >> shift) & 0x1f) as usize];
+        }
+        // the alphabet is ASCII, so this cannot fail
+        write!(f, "{}", std::str::from_utf8(&out).unwrap())
+    }
+}
+
+impl std::str::FromStr for Ulid {
+    type Err = IdParseError;
+    /// Parse a ULID from its 26-character Crockford base32 form.
+    /// Lowercase letters are accepted.
+    fn from_str(input: &str) -> Result<Self, IdParseError> {
+        if input.len() != 26 {
+            return Err(IdParseError::BadLength);
+        }
+        let mut value: u128 = 0;
+        for (i, c) in input.bytes().enumerate() {
+            let digit = CROCKFORD
+                .iter()
+                .position(|a| *a == c.to_ascii_uppercase())
+                .ok_or(IdParseError::InvalidCharacter)?;
+            // the first character only carries 3 bits
+            if i == 0 && digit > 7 {
+                return Err(IdParseError::Overflow);
+            }
+            value = (value << 5) | digit as u128;
+        }
+        Ok(Ulid(value))
+    }
+}
+
+impl Serialize for Ulid {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'a> Deserialize<'a> for Ulid {
+    fn deserialize<D>(deserializer: D) -> Result<Ulid, D::Error>
+    where
+        D: serde::de::Deserializer<'a>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A version 7 UUID: 48 bits of millisecond timestamp, then random bits, with
+/// the version and variant fields set. Like [`Ulid`]s, these sort by creation
+/// millisecond.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UuidV7(u128);
+
+impl UuidV7 {
+    /// Generate a new UUIDv7 from the current time.
+    pub fn new() -> Self {
+        Self::from_parts(now_millis(), rand::random())
+    }
+    /// Build a UUIDv7 from a millisecond timestamp and random bits.
+    /// Only the low 48 bits of `millis` and 74 bits of `random` are used.
+    pub fn from_parts(millis: u64, random: u128) -> Self {
+        let millis = (millis as u128) & ((1 << 48) - 1);
+        let rand_a = (random >> 62) & 0xfff;
+        let rand_b = random & ((1 << 62) - 1);
+        UuidV7((millis << 80) | (0x7 << 76) | (rand_a << 64) | (0b10 << 62) | rand_b)
+    }
+    /// The millisecond timestamp this UUID was generated at.
+    pub fn timestamp_millis(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+    /// The 16 big-endian bytes of this UUID, for use as a compact kv key.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        UuidV7(u128::from_be_bytes(bytes))
+    }
+    /// This UUID as a kv key. Keys generated in a later millisecond sort after
+    /// keys generated earlier; within the same millisecond their order is random.
+    pub fn as_kv_key(&self) -> Vec<u8> {
+        self.to_bytes().to_vec()
+    }
+    /// This UUID as a SurrealDB record id in the given table, e.g. `user:⟨0190...⟩`.
+    pub fn as_record_id(&self, table: &str) -> String {
+        format!("{}:⟨{}⟩", table, self)
+    }
+}
+
+impl Default for UuidV7 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for UuidV7 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let v = self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            (v >> 96) as u32,
+            ((v >> 80) & 0xffff) as u16,
+            ((v >> 64) & 0xffff) as u16,
+            ((v >> 48) & 0xffff) as u16,
+            (v & 0xffff_ffff_ffff) as u64,
+        )
+    }
+}
+
+impl std::str::FromStr for UuidV7 {
+    type Err = IdParseError;
+    /// Parse a UUIDv7 from its hyphenated hex form, `8-4-4-4-12` digits.
+    /// Uppercase digits are accepted; other UUID versions are not.
+    fn from_str(input: &str) -> Result<Self, IdParseError> {
+        if input.len() != 36 {
+            return Err(IdParseError::BadLength);
+        }
+        let mut value: u128 = 0;
+        for (i, c) in input.bytes().enumerate() {
+            if matches!(i, 8 | 13 | 18 | 23) {
+                if c != b'-' {
+                    return Err(IdParseError::InvalidCharacter);
+                }
+                continue;
+            }
+            let digit = (c as char)
+                .to_digit(16)
+                .ok_or(IdParseError::InvalidCharacter)?;
+            value = (value << 4) | digit as u128;
+        }
+        if (value >> 76) & 0xf != 0x7 || (value >> 62) & 0b11 != 0b10 {
+            return Err(IdParseError::WrongVersion);
+        }
+        Ok(UuidV7(value))
+    }
+}
+
+impl Serialize for UuidV7 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'a> Deserialize<'a> for UuidV7 {
+    fn deserialize<D>(deserializer: D) -> Result<UuidV7, D::Error>
+    where
+        D: serde::de::Deserializer<'a>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Error type for parsing a [`Ulid`] or [`UuidV7`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdParseError {
+    BadLength,
+    InvalidCharacter,
+    Overflow,
+    /// A UUID that is not a version 7, RFC 4122 variant UUID.
+    WrongVersion,
+}
+
+impl std::fmt::Display for IdParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                IdParseError::BadLength => "Wrong length for id string",
+                IdParseError::InvalidCharacter => "Invalid character in id string",
+                IdParseError::Overflow => "Id string out of range",
+                IdParseError::WrongVersion => "UUID is not version 7",
+            }
+        )
+    }
+}
+
+impl std::error::Error for IdParseError {
+    fn description(&self) -> &str {
+        match self {
+            IdParseError::BadLength => "Wrong length for id string",
+            IdParseError::InvalidCharacter => "Invalid character in id string",
+            IdParseError::Overflow => "Id string out of range",
+            IdParseError::WrongVersion => "UUID is not version 7",
+        }
+    }
+}
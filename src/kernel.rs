@@ -0,0 +1,21 @@
+use crate::kernel_types::{KernelCommand, KernelResponse};
+use crate::{Message, ProcessId, ProcessLibError, Request};
+
+/// Kill a process on this node immediately. Messages it has not handled
+/// are dropped.
+pub fn kill_process(process: &ProcessId) -> Result<(), ProcessLibError> {
+    let res = Request::new()
+        .target(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(&KernelCommand::KillProcess(
+            process.clone(),
+        ))?)
+        .send_and_await_response(5)??;
+
+    match res {
+        Message::Response { body, .. } => match serde_json::from_slice::<KernelResponse>(&body)? {
+            KernelResponse::KilledProcess(_) => Ok(()),
+            response => Err(ProcessLibError::unexpected(response)),
+        },
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
@@ -0,0 +1,294 @@
+//! Encode tuples of values into kv keys whose byte order matches the order
+//! of the values, so that range scans over composite keys come back in
+//! logical order: `(user, 9)` before `(user, 10)`, unlike with `format!`.
+//!
+//! ```
+//! # use kinode_process_lib::{keycode, time::Timestamp};
+//! let placed_at = Timestamp::from_millis(1_700_000_000_000);
+//! let key = keycode::encode(&("orders", "alice", placed_at));
+//! // `kv.scan_prefix(&prefix)` returns every order of alice's, oldest first
+//! let prefix = keycode::encode(&("orders", "alice"));
+//! assert!(key.starts_with(&prefix));
+//! assert!(keycode::encode(&("user", 9u64)) < keycode::encode(&("user", 10u64)));
+//! let (_, _, at): (String, String, Timestamp) = keycode::decode(&key).unwrap();
+//! assert_eq!(at, placed_at);
+//! ```
+//!
+//! A tuple's encoding starts with the encoding of each shorter tuple of its
+//! leading values, so those work as prefixes for scans. Integers are
+//! fixed-width big-endian, with the sign bit flipped for signed ones, and
+//! strings and bytes escape each `0x00` as `0x00 0xFF` and end in
+//! `0x00 0x01`, so no value is a prefix of a greater one.
+use crate::time::Timestamp;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum KeycodeError {
+    #[error("keycode: key ends in the middle of a value")]
+    Truncated,
+    #[error("keycode: string in key is not valid UTF-8")]
+    NotUtf8,
+    #[error("keycode: bad escape in string or bytes")]
+    BadEscape,
+    #[error("keycode: {0} bytes left over after the key")]
+    TrailingBytes(usize),
+}
+
+/// A value that can be part of an ordered key.
+pub trait EncodeKey {
+    /// Append this value's encoding to `out`.
+    fn encode_into(&self, out: &mut Vec<u8>);
+}
+
+/// A value that can be read back from an ordered key.
+pub trait DecodeKey: Sized {
+    /// Read a value from the front of `input`, advancing past it.
+    fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError>;
+}
+
+/// Encode a value, usually a tuple, as a key.
+pub fn encode<K: EncodeKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut out = vec![];
+    key.encode_into(&mut out);
+    out
+}
+
+/// Decode a whole key, e.g. one returned by a scan.
+pub fn decode<K: DecodeKey>(mut bytes: &[u8]) -> Result<K, KeycodeError> {
+    let key = K::decode_from(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(KeycodeError::TrailingBytes(bytes.len()));
+    }
+    Ok(key)
+}
+
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], KeycodeError> {
+    if input.len() < N {
+        return Err(KeycodeError::Truncated);
+    }
+    let (head, rest) = input.split_at(N);
+    *input = rest;
+    Ok(head.try_into().unwrap())
+}
+
+macro_rules! unsigned_key_part {
+    ($($t:ty),*) => {$(
+        impl EncodeKey for $t {
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+        }
+        impl DecodeKey for $t {
+            fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+                Ok(<$t>::from_be_bytes(take(input)?))
+            }
+        }
+    )*};
+}
+
+unsigned_key_part!(u8, u16, u32, u64, u128);
+
+macro_rules! signed_key_part {
+    ($($t:ty => $u:ty),*) => {$(
+        /// Offset by the minimum, so negative values sort first.
+        impl EncodeKey for $t {
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                ((*self as $u) ^ (1 << (<$u>::BITS - 1))).encode_into(out);
+            }
+        }
+        impl DecodeKey for $t {
+            fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+                Ok((<$u>::decode_from(input)? ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+    )*};
+}
+
+signed_key_part!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl EncodeKey for bool {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl DecodeKey for bool {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+        Ok(u8::decode_from(input)? != 0)
+    }
+}
+
+/// Milliseconds since the unix epoch, as a `u64`.
+impl EncodeKey for Timestamp {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.as_millis().encode_into(out);
+    }
+}
+
+impl DecodeKey for Timestamp {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+        Ok(Timestamp::from_millis(u64::decode_from(input)?))
+    }
+}
+
+impl EncodeKey for [u8] {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out);
+    }
+}
+
+impl EncodeKey for Vec<u8> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out);
+    }
+}
+
+impl DecodeKey for Vec<u8> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+        decode_bytes(input)
+    }
+}
+
+/// Keys with a `&str` decode as `String`.
+impl EncodeKey for str {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+}
+
+impl EncodeKey for String {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+}
+
+impl DecodeKey for String {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+        String::from_utf8(decode_bytes(input)?).map_err(|_| KeycodeError::NotUtf8)
+    }
+}
+
+impl<T: EncodeKey + ?Sized> EncodeKey for &T {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        (**self).encode_into(out);
+    }
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for byte in bytes {
+        out.push(*byte);
+        if *byte == 0 {
+            out.push(0xFF);
+        }
+    }
+    out.extend_from_slice(&[0x00, 0x01]);
+}
+
+fn decode_bytes(input: &mut &[u8]) -> Result<Vec<u8>, KeycodeError> {
+    let mut bytes = vec![];
+    loop {
+        let [byte] = take(input)?;
+        if byte != 0 {
+            bytes.push(byte);
+            continue;
+        }
+        match take(input)? {
+            [0xFF] => bytes.push(0),
+            [0x01] => return Ok(bytes),
+            _ => return Err(KeycodeError::BadEscape),
+        }
+    }
+}
+
+macro_rules! tuple_key_part {
+    ($($name:ident $value:ident),+) => {
+        impl<$($name: EncodeKey),+> EncodeKey for ($($name,)+) {
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                let ($($value,)+) = self;
+                $($value.encode_into(out);)+
+            }
+        }
+
+        impl<$($name: DecodeKey),+> DecodeKey for ($($name,)+) {
+            fn decode_from(input: &mut &[u8]) -> Result<Self, KeycodeError> {
+                Ok(($($name::decode_from(input)?,)+))
+            }
+        }
+    };
+}
+
+tuple_key_part!(A a);
+tuple_key_part!(A a, B b);
+tuple_key_part!(A a, B b, C c);
+tuple_key_part!(A a, B b, C c, D d);
+tuple_key_part!(A a, B b, C c, D d, E e);
+tuple_key_part!(A a, B b, C c, D d, E e, F f);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn round_trips(
+            name in ".{0,16}",
+            bytes in proptest::collection::vec(any::<u8>(), 0..16),
+            n in any::<u64>(),
+            i in any::<i64>(),
+            flag in any::<bool>(),
+        ) {
+            let key = (name.clone(), bytes.clone(), n, i, flag);
+            prop_assert_eq!(decode(&encode(&key)), Ok(key));
+        }
+
+        #[test]
+        fn orders_unsigned(a in any::<u64>(), b in any::<u64>()) {
+            prop_assert_eq!(encode(&a).cmp(&encode(&b)), a.cmp(&b));
+        }
+
+        #[test]
+        fn orders_signed(a in any::<i64>(), b in any::<i64>()) {
+            prop_assert_eq!(encode(&a).cmp(&encode(&b)), a.cmp(&b));
+        }
+
+        #[test]
+        fn orders_bytes(
+            a in proptest::collection::vec(prop_oneof![Just(0u8), Just(1), any::<u8>()], 0..8),
+            b in proptest::collection::vec(prop_oneof![Just(0u8), Just(1), any::<u8>()], 0..8),
+        ) {
+            prop_assert_eq!(encode(&a).cmp(&encode(&b)), a.cmp(&b));
+        }
+
+        #[test]
+        fn orders_tuples(a in (".{0,4}", any::<u16>()), b in (".{0,4}", any::<u16>())) {
+            prop_assert_eq!(encode(&a).cmp(&encode(&b)), a.cmp(&b));
+        }
+
+        #[test]
+        fn leading_values_are_a_prefix(name in ".{0,16}", n in any::<u64>()) {
+            prop_assert!(encode(&(&name, n)).starts_with(&encode(&(&name,))));
+        }
+    }
+
+    #[test]
+    fn orders_numbers_not_digits() {
+        assert!(encode(&("user", 9u64)) < encode(&("user", 10u64)));
+    }
+
+    #[test]
+    fn escapes_nul() {
+        assert_eq!(encode("a\0"), b"a\0\xFF\0\x01");
+        assert!(encode("a") < encode("a\0"));
+        assert_eq!(decode::<String>(&encode("a\0")), Ok("a\0".to_string()));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert_eq!(decode::<u64>(&[0; 7]), Err(KeycodeError::Truncated));
+        assert_eq!(decode::<String>(b"abc"), Err(KeycodeError::Truncated));
+        assert_eq!(decode::<String>(b"a\0\x02"), Err(KeycodeError::BadEscape));
+        assert_eq!(decode::<String>(b"\xFF\0\x01"), Err(KeycodeError::NotUtf8));
+        assert_eq!(decode::<u8>(&[1, 2]), Err(KeycodeError::TrailingBytes(1)));
+    }
+}
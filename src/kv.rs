@@ -1,4 +1,8 @@
-use crate::{get_blob, Message, PackageId, Request};
+use crate::time::Timestamp;
+use crate::{
+    get_blob, Address, Capability, Message, ModuleError, PackageId, ProcessLibError, RemoteError,
+    Request,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -16,28 +20,113 @@ pub struct KvRequest {
 pub enum KvAction {
     Open,
     RemoveDb,
-    Set { key: Vec<u8>, tx_id: Option<u64> },
-    Delete { key: Vec<u8>, tx_id: Option<u64> },
-    Get { key: Vec<u8> },
+    Set {
+        key: Vec<u8>,
+        tx_id: Option<u64>,
+    },
+    Delete {
+        key: Vec<u8>,
+        tx_id: Option<u64>,
+    },
+    Get {
+        key: Vec<u8>,
+    },
     BeginTx,
-    Commit { tx_id: u64 },
+    /// Commit a transaction. If the request carries a blob, it holds a
+    /// JSON-serialized `Vec<KvBatchOp>` that is applied inside the
+    /// transaction before it commits.
+    Commit {
+        tx_id: u64,
+    },
     Backup,
+    /// Write a consistent copy of the db to a file in the vfs.
+    BackupTo {
+        vfs_path: String,
+    },
+    /// Replace the contents of the db with a copy written by `BackupTo`,
+    /// creating the db if it does not exist.
+    RestoreFrom {
+        vfs_path: String,
+    },
+    /// Read up to `limit` entries with keys from `start` (inclusive) to `end`
+    /// (exclusive, or to the last key if `None`), in key order. The entries
+    /// come back as a JSON `Vec<(Vec<u8>, Vec<u8>)>` in the blob.
+    Range {
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
+        limit: usize,
+    },
+    /// Atomically set `key` to `new` if its value is `old`, where `None`
+    /// means absent. `new` of `None` deletes the key.
+    CompareAndSwap {
+        key: Vec<u8>,
+        old: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    },
+    /// Send the requesting process a [`KvWatchEvent`] each time a key in the
+    /// db is set or deleted, by any process, until unwatched.
+    Watch,
+    Unwatch,
+}
+
+/// Pushed to watchers, as a request from `kv:distro:sys`, when a key in a
+/// watched db changes. Writes in a transaction are reported on commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvWatchEvent {
+    pub package_id: PackageId,
+    pub db: String,
+    pub key: Vec<u8>,
+    pub deleted: bool,
+}
+
+/// Parse a message as a [`KvWatchEvent`], or return `None` if it is not one.
+pub fn parse_watch_event(message: &Message) -> Option<KvWatchEvent> {
+    if !message.is_request() || !message.source().is_process("kv", "distro", "sys") {
+        return None;
+    }
+    serde_json::from_slice(message.body()).ok()
+}
+
+/// A write buffered locally by a [`KvBufferedTx`] and shipped to the runtime
+/// in the blob of [`KvAction::Commit`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum KvBatchOp {
+    Set { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum KvResponse {
     Ok,
-    BeginTx { tx_id: u64 },
-    Get { key: Vec<u8> },
-    Err { error: KvError },
+    BeginTx {
+        tx_id: u64,
+    },
+    Get {
+        key: Vec<u8>,
+    },
+    CompareAndSwap {
+        swapped: bool,
+    },
+    /// `more` is set if entries remain in the range past the last one sent.
+    Range {
+        more: bool,
+    },
+    Err {
+        error: KvError,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Error)]
+/// Errors from the kv module. Errors about a db or key name them, so logs
+/// from a process using several dbs say which one failed.
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+#[serde(from = "KvErrorWire", into = "KvErrorWire")]
 pub enum KvError {
-    #[error("kv: DbDoesNotExist")]
-    NoDb,
-    #[error("kv: KeyNotFound")]
-    KeyNotFound,
+    #[error("kv: db {db:?} does not exist")]
+    NoDb { db: String },
+    /// `key` is shown as text if it is UTF-8 and hex otherwise, and is
+    /// truncated if long.
+    #[error("kv: key {key} not found in db {db:?}")]
+    KeyNotFound { db: String, key: String },
     #[error("kv: no Tx found")]
     NoTx,
     #[error("kv: No capability: {error}")]
@@ -48,200 +137,2359 @@ pub enum KvError {
     InputError { error: String },
     #[error("kv: IO error: {error}")]
     IOError { error: String },
+    #[error("kv: gave up after {attempts} conflicting updates")]
+    Contention { attempts: u32 },
+}
+
+/// The longest key, in characters, that a [`KvError`] shows.
+const ERROR_KEY_LEN: usize = 64;
+
+impl KvError {
+    /// Fill in the db, and the key if there is one, that this error is
+    /// about. Errors from the runtime arrive without them.
+    pub fn with_context(self, db: &str, key: Option<&[u8]>) -> Self {
+        match self {
+            KvError::NoDb { .. } => KvError::NoDb { db: db.to_string() },
+            KvError::KeyNotFound { key: shown, .. } => KvError::KeyNotFound {
+                db: db.to_string(),
+                key: key.map(show_key).unwrap_or(shown),
+            },
+            error => error,
+        }
+    }
+}
+
+/// A key as text, if it is UTF-8, or hex, truncated to [`ERROR_KEY_LEN`].
+fn show_key(key: &[u8]) -> String {
+    let shown = match std::str::from_utf8(key) {
+        Ok(key) => format!("{:?}", key),
+        Err(_) => key.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+    match shown.char_indices().nth(ERROR_KEY_LEN) {
+        Some((end, _)) => format!("{}…", &shown[..end]),
+        None => shown,
+    }
+}
+
+/// [`KvError`] as the runtime sends it, without db or key.
+#[derive(Serialize, Deserialize)]
+enum KvErrorWire {
+    NoDb,
+    KeyNotFound,
+    NoTx,
+    NoCap { error: String },
+    RocksDBError { action: String, error: String },
+    InputError { error: String },
+    IOError { error: String },
+    Contention { attempts: u32 },
+}
+
+impl From<KvErrorWire> for KvError {
+    fn from(error: KvErrorWire) -> Self {
+        match error {
+            KvErrorWire::NoDb => KvError::NoDb { db: String::new() },
+            KvErrorWire::KeyNotFound => KvError::KeyNotFound {
+                db: String::new(),
+                key: String::new(),
+            },
+            KvErrorWire::NoTx => KvError::NoTx,
+            KvErrorWire::NoCap { error } => KvError::NoCap { error },
+            KvErrorWire::RocksDBError { action, error } => KvError::RocksDBError { action, error },
+            KvErrorWire::InputError { error } => KvError::InputError { error },
+            KvErrorWire::IOError { error } => KvError::IOError { error },
+            KvErrorWire::Contention { attempts } => KvError::Contention { attempts },
+        }
+    }
+}
+
+impl From<KvError> for KvErrorWire {
+    fn from(error: KvError) -> Self {
+        match error {
+            KvError::NoDb { .. } => KvErrorWire::NoDb,
+            KvError::KeyNotFound { .. } => KvErrorWire::KeyNotFound,
+            KvError::NoTx => KvErrorWire::NoTx,
+            KvError::NoCap { error } => KvErrorWire::NoCap { error },
+            KvError::RocksDBError { action, error } => KvErrorWire::RocksDBError { action, error },
+            KvError::InputError { error } => KvErrorWire::InputError { error },
+            KvError::IOError { error } => KvErrorWire::IOError { error },
+            KvError::Contention { attempts } => KvErrorWire::Contention { attempts },
+        }
+    }
 }
 
+/// Marks a value written by [`Kv::set_compressed()`]. An uncompressed value
+/// that happens to start with these bytes will be misread as compressed.
+#[cfg(feature = "compression")]
+const COMPRESSED_HEADER: &[u8] = b"\0kvlz4";
+
 /// Kv helper struct for a db.
 /// Opening or creating a kv will give you a Result<Kv>.
 /// You can call it's impl functions to interact with it.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Kv {
     pub package_id: PackageId,
     pub db: String,
 }
 
+thread_local! {
+    /// Operation counters for the dbs [`Kv::with_metrics()`] was called on.
+    /// Kept here rather than in the handle, so every handle to a db counts.
+    static METRICS: std::cell::RefCell<std::collections::HashMap<(PackageId, String), KvMetrics>> =
+        std::cell::RefCell::new(std::collections::HashMap::new());
+}
+
+/// Counts of the operations made on a db through any [`Kv`] handle in this
+/// process, from [`Kv::metrics()`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct KvMetrics {
+    pub gets: u64,
+    pub sets: u64,
+    pub deletes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Operations that failed. A get of a missing key is not a failure.
+    pub errors: u64,
+    /// When counting started.
+    pub since: crate::time::Timestamp,
+}
+
+impl KvMetrics {
+    /// Average operations per second since counting started.
+    pub fn ops_per_sec(&self) -> f64 {
+        let secs = self.since.elapsed().as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        (self.gets + self.sets + self.deletes) as f64 / secs
+    }
+
+    /// Copy these counts into a metrics registry, named
+    /// `kv.{db}.{counter}`, so a scrape of the process reports them.
+    #[cfg(feature = "metrics")]
+    pub fn record_into(&self, db: &str, metrics: &mut crate::metrics::Metrics) {
+        for (name, count) in [
+            ("gets", self.gets),
+            ("sets", self.sets),
+            ("deletes", self.deletes),
+            ("bytes_read", self.bytes_read),
+            ("bytes_written", self.bytes_written),
+            ("errors", self.errors),
+        ] {
+            metrics.set_counter(&format!("kv.{}.{}", db, name), count);
+        }
+        metrics.set_gauge(&format!("kv.{}.ops_per_sec", db), self.ops_per_sec());
+    }
+}
+
+/// The kinds of operation [`KvMetrics`] counts.
+enum KvOp {
+    Get,
+    Set,
+    Delete,
+}
+
 impl Kv {
+    /// A handle to a db this process has already opened, e.g. one recorded
+    /// in its saved state, without the [`open()`] round trip to the kv
+    /// module. If the db does not exist, requests through the handle fail
+    /// with [`KvError::NoDb`].
+    pub fn assume_open(package_id: PackageId, db: &str) -> Self {
+        Kv {
+            package_id,
+            db: db.to_string(),
+        }
+    }
+
+    /// Count the operations made on this db, through this or any other
+    /// handle to it, to be read with [`Kv::metrics()`]. Counting starts over
+    /// if already enabled.
+    pub fn with_metrics(self) -> Self {
+        let metrics = KvMetrics {
+            since: crate::time::Timestamp::now(),
+            ..KvMetrics::default()
+        };
+        METRICS.with(|all| all.borrow_mut().insert(self.metrics_key(), metrics));
+        self
+    }
+
+    /// The operation counts so far, or `None` unless enabled with
+    /// [`Kv::with_metrics()`].
+    pub fn metrics(&self) -> Option<KvMetrics> {
+        METRICS.with(|all| all.borrow().get(&self.metrics_key()).cloned())
+    }
+
+    fn metrics_key(&self) -> (PackageId, String) {
+        (self.package_id.clone(), self.db.clone())
+    }
+
+    fn track<T>(
+        &self,
+        op: KvOp,
+        bytes: usize,
+        result: Result<T, ProcessLibError>,
+    ) -> Result<T, ProcessLibError> {
+        METRICS.with(|all| {
+            let mut all = all.borrow_mut();
+            // skips building the key when nothing is counted
+            if all.is_empty() {
+                return;
+            }
+            let Some(stats) = all.get_mut(&self.metrics_key()) else {
+                return;
+            };
+            match &result {
+                Ok(_) => match op {
+                    KvOp::Get => {
+                        stats.gets += 1;
+                        stats.bytes_read += bytes as u64;
+                    }
+                    KvOp::Set => {
+                        stats.sets += 1;
+                        stats.bytes_written += bytes as u64;
+                    }
+                    KvOp::Delete => stats.deletes += 1,
+                },
+                Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {
+                    stats.gets += 1;
+                }
+                Err(_) => stats.errors += 1,
+            }
+        });
+        result
+    }
+
     /// Get a value.
-    pub fn get(&self, key: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        let result = get(&self.package_id, &self.db, key);
+        let bytes = result.as_ref().map(|value| value.len()).unwrap_or(0);
+        self.track(KvOp::Get, bytes, result)
+    }
+
+    /// Set a value compressed with lz4, optionally in a transaction. Read it
+    /// back with [`Kv::get_decompressed()`].
+    #[cfg(feature = "compression")]
+    pub fn set_compressed(
+        &self,
+        key: Vec<u8>,
+        value: &[u8],
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
+        let compressed = [COMPRESSED_HEADER, &lz4_flex::compress_prepend_size(value)].concat();
+        self.set(key, compressed, tx_id)
+    }
+
+    /// Get a value written with [`Kv::set_compressed()`], decompressing it.
+    /// Values written uncompressed with [`Kv::set()`] are returned as they
+    /// are, so a db can hold a mix of both.
+    #[cfg(feature = "compression")]
+    pub fn get_decompressed(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        let value = self.get(key)?;
+        match value.strip_prefix(COMPRESSED_HEADER) {
+            Some(compressed) => lz4_flex::decompress_size_prepended(compressed).map_err(|e| {
+                KvError::InputError {
+                    error: format!("failed to decompress value: {}", e),
+                }
+                .into()
+            }),
+            None => Ok(value),
+        }
+    }
+
+    /// Iterate over the entries with keys from `start` (inclusive) to `end`
+    /// (exclusive, or to the last key if `None`), in key order. Entries are
+    /// fetched a page at a time as the iterator advances.
+    pub fn range(&self, start: Vec<u8>, end: Option<Vec<u8>>) -> KvIter {
+        KvIter {
+            package_id: self.package_id.clone(),
+            db: self.db.clone(),
+            next_start: Some(start),
+            end,
+            page_size: KV_ITER_PAGE_SIZE,
+            page: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Iterate over the entries whose keys start with `prefix`, in key order.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> KvIter {
+        self.range(prefix.to_vec(), prefix_end(prefix))
+    }
+
+    /// A handle to the part of this db whose keys start with `prefix`, e.g.
+    /// `"users:"`, so that several modules of a process can share one db.
+    /// Keys given to the handle have the prefix added, and keys it returns
+    /// have it removed.
+    pub fn namespace(&self, prefix: &str) -> KvNamespace {
+        KvNamespace {
+            kv: self.clone(),
+            prefix: prefix.as_bytes().to_vec(),
+        }
+    }
+
+    /// A read-only handle to the same db, to hand to code that should not write.
+    pub fn read_only(&self) -> KvRead {
+        KvRead {
+            package_id: self.package_id.clone(),
+            db: self.db.clone(),
+        }
+    }
+
+    /// Atomically replace the value at `key` with `new` if it currently equals
+    /// `old`, where `None` means the key is absent. Returns whether the swap
+    /// happened.
+    pub fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        old: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool, ProcessLibError> {
         let res = Request::new()
             .target(("our", "kv", "distro", "sys"))
             .body(serde_json::to_vec(&KvRequest {
                 package_id: self.package_id.clone(),
                 db: self.db.clone(),
-                action: KvAction::Get { key },
+                action: KvAction::CompareAndSwap {
+                    key: key.clone(),
+                    old,
+                    new,
+                },
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<KvResponse>(&body)?;
 
                 match response {
-                    KvResponse::Get { .. } => {
-                        let bytes = match get_blob() {
-                            Some(bytes) => bytes.bytes,
-                            None => return Err(anyhow::anyhow!("kv: no blob")),
-                        };
-                        Ok(bytes)
+                    KvResponse::CompareAndSwap { swapped } => Ok(swapped),
+                    KvResponse::Err { error } => {
+                        Err(error.with_context(&self.db, Some(&key)).into())
                     }
-                    KvResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+
+    /// Read-modify-write a value. `f` is given the current value (or `None`
+    /// if the key is absent) and returns the new one (or `None` to delete it).
+    /// If another write lands between the read and the write, `f` is called
+    /// again on the fresh value, so it should have no side effects.
+    ///
+    /// Returns the value that was written.
+    pub fn update<F>(&self, key: Vec<u8>, mut f: F) -> Result<Option<Vec<u8>>, ProcessLibError>
+    where
+        F: FnMut(Option<Vec<u8>>) -> Option<Vec<u8>>,
+    {
+        const MAX_ATTEMPTS: u32 = 16;
+        for _ in 0..MAX_ATTEMPTS {
+            let old = match self.get(key.clone()) {
+                Ok(value) => Some(value),
+                Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => None,
+                Err(e) => return Err(e),
+            };
+            let new = f(old.clone());
+            if new == old {
+                return Ok(new);
+            }
+            if self.compare_and_swap(key.clone(), old, new.clone())? {
+                return Ok(new);
+            }
         }
+        Err(KvError::Contention {
+            attempts: MAX_ATTEMPTS,
+        }
+        .into())
     }
 
     /// Set a value, optionally in a transaction.
-    pub fn set(&self, key: Vec<u8>, value: Vec<u8>, tx_id: Option<u64>) -> anyhow::Result<()> {
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
+        let bytes = value.len();
+        let result = self.send_set(key, value, tx_id);
+        self.track(KvOp::Set, bytes, result)
+    }
+
+    fn send_set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "kv", "distro", "sys"))
             .body(serde_json::to_vec(&KvRequest {
                 package_id: self.package_id.clone(),
                 db: self.db.clone(),
-                action: KvAction::Set { key, tx_id },
+                action: KvAction::Set {
+                    key: key.clone(),
+                    tx_id,
+                },
             })?)
             .blob_bytes(value)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<KvResponse>(&body)?;
 
                 match response {
                     KvResponse::Ok => Ok(()),
-                    KvResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+                    KvResponse::Err { error } => {
+                        Err(error.with_context(&self.db, Some(&key)).into())
+                    }
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 
     /// Delete a value, optionally in a transaction.
-    pub fn delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> anyhow::Result<()> {
+    pub fn delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> Result<(), ProcessLibError> {
+        let result = self.send_delete(key, tx_id);
+        self.track(KvOp::Delete, 0, result)
+    }
+
+    fn send_delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "kv", "distro", "sys"))
             .body(serde_json::to_vec(&KvRequest {
                 package_id: self.package_id.clone(),
                 db: self.db.clone(),
-                action: KvAction::Delete { key, tx_id },
+                action: KvAction::Delete {
+                    key: key.clone(),
+                    tx_id,
+                },
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<KvResponse>(&body)?;
 
                 match response {
                     KvResponse::Ok => Ok(()),
-                    KvResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+                    KvResponse::Err { error } => {
+                        Err(error.with_context(&self.db, Some(&key)).into())
+                    }
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 
-    /// Begin a transaction.
-    pub fn begin_tx(&self) -> anyhow::Result<u64> {
+    /// Write a consistent copy of the db to a file in the vfs.
+    pub fn backup_to(&self, vfs_path: &str) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "kv", "distro", "sys"))
             .body(serde_json::to_vec(&KvRequest {
                 package_id: self.package_id.clone(),
                 db: self.db.clone(),
-                action: KvAction::BeginTx,
+                action: KvAction::BackupTo {
+                    vfs_path: vfs_path.to_string(),
+                },
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(30)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<KvResponse>(&body)?;
 
                 match response {
-                    KvResponse::BeginTx { tx_id } => Ok(tx_id),
-                    KvResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+                    KvResponse::Ok => Ok(()),
+                    KvResponse::Err { error } => Err(error.with_context(&self.db, None).into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 
-    /// Commit a transaction.
-    pub fn commit_tx(&self, tx_id: u64) -> anyhow::Result<()> {
+    /// Replace the contents of the db with a backup from [`Kv::backup_to()`].
+    pub fn restore_from(&self, vfs_path: &str) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "kv", "distro", "sys"))
             .body(serde_json::to_vec(&KvRequest {
                 package_id: self.package_id.clone(),
                 db: self.db.clone(),
-                action: KvAction::Commit { tx_id },
+                action: KvAction::RestoreFrom {
+                    vfs_path: vfs_path.to_string(),
+                },
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(30)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<KvResponse>(&body)?;
 
                 match response {
                     KvResponse::Ok => Ok(()),
-                    KvResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+                    KvResponse::Err { error } => Err(error.with_context(&self.db, None).into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
-}
-
-/// Opens or creates a kv db.
-pub fn open(package_id: PackageId, db: &str) -> anyhow::Result<Kv> {
-    let res = Request::new()
-        .target(("our", "kv", "distro", "sys"))
-        .body(serde_json::to_vec(&KvRequest {
-            package_id: package_id.clone(),
-            db: db.to_string(),
-            action: KvAction::Open,
-        })?)
-        .send_and_await_response(5)?;
 
-    match res {
-        Ok(Message::Response { body, .. }) => {
-            let response = serde_json::from_slice::<KvResponse>(&body)?;
+    /// Copy the db to the process at `target`, usually our own process on
+    /// another node, which restores it over its db of the same name with a
+    /// [`KvReplicaReceiver`]. The db is backed up to `staging_path` in our
+    /// vfs, so the copy is consistent, then streamed in pieces, each
+    /// acknowledged before the next is sent. The staging file is removed
+    /// afterwards. Returns the number of bytes sent.
+    #[cfg(feature = "vfs")]
+    pub fn replicate_to(
+        &self,
+        target: &Address,
+        staging_path: &str,
+    ) -> Result<u64, ProcessLibError> {
+        self.backup_to(staging_path)?;
+        let result = self.send_replica(target, staging_path);
+        let _ = crate::vfs::remove_file(staging_path);
+        result
+    }
 
-            match response {
-                KvResponse::Ok => Ok(Kv {
-                    package_id,
-                    db: db.to_string(),
-                }),
+    #[cfg(feature = "vfs")]
+    fn send_replica(&self, target: &Address, staging_path: &str) -> Result<u64, ProcessLibError> {
+        let len = crate::vfs::open_file(staging_path, false)?.metadata()?.len;
+        let send = |offset: u64, piece: &[u8]| -> Result<(), ProcessLibError> {
+            let chunk = KvReplicaChunk {
+                db: self.db.clone(),
+                offset,
+                last: offset + piece.len() as u64 >= len,
+            };
+            let res = Request::new()
+                .target(target.clone())
+                .body(serde_json::to_vec(&chunk)?)
+                .blob_bytes(piece.to_vec())
+                .send_and_await_response(REPLICA_CHUNK_TIMEOUT)?
+                .map_err(|e| RemoteError::new(target.node(), e))?;
+            match serde_json::from_slice::<KvResponse>(res.body())? {
+                KvResponse::Ok => Ok(()),
                 KvResponse::Err { error } => Err(error.into()),
-                _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+                response => Err(ProcessLibError::unexpected(response)),
             }
+        };
+        if len == 0 {
+            send(0, &[])?;
+            return Ok(0);
         }
-        _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+        let mut offset = 0;
+        read_in_pieces(staging_path, |piece| {
+            send(offset, piece)?;
+            offset += piece.len() as u64;
+            Ok(())
+        })?;
+        Ok(offset)
     }
-}
 
-/// Removes and deletes a kv db.
-pub fn remove_db(package_id: PackageId, db: &str) -> anyhow::Result<()> {
-    let res = Request::new()
-        .target(("our", "kv", "distro", "sys"))
-        .body(serde_json::to_vec(&KvRequest {
-            package_id: package_id.clone(),
-            db: db.to_string(),
-            action: KvAction::RemoveDb,
-        })?)
-        .send_and_await_response(5)?;
+    /// Import a CSV file from the vfs. The first row names the columns; each
+    /// row after it is stored as a JSON object of column name to string
+    /// value, under the value of its `key_column`. Fields may be quoted, with
+    /// `""` for a quote inside a quoted field. A row with more or fewer
+    /// fields than the header is an error. Returns the number of rows.
+    ///
+    /// The file is read and written in pieces, so it may be larger than a
+    /// single message; each batch of [`IMPORT_BATCH_SIZE`] rows is its own
+    /// transaction, so a failed import may leave the batches before it written.
+    #[cfg(feature = "vfs")]
+    pub fn import_csv(&self, vfs_path: &str, key_column: &str) -> Result<usize, ProcessLibError> {
+        let mut parser = CsvParser::default();
+        let mut header: Option<(Vec<String>, usize)> = None;
+        let mut batch = ImportBatch::new(self);
+        let mut on_row = |row: Vec<String>| -> Result<(), ProcessLibError> {
+            let Some((columns, key_index)) = &header else {
+                let key_index = row
+                    .iter()
+                    .position(|column| column == key_column)
+                    .ok_or_else(|| {
+                        import_error(format!("{} has no column {:?}", vfs_path, key_column))
+                    })?;
+                header = Some((row, key_index));
+                return Ok(());
+            };
+            if row.len() != columns.len() {
+                return Err(import_error(format!(
+                    "{}: row {} has {} fields, but the header has {}",
+                    vfs_path,
+                    batch.count + 1,
+                    row.len(),
+                    columns.len()
+                )));
+            }
+            let key = row[*key_index].clone().into_bytes();
+            let value: serde_json::Map<String, serde_json::Value> = columns
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(serde_json::Value::String))
+                .collect();
+            batch.set(key, serde_json::to_vec(&value)?)
+        };
+        read_in_pieces(vfs_path, |bytes| {
+            for &byte in bytes {
+                if let Some(row) = parser.push(byte)? {
+                    on_row(row)?;
+                }
+            }
+            Ok(())
+        })?;
+        if let Some(row) = parser.finish()? {
+            on_row(row)?;
+        }
+        batch.finish()
+    }
 
-    match res {
-        Ok(Message::Response { body, .. }) => {
-            let response = serde_json::from_slice::<KvResponse>(&body)?;
+    /// Import a file from the vfs holding a JSON array. Each element is
+    /// stored as JSON under the key `key_fn` gives it. Returns the number of
+    /// elements. As with [`Kv::import_csv()`], the file is read and written
+    /// in pieces, in batches of [`IMPORT_BATCH_SIZE`].
+    #[cfg(feature = "vfs")]
+    pub fn import_json_array<F>(
+        &self,
+        vfs_path: &str,
+        mut key_fn: F,
+    ) -> Result<usize, ProcessLibError>
+    where
+        F: FnMut(&serde_json::Value) -> Vec<u8>,
+    {
+        let mut splitter = JsonArraySplitter::default();
+        let mut batch = ImportBatch::new(self);
+        read_in_pieces(vfs_path, |bytes| {
+            for &byte in bytes {
+                if let Some(element) = splitter.push(byte)? {
+                    let value: serde_json::Value = serde_json::from_slice(&element)?;
+                    batch.set(key_fn(&value), element)?;
+                }
+            }
+            Ok(())
+        })?;
+        if !splitter.done {
+            return Err(import_error(format!("{} ends inside its array", vfs_path)));
+        }
+        batch.finish()
+    }
 
-            match response {
-                KvResponse::Ok => Ok(()),
-                KvResponse::Err { error } => Err(error.into()),
-                _ => Err(anyhow::anyhow!("kv: unexpected response {:?}", response)),
+    /// Back the db up to `vfs_dir` every `interval`, keeping the newest
+    /// `keep_last_n` backups and deleting older ones. The first backup is
+    /// taken when the first interval has passed; pass every incoming message
+    /// to [`KvBackups::handle()`] so the timer can drive the rest.
+    #[cfg(all(feature = "timer", feature = "vfs"))]
+    pub fn schedule_backups(
+        &self,
+        interval: std::time::Duration,
+        keep_last_n: usize,
+        vfs_dir: &str,
+    ) -> KvBackups {
+        let backups = KvBackups {
+            kv: self.clone(),
+            interval,
+            keep_last_n: keep_last_n.max(1),
+            vfs_dir: vfs_dir.trim_end_matches('/').to_string(),
+        };
+        backups.schedule();
+        backups
+    }
+
+    /// Append a value to the event log named `log`, and return its position.
+    /// Positions increase with each append, even within the same millisecond
+    /// or across restarts, so entries read back in the order appended.
+    ///
+    /// The first append to a log in this process reads the whole log to find
+    /// its last position. Entries are written only where no key exists yet,
+    /// so an append never overwrites one made by another handle or process.
+    pub fn append_log(&self, log: &str, value: Vec<u8>) -> Result<LogPosition, ProcessLibError> {
+        self.append_log_after(log, None, value)
+    }
+
+    /// Like [`Kv::append_log()`], but the position is also after `floor`,
+    /// for callers that remember a position the log may no longer hold,
+    /// such as one whose entries were compacted away.
+    pub fn append_log_after(
+        &self,
+        log: &str,
+        floor: Option<LogPosition>,
+        value: Vec<u8>,
+    ) -> Result<LogPosition, ProcessLibError> {
+        check_log_name(log)?;
+        let id = (
+            self.package_id.to_string(),
+            self.db.clone(),
+            log.to_string(),
+        );
+        let last = match LAST_LOG_POSITIONS.with(|last| last.borrow().get(&id).copied()) {
+            Some(position) => Some(position),
+            None => self.last_log_position(log)?,
+        };
+        let mut last = last.max(floor);
+        loop {
+            let position = LogPosition::next_after(last);
+            let swapped =
+                self.compare_and_swap(log_key(log, &position), None, Some(value.clone()))?;
+            LAST_LOG_POSITIONS.with(|last| last.borrow_mut().insert(id.clone(), position));
+            if swapped {
+                return Ok(position);
+            }
+            last = Some(position);
+        }
+    }
+
+    /// The position of the last entry of `log`, found by reading all of it.
+    fn last_log_position(&self, log: &str) -> Result<Option<LogPosition>, ProcessLibError> {
+        let skip = log_prefix(log).len();
+        let mut last = None;
+        for entry in self.range(log_prefix(log), Some(log_end(log))) {
+            let (key, _) = entry?;
+            last = LogPosition::from_bytes(&key[skip..]).or(last);
+        }
+        Ok(last)
+    }
+
+    /// The entries of `log` appended from `from` (inclusive) to `to`
+    /// (exclusive, or to the end if `None`), oldest first.
+    pub fn read_log_range(
+        &self,
+        log: &str,
+        from: Timestamp,
+        to: Option<Timestamp>,
+    ) -> Result<Vec<LogEntry>, ProcessLibError> {
+        check_log_name(log)?;
+        let start = log_key(log, &LogPosition::start_of(from));
+        let end = match to {
+            Some(to) => log_key(log, &LogPosition::start_of(to)),
+            None => log_end(log),
+        };
+        self.read_log_keys(log, start, end)
+    }
+
+    /// The entries of `log` after `position`, or all of them if `None`,
+    /// oldest first.
+    pub fn read_log_after(
+        &self,
+        log: &str,
+        position: Option<LogPosition>,
+    ) -> Result<Vec<LogEntry>, ProcessLibError> {
+        check_log_name(log)?;
+        let start = match position {
+            Some(position) => log_key(log, &position.successor()),
+            None => log_prefix(log),
+        };
+        self.read_log_keys(log, start, log_end(log))
+    }
+
+    /// Delete the entries of `log` before `before`, in one transaction, and
+    /// return how many were deleted.
+    pub fn compact_log(&self, log: &str, before: LogPosition) -> Result<usize, ProcessLibError> {
+        check_log_name(log)?;
+        let keys = self
+            .range(log_prefix(log), Some(log_key(log, &before)))
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let mut tx = self.buffered_tx()?;
+        for key in &keys {
+            tx.delete(key.clone());
+        }
+        tx.commit()?;
+        Ok(keys.len())
+    }
+
+    fn read_log_keys(
+        &self,
+        log: &str,
+        start: Vec<u8>,
+        end: Vec<u8>,
+    ) -> Result<Vec<LogEntry>, ProcessLibError> {
+        let skip = log_prefix(log).len();
+        self.range(start, Some(end))
+            .map(|entry| -> Result<LogEntry, ProcessLibError> {
+                let (key, value) = entry?;
+                let position =
+                    LogPosition::from_bytes(&key[skip..]).ok_or_else(|| KvError::InputError {
+                        error: format!("malformed key in log {:?}", log),
+                    })?;
+                Ok(LogEntry { position, value })
+            })
+            .collect()
+    }
+
+    /// Receive a [`KvWatchEvent`] each time a key in this db changes. Pass
+    /// incoming messages to [`parse_watch_event()`] to pick them out.
+    pub fn watch(&self) -> Result<(), ProcessLibError> {
+        self.send_subscription(KvAction::Watch)
+    }
+
+    pub fn unwatch(&self) -> Result<(), ProcessLibError> {
+        self.send_subscription(KvAction::Unwatch)
+    }
+
+    fn send_subscription(&self, action: KvAction) -> Result<(), ProcessLibError> {
+        let res = Request::new()
+            .target(("our", "kv", "distro", "sys"))
+            .body(serde_json::to_vec(&KvRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action,
+            })?)
+            .send_and_await_response(5)??;
+
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+                match response {
+                    KvResponse::Ok => Ok(()),
+                    KvResponse::Err { error } => Err(error.with_context(&self.db, None).into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+
+    /// Begin a transaction.
+    pub fn begin_tx(&self) -> Result<u64, ProcessLibError> {
+        let res = Request::new()
+            .target(("our", "kv", "distro", "sys"))
+            .body(serde_json::to_vec(&KvRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: KvAction::BeginTx,
+            })?)
+            .send_and_await_response(5)??;
+
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+                match response {
+                    KvResponse::BeginTx { tx_id } => Ok(tx_id),
+                    KvResponse::Err { error } => Err(error.with_context(&self.db, None).into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+
+    /// Commit a transaction.
+    pub fn commit_tx(&self, tx_id: u64) -> Result<(), ProcessLibError> {
+        self.commit_with_ops(tx_id, None)
+    }
+
+    /// Begin a transaction whose writes are buffered locally and sent to the
+    /// runtime all at once on [`KvBufferedTx::commit()`], so a transaction
+    /// costs two round trips no matter how many writes it contains.
+    pub fn buffered_tx(&self) -> Result<KvBufferedTx<'_>, ProcessLibError> {
+        Ok(KvBufferedTx {
+            kv: self,
+            tx_id: self.begin_tx()?,
+            ops: Vec::new(),
+        })
+    }
+
+    fn commit_with_ops(
+        &self,
+        tx_id: u64,
+        ops: Option<&[KvBatchOp]>,
+    ) -> Result<(), ProcessLibError> {
+        let mut request =
+            Request::new()
+                .target(("our", "kv", "distro", "sys"))
+                .body(serde_json::to_vec(&KvRequest {
+                    package_id: self.package_id.clone(),
+                    db: self.db.clone(),
+                    action: KvAction::Commit { tx_id },
+                })?);
+        if let Some(ops) = ops {
+            request = request.blob_bytes(serde_json::to_vec(ops)?);
+        }
+        let res = request.send_and_await_response(5)??;
+
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+                match response {
+                    KvResponse::Ok => Ok(()),
+                    KvResponse::Err { error } => Err(error.with_context(&self.db, None).into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
             }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+}
+
+/// Prefix of the keys event logs are kept under, followed by the log's name,
+/// a NUL, and each entry's [`LogPosition`].
+const LOG_PREFIX: &[u8] = b"\0kv_log:";
+
+thread_local! {
+    /// The last position appended to each log, by package id, db and log
+    /// name. Seeded from the log itself the first time it is appended to.
+    static LAST_LOG_POSITIONS: std::cell::RefCell<
+        std::collections::BTreeMap<(String, String, String), LogPosition>,
+    > = const { std::cell::RefCell::new(std::collections::BTreeMap::new()) };
+}
+
+/// Where an entry sits in an event log: when it was appended, and a
+/// sequence number that orders entries appended in the same millisecond.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct LogPosition {
+    pub at: Timestamp,
+    pub seq: u32,
+}
+
+impl LogPosition {
+    /// The first position at time `at`.
+    pub fn start_of(at: Timestamp) -> Self {
+        LogPosition { at, seq: 0 }
+    }
+
+    /// The position right after this one.
+    pub fn successor(&self) -> Self {
+        match self.seq.checked_add(1) {
+            Some(seq) => LogPosition { at: self.at, seq },
+            None => LogPosition::start_of(Timestamp::from_millis(self.at.as_millis() + 1)),
         }
-        _ => Err(anyhow::anyhow!("kv: unexpected message: {:?}", res)),
+    }
+
+    /// A position after `last`, at the current time unless the clock has
+    /// gone backwards.
+    fn next_after(last: Option<LogPosition>) -> Self {
+        let now = LogPosition::start_of(Timestamp::from_millis(crate::time::now_millis()));
+        match last {
+            Some(last) if last >= now => last.successor(),
+            _ => now,
+        }
+    }
+
+    /// Big-endian, so that keys sort by position.
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&self.at.as_millis().to_be_bytes());
+        bytes[8..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; 12] = bytes.try_into().ok()?;
+        Some(LogPosition {
+            at: Timestamp::from_millis(u64::from_be_bytes(bytes[..8].try_into().unwrap())),
+            seq: u32::from_be_bytes(bytes[8..].try_into().unwrap()),
+        })
+    }
+}
+
+/// An entry of an event log, from [`Kv::read_log_range()`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub position: LogPosition,
+    pub value: Vec<u8>,
+}
+
+/// Log names end at a NUL in keys, so they may not contain one.
+fn check_log_name(log: &str) -> Result<(), KvError> {
+    if log.contains('\0') {
+        return Err(KvError::InputError {
+            error: format!("invalid log name {:?}", log),
+        });
+    }
+    Ok(())
+}
+
+fn log_prefix(log: &str) -> Vec<u8> {
+    [LOG_PREFIX, log.as_bytes(), b"\0"].concat()
+}
+
+/// The key just past every entry of a log.
+fn log_end(log: &str) -> Vec<u8> {
+    [LOG_PREFIX, log.as_bytes(), b"\x01"].concat()
+}
+
+fn log_key(log: &str, position: &LogPosition) -> Vec<u8> {
+    [log_prefix(log).as_slice(), &position.to_bytes()].concat()
+}
+
+/// How many rows [`Kv::import_csv()`] and [`Kv::import_json_array()`] write
+/// per transaction.
+#[cfg(feature = "vfs")]
+pub const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// How many bytes of a file an import reads per request.
+#[cfg(feature = "vfs")]
+const IMPORT_READ_SIZE: u64 = 256 * 1024;
+
+/// Rows of an import, committed every [`IMPORT_BATCH_SIZE`].
+#[cfg(feature = "vfs")]
+struct ImportBatch<'a> {
+    kv: &'a Kv,
+    tx: Option<KvBufferedTx<'a>>,
+    count: usize,
+}
+
+#[cfg(feature = "vfs")]
+impl<'a> ImportBatch<'a> {
+    fn new(kv: &'a Kv) -> Self {
+        ImportBatch {
+            kv,
+            tx: None,
+            count: 0,
+        }
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ProcessLibError> {
+        let tx = match self.tx.as_mut() {
+            Some(tx) => tx,
+            None => self.tx.insert(self.kv.buffered_tx()?),
+        };
+        tx.set(key, value);
+        self.count += 1;
+        if tx.len() >= IMPORT_BATCH_SIZE {
+            self.tx.take().unwrap().commit()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<usize, ProcessLibError> {
+        if let Some(tx) = self.tx.take() {
+            tx.commit()?;
+        }
+        Ok(self.count)
+    }
+}
+
+/// Read a vfs file from the start, [`IMPORT_READ_SIZE`] bytes at a time.
+#[cfg(feature = "vfs")]
+fn read_in_pieces<F>(vfs_path: &str, mut f: F) -> Result<(), ProcessLibError>
+where
+    F: FnMut(&[u8]) -> Result<(), ProcessLibError>,
+{
+    let mut file = crate::vfs::open_file(vfs_path, false)?;
+    let len = file.metadata()?.len;
+    file.seek(crate::vfs::SeekFrom::Start(0))?;
+    let mut buffer = vec![];
+    let mut read = 0;
+    while read < len {
+        buffer.resize((len - read).min(IMPORT_READ_SIZE) as usize, 0);
+        let n = file.read_at(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        f(&buffer[..n])?;
+        read += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "vfs")]
+fn import_error(error: String) -> ProcessLibError {
+    KvError::InputError {
+        error: format!("import: {}", error),
+    }
+    .into()
+}
+
+/// Splits CSV into rows a byte at a time, so rows may span reads.
+#[cfg(feature = "vfs")]
+#[derive(Default)]
+struct CsvParser {
+    row: Vec<String>,
+    field: Vec<u8>,
+    in_quotes: bool,
+    /// A quote was seen inside quotes: either the field's closing quote or
+    /// the first of a `""`.
+    quote_pending: bool,
+}
+
+#[cfg(feature = "vfs")]
+impl CsvParser {
+    /// Take the next byte, returning a row if it ended one.
+    fn push(&mut self, byte: u8) -> Result<Option<Vec<String>>, ProcessLibError> {
+        if self.in_quotes {
+            if self.quote_pending {
+                self.quote_pending = false;
+                if byte == b'"' {
+                    self.field.push(b'"');
+                    return Ok(None);
+                }
+                self.in_quotes = false;
+            } else {
+                match byte {
+                    b'"' => self.quote_pending = true,
+                    _ => self.field.push(byte),
+                }
+                return Ok(None);
+            }
+        }
+        match byte {
+            b'"' if self.field.is_empty() => self.in_quotes = true,
+            b',' => self.end_field()?,
+            b'\n' => return self.end_row(),
+            b'\r' => {}
+            _ => self.field.push(byte),
+        }
+        Ok(None)
+    }
+
+    /// The last row, if the input did not end with a newline.
+    fn finish(&mut self) -> Result<Option<Vec<String>>, ProcessLibError> {
+        if self.in_quotes && !self.quote_pending {
+            return Err(import_error("csv ends inside a quoted field".to_string()));
+        }
+        self.in_quotes = false;
+        self.quote_pending = false;
+        self.end_row()
+    }
+
+    fn end_field(&mut self) -> Result<(), ProcessLibError> {
+        let field = String::from_utf8(std::mem::take(&mut self.field))
+            .map_err(|_| import_error("csv is not UTF-8".to_string()))?;
+        self.row.push(field);
+        Ok(())
+    }
+
+    fn end_row(&mut self) -> Result<Option<Vec<String>>, ProcessLibError> {
+        self.end_field()?;
+        let row = std::mem::take(&mut self.row);
+        // skip blank lines
+        if row.len() == 1 && row[0].is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(row))
+    }
+}
+
+/// Splits a JSON array into its elements a byte at a time, without parsing
+/// them, so that elements may span reads.
+#[cfg(feature = "vfs")]
+#[derive(Default)]
+struct JsonArraySplitter {
+    started: bool,
+    done: bool,
+    element: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+#[cfg(feature = "vfs")]
+impl JsonArraySplitter {
+    /// Take the next byte, returning an element if it ended one.
+    fn push(&mut self, byte: u8) -> Result<Option<Vec<u8>>, ProcessLibError> {
+        if self.done {
+            if byte.is_ascii_whitespace() {
+                return Ok(None);
+            }
+            return Err(import_error("trailing data after json array".to_string()));
+        }
+        if !self.started {
+            match byte {
+                b'[' => self.started = true,
+                _ if byte.is_ascii_whitespace() => {}
+                _ => return Err(import_error("json is not an array".to_string())),
+            }
+            return Ok(None);
+        }
+        if self.in_string {
+            self.element.push(byte);
+            match byte {
+                _ if self.escaped => self.escaped = false,
+                b'\\' => self.escaped = true,
+                b'"' => self.in_string = false,
+                _ => {}
+            }
+            return Ok(None);
+        }
+        match byte {
+            b',' | b']' if self.depth == 0 => {
+                self.done = byte == b']';
+                let element = std::mem::take(&mut self.element);
+                // `[]` has no elements
+                if element.is_empty() && self.done {
+                    return Ok(None);
+                }
+                if element.is_empty() {
+                    return Err(import_error("empty json array element".to_string()));
+                }
+                return Ok(Some(element));
+            }
+            _ if byte.is_ascii_whitespace() && self.depth == 0 => return Ok(None),
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        self.element.push(byte);
+        Ok(None)
+    }
+}
+
+/// A transaction started with [`Kv::buffered_tx()`]. Writes are held in
+/// memory until [`KvBufferedTx::commit()`], so reads made through the
+/// [`Kv`] handle in the meantime will not see them.
+///
+/// Dropping this without committing leaves the transaction open in the
+/// runtime with none of the buffered writes applied.
+pub struct KvBufferedTx<'a> {
+    kv: &'a Kv,
+    tx_id: u64,
+    ops: Vec<KvBatchOp>,
+}
+
+impl<'a> KvBufferedTx<'a> {
+    pub fn tx_id(&self) -> u64 {
+        self.tx_id
+    }
+
+    /// Buffer a set.
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push(KvBatchOp::Set { key, value });
+    }
+
+    /// Buffer a delete.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push(KvBatchOp::Delete { key });
+    }
+
+    /// The number of writes buffered so far.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Send every buffered write to the runtime and commit the transaction.
+    pub fn commit(self) -> Result<(), ProcessLibError> {
+        self.kv.commit_with_ops(self.tx_id, Some(&self.ops))
+    }
+}
+
+/// A read-only handle to a kv db, from [`open_read_only()`], [`Kv::read_only()`],
+/// or a read capability with [`KvRead::from_capability()`]. It has no write
+/// methods, so code holding one cannot modify the db.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KvRead {
+    pub package_id: PackageId,
+    pub db: String,
+}
+
+impl KvRead {
+    /// Build a handle to the db a kv read capability refers to.
+    /// `package_id` is the package that owns the db. Errors if `capability`
+    /// is not a kv read capability.
+    pub fn from_capability(
+        package_id: PackageId,
+        capability: &Capability,
+    ) -> Result<Self, KvError> {
+        let bad_cap = || KvError::NoCap {
+            error: format!("not a kv read capability: {}", capability),
+        };
+        if !capability.issuer().is_process("kv", "distro", "sys") {
+            return Err(bad_cap());
+        }
+        let params = capability.params_json().map_err(|_| bad_cap())?;
+        if params["kind"] != "read" {
+            return Err(bad_cap());
+        }
+        let db = params["db"].as_str().ok_or_else(bad_cap)?;
+        Ok(KvRead {
+            package_id,
+            db: db.to_string(),
+        })
+    }
+
+    /// Get a value.
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        get(&self.package_id, &self.db, key)
+    }
+
+    /// The capability a process on our node needs to read this db. Grant it
+    /// to a consumer instead of the write capability to share the db read-only.
+    pub fn capability(&self, our: &Address) -> Capability {
+        Capability::kv_read(our, &self.db)
+    }
+}
+
+/// A kv db whose values are encrypted at rest with a key derived from a
+/// master key, so reading the db's files does not reveal its contents.
+/// Each value is bound to its key, so values cannot be swapped between keys
+/// without detection. With [`EncryptedKv::hash_keys()`], keys are replaced by
+/// a keyed hash too, hiding them at the cost of no longer being listable.
+///
+/// The master key must be kept somewhere other than this db, e.g. supplied
+/// by the user at install time.
+#[cfg(feature = "crypto")]
+pub struct EncryptedKv {
+    kv: Kv,
+    /// Encrypts values.
+    value_key: crate::crypto::Key,
+    /// Hashes keys, with [`EncryptedKv::hash_keys()`].
+    key_hash_key: crate::crypto::Key,
+    hash_keys: bool,
+}
+
+#[cfg(feature = "crypto")]
+impl EncryptedKv {
+    /// Wrap `kv`, encrypting with keys derived from `master_key` for this db:
+    /// one for values and another for hashing keys.
+    pub fn new(kv: Kv, master_key: &[u8]) -> Self {
+        let context = format!("kv:{}:{}", kv.package_id, kv.db);
+        EncryptedKv {
+            value_key: crate::crypto::derive_key(master_key, &format!("{}:values", context)),
+            key_hash_key: crate::crypto::derive_key(master_key, &format!("{}:keys", context)),
+            kv,
+            hash_keys: false,
+        }
+    }
+
+    /// Store keys as a keyed hash rather than as given.
+    pub fn hash_keys(mut self) -> Self {
+        self.hash_keys = true;
+        self
+    }
+
+    /// Get and decrypt a value.
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        let stored_key = self.stored_key(&key);
+        let ciphertext = self.kv.get(stored_key)?;
+        Ok(crate::crypto::decrypt(&self.value_key, &ciphertext, &key)?)
+    }
+
+    /// Encrypt and set a value, optionally in a transaction.
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: &[u8],
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
+        let ciphertext = crate::crypto::encrypt(&self.value_key, value, &key);
+        self.kv.set(self.stored_key(&key), ciphertext, tx_id)
+    }
+
+    /// Delete a value, optionally in a transaction.
+    pub fn delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> Result<(), ProcessLibError> {
+        self.kv.delete(self.stored_key(&key), tx_id)
+    }
+
+    /// The underlying db, for transactions.
+    pub fn kv(&self) -> &Kv {
+        &self.kv
+    }
+
+    fn stored_key(&self, key: &[u8]) -> Vec<u8> {
+        if self.hash_keys {
+            crate::crypto::keyed_hash(&self.key_hash_key, key).to_vec()
+        } else {
+            key.to_vec()
+        }
+    }
+}
+
+/// Periodic backups of a kv db, from [`Kv::schedule_backups()`].
+///
+/// ```ignore
+/// let backups = kv.schedule_backups(Duration::from_secs(3600), 24, &backup_dir);
+/// loop {
+///     let message = await_message()?;
+///     if let Some(result) = backups.handle(&message) {
+///         if let Err(e) = result { println!("backup failed: {}", e); }
+///         continue;
+///     }
+///     ...
+/// }
+/// ```
+///
+/// Backups are named `{db}-{millis}.kvbak`, by the time they were taken, so
+/// several dbs can share a directory. A failed backup does not stop the
+/// schedule.
+#[cfg(all(feature = "timer", feature = "vfs"))]
+#[derive(Clone, Debug)]
+pub struct KvBackups {
+    kv: Kv,
+    interval: std::time::Duration,
+    keep_last_n: usize,
+    vfs_dir: String,
+}
+
+#[cfg(all(feature = "timer", feature = "vfs"))]
+impl KvBackups {
+    /// If `message` is this schedule's timer firing, take a backup, prune
+    /// old ones, schedule the next, and return the backup's result.
+    /// Otherwise return `None`.
+    pub fn handle(&self, message: &Message) -> Option<Result<String, ProcessLibError>> {
+        if message.is_request()
+            || !message.source().is_process("timer", "distro", "sys")
+            || message.context() != Some(self.context().as_slice())
+        {
+            return None;
+        }
+        let result = self.run_now();
+        self.schedule();
+        Some(result)
+    }
+
+    /// Take a backup now, prune old ones, and return the new backup's path.
+    pub fn run_now(&self) -> Result<String, ProcessLibError> {
+        let path = format!(
+            "{}/{}-{:020}.kvbak",
+            self.vfs_dir,
+            self.kv.db,
+            crate::time::now_millis()
+        );
+        self.kv.backup_to(&path)?;
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Paths of this db's backups, oldest first.
+    pub fn list(&self) -> Result<Vec<String>, ProcessLibError> {
+        let prefix = format!("{}-", self.kv.db);
+        let mut names: Vec<String> = crate::vfs::open_dir(&self.vfs_dir, true)?
+            .read()?
+            .into_iter()
+            .filter_map(|entry| {
+                let name = entry.path.rsplit('/').next()?.to_string();
+                let stamp = name.strip_prefix(&prefix)?.strip_suffix(".kvbak")?;
+                // a db named e.g. "a" must not pick up backups of "a-b"
+                stamp.bytes().all(|b| b.is_ascii_digit()).then_some(name)
+            })
+            .collect();
+        names.sort();
+        Ok(names
+            .into_iter()
+            .map(|name| format!("{}/{}", self.vfs_dir, name))
+            .collect())
+    }
+
+    fn prune(&self) -> Result<(), ProcessLibError> {
+        let backups = self.list()?;
+        let excess = backups.len().saturating_sub(self.keep_last_n);
+        for path in &backups[..excess] {
+            crate::vfs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn schedule(&self) {
+        crate::timer::set_timer_for(self.interval, Some(self.context()));
+    }
+
+    /// Timer context, per db so several schedules can run in one process.
+    fn context(&self) -> Vec<u8> {
+        format!("process_lib:kv_backup:{}", self.kv.db).into_bytes()
+    }
+}
+
+/// How long, in seconds, [`Kv::replicate_to()`] waits for each piece to be
+/// acknowledged, which includes the restore after the last.
+#[cfg(feature = "vfs")]
+const REPLICA_CHUNK_TIMEOUT: u64 = 60;
+
+/// One piece of a backup sent by [`Kv::replicate_to()`], with the piece as
+/// the blob. Answered with a [`KvResponse`].
+#[cfg(feature = "vfs")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KvReplicaChunk {
+    pub db: String,
+    /// Where the piece starts in the backup.
+    pub offset: u64,
+    pub last: bool,
+}
+
+/// Receives dbs sent with [`Kv::replicate_to()`] by processes of our own
+/// package on other nodes, and restores each over our db of the same name.
+/// Pieces are staged in a vfs directory until the last arrives.
+///
+/// ```ignore
+/// let replicas = KvReplicaReceiver::new(&our, "/app:app:pub.os/replicas", vec!["backup.os".into()]);
+/// loop {
+///     let message = await_message()?;
+///     if let Some(result) = replicas.handle(&message) {
+///         if let Err(e) = result { println!("replica failed: {e}"); }
+///         continue;
+///     }
+///     ...
+/// }
+/// ```
+#[cfg(feature = "vfs")]
+#[derive(Clone, Debug)]
+pub struct KvReplicaReceiver {
+    package_id: PackageId,
+    staging_dir: String,
+    allowed: Vec<String>,
+}
+
+#[cfg(feature = "vfs")]
+impl KvReplicaReceiver {
+    /// Accept replicas from the nodes in `allowed` only, since a replica
+    /// replaces the whole db.
+    pub fn new(our: &Address, staging_dir: &str, allowed: Vec<String>) -> Self {
+        KvReplicaReceiver {
+            package_id: our.package_id(),
+            staging_dir: staging_dir.trim_end_matches('/').to_string(),
+            allowed,
+        }
+    }
+
+    /// If `message` is a piece of a replica, store it, restore the db if it
+    /// was the last, answer the sender, and return whether the db was
+    /// restored. Otherwise return `None`.
+    pub fn handle(&self, message: &Message) -> Option<Result<bool, ProcessLibError>> {
+        if !message.is_request() {
+            return None;
+        }
+        let chunk: KvReplicaChunk = serde_json::from_slice(message.body()).ok()?;
+        let result = self.receive(message.source(), &chunk);
+        let response = match &result {
+            Ok(_) => KvResponse::Ok,
+            Err(ProcessLibError::Module(ModuleError::Kv(error))) => KvResponse::Err {
+                error: error.clone(),
+            },
+            Err(e) => KvResponse::Err {
+                error: KvError::IOError {
+                    error: e.to_string(),
+                },
+            },
+        };
+        let _ = crate::Response::new()
+            .body(serde_json::to_vec(&response).unwrap())
+            .send();
+        Some(result)
+    }
+
+    fn receive(&self, source: &Address, chunk: &KvReplicaChunk) -> Result<bool, ProcessLibError> {
+        if source.package_id() != self.package_id
+            || !self.allowed.iter().any(|node| node == source.node())
+        {
+            return Err(KvError::NoCap {
+                error: format!("{} may not replicate to us", source),
+            }
+            .into());
+        }
+        if chunk.db.is_empty() || chunk.db.contains('/') || chunk.db.starts_with('.') {
+            return Err(KvError::InputError {
+                error: format!("bad db name {:?}", chunk.db),
+            }
+            .into());
+        }
+        let path = format!(
+            "{}/{}-{}.replica",
+            self.staging_dir,
+            source.node(),
+            chunk.db
+        );
+        let mut file = if chunk.offset == 0 {
+            crate::vfs::create_file(&path)?
+        } else {
+            crate::vfs::open_file(&path, false)?
+        };
+        let staged = file.metadata()?.len;
+        if staged != chunk.offset {
+            return Err(KvError::InputError {
+                error: format!(
+                    "piece at {} arrived with {} bytes staged",
+                    chunk.offset, staged
+                ),
+            }
+            .into());
+        }
+        file.append(&get_blob().map(|blob| blob.bytes).unwrap_or_default())?;
+        if !chunk.last {
+            return Ok(false);
+        }
+        let restored = Kv::assume_open(self.package_id.clone(), &chunk.db).restore_from(&path);
+        let _ = crate::vfs::remove_file(&path);
+        restored.map(|()| true)
+    }
+}
+
+/// A [`Kv`] handle that adds a prefix to every key, from [`Kv::namespace()`].
+/// Prefixes are not checked for overlap, so pick ones where none starts
+/// with another, e.g. by ending each in a separator like `:`.
+#[derive(Clone, Debug)]
+pub struct KvNamespace {
+    kv: Kv,
+    prefix: Vec<u8>,
+}
+
+impl KvNamespace {
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        self.kv.get(self.full_key(&key))
+    }
+
+    /// Set a value, optionally in a transaction begun on [`KvNamespace::kv()`].
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
+        self.kv.set(self.full_key(&key), value, tx_id)
+    }
+
+    /// Delete a value, optionally in a transaction begun on [`KvNamespace::kv()`].
+    pub fn delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> Result<(), ProcessLibError> {
+        self.kv.delete(self.full_key(&key), tx_id)
+    }
+
+    pub fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        old: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool, ProcessLibError> {
+        self.kv.compare_and_swap(self.full_key(&key), old, new)
+    }
+
+    /// Iterate over the entries in this namespace whose keys, without the
+    /// namespace's prefix, start with `prefix`.
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), ProcessLibError>> {
+        let strip = self.prefix.len();
+        self.kv
+            .scan_prefix(&self.full_key(prefix))
+            .map(move |entry| entry.map(|(key, value)| (key[strip..].to_vec(), value)))
+    }
+
+    /// Iterate over every entry in this namespace.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), ProcessLibError>> {
+        self.scan_prefix(&[])
+    }
+
+    /// A namespace inside this one, with both prefixes.
+    pub fn namespace(&self, prefix: &str) -> KvNamespace {
+        KvNamespace {
+            kv: self.kv.clone(),
+            prefix: self.full_key(prefix.as_bytes()),
+        }
+    }
+
+    /// The whole db, for transactions.
+    pub fn kv(&self) -> &Kv {
+        &self.kv
+    }
+
+    /// A key in the whole db.
+    pub fn full_key(&self, key: &[u8]) -> Vec<u8> {
+        [self.prefix.as_slice(), key].concat()
+    }
+}
+
+/// Prefix of the keys a [`KvTtl`] uses to index entries by expiry time.
+const TTL_INDEX_PREFIX: &[u8] = b"\0kv_ttl:at:";
+/// Prefix of the keys a [`KvTtl`] uses to store each entry's expiry time.
+const TTL_EXPIRY_PREFIX: &[u8] = b"\0kv_ttl:key:";
+
+/// Expiring keys for runtimes whose kv has no native TTL. Each entry set
+/// with a TTL is also written to an index, under a reserved prefix, ordered
+/// by expiry time; [`KvTtl::sweep_expired()`] deletes every entry whose time
+/// has passed. Call it periodically, e.g. each time a timer fires:
+///
+/// ```ignore
+/// let sessions = KvTtl::new(kv::open(our.package_id(), "sessions")?);
+/// sessions.set(token, session, Duration::from_secs(3600))?;
+/// // in the timer handler
+/// sessions.sweep_expired()?;
+/// timer::set_timer(60_000, None);
+/// ```
+///
+/// An expired entry that has not been swept yet is already reported as not
+/// found by [`KvTtl::get()`], so expiry takes effect on time either way.
+/// Keys must not start with `\0kv_ttl:`.
+#[derive(Clone, Debug)]
+pub struct KvTtl {
+    kv: Kv,
+}
+
+impl KvTtl {
+    pub fn new(kv: Kv) -> Self {
+        KvTtl { kv }
+    }
+
+    /// Get a value, unless it has expired.
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        if let Some(expires_at) = self.expires_at(&key)? {
+            if expires_at.has_passed() {
+                return Err(KvError::KeyNotFound {
+                    db: self.kv.db.clone(),
+                    key: show_key(&key),
+                }
+                .into());
+            }
+        }
+        self.kv.get(key)
+    }
+
+    /// Set a value that expires after `ttl`, replacing any earlier expiry.
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: std::time::Duration,
+    ) -> Result<(), ProcessLibError> {
+        self.set_until(key, value, Timestamp::now() + ttl)
+    }
+
+    /// Set a value that expires at `expires_at`, replacing any earlier expiry.
+    pub fn set_until(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Timestamp,
+    ) -> Result<(), ProcessLibError> {
+        let old = self.expires_at(&key)?;
+        let mut tx = self.kv.buffered_tx()?;
+        if let Some(old) = old {
+            tx.delete(ttl_index_key(old, &key));
+        }
+        tx.set(ttl_index_key(expires_at, &key), vec![]);
+        tx.set(
+            ttl_expiry_key(&key),
+            expires_at.as_millis().to_be_bytes().to_vec(),
+        );
+        tx.set(key, value);
+        tx.commit()
+    }
+
+    /// Set a value that never expires, removing any expiry it had.
+    pub fn set_persistent(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ProcessLibError> {
+        let old = self.expires_at(&key)?;
+        let mut tx = self.kv.buffered_tx()?;
+        if let Some(old) = old {
+            tx.delete(ttl_index_key(old, &key));
+            tx.delete(ttl_expiry_key(&key));
+        }
+        tx.set(key, value);
+        tx.commit()
+    }
+
+    /// Delete a value and its expiry.
+    pub fn delete(&self, key: Vec<u8>) -> Result<(), ProcessLibError> {
+        let old = self.expires_at(&key)?;
+        let mut tx = self.kv.buffered_tx()?;
+        if let Some(old) = old {
+            tx.delete(ttl_index_key(old, &key));
+            tx.delete(ttl_expiry_key(&key));
+        }
+        tx.delete(key);
+        tx.commit()
+    }
+
+    /// Time left before a value expires, or `None` if it never does.
+    pub fn ttl(&self, key: &[u8]) -> Result<Option<std::time::Duration>, ProcessLibError> {
+        Ok(self.expires_at(key)?.map(|expires_at| expires_at.until()))
+    }
+
+    /// Delete every entry whose expiry has passed, in one transaction, and
+    /// return how many were deleted.
+    pub fn sweep_expired(&self) -> Result<usize, ProcessLibError> {
+        let end = ttl_index_key(Timestamp::now() + std::time::Duration::from_millis(1), &[]);
+        let expired = self
+            .kv
+            .range(TTL_INDEX_PREFIX.to_vec(), Some(end))
+            .map(|entry| entry.map(|(index_key, _)| index_key))
+            .collect::<Result<Vec<_>, _>>()?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        let mut tx = self.kv.buffered_tx()?;
+        for index_key in &expired {
+            let key = index_key[TTL_INDEX_PREFIX.len() + 8..].to_vec();
+            tx.delete(ttl_expiry_key(&key));
+            tx.delete(key);
+            tx.delete(index_key.clone());
+        }
+        tx.commit()?;
+        Ok(expired.len())
+    }
+
+    /// The underlying db.
+    pub fn kv(&self) -> &Kv {
+        &self.kv
+    }
+
+    /// When a value expires, or `None` if it never does.
+    pub fn expires_at(&self, key: &[u8]) -> Result<Option<Timestamp>, ProcessLibError> {
+        match self.kv.get(ttl_expiry_key(key)) {
+            Ok(bytes) => {
+                let bytes: [u8; 8] = bytes.try_into().map_err(|_| KvError::InputError {
+                    error: "malformed ttl expiry".to_string(),
+                })?;
+                Ok(Some(Timestamp::from_millis(u64::from_be_bytes(bytes))))
+            }
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Big-endian, so that index keys sort by expiry time.
+fn ttl_index_key(expires_at: Timestamp, key: &[u8]) -> Vec<u8> {
+    [TTL_INDEX_PREFIX, &expires_at.as_millis().to_be_bytes(), key].concat()
+}
+
+fn ttl_expiry_key(key: &[u8]) -> Vec<u8> {
+    [TTL_EXPIRY_PREFIX, key].concat()
+}
+
+/// A [`Kv`] handle that keeps the most recently used values in process
+/// memory, so that reads of hot keys cost no round trip. Writes go through
+/// to the db before the cache is updated.
+///
+/// If other processes write to the db, enable [`CachedKv::watch()`] and
+/// pass incoming messages to [`CachedKv::handle()`], so their writes evict
+/// the keys they change:
+///
+/// ```ignore
+/// let config = CachedKv::new(kv::open(our.package_id(), "config")?, 1000);
+/// config.watch()?;
+/// loop {
+///     let message = await_message()?;
+///     if config.handle(&message) {
+///         continue;
+///     }
+///     let limit = config.get(b"rate_limit".to_vec())?;
+///     ...
+/// }
+/// ```
+///
+/// This handle's own writes are reported by the watch too, so each evicts
+/// the value just cached and the next read of that key goes to the db.
+/// Writes made through another handle to the same db, e.g. in a
+/// transaction, are only seen once the watch reports them.
+#[derive(Debug)]
+pub struct CachedKv {
+    kv: Kv,
+    cache: std::cell::RefCell<LruCache>,
+}
+
+impl CachedKv {
+    /// Cache up to `capacity` values.
+    pub fn new(kv: Kv, capacity: usize) -> Self {
+        CachedKv {
+            kv,
+            cache: std::cell::RefCell::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Get a value, from the cache if it is there.
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        if let Some(value) = self.cache.borrow_mut().get(&key) {
+            return Ok(value.clone());
+        }
+        let value = self.kv.get(key.clone())?;
+        self.cache.borrow_mut().insert(key, value.clone());
+        Ok(value)
+    }
+
+    /// Set a value, optionally in a transaction begun on [`CachedKv::kv()`].
+    /// A value set in a transaction is evicted rather than cached, since it
+    /// is not visible until the transaction commits.
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
+        if tx_id.is_some() {
+            self.invalidate(&key);
+            return self.kv.set(key, value, tx_id);
+        }
+        self.kv.set(key.clone(), value.clone(), None)?;
+        self.cache.borrow_mut().insert(key, value);
+        Ok(())
+    }
+
+    /// Delete a value, optionally in a transaction begun on [`CachedKv::kv()`].
+    pub fn delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> Result<(), ProcessLibError> {
+        self.invalidate(&key);
+        self.kv.delete(key, tx_id)
+    }
+
+    /// Drop a key from the cache, so its next read goes to the db.
+    pub fn invalidate(&self, key: &[u8]) {
+        self.cache.borrow_mut().remove(key);
+    }
+
+    /// Drop every key from the cache.
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// How many values are cached.
+    pub fn len(&self) -> usize {
+        self.cache.borrow().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Watch the db, so that writes to it by other processes reach
+    /// [`CachedKv::handle()`].
+    pub fn watch(&self) -> Result<(), ProcessLibError> {
+        self.kv.watch()
+    }
+
+    /// If `message` is a [`KvWatchEvent`] for this db, evict the key it
+    /// names and return `true`.
+    pub fn handle(&self, message: &Message) -> bool {
+        match parse_watch_event(message) {
+            Some(event) if event.package_id == self.kv.package_id && event.db == self.kv.db => {
+                self.invalidate(&event.key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The underlying db, for transactions.
+    pub fn kv(&self) -> &Kv {
+        &self.kv
+    }
+}
+
+/// Values by key, evicting the least recently used once full.
+#[derive(Debug)]
+struct LruCache {
+    capacity: usize,
+    /// Incremented on every use, so a lower tick means less recently used.
+    tick: u64,
+    entries: std::collections::HashMap<Vec<u8>, (Vec<u8>, u64)>,
+    by_use: std::collections::BTreeMap<u64, Vec<u8>>,
+}
+
+impl LruCache {
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            tick: 0,
+            entries: std::collections::HashMap::new(),
+            by_use: std::collections::BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<&Vec<u8>> {
+        let tick = self.next_tick();
+        let (value, used) = self.entries.get_mut(key)?;
+        let key = self.by_use.remove(used)?;
+        *used = tick;
+        self.by_use.insert(tick, key);
+        Some(value)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        let tick = self.next_tick();
+        self.by_use.insert(tick, key.clone());
+        self.entries.insert(key, (value, tick));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.by_use.remove(&used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.by_use.clear();
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/// The timeout of a remote handle serialized without one.
+fn default_remote_timeout() -> u64 {
+    30
+}
+
+/// A handle to a kv db on another node, which that node's owner has shared
+/// with us by capability. Requests go over the network, so the default
+/// timeout is longer than a local db's, and a request that is not delivered
+/// fails with [`ProcessLibError::Remote`] rather than a [`KvError`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RemoteKv {
+    pub node: String,
+    pub package_id: PackageId,
+    pub db: String,
+    /// Timeout, in seconds, for every request made through this handle.
+    #[serde(default = "default_remote_timeout")]
+    pub timeout: u64,
+}
+
+impl RemoteKv {
+    /// A handle to the db `db` of package `package_id` on `node`. Nothing is
+    /// sent until the first request.
+    pub fn new(node: &str, package_id: PackageId, db: &str) -> Self {
+        RemoteKv {
+            node: node.to_string(),
+            package_id,
+            db: db.to_string(),
+            timeout: 30,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get a value.
+    pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+        match self.send(KvAction::Get { key: key.clone() }, None, Some(&key))? {
+            KvResponse::Get { .. } => match get_blob() {
+                Some(bytes) => Ok(bytes.bytes),
+                None => Err(ProcessLibError::NoBlob),
+            },
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Set a value, optionally in a transaction.
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        tx_id: Option<u64>,
+    ) -> Result<(), ProcessLibError> {
+        let action = KvAction::Set {
+            key: key.clone(),
+            tx_id,
+        };
+        match self.send(action, Some(value), Some(&key))? {
+            KvResponse::Ok => Ok(()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Delete a value, optionally in a transaction.
+    pub fn delete(&self, key: Vec<u8>, tx_id: Option<u64>) -> Result<(), ProcessLibError> {
+        let action = KvAction::Delete {
+            key: key.clone(),
+            tx_id,
+        };
+        match self.send(action, None, Some(&key))? {
+            KvResponse::Ok => Ok(()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Send an action to the remote kv module, returning its response unless
+    /// it is an error.
+    fn send(
+        &self,
+        action: KvAction,
+        blob: Option<Vec<u8>>,
+        key: Option<&[u8]>,
+    ) -> Result<KvResponse, ProcessLibError> {
+        let mut request = Request::new()
+            .target((self.node.as_str(), "kv", "distro", "sys"))
+            .body(serde_json::to_vec(&KvRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action,
+            })?);
+        if let Some(blob) = blob {
+            request = request.blob_bytes(blob);
+        }
+        let res = request
+            .send_and_await_response(self.timeout)?
+            .map_err(|e| RemoteError::new(&self.node, e))?;
+
+        match res {
+            Message::Response { body, .. } => match serde_json::from_slice::<KvResponse>(&body)? {
+                KvResponse::Err { error } => Err(error.with_context(&self.db, key).into()),
+                response => Ok(response),
+            },
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+}
+
+/// How many entries a [`KvIter`] fetches per request, unless set with
+/// [`KvIter::page_size()`].
+const KV_ITER_PAGE_SIZE: usize = 100;
+
+/// An iterator over a range of a kv db's entries, from [`Kv::range()`] or
+/// [`Kv::scan_prefix()`]. Fetches entries from the runtime a page at a time,
+/// so it can walk a large db without holding it in memory. A failed fetch is
+/// yielded as an error and ends the iteration.
+pub struct KvIter {
+    package_id: PackageId,
+    db: String,
+    /// Where the next page starts, or `None` once the range is exhausted.
+    next_start: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+    page_size: usize,
+    page: std::collections::VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl KvIter {
+    /// Fetch `page_size` entries per request.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    fn fetch_page(&mut self) -> Result<(), ProcessLibError> {
+        let Some(start) = self.next_start.take() else {
+            return Ok(());
+        };
+        let res = Request::new()
+            .target(("our", "kv", "distro", "sys"))
+            .body(serde_json::to_vec(&KvRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: KvAction::Range {
+                    start,
+                    end: self.end.clone(),
+                    limit: self.page_size,
+                },
+            })?)
+            .send_and_await_response(5)??;
+
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+                match response {
+                    KvResponse::Range { more } => {
+                        let bytes = match get_blob() {
+                            Some(bytes) => bytes.bytes,
+                            None => return Err(ProcessLibError::NoBlob),
+                        };
+                        let entries: Vec<(Vec<u8>, Vec<u8>)> = serde_json::from_slice(&bytes)?;
+                        if more {
+                            // the smallest key after the last one we have
+                            self.next_start = entries.last().map(|(key, _)| {
+                                let mut next = key.clone();
+                                next.push(0);
+                                next
+                            });
+                        }
+                        self.page.extend(entries);
+                        Ok(())
+                    }
+                    KvResponse::Err { error } => Err(error.with_context(&self.db, None).into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+}
+
+impl Iterator for KvIter {
+    type Item = Result<(Vec<u8>, Vec<u8>), ProcessLibError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() {
+            if let Err(e) = self.fetch_page() {
+                return Some(Err(e));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+/// The first key after every key starting with `prefix`, or `None` if there
+/// is no such key, i.e. the prefix is empty or all `0xff`.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Opens or creates a kv db.
+pub fn open(package_id: PackageId, db: &str) -> Result<Kv, ProcessLibError> {
+    let res = Request::new()
+        .target(("our", "kv", "distro", "sys"))
+        .body(serde_json::to_vec(&KvRequest {
+            package_id: package_id.clone(),
+            db: db.to_string(),
+            action: KvAction::Open,
+        })?)
+        .send_and_await_response(5)??;
+
+    match res {
+        Message::Response { body, .. } => {
+            let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+            match response {
+                KvResponse::Ok => Ok(Kv {
+                    package_id,
+                    db: db.to_string(),
+                }),
+                KvResponse::Err { error } => Err(error.with_context(db, None).into()),
+                _ => Err(ProcessLibError::unexpected(response)),
+            }
+        }
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
+
+/// A handle that can only read from a kv db, which may belong to another
+/// package that has granted us a read capability. Unlike [`open()`], nothing
+/// is sent, so the db is never created: if it does not exist, or we lack
+/// the capability, requests through the handle fail.
+pub fn open_read_only(package_id: PackageId, db: &str) -> KvRead {
+    KvRead {
+        package_id,
+        db: db.to_string(),
+    }
+}
+
+/// Removes and deletes a kv db.
+pub fn remove_db(package_id: PackageId, db: &str) -> Result<(), ProcessLibError> {
+    let res = Request::new()
+        .target(("our", "kv", "distro", "sys"))
+        .body(serde_json::to_vec(&KvRequest {
+            package_id: package_id.clone(),
+            db: db.to_string(),
+            action: KvAction::RemoveDb,
+        })?)
+        .send_and_await_response(5)??;
+
+    match res {
+        Message::Response { body, .. } => {
+            let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+            match response {
+                KvResponse::Ok => Ok(()),
+                KvResponse::Err { error } => Err(error.with_context(db, None).into()),
+                _ => Err(ProcessLibError::unexpected(response)),
+            }
+        }
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
+
+fn get(package_id: &PackageId, db: &str, key: Vec<u8>) -> Result<Vec<u8>, ProcessLibError> {
+    let res = Request::new()
+        .target(("our", "kv", "distro", "sys"))
+        .body(serde_json::to_vec(&KvRequest {
+            package_id: package_id.clone(),
+            db: db.to_string(),
+            action: KvAction::Get { key: key.clone() },
+        })?)
+        .send_and_await_response(5)??;
+
+    match res {
+        Message::Response { body, .. } => {
+            let response = serde_json::from_slice::<KvResponse>(&body)?;
+
+            match response {
+                KvResponse::Get { .. } => {
+                    let bytes = match get_blob() {
+                        Some(bytes) => bytes.bytes,
+                        None => return Err(ProcessLibError::NoBlob),
+                    };
+                    Ok(bytes)
+                }
+                KvResponse::Err { error } => Err(error.with_context(db, Some(&key)).into()),
+                _ => Err(ProcessLibError::unexpected(response)),
+            }
+        }
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
+
+#[cfg(all(test, feature = "vfs"))]
+mod tests {
+    use super::*;
+
+    fn csv(input: &str) -> Result<Vec<Vec<String>>, ProcessLibError> {
+        let mut parser = CsvParser::default();
+        let mut rows = vec![];
+        for &byte in input.as_bytes() {
+            rows.extend(parser.push(byte)?);
+        }
+        rows.extend(parser.finish()?);
+        Ok(rows)
+    }
+
+    fn json(input: &str) -> Result<(Vec<String>, bool), ProcessLibError> {
+        let mut splitter = JsonArraySplitter::default();
+        let mut elements = vec![];
+        for &byte in input.as_bytes() {
+            if let Some(element) = splitter.push(byte)? {
+                elements.push(String::from_utf8(element).unwrap());
+            }
+        }
+        Ok((elements, splitter.done))
+    }
+
+    #[test]
+    fn csv_splits_rows_and_fields() {
+        assert_eq!(csv("a,b\n1,2\n").unwrap(), [["a", "b"], ["1", "2"]]);
+        assert_eq!(csv("a,b\r\n1,\r\n").unwrap(), [["a", "b"], ["1", ""]]);
+        // no final newline, and blank lines skipped
+        assert_eq!(csv("\na\n\nb").unwrap(), [["a"], ["b"]]);
+        assert!(csv("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn csv_handles_quotes() {
+        assert_eq!(csv("a,\"b,c\"\n").unwrap(), [["a", "b,c"]]);
+        assert_eq!(csv("\"say \"\"hi\"\"\"\n").unwrap(), [["say \"hi\""]]);
+        assert_eq!(csv("\"x\ny\",z").unwrap(), [["x\ny", "z"]]);
+        assert_eq!(csv("\"\"").unwrap(), Vec::<Vec<String>>::new());
+        assert!(csv("\"abc").is_err());
+    }
+
+    #[test]
+    fn csv_rejects_invalid_utf8() {
+        let mut parser = CsvParser::default();
+        assert!(parser.push(0xff).unwrap().is_none());
+        assert!(parser.push(b'\n').is_err());
+    }
+
+    #[test]
+    fn json_splits_elements() {
+        let (elements, done) = json(r#" [1, {"a": [2, 3]}, "x,]", "a\"]"] "#).unwrap();
+        assert_eq!(elements, ["1", r#"{"a": [2, 3]}"#, r#""x,]""#, r#""a\"]""#]);
+        assert!(done);
+        assert_eq!(json("[]").unwrap(), (vec![], true));
+        assert_eq!(json("[1, 2").unwrap(), (vec!["1".to_string()], false));
+    }
+
+    #[test]
+    fn json_rejects_malformed_arrays() {
+        assert!(json("{}").is_err());
+        assert!(json("[1,,2]").is_err());
+        assert!(json("[1] x").is_err());
     }
 }
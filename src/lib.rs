@@ -22,25 +22,86 @@ wit_bindgen::generate!({
     world: "lib",
 });
 
+/// Manage installed packages through the app store
+#[cfg(feature = "apps")]
+pub mod apps;
+/// Mirror a kv prefix into a graphdb table
+#[cfg(all(feature = "graphdb", feature = "kv"))]
+pub mod bridge;
+/// Conflict-free replicated data types and a sync protocol for them
+pub mod crdt;
+/// Symmetric encryption, key derivation and message signing
+#[cfg(feature = "crypto")]
+pub mod crypto;
 /// Interact with the eth provider module.
 #[cfg(feature = "eth")]
 pub mod eth;
 /// Interact with the graphdb module
+#[cfg(feature = "graphdb")]
 pub mod graphdb;
+/// Periodic liveness beats and pings between processes
+#[cfg(feature = "timer")]
+pub mod heartbeat;
 /// Interact with the HTTP server and client modules.
 /// Contains types from the `http` crate to use as well.
+#[cfg(feature = "http")]
 pub mod http;
+/// Sortable unique ids (ULID, UUIDv7) for database records
+pub mod ids;
+/// Commands to the kernel, such as killing a process
+pub mod kernel;
 /// The types that the kernel itself uses -- warning -- these will
 /// be incompatible with WIT types in some cases, leading to annoying errors.
 /// Use only to interact with the kernel or runtime in certain ways.
 pub mod kernel_types;
+/// Encode tuples into kv keys that sort in the same order as their values
+pub mod keycode;
 /// Interact with the key_value module
+#[cfg(feature = "kv")]
 pub mod kv;
+/// Interact with an LLM inference runtime
+#[cfg(feature = "llm")]
+pub mod llm;
+/// Read a package's manifest and metadata from vfs
+#[cfg(feature = "vfs")]
+pub mod manifest;
+/// Process-local metrics and a standard scrape responder
+#[cfg(feature = "metrics")]
+pub mod metrics;
+/// Requests to the networking module: signing and peer presence
+pub mod net;
+/// Run a package's Python scripts through a runner process
+#[cfg(feature = "python")]
+pub mod python;
+/// Record sent and received messages to the vfs, and replay them
+#[cfg(feature = "vfs")]
+pub mod recorder;
+/// Typed request/response protocols between processes, from `remote_api!`
+pub mod remote_api;
+/// Multi-step workflows with compensation, persisted in kv
+#[cfg(all(feature = "kv", feature = "timer"))]
+pub mod saga;
+/// Argument parsing and an entry point for terminal scripts
+pub mod script;
+/// Encrypted, versioned secret storage in kv
+#[cfg(all(feature = "crypto", feature = "kv"))]
+pub mod secrets;
+/// Typed process settings, persisted in kv and exposed to other processes
+#[cfg(feature = "settings")]
+pub mod settings;
 /// Interact with the sqlite module
+#[cfg(feature = "sqlite")]
 pub mod sqlite;
+/// In-memory mock of the message-passing layer for unit tests.
+#[cfg(feature = "testing")]
+pub mod testing;
+/// Clock helpers and a serializable timestamp type
+pub mod time;
 /// Interact with the timer runtime module.
+#[cfg(feature = "timer")]
 pub mod timer;
 /// Interact with the virtual filesystem
+#[cfg(feature = "vfs")]
 pub mod vfs;
 
 // Types
@@ -50,7 +111,7 @@ pub use package_id::PackageId;
 mod process_id;
 pub use process_id::{ProcessId, ProcessIdParseError};
 mod address;
-pub use address::{Address, AddressParseError};
+pub use address::{Address, AddressBuilder, AddressParseError, AddressPattern};
 mod request;
 pub use request::Request;
 mod response;
@@ -62,6 +123,43 @@ mod on_exit;
 pub use on_exit::OnExit;
 mod capability;
 pub use capability::Capability;
+mod blob;
+pub use blob::Blob;
+mod error;
+pub use error::{ModuleError, ProcessLibError, RemoteError};
+mod waiter;
+pub use waiter::ResponseWaiter;
+mod dedup;
+pub use dedup::Dedup;
+mod retry;
+pub use retry::RetryPolicy;
+#[cfg(feature = "timer")]
+mod debounce;
+#[cfg(feature = "timer")]
+pub use debounce::{Debouncer, Throttle};
+#[cfg(feature = "kv")]
+mod dead_letter;
+#[cfg(feature = "kv")]
+pub use dead_letter::{DeadLetter, DeadLetterEntry};
+#[cfg(feature = "kv")]
+mod event_store;
+#[cfg(feature = "kv")]
+pub use event_store::{EventSourced, EventStore, SnapshotStore};
+#[cfg(all(feature = "kv", feature = "timer"))]
+mod outbox;
+#[cfg(all(feature = "kv", feature = "timer"))]
+pub use outbox::{Outbox, OutboxEntry};
+mod rate_limit;
+pub use rate_limit::RateLimiter;
+mod shutdown;
+pub use shutdown::{on_shutdown, run_shutdown_handlers, shutdown_guard, ShutdownGuard};
+mod worker_pool;
+pub use worker_pool::{Dispatch, WorkerPool, WorkerPoolError};
+
+// With the `testing` feature, these shadow the wit imports of the same name,
+// so every send and receive in this crate goes through the mock instead.
+#[cfg(feature = "testing")]
+pub use testing::{get_blob, receive, send_and_await_response, send_request, send_response};
 
 /// Implement the wit-bindgen specific code that the kernel uses to hook into
 /// a process. Write an `init(our: Address)` function and call it with this.
@@ -100,7 +198,13 @@ macro_rules! println {
 ///
 /// TODO: example of usage
 pub fn await_message() -> Result<Message, SendError> {
-    match crate::receive() {
+    // the previous message is handled, so its blob may now be replaced; a
+    // failed write can't be returned as a SendError, so it is printed
+    #[cfg(feature = "vfs")]
+    if let Err(e) = recorder::flush() {
+        print_to_terminal(0, &format!("recorder: failed to write recording: {}", e));
+    }
+    let message = match crate::receive() {
         Ok((source, message)) => Ok(wit_message_to_message(source, message)),
         Err((send_err, context)) => Err(SendError {
             kind: match send_err.kind {
@@ -114,7 +218,10 @@ pub fn await_message() -> Result<Message, SendError> {
             lazy_load_blob: send_err.lazy_load_blob,
             context,
         }),
-    }
+    };
+    #[cfg(feature = "vfs")]
+    recorder::record_received(&message);
+    message
 }
 
 /// Simple wrapper over spawn() in WIT to make use of our good types
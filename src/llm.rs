@@ -0,0 +1,241 @@
+use crate::{get_blob, Address, Message, PackageId, ProcessLibError, Request};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Actions are sent to an LLM inference runtime process, "package_id" is the
+/// package making the request. The runtime may be local or on another node,
+/// and may front a local model or a remote inference API.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LlmRequest {
+    pub package_id: PackageId,
+    pub action: LlmAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum LlmAction {
+    /// List the models the runtime can serve.
+    ListModels,
+    /// Complete a prompt. The completion is returned in the response blob as UTF-8.
+    Complete {
+        model: String,
+        prompt: String,
+        params: CompletionParams,
+    },
+    /// Embed each of the given texts. The embeddings are returned in the
+    /// response blob as a JSON `Vec<Vec<f32>>`, in the same order as `texts`.
+    Embed { model: String, texts: Vec<String> },
+    /// Complete a prompt, streaming tokens back to the requester as
+    /// [`LlmStreamEvent`] requests tagged with `sub_id`.
+    Subscribe {
+        sub_id: u64,
+        model: String,
+        prompt: String,
+        params: CompletionParams,
+    },
+    /// Stop a streaming completion of a given ID.
+    Unsubscribe { sub_id: u64 },
+}
+
+/// Sampling parameters for a completion. Fields left as `None` use the
+/// runtime's defaults for the given model.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct CompletionParams {
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum LlmResponse {
+    Ok,
+    Models { models: Vec<String> },
+    Complete,
+    Embed,
+    Err { error: LlmError },
+}
+
+/// Requests sent by the runtime to a process that has a streaming completion open.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum LlmStreamEvent {
+    Token { sub_id: u64, token: String },
+    Done { sub_id: u64 },
+    Err { sub_id: u64, error: LlmError },
+}
+
+impl LlmStreamEvent {
+    /// Parse a byte slice into an LlmStreamEvent.
+    pub fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes)
+    }
+
+    /// The ID of the streaming completion this event belongs to.
+    pub fn sub_id(&self) -> u64 {
+        match self {
+            LlmStreamEvent::Token { sub_id, .. } => *sub_id,
+            LlmStreamEvent::Done { sub_id } => *sub_id,
+            LlmStreamEvent::Err { sub_id, .. } => *sub_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Error)]
+pub enum LlmError {
+    #[error("llm: model not found: {model}")]
+    ModelNotFound { model: String },
+    #[error("llm: No capability: {error}")]
+    NoCap { error: String },
+    #[error("llm: subscription ID already exists")]
+    SubscriptionIdCollision,
+    #[error("llm: subscription not found")]
+    SubscriptionNotFound,
+    #[error("llm: inference error: {error}")]
+    InferenceError { error: String },
+    #[error("llm: input bytes/json error: {error}")]
+    InputError { error: String },
+}
+
+/// Llm helper struct for an inference runtime.
+/// Create one with [`Llm::new()`], then call its impl functions to interact with it.
+/// By default requests go to `our@llm:distro:sys`; use [`Llm::target()`] to point
+/// at a different runtime process, e.g. one on another node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Llm {
+    pub package_id: PackageId,
+    pub target: Address,
+    pub timeout: u64,
+}
+
+impl Llm {
+    /// Create a new handle to the default local inference runtime.
+    pub fn new(package_id: PackageId) -> Self {
+        Llm {
+            package_id,
+            target: ("our", "llm", "distro", "sys").into(),
+            timeout: 60,
+        }
+    }
+
+    /// Send requests to a different inference runtime process.
+    pub fn target<T>(mut self, target: T) -> Self
+    where
+        T: Into<Address>,
+    {
+        self.target = target.into();
+        self
+    }
+
+    /// Set the timeout, in seconds, for completion and embedding requests.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// List the models the runtime can serve.
+    pub fn list_models(&self) -> Result<Vec<String>, ProcessLibError> {
+        match self.send(LlmAction::ListModels, 5)? {
+            LlmResponse::Models { models } => Ok(models),
+            LlmResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Complete a prompt and return the generated text.
+    pub fn complete(
+        &self,
+        model: &str,
+        prompt: &str,
+        params: CompletionParams,
+    ) -> Result<String, ProcessLibError> {
+        let action = LlmAction::Complete {
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            params,
+        };
+        match self.send(action, self.timeout)? {
+            LlmResponse::Complete => {
+                let blob = get_blob().ok_or_else(|| LlmError::InputError {
+                    error: "no blob".to_string(),
+                })?;
+                String::from_utf8(blob.bytes).map_err(|e| {
+                    LlmError::InputError {
+                        error: format!("gave non-UTF-8 response: {}", e),
+                    }
+                    .into()
+                })
+            }
+            LlmResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Embed each of the given texts, returning one vector per text.
+    pub fn embed(&self, model: &str, texts: Vec<String>) -> Result<Vec<Vec<f32>>, ProcessLibError> {
+        let action = LlmAction::Embed {
+            model: model.to_string(),
+            texts,
+        };
+        match self.send(action, self.timeout)? {
+            LlmResponse::Embed => {
+                let blob = get_blob().ok_or_else(|| LlmError::InputError {
+                    error: "no blob".to_string(),
+                })?;
+                let embeddings =
+                    serde_json::from_slice::<Vec<Vec<f32>>>(&blob.bytes).map_err(|e| {
+                        LlmError::InputError {
+                            error: format!("gave unparsable response: {}", e),
+                        }
+                    })?;
+                Ok(embeddings)
+            }
+            LlmResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Start a streaming completion. Tokens will arrive in the main event loop as
+    /// requests from the runtime, which can be parsed with [`LlmStreamEvent::from_bytes()`].
+    pub fn subscribe(
+        &self,
+        sub_id: u64,
+        model: &str,
+        prompt: &str,
+        params: CompletionParams,
+    ) -> Result<(), ProcessLibError> {
+        let action = LlmAction::Subscribe {
+            sub_id,
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            params,
+        };
+        match self.send(action, 5)? {
+            LlmResponse::Ok => Ok(()),
+            LlmResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Stop a streaming completion.
+    pub fn unsubscribe(&self, sub_id: u64) -> Result<(), ProcessLibError> {
+        match self.send(LlmAction::Unsubscribe { sub_id }, 5)? {
+            LlmResponse::Ok => Ok(()),
+            LlmResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    fn send(&self, action: LlmAction, timeout: u64) -> Result<LlmResponse, ProcessLibError> {
+        let res = Request::new()
+            .target(&self.target)
+            .body(serde_json::to_vec(&LlmRequest {
+                package_id: self.package_id.clone(),
+                action,
+            })?)
+            .send_and_await_response(timeout)??;
+
+        match res {
+            Message::Response { body, .. } => Ok(serde_json::from_slice::<LlmResponse>(&body)?),
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Read a package's `manifest.json` and `metadata.json` from its vfs drive,
+//! for installers and for processes that inspect their own package.
+//!
+//! ```ignore
+//! let package = manifest::load(&our.package_id())?;
+//! let me = package.process(our.process()).unwrap();
+//! for cap in me.requested_capabilities()? { ... }
+//! ```
+use crate::kernel_types::{PackageManifestEntry, PackageMetadata, PackageVersion};
+use crate::{vfs, PackageId, ProcessId, ProcessIdParseError};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("manifest: could not read {path}: {error}")]
+    Read { path: String, error: String },
+    #[error("manifest: malformed capability: {value}")]
+    BadCapability { value: String },
+    #[error("manifest: bad process id in capability: {0}")]
+    BadProcessId(#[from] ProcessIdParseError),
+    #[error("manifest: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Path of the manifest in a package's vfs drive.
+pub fn manifest_path(package_id: &PackageId) -> String {
+    format!("/{}/pkg/manifest.json", package_id)
+}
+
+/// Path of the metadata in a package's vfs drive.
+pub fn metadata_path(package_id: &PackageId) -> String {
+    format!("/{}/pkg/metadata.json", package_id)
+}
+
+/// A package's metadata and the processes its manifest declares.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub metadata: PackageMetadata,
+    pub processes: Vec<PackageManifestEntry>,
+}
+
+impl Manifest {
+    pub fn version(&self) -> PackageVersion {
+        self.metadata.version
+    }
+
+    /// The version as `major.minor.patch`.
+    pub fn version_string(&self) -> String {
+        let (major, minor, patch) = self.metadata.version;
+        format!("{}.{}.{}", major, minor, patch)
+    }
+
+    /// The manifest entry for the process named `process_name`.
+    pub fn process(&self, process_name: &str) -> Option<&PackageManifestEntry> {
+        self.processes
+            .iter()
+            .find(|entry| entry.process_name == process_name)
+    }
+}
+
+/// A capability as listed in a manifest entry's `request_capabilities` or
+/// `grant_capabilities`: either a bare process id, meaning the messaging
+/// capability, or an object with the process and params.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestCapability {
+    pub process: ProcessId,
+    /// `None` for the messaging capability.
+    pub params: Option<serde_json::Value>,
+}
+
+impl ManifestCapability {
+    pub fn parse(value: &serde_json::Value) -> Result<Self, ManifestError> {
+        match value {
+            serde_json::Value::String(process) => Ok(ManifestCapability {
+                process: process.parse()?,
+                params: None,
+            }),
+            serde_json::Value::Object(object) => {
+                let process = object
+                    .get("process")
+                    .and_then(|process| process.as_str())
+                    .ok_or_else(|| ManifestError::BadCapability {
+                        value: value.to_string(),
+                    })?;
+                Ok(ManifestCapability {
+                    process: process.parse()?,
+                    params: object.get("params").cloned(),
+                })
+            }
+            _ => Err(ManifestError::BadCapability {
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl PackageManifestEntry {
+    /// The capabilities this process asks for at install time.
+    pub fn requested_capabilities(&self) -> Result<Vec<ManifestCapability>, ManifestError> {
+        self.request_capabilities
+            .iter()
+            .map(ManifestCapability::parse)
+            .collect()
+    }
+
+    /// The capabilities to this process that it grants to others at install time.
+    pub fn granted_capabilities(&self) -> Result<Vec<ManifestCapability>, ManifestError> {
+        self.grant_capabilities
+            .iter()
+            .map(ManifestCapability::parse)
+            .collect()
+    }
+}
+
+/// Read and parse a package's `manifest.json`.
+pub fn read_manifest(package_id: &PackageId) -> Result<Vec<PackageManifestEntry>, ManifestError> {
+    let bytes = read_file(&manifest_path(package_id))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Read and parse a package's `metadata.json`.
+pub fn read_metadata(package_id: &PackageId) -> Result<PackageMetadata, ManifestError> {
+    let bytes = read_file(&metadata_path(package_id))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Read and parse both of a package's files.
+pub fn load(package_id: &PackageId) -> Result<Manifest, ManifestError> {
+    Ok(Manifest {
+        metadata: read_metadata(package_id)?,
+        processes: read_manifest(package_id)?,
+    })
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, ManifestError> {
+    vfs::open_file(path, false)
+        .and_then(|file| file.read())
+        .map_err(|e| ManifestError::Read {
+            path: path.to_string(),
+            error: e.to_string(),
+        })
+}
@@ -71,6 +71,24 @@ impl Message {
             Message::Response { capabilities, .. } => capabilities,
         }
     }
+    /// Build a request that passes this message on to `target` unchanged, for
+    /// proxies and routers. The request inherits, so it carries this message's
+    /// blob without pulling it into the process, and if this was a request that
+    /// expects a response, `target`'s response goes straight back to the
+    /// original requester. Capabilities are not forwarded.
+    ///
+    /// Must be sent before the next call to `await_message()`, since inheritance
+    /// refers to the most recently received message.
+    pub fn forward_to<T>(&self, target: T) -> Request
+    where
+        T: Into<Address>,
+    {
+        let request = Request::to(target).inherit(true).body(self.body());
+        match self.metadata() {
+            Some(metadata) => request.metadata(metadata),
+            None => request,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,214 @@
+use crate::{timer, Address, Message, ProcessLibError, Request, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Requests a monitoring process can send to any process that opts in to
+/// metrics collection. Handle them with [`Metrics::handle_message()`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum MetricsRequest {
+    /// Ask for a [`MetricsSnapshot`] of every metric this process records.
+    Scrape,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum MetricsResponse {
+    Scrape(MetricsSnapshot),
+}
+
+/// Request sent to a collector when a process pushes its metrics,
+/// see [`Metrics::push()`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetricsPush {
+    pub snapshot: MetricsSnapshot,
+}
+
+/// A point-in-time copy of a process's metrics, keyed by metric name.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub metrics: BTreeMap<String, MetricValue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MetricValue {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(Histogram),
+}
+
+/// A histogram with fixed bucket upper bounds. `counts[i]` is the number of
+/// observations `<= bounds[i]`, and the final entry of `counts` holds
+/// observations larger than every bound.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Histogram {
+    pub bounds: Vec<f64>,
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with the given bucket upper bounds.
+    pub fn new(mut bounds: Vec<f64>) -> Self {
+        bounds.sort_by(|a, b| a.total_cmp(b));
+        let counts = vec![0; bounds.len() + 1];
+        Histogram {
+            bounds,
+            counts,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Record an observation.
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Default bucket bounds for [`Metrics::observe()`], suited to latencies in milliseconds.
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0,
+];
+
+const PUSH_CONTEXT: &[u8] = b"metrics:push";
+
+/// Process-local metrics registry. Create one at startup, record into it while
+/// handling messages, and answer scrapes from a monitoring process by passing
+/// incoming messages to [`Metrics::handle_message()`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    snapshot: MetricsSnapshot,
+    push: Option<(Address, u64)>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter by 1.
+    pub fn inc(&mut self, name: &str) {
+        self.inc_by(name, 1);
+    }
+
+    /// Increment a counter by the given amount.
+    pub fn inc_by(&mut self, name: &str, amount: u64) {
+        match self.snapshot.metrics.get_mut(name) {
+            Some(MetricValue::Counter(count)) => *count += amount,
+            _ => {
+                self.snapshot
+                    .metrics
+                    .insert(name.to_string(), MetricValue::Counter(amount));
+            }
+        }
+    }
+
+    /// Set a counter to the given value, for counts kept elsewhere and
+    /// copied in, such as a kv db's operation counts.
+    pub fn set_counter(&mut self, name: &str, value: u64) {
+        self.snapshot
+            .metrics
+            .insert(name.to_string(), MetricValue::Counter(value));
+    }
+
+    /// Set a gauge to the given value.
+    pub fn set_gauge(&mut self, name: &str, value: f64) {
+        self.snapshot
+            .metrics
+            .insert(name.to_string(), MetricValue::Gauge(value));
+    }
+
+    /// Register a histogram with custom bucket bounds. Observations recorded
+    /// with [`Metrics::observe()`] before this is called use [`DEFAULT_BUCKETS`].
+    pub fn register_histogram(&mut self, name: &str, bounds: Vec<f64>) {
+        self.snapshot.metrics.insert(
+            name.to_string(),
+            MetricValue::Histogram(Histogram::new(bounds)),
+        );
+    }
+
+    /// Record an observation in a histogram.
+    pub fn observe(&mut self, name: &str, value: f64) {
+        match self.snapshot.metrics.get_mut(name) {
+            Some(MetricValue::Histogram(histogram)) => histogram.observe(value),
+            _ => {
+                let mut histogram = Histogram::new(DEFAULT_BUCKETS.to_vec());
+                histogram.observe(value);
+                self.snapshot
+                    .metrics
+                    .insert(name.to_string(), MetricValue::Histogram(histogram));
+            }
+        }
+    }
+
+    /// Get a copy of every metric recorded so far.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.snapshot.clone()
+    }
+
+    /// Handle an incoming [`MetricsRequest`], responding with a snapshot.
+    /// Also handles the timer responses set by [`Metrics::push_every()`].
+    ///
+    /// Returns `false` if the message was not meant for the metrics registry,
+    /// so it can be passed on to other handlers.
+    pub fn handle_message(&mut self, message: &Message) -> Result<bool, ProcessLibError> {
+        match message {
+            Message::Request { body, .. } => {
+                let Ok(MetricsRequest::Scrape) = serde_json::from_slice::<MetricsRequest>(body)
+                else {
+                    return Ok(false);
+                };
+                Response::new()
+                    .body(serde_json::to_vec(&MetricsResponse::Scrape(
+                        self.snapshot(),
+                    ))?)
+                    .send()?;
+                Ok(true)
+            }
+            Message::Response { context, .. } => {
+                if context.as_deref() != Some(PUSH_CONTEXT) {
+                    return Ok(false);
+                }
+                if let Some((_, interval)) = &self.push {
+                    let interval = *interval;
+                    self.push()?;
+                    timer::set_timer(interval, Some(PUSH_CONTEXT.to_vec()));
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Push a snapshot to the collector set with [`Metrics::push_every()`], if any.
+    pub fn push(&self) -> Result<(), ProcessLibError> {
+        let Some((collector, _)) = &self.push else {
+            return Ok(());
+        };
+        Request::to(collector)
+            .body(serde_json::to_vec(&MetricsPush {
+                snapshot: self.snapshot(),
+            })?)
+            .send()?;
+        Ok(())
+    }
+
+    /// Periodically push a snapshot to a collector every `interval` milliseconds,
+    /// using the timer module. Timer responses must be passed to
+    /// [`Metrics::handle_message()`] to keep the pushes going.
+    pub fn push_every(&mut self, collector: Address, interval: u64) {
+        self.push = Some((collector, interval));
+        timer::set_timer(interval, Some(PUSH_CONTEXT.to_vec()));
+    }
+
+    /// Stop periodic pushes. The pending timer will fire once more and be ignored.
+    pub fn stop_push(&mut self) {
+        self.push = None;
+    }
+}
@@ -0,0 +1,108 @@
+//! Requests to the networking module, `net:distro:sys`: signing with the
+//! node's networking key, used by `crypto::sign()`, and peer presence.
+//!
+//! To show whether a peer is online, and to hold sends to it while it is
+//! not, subscribe to its status and watch for the events:
+//!
+//! ```ignore
+//! let online = net::subscribe_peer_status("bob.os")?;
+//! loop {
+//!     let message = await_message()?;
+//!     if let Some(PeerStatus { node, online }) = net::parse_peer_status(&message) {
+//!         set_presence(&node, online);
+//!         continue;
+//!     }
+//!     ...
+//! }
+//! ```
+use crate::kernel_types::NodeId;
+use crate::{Address, Message, ProcessLibError, Request};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NetAction {
+    /// Sign the blob with our networking key. The signature covers the
+    /// requesting process's address followed by the blob.
+    Sign,
+    /// Check a signature over the blob made by `from`.
+    Verify {
+        from: Address,
+        signature: Vec<u8>,
+    },
+    /// Send the requesting process a [`PeerStatus`] each time `node` comes
+    /// online or goes offline, until unsubscribed.
+    SubscribePeerStatus {
+        node: NodeId,
+    },
+    UnsubscribePeerStatus {
+        node: NodeId,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NetResponse {
+    /// The signature is in the blob.
+    Signed,
+    Verified(bool),
+    /// Subscribed, and whether the peer is online now.
+    PeerStatus {
+        online: bool,
+    },
+    Unsubscribed,
+}
+
+/// Pushed to subscribers, as a request from `net:distro:sys`, when a peer
+/// comes online or goes offline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub node: NodeId,
+    pub online: bool,
+}
+
+/// Subscribe to a peer's [`PeerStatus`] events, and return whether it is
+/// online now. Subscribing again to the same node does nothing more.
+pub fn subscribe_peer_status(node: &str) -> Result<bool, ProcessLibError> {
+    let action = NetAction::SubscribePeerStatus {
+        node: node.to_string(),
+    };
+    match net_request(action, None)? {
+        NetResponse::PeerStatus { online } => Ok(online),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+pub fn unsubscribe_peer_status(node: &str) -> Result<(), ProcessLibError> {
+    let action = NetAction::UnsubscribePeerStatus {
+        node: node.to_string(),
+    };
+    match net_request(action, None)? {
+        NetResponse::Unsubscribed => Ok(()),
+        response => Err(ProcessLibError::unexpected(response)),
+    }
+}
+
+/// Parse a message as a [`PeerStatus`] event, or return `None` if it is not one.
+pub fn parse_peer_status(message: &Message) -> Option<PeerStatus> {
+    if !message.is_request() || !message.source().is_process("net", "distro", "sys") {
+        return None;
+    }
+    serde_json::from_slice(message.body()).ok()
+}
+
+/// Send an action to the net module, with `data` as the blob, and parse its response.
+pub(crate) fn net_request(
+    action: NetAction,
+    data: Option<&[u8]>,
+) -> Result<NetResponse, ProcessLibError> {
+    let mut request = Request::new()
+        .target(("our", "net", "distro", "sys"))
+        .body(serde_json::to_vec(&action)?);
+    if let Some(data) = data {
+        request = request.blob_bytes(data.to_vec());
+    }
+    let res = request.send_and_await_response(5)??;
+    match res {
+        Message::Response { body, .. } => Ok(serde_json::from_slice(&body)?),
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
@@ -0,0 +1,309 @@
+use crate::ids::Ulid;
+use crate::kv::{Kv, KvError};
+use crate::net::{self, PeerStatus};
+use crate::time::Timestamp;
+use crate::{timer, Address, Message, ModuleError, ProcessLibError, Request, SendError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Prefix of every key an [`Outbox`] writes.
+const KEY_PREFIX: &[u8] = b"outbox:";
+/// Context of the retry timer an [`Outbox`] sets.
+const OUTBOX_CONTEXT: &[u8] = b"process_lib:outbox";
+/// Prefix of the metadata an [`Outbox`] sends each request with.
+const DELIVERY_ID_PREFIX: &str = "outbox:";
+
+/// A request waiting in an [`Outbox`] to be delivered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub id: Ulid,
+    pub target: Address,
+    pub body: Vec<u8>,
+    pub blob: Option<Vec<u8>>,
+    /// How many times delivery has been tried.
+    pub attempts: u32,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    pub queued_at: Timestamp,
+}
+
+/// Store-and-forward delivery of requests to nodes that may be offline.
+/// Each request is kept in kv until it gets a response, and is retried
+/// when its node comes back online and on a timer, surviving restarts:
+///
+/// ```ignore
+/// let mut outbox = Outbox::new(kv::open(our.package_id(), "outbox")?, Duration::from_secs(60));
+/// outbox.resume()?;
+/// outbox.send(&Address::new("bob.os", our.process.clone()), body)?;
+/// loop {
+///     let message = await_message()?;
+///     if let Some(delivered) = outbox.handle(&message) {
+///         for (entry, response) in delivered? { ... }
+///         continue;
+///     }
+///     ...
+/// }
+/// ```
+///
+/// A request whose response is lost may be delivered more than once, so
+/// each is sent with metadata holding its id; the receiver gets it with
+/// [`Outbox::delivery_id()`] and drops repeats, e.g. with [`crate::Dedup::seen_id()`],
+/// so each request is handled exactly once. The metadata is the outbox's,
+/// so requests sent through it cannot carry their own.
+///
+/// Give the outbox a db of its own, or at least no other keys starting
+/// with `outbox:`.
+pub struct Outbox {
+    kv: Kv,
+    retry_interval: Duration,
+    /// Timeout, in seconds, for each delivery attempt.
+    timeout: u64,
+    /// Nodes whose status we have subscribed to.
+    watching: HashSet<String>,
+    timer_set: bool,
+}
+
+impl Outbox {
+    pub fn new(kv: Kv, retry_interval: Duration) -> Self {
+        Outbox {
+            kv,
+            retry_interval,
+            timeout: 5,
+            watching: HashSet::new(),
+            timer_set: false,
+        }
+    }
+
+    /// Set the timeout, in seconds, for each delivery attempt.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Pick up requests queued before a restart: watch their nodes and set
+    /// the retry timer. Call once at startup.
+    pub fn resume(&mut self) -> Result<(), ProcessLibError> {
+        let pending = self.pending()?;
+        for entry in &pending {
+            self.watch(entry.target.node());
+        }
+        if !pending.is_empty() {
+            self.schedule();
+        }
+        Ok(())
+    }
+
+    /// Queue a request and try to deliver it now. Returns its id, and the
+    /// response if it was delivered.
+    pub fn send(
+        &mut self,
+        target: &Address,
+        body: Vec<u8>,
+    ) -> Result<(Ulid, Option<Message>), ProcessLibError> {
+        self.send_with_blob(target, body, None)
+    }
+
+    /// [`Outbox::send()`] with a blob.
+    pub fn send_with_blob(
+        &mut self,
+        target: &Address,
+        body: Vec<u8>,
+        blob: Option<Vec<u8>>,
+    ) -> Result<(Ulid, Option<Message>), ProcessLibError> {
+        let entry = OutboxEntry {
+            id: Ulid::new(),
+            target: target.clone(),
+            body,
+            blob,
+            attempts: 0,
+            error: None,
+            queued_at: Timestamp::now(),
+        };
+        self.push(&entry)?;
+        let response = self.deliver(&entry)?.ok();
+        if response.is_none() {
+            self.watch(target.node());
+            self.schedule();
+        }
+        Ok((entry.id, response))
+    }
+
+    /// If `message` is a node coming online or the retry timer, try to
+    /// deliver what is waiting, and return the responses to the requests
+    /// that were delivered. Returns `None` for any other message.
+    pub fn handle(
+        &mut self,
+        message: &Message,
+    ) -> Option<Result<Vec<(OutboxEntry, Message)>, ProcessLibError>> {
+        if let Some(PeerStatus { node, online }) = net::parse_peer_status(message) {
+            if !self.watching.contains(&node) {
+                return None;
+            }
+            if !online {
+                return Some(Ok(vec![]));
+            }
+            return Some(self.flush_node(&node));
+        }
+        if message.is_request()
+            || !message.source().is_process("timer", "distro", "sys")
+            || message.context() != Some(OUTBOX_CONTEXT)
+        {
+            return None;
+        }
+        self.timer_set = false;
+        let result = self.flush_all();
+        if matches!(self.pending().map(|p| p.is_empty()), Ok(false)) {
+            self.schedule();
+        }
+        Some(result)
+    }
+
+    /// Try to deliver every waiting request, oldest first.
+    pub fn flush_all(&mut self) -> Result<Vec<(OutboxEntry, Message)>, ProcessLibError> {
+        let pending = self.pending()?;
+        self.flush(pending)
+    }
+
+    /// Try to deliver every request waiting for `node`, oldest first.
+    pub fn flush_node(
+        &mut self,
+        node: &str,
+    ) -> Result<Vec<(OutboxEntry, Message)>, ProcessLibError> {
+        let pending = self.pending_for(node)?;
+        self.flush(pending)
+    }
+
+    /// Every waiting request, oldest first.
+    pub fn pending(&self) -> Result<Vec<OutboxEntry>, ProcessLibError> {
+        self.kv
+            .scan_prefix(KEY_PREFIX)
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect()
+    }
+
+    /// The requests waiting for `node`, oldest first.
+    pub fn pending_for(&self, node: &str) -> Result<Vec<OutboxEntry>, ProcessLibError> {
+        Ok(self
+            .pending()?
+            .into_iter()
+            .filter(|entry| entry.target.node() == node)
+            .collect())
+    }
+
+    pub fn get(&self, id: &Ulid) -> Result<Option<OutboxEntry>, ProcessLibError> {
+        match self.kv.get(entry_key(id)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Give up on a request.
+    pub fn cancel(&self, id: &Ulid) -> Result<(), ProcessLibError> {
+        self.kv.delete(entry_key(id), None)
+    }
+
+    /// The outbox id of a request sent through an [`Outbox`], for the
+    /// receiver to drop repeated deliveries of the same request.
+    pub fn delivery_id(message: &Message) -> Option<Ulid> {
+        message
+            .metadata()?
+            .strip_prefix(DELIVERY_ID_PREFIX)?
+            .parse()
+            .ok()
+    }
+
+    fn flush(
+        &mut self,
+        pending: Vec<OutboxEntry>,
+    ) -> Result<Vec<(OutboxEntry, Message)>, ProcessLibError> {
+        let mut delivered = vec![];
+        // once a node is found offline, skip its other requests this round
+        let mut offline = HashSet::new();
+        for entry in pending {
+            if offline.contains(entry.target.node()) {
+                continue;
+            }
+            match self.deliver(&entry)? {
+                Ok(response) => delivered.push((entry, response)),
+                Err(_) => {
+                    offline.insert(entry.target.node().to_string());
+                }
+            }
+        }
+        if !delivered.is_empty() {
+            self.unwatch_idle()?;
+        }
+        Ok(delivered)
+    }
+
+    /// Send an entry's request. If it gets a response, the entry is removed;
+    /// if not, its attempt count and error are updated and it stays queued.
+    fn deliver(&self, entry: &OutboxEntry) -> Result<Result<Message, SendError>, ProcessLibError> {
+        let mut request = Request::new()
+            .target(entry.target.clone())
+            .body(entry.body.clone())
+            .metadata(&format!("{}{}", DELIVERY_ID_PREFIX, entry.id));
+        if let Some(blob) = &entry.blob {
+            request = request.blob_bytes(blob.clone());
+        }
+        let result = request.send_and_await_response(self.timeout)?;
+        match &result {
+            Ok(_) => self.cancel(&entry.id)?,
+            Err(e) => self.push(&OutboxEntry {
+                attempts: entry.attempts + 1,
+                error: Some(e.to_string()),
+                ..entry.clone()
+            })?,
+        }
+        Ok(result)
+    }
+
+    fn push(&self, entry: &OutboxEntry) -> Result<(), ProcessLibError> {
+        self.kv
+            .set(entry_key(&entry.id), serde_json::to_vec(entry)?, None)
+    }
+
+    /// Subscribe to a node's status, once.
+    fn watch(&mut self, node: &str) {
+        if self.watching.contains(node) {
+            return;
+        }
+        // without presence events, the retry timer still delivers
+        if net::subscribe_peer_status(node).is_ok() {
+            self.watching.insert(node.to_string());
+        }
+    }
+
+    /// Unsubscribe from the status of nodes with nothing left waiting.
+    fn unwatch_idle(&mut self) -> Result<(), ProcessLibError> {
+        let waiting: HashSet<String> = self
+            .pending()?
+            .into_iter()
+            .map(|entry| entry.target.node().to_string())
+            .collect();
+        let idle: Vec<String> = self
+            .watching
+            .iter()
+            .filter(|node| !waiting.contains(*node))
+            .cloned()
+            .collect();
+        for node in idle {
+            let _ = net::unsubscribe_peer_status(&node);
+            self.watching.remove(&node);
+        }
+        Ok(())
+    }
+
+    fn schedule(&mut self) {
+        if !self.timer_set {
+            timer::set_timer_for(self.retry_interval, Some(OUTBOX_CONTEXT.to_vec()));
+            self.timer_set = true;
+        }
+    }
+}
+
+fn entry_key(id: &Ulid) -> Vec<u8> {
+    [KEY_PREFIX, &id.to_bytes()].concat()
+}
@@ -1,3 +1,4 @@
+use crate::process_id::validate_segment;
 use crate::ProcessIdParseError;
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
@@ -34,12 +35,12 @@ impl PackageId {
 impl std::str::FromStr for PackageId {
     type Err = ProcessIdParseError;
     /// Attempt to parse a `PackageId` from a string. The string must
-    /// contain exactly two segments, where segments are strings separated
-    /// by a colon `:`. The segments cannot themselves contain colons.
-    /// Please note that while any string without colons will parse successfully
-    /// to create a `PackageId`, not all strings without colons are actually
-    /// valid usernames, which the `publisher_node` field of a `PackageId` will
-    /// always in practice be.
+    /// contain exactly two non-empty segments, where segments are strings separated
+    /// by a colon `:`. The segments cannot themselves contain colons, `@`,
+    /// whitespace, or control characters.
+    /// Please note that while other strings will parse successfully to create a
+    /// `PackageId`, not all of them are actually valid usernames, which the
+    /// `publisher_node` field of a `PackageId` will always in practice be.
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         // split string on colons into 2 segments
         let mut segments = input.split(':');
@@ -54,6 +55,8 @@ impl std::str::FromStr for PackageId {
         if segments.next().is_some() {
             return Err(ProcessIdParseError::TooManyColons);
         }
+        validate_segment(&package_name)?;
+        validate_segment(&publisher_node)?;
         Ok(PackageId {
             package_name,
             publisher_node,
@@ -61,8 +64,67 @@ impl std::str::FromStr for PackageId {
     }
 }
 
+impl TryFrom<&str> for PackageId {
+    type Error = ProcessIdParseError;
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
 impl std::fmt::Display for PackageId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{}", self.package_name, self.publisher_node)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const SEGMENT: &str = "[a-zA-Z0-9_.-]{1,16}";
+
+    proptest! {
+        #[test]
+        fn round_trips(package in SEGMENT, publisher in SEGMENT) {
+            let id = PackageId::new(&package, &publisher);
+            prop_assert_eq!(id.to_string().parse::<PackageId>(), Ok(id.clone()));
+            prop_assert_eq!(PackageId::try_from(id.to_string().as_str()), Ok(id));
+        }
+
+        #[test]
+        fn rejects_wrong_segment_counts(a in SEGMENT, b in SEGMENT, c in SEGMENT) {
+            prop_assert_eq!(a.parse::<PackageId>(), Err(ProcessIdParseError::MissingField));
+            let input = format!("{}:{}:{}", a, b, c);
+            prop_assert_eq!(input.parse::<PackageId>(), Err(ProcessIdParseError::TooManyColons));
+        }
+
+        #[test]
+        fn rejects_invalid_characters(
+            a in SEGMENT,
+            b in SEGMENT,
+            bad in prop::sample::select(vec!['@', ' ', '\r', '\u{7f}']),
+            at in 0usize..16,
+        ) {
+            let mut a = a;
+            a.insert(at.min(a.len()), bad);
+            let input = format!("{}:{}", a, b);
+            prop_assert_eq!(
+                input.parse::<PackageId>(),
+                Err(ProcessIdParseError::InvalidCharacter(bad))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_segments() {
+        for input in [":", "a:", ":b"] {
+            assert_eq!(
+                input.parse::<PackageId>(),
+                Err(ProcessIdParseError::EmptyField),
+                "{:?}",
+                input
+            );
+        }
+    }
+}
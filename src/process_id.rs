@@ -37,12 +37,15 @@ impl ProcessId {
 impl std::str::FromStr for ProcessId {
     type Err = ProcessIdParseError;
     /// Attempts to parse a `ProcessId` from a string. To succeed, the string must contain
-    /// exactly 3 segments, separated by colons `:`. The segments must not contain colons.
-    /// Please note that while any string without colons will parse successfully
-    /// to create a `ProcessId`, not all strings without colons are actually
-    /// valid usernames, which the `publisher_node` field of a `ProcessId` will
-    /// always in practice be.
+    /// exactly 3 non-empty segments, separated by colons `:`. The segments must not
+    /// contain colons, `@`, whitespace, or control characters.
+    /// Please note that while other strings will parse successfully to create a
+    /// `ProcessId`, not all of them are actually valid usernames, which the
+    /// `publisher_node` field of a `ProcessId` will always in practice be.
     fn from_str(input: &str) -> Result<Self, ProcessIdParseError> {
+        if input.is_empty() {
+            return Err(ProcessIdParseError::EmptyField);
+        }
         // split string on colons into 3 segments
         let mut segments = input.split(':');
         let process_name = segments
@@ -60,6 +63,9 @@ impl std::str::FromStr for ProcessId {
         if segments.next().is_some() {
             return Err(ProcessIdParseError::TooManyColons);
         }
+        validate_segment(&process_name)?;
+        validate_segment(&package_name)?;
+        validate_segment(&publisher_node)?;
         Ok(ProcessId {
             process_name,
             package_name,
@@ -68,6 +74,28 @@ impl std::str::FromStr for ProcessId {
     }
 }
 
+impl TryFrom<&str> for ProcessId {
+    type Error = ProcessIdParseError;
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+/// Check that a single segment of a `ProcessId`, `PackageId`, or `Address`
+/// string is non-empty and contains no separators, whitespace, or control characters.
+pub(crate) fn validate_segment(segment: &str) -> Result<(), ProcessIdParseError> {
+    if segment.is_empty() {
+        return Err(ProcessIdParseError::EmptyField);
+    }
+    match segment
+        .chars()
+        .find(|c| *c == ':' || *c == '@' || c.is_whitespace() || c.is_control())
+    {
+        Some(c) => Err(ProcessIdParseError::InvalidCharacter(c)),
+        None => Ok(()),
+    }
+}
+
 impl Serialize for ProcessId {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -133,22 +161,25 @@ impl PartialEq<ProcessId> for &str {
     }
 }
 
-#[derive(Debug)]
+/// Error type for parsing a `ProcessId` or `PackageId` from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ProcessIdParseError {
     TooManyColons,
     MissingField,
+    EmptyField,
+    InvalidCharacter(char),
 }
 
 impl std::fmt::Display for ProcessIdParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                ProcessIdParseError::TooManyColons => "Too many colons in ProcessId string",
-                ProcessIdParseError::MissingField => "Missing field in ProcessId string",
+        match self {
+            ProcessIdParseError::TooManyColons => write!(f, "Too many colons in ProcessId string"),
+            ProcessIdParseError::MissingField => write!(f, "Missing field in ProcessId string"),
+            ProcessIdParseError::EmptyField => write!(f, "Empty field in ProcessId string"),
+            ProcessIdParseError::InvalidCharacter(c) => {
+                write!(f, "Invalid character {:?} in ProcessId string", c)
             }
-        )
+        }
     }
 }
 
@@ -157,6 +188,67 @@ impl std::error::Error for ProcessIdParseError {
         match self {
             ProcessIdParseError::TooManyColons => "Too many colons in ProcessId string",
             ProcessIdParseError::MissingField => "Missing field in ProcessId string",
+            ProcessIdParseError::EmptyField => "Empty field in ProcessId string",
+            ProcessIdParseError::InvalidCharacter(_) => "Invalid character in ProcessId string",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const SEGMENT: &str = "[a-zA-Z0-9_.-]{1,16}";
+
+    proptest! {
+        #[test]
+        fn round_trips(process in SEGMENT, package in SEGMENT, publisher in SEGMENT) {
+            let id = ProcessId::new(Some(&process), &package, &publisher);
+            prop_assert_eq!(id.to_string().parse::<ProcessId>(), Ok(id.clone()));
+            prop_assert_eq!(ProcessId::try_from(id.to_string().as_str()), Ok(id));
+        }
+
+        #[test]
+        fn rejects_extra_segments(a in SEGMENT, b in SEGMENT, c in SEGMENT, d in SEGMENT) {
+            let input = format!("{}:{}:{}:{}", a, b, c, d);
+            prop_assert_eq!(input.parse::<ProcessId>(), Err(ProcessIdParseError::TooManyColons));
+        }
+
+        #[test]
+        fn rejects_missing_segments(a in SEGMENT, b in SEGMENT) {
+            prop_assert_eq!(a.parse::<ProcessId>(), Err(ProcessIdParseError::MissingField));
+            let input = format!("{}:{}", a, b);
+            prop_assert_eq!(input.parse::<ProcessId>(), Err(ProcessIdParseError::MissingField));
+        }
+
+        #[test]
+        fn rejects_invalid_characters(
+            a in SEGMENT,
+            b in SEGMENT,
+            c in SEGMENT,
+            bad in prop::sample::select(vec!['@', ' ', '\t', '\n', '\u{0}']),
+            at in 0usize..16,
+        ) {
+            let mut b = b;
+            b.insert(at.min(b.len()), bad);
+            let input = format!("{}:{}:{}", a, b, c);
+            prop_assert_eq!(
+                input.parse::<ProcessId>(),
+                Err(ProcessIdParseError::InvalidCharacter(bad))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_segments() {
+        for input in ["", "::", "a::c", ":b:c", "a:b:"] {
+            assert_eq!(
+                input.parse::<ProcessId>(),
+                Err(ProcessIdParseError::EmptyField),
+                "{:?}",
+                input
+            );
         }
     }
 }
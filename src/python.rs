@@ -0,0 +1,362 @@
+use crate::kv::{self, Kv, KvError, KvTtl};
+use crate::{get_blob, Address, Message, ModuleError, PackageId, ProcessLibError, Request};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The kv db, in the calling package, that [`Python::cached()`] stores
+/// results in.
+pub const CACHE_DB: &str = "python_cache";
+
+/// Actions are sent to a Python runner process, "package_id" is the package
+/// making the request, whose scripts the runner looks up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PythonRequest {
+    pub package_id: PackageId,
+    pub action: PythonAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum PythonAction {
+    /// Call a function of one of the package's scripts. Answered with
+    /// [`PythonResponse::Result`].
+    RunScript(RunScript),
+    /// Load the given scripts, and the libraries they import, into a warm
+    /// interpreter ahead of time, so the first call to them does not pay
+    /// for the imports. Answered with [`PythonResponse::Ok`].
+    Preload { scripts: Vec<String> },
+    /// List the accelerators the runner can place work on. Answered with
+    /// [`PythonResponse::Devices`].
+    ListDevices,
+    /// While enabled, re-read the package's scripts from disk on every
+    /// call instead of running the cached bytecode, so edits take effect
+    /// without a restart. Answered with [`PythonResponse::Ok`].
+    SetReload { enabled: bool },
+    /// List the Python versions the runner has interpreters for. Answered
+    /// with [`PythonResponse::Versions`].
+    Versions,
+}
+
+/// A call of `func` in the script at `script`, a path relative to the
+/// package's `scripts` directory, with `args` as its positional arguments.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunScript {
+    pub script: String,
+    pub func: String,
+    pub args: Vec<serde_json::Value>,
+    /// Send the return value as the response blob, as raw bytes, instead
+    /// of as JSON in the response body. The function must return `bytes`
+    /// or `str`, or an image: a matplotlib figure, which the runner renders
+    /// as a PNG, or a `(bytes, mime)` tuple. The blob's mime is set to the
+    /// image's type.
+    #[serde(default)]
+    pub blob_response: bool,
+    /// The device to run on, such as `"cpu"` or `"cuda:0"`, passed to the
+    /// script as a hint. The runner falls back to the CPU if it has no
+    /// such device.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// A vfs directory, such as `/my-package:publisher.os/outputs`, that the
+    /// runner mounts writable as the script's working directory, so it can
+    /// write files there. Created if it does not exist.
+    #[serde(default)]
+    pub outputs_dir: Option<String>,
+    /// The Python version to run the script with, such as `"3.11"`. The
+    /// runner answers [`PythonError::VersionUnavailable`] if it has no
+    /// interpreter of that version, rather than running the script on
+    /// another one.
+    #[serde(default)]
+    pub python_version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PythonResponse {
+    Ok,
+    /// The function's return value, or `None` if it was sent as the blob,
+    /// and the vfs paths of the files it wrote to its `outputs_dir`.
+    Result {
+        data: Option<serde_json::Value>,
+        #[serde(default)]
+        files: Vec<String>,
+    },
+    /// The devices the runner has, e.g. `["cpu", "cuda:0"]`.
+    Devices {
+        devices: Vec<String>,
+    },
+    /// The Python versions the runner has, e.g. `["3.10", "3.12"]`.
+    Versions {
+        versions: Vec<String>,
+    },
+    Err {
+        error: PythonError,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Error)]
+pub enum PythonError {
+    #[error("python: script not found: {script}")]
+    ScriptNotFound { script: String },
+    #[error("python: No capability: {error}")]
+    NoCap { error: String },
+    /// The script raised an exception. `error` holds its traceback.
+    #[error("python: script raised: {error}")]
+    ScriptError { error: String },
+    #[error("python: no interpreter for Python {version}, only {available:?}")]
+    VersionUnavailable {
+        version: String,
+        available: Vec<String>,
+    },
+    #[error("python: input bytes/json error: {error}")]
+    InputError { error: String },
+}
+
+/// Python helper struct for a script runner.
+/// Create one with [`Python::new()`], then call its impl functions to interact with it.
+/// By default requests go to `our@python:distro:sys`; use [`Python::target()`] to
+/// point at a different runner process.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Python {
+    pub package_id: PackageId,
+    pub target: Address,
+    pub timeout: u64,
+    pub device: Option<String>,
+    pub python_version: Option<String>,
+    pub cache: Option<PythonCache>,
+}
+
+/// Where, and for how long, [`Python::cached()`] keeps results.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PythonCache {
+    pub kv: Kv,
+    pub ttl: Duration,
+}
+
+impl Python {
+    /// Create a new handle to the default local runner.
+    pub fn new(package_id: PackageId) -> Self {
+        Python {
+            package_id,
+            target: ("our", "python", "distro", "sys").into(),
+            timeout: 60,
+            device: None,
+            python_version: None,
+            cache: None,
+        }
+    }
+
+    /// Send requests to a different runner process.
+    pub fn target<T>(mut self, target: T) -> Self
+    where
+        T: Into<Address>,
+    {
+        self.target = target.into();
+        self
+    }
+
+    /// Set the timeout, in seconds, for script calls.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Ask for scripts to run on `device`, such as `"cuda:0"`, when the
+    /// runner has it. See [`Python::list_devices()`].
+    pub fn device(mut self, device: &str) -> Self {
+        self.device = Some(device.to_string());
+        self
+    }
+
+    /// Run scripts with Python `version`, such as `"3.11"`, and fail with
+    /// [`PythonError::VersionUnavailable`] on runners that do not have it.
+    /// See [`Python::versions()`].
+    pub fn python_version(mut self, version: &str) -> Self {
+        self.python_version = Some(version.to_string());
+        self
+    }
+
+    /// Keep the results of [`Python::run_script()`] in the [`CACHE_DB`] kv
+    /// db for `ttl`, keyed by a hash of the call, and answer repeated calls
+    /// from there. Only use this for scripts whose results depend on their
+    /// arguments alone. Expired results are not read, and are deleted by
+    /// sweeping the db with [`KvTtl::sweep_expired()`].
+    pub fn cached(mut self, ttl: Duration) -> Result<Self, ProcessLibError> {
+        self.cache = Some(PythonCache {
+            kv: kv::open(self.package_id.clone(), CACHE_DB)?,
+            ttl,
+        });
+        Ok(self)
+    }
+
+    /// Call `func` in `script` and deserialize its return value.
+    pub fn run_script<T: DeserializeOwned>(
+        &self,
+        script: &str,
+        func: &str,
+        args: Vec<serde_json::Value>,
+    ) -> Result<T, ProcessLibError> {
+        let action = PythonAction::RunScript(self.run(script, func, args, false));
+        let Some(cache) = &self.cache else {
+            return Ok(serde_json::from_value(self.run_uncached(action)?)?);
+        };
+        let cache_key = Sha256::digest(serde_json::to_vec(&action)?).to_vec();
+        let results = KvTtl::new(cache.kv.clone());
+        match results.get(cache_key.clone()) {
+            Ok(bytes) => return Ok(serde_json::from_slice(&bytes)?),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {}
+            Err(e) => return Err(e),
+        }
+        let data = self.run_uncached(action)?;
+        results.set(cache_key, serde_json::to_vec(&data)?, cache.ttl)?;
+        Ok(serde_json::from_value(data)?)
+    }
+
+    /// Call `func` in `script` and return its return value as raw bytes,
+    /// sent as the response blob. For large outputs, such as images or
+    /// CSVs, this saves encoding them as JSON.
+    pub fn run_script_blob(
+        &self,
+        script: &str,
+        func: &str,
+        args: Vec<serde_json::Value>,
+    ) -> Result<Vec<u8>, ProcessLibError> {
+        let action = PythonAction::RunScript(self.run(script, func, args, true));
+        match self.send(action, self.timeout)? {
+            PythonResponse::Result { data: None, .. } => {
+                Ok(get_blob().ok_or(ProcessLibError::NoBlob)?.bytes)
+            }
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Call `func` in `script` with `outputs_dir`, a vfs directory, to
+    /// write files to, and return its return value and the vfs paths of
+    /// the files it wrote there. Results are never cached.
+    pub fn run_script_with_outputs<T: DeserializeOwned>(
+        &self,
+        script: &str,
+        func: &str,
+        args: Vec<serde_json::Value>,
+        outputs_dir: &str,
+    ) -> Result<(T, Vec<String>), ProcessLibError> {
+        let mut run = self.run(script, func, args, false);
+        run.outputs_dir = Some(outputs_dir.to_string());
+        match self.send(PythonAction::RunScript(run), self.timeout)? {
+            PythonResponse::Result {
+                data: Some(data),
+                files,
+            } => Ok((serde_json::from_value(data)?, files)),
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Call `func` in `script`, which returns an image, such as a chart,
+    /// and return the image and its mime type, e.g. `"image/png"`, ready to
+    /// serve to a UI.
+    pub fn run_plot(
+        &self,
+        script: &str,
+        func: &str,
+        args: Vec<serde_json::Value>,
+    ) -> Result<(Vec<u8>, String), ProcessLibError> {
+        let action = PythonAction::RunScript(self.run(script, func, args, true));
+        match self.send(action, self.timeout)? {
+            PythonResponse::Result { data: None, .. } => {
+                let blob = get_blob().ok_or(ProcessLibError::NoBlob)?;
+                match blob.mime {
+                    Some(mime) => Ok((blob.bytes, mime)),
+                    None => Err(ProcessLibError::unexpected("plot with no mime type")),
+                }
+            }
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Have the runner import `scripts` now, e.g. at startup, so the first
+    /// user-visible call to them does not wait for heavy libraries such as
+    /// numpy or torch to load.
+    pub fn preload(&self, scripts: &[&str]) -> Result<(), ProcessLibError> {
+        let action = PythonAction::Preload {
+            scripts: scripts.iter().map(|s| s.to_string()).collect(),
+        };
+        match self.send(action, self.timeout)? {
+            PythonResponse::Ok => Ok(()),
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// The devices the runner can run scripts on. Always includes `"cpu"`.
+    pub fn list_devices(&self) -> Result<Vec<String>, ProcessLibError> {
+        match self.send(PythonAction::ListDevices, self.timeout)? {
+            PythonResponse::Devices { devices } => Ok(devices),
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// The Python versions the runner can run scripts with.
+    pub fn versions(&self) -> Result<Vec<String>, ProcessLibError> {
+        match self.send(PythonAction::Versions, self.timeout)? {
+            PythonResponse::Versions { versions } => Ok(versions),
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    /// Turn script hot-reloading on or off for this package. Meant for
+    /// development: with it on, every call pays to recompile its script.
+    pub fn dev_mode(&self, enabled: bool) -> Result<(), ProcessLibError> {
+        match self.send(PythonAction::SetReload { enabled }, self.timeout)? {
+            PythonResponse::Ok => Ok(()),
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    fn run(
+        &self,
+        script: &str,
+        func: &str,
+        args: Vec<serde_json::Value>,
+        blob_response: bool,
+    ) -> RunScript {
+        RunScript {
+            script: script.to_string(),
+            func: func.to_string(),
+            args,
+            blob_response,
+            device: self.device.clone(),
+            outputs_dir: None,
+            python_version: self.python_version.clone(),
+        }
+    }
+
+    fn run_uncached(&self, action: PythonAction) -> Result<serde_json::Value, ProcessLibError> {
+        match self.send(action, self.timeout)? {
+            PythonResponse::Result {
+                data: Some(data), ..
+            } => Ok(data),
+            PythonResponse::Err { error } => Err(error.into()),
+            response => Err(ProcessLibError::unexpected(response)),
+        }
+    }
+
+    fn send(&self, action: PythonAction, timeout: u64) -> Result<PythonResponse, ProcessLibError> {
+        let res = Request::new()
+            .target(&self.target)
+            .body(serde_json::to_vec(&PythonRequest {
+                package_id: self.package_id.clone(),
+                action,
+            })?)
+            .send_and_await_response(timeout)??;
+
+        match res {
+            Message::Response { body, .. } => Ok(serde_json::from_slice::<PythonResponse>(&body)?),
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+}
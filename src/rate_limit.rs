@@ -0,0 +1,94 @@
+use crate::time::now_millis;
+use crate::Address;
+use std::collections::HashMap;
+
+/// A token-bucket rate limiter with one bucket per source address.
+/// Each source may burst up to `capacity` messages, then is limited to
+/// `refill_per_sec` messages per second:
+///
+/// ```ignore
+/// let mut limiter = RateLimiter::new(10, 1.0);
+/// loop {
+///     let message = await_message()?;
+///     if !limiter.check(message.source()) {
+///         continue;
+///     }
+///     handle_message(message)?;
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_ms: f64,
+    buckets: HashMap<Address, Bucket>,
+}
+
+#[derive(Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: u64,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        RateLimiter {
+            capacity: capacity as f64,
+            refill_per_ms: refill_per_sec / 1000.0,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token from `source`'s bucket. Returns `false` if the bucket is
+    /// empty, in which case the message should be dropped or rejected.
+    pub fn check(&mut self, source: &Address) -> bool {
+        self.check_n(source, 1)
+    }
+
+    /// Take `n` tokens from `source`'s bucket, for messages that cost more
+    /// than others. Takes nothing and returns `false` if there are not enough.
+    pub fn check_n(&mut self, source: &Address, n: u32) -> bool {
+        let now = now_millis();
+        let bucket = self.refill(source, now);
+        if bucket.tokens >= n as f64 {
+            bucket.tokens -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Tokens currently left in `source`'s bucket.
+    pub fn remaining(&mut self, source: &Address) -> u32 {
+        let now = now_millis();
+        self.refill(source, now).tokens as u32
+    }
+
+    /// Forget `source`'s bucket, giving it a full allowance again.
+    pub fn reset(&mut self, source: &Address) {
+        self.buckets.remove(source);
+    }
+
+    /// Drop buckets that have refilled completely, since they are the same as
+    /// no bucket at all. Call this periodically to bound memory use when many
+    /// distinct sources send messages.
+    pub fn prune(&mut self) {
+        let now = now_millis();
+        let (capacity, refill_per_ms) = (self.capacity, self.refill_per_ms);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_sub(bucket.last_refill) as f64;
+            bucket.tokens + elapsed * refill_per_ms < capacity
+        });
+    }
+
+    fn refill(&mut self, source: &Address, now: u64) -> &mut Bucket {
+        let capacity = self.capacity;
+        let bucket = self.buckets.entry(source.clone()).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_sub(bucket.last_refill) as f64;
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_ms).min(capacity);
+        bucket.last_refill = now;
+        bucket
+    }
+}
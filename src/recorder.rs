@@ -0,0 +1,204 @@
+//! Record every message a process sends and receives to a log in the vfs,
+//! to reproduce a bug by feeding the same messages through the process's
+//! handler again with [`replay()`].
+//!
+//! ```ignore
+//! recorder::start("/my_app:my_app:publisher.os/debug/messages.jsonl", 4096)?;
+//! // ... run as usual, then, in a test or a debug build:
+//! recorder::replay(path, |message, _blob| handle_message(&our, message))?;
+//! ```
+//!
+//! The log has one JSON [`RecordedMessage`] per line. Blobs are kept up to a
+//! length set at [`start()`], so logs of processes that move large files
+//! stay small, but a replayed message's blob may be cut short.
+//!
+//! Entries are buffered in memory and written out each time the process
+//! awaits its next message, since writing to the vfs in between would
+//! replace the blob of the message being handled.
+use crate::time::Timestamp;
+use crate::vfs::{self, File};
+use crate::{
+    Address, Capability, LazyLoadBlob, Message, ProcessLibError, SendError, SendErrorKind,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+    /// Set while the recorder writes its log, so that its own vfs requests
+    /// are not recorded.
+    static WRITING: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Recorder {
+    path: String,
+    max_blob_len: usize,
+    pending: Vec<RecordedMessage>,
+}
+
+/// One line of a recorder log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    pub at: Timestamp,
+    pub event: RecordedEvent,
+    /// The message's blob, truncated to the recorder's maximum length.
+    pub blob: Option<Vec<u8>>,
+    /// The length of the blob before truncation.
+    pub blob_len: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    /// A message this process received, from `await_message()` or as the
+    /// response to a request it awaited.
+    Received(Message),
+    /// A request this process sent came back undelivered.
+    SendFailed {
+        kind: SendErrorKind,
+        message: Message,
+        context: Option<Vec<u8>>,
+    },
+    /// A request (with a `target`) or response (without) this process sent.
+    Sent {
+        target: Option<Address>,
+        expects_response: Option<u64>,
+        body: Vec<u8>,
+        metadata: Option<String>,
+        context: Option<Vec<u8>>,
+        capabilities: Vec<Capability>,
+    },
+}
+
+/// Start recording to the file at `path`, creating it if needed and
+/// appending if it exists. Blobs longer than `max_blob_len` are truncated.
+pub fn start(path: &str, max_blob_len: usize) -> Result<(), ProcessLibError> {
+    vfs::open_file(path, true)?;
+    RECORDER.with(|recorder| {
+        *recorder.borrow_mut() = Some(Recorder {
+            path: path.to_string(),
+            max_blob_len,
+            pending: vec![],
+        })
+    });
+    Ok(())
+}
+
+/// Write out anything still buffered and stop recording.
+pub fn stop() -> Result<(), ProcessLibError> {
+    flush()?;
+    RECORDER.with(|recorder| *recorder.borrow_mut() = None);
+    Ok(())
+}
+
+pub fn is_recording() -> bool {
+    !WRITING.with(Cell::get) && RECORDER.with(|recorder| recorder.borrow().is_some())
+}
+
+/// Write buffered entries to the log. Called by `await_message()` before
+/// it receives, so there is no need to call it otherwise.
+pub fn flush() -> Result<(), ProcessLibError> {
+    let Some((mut file, pending)) = RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        let recorder = recorder.as_mut()?;
+        if recorder.pending.is_empty() {
+            return None;
+        }
+        let file = File {
+            path: recorder.path.clone(),
+        };
+        Some((file, std::mem::take(&mut recorder.pending)))
+    }) else {
+        return Ok(());
+    };
+    let mut lines = vec![];
+    for entry in &pending {
+        serde_json::to_writer(&mut lines, entry)?;
+        lines.push(b'\n');
+    }
+    WRITING.with(|writing| writing.set(true));
+    let result = file.append(&lines);
+    WRITING.with(|writing| writing.set(false));
+    Ok(result?)
+}
+
+/// Buffer an entry, if recording.
+pub(crate) fn record(event: RecordedEvent, blob: Option<&LazyLoadBlob>) {
+    if WRITING.with(Cell::get) {
+        return;
+    }
+    RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        let Some(recorder) = recorder.as_mut() else {
+            return;
+        };
+        let blob_len = blob.map(|blob| blob.bytes.len()).unwrap_or(0);
+        let blob = blob.map(|blob| blob.bytes[..blob_len.min(recorder.max_blob_len)].to_vec());
+        recorder.pending.push(RecordedMessage {
+            at: Timestamp::now(),
+            event,
+            blob,
+            blob_len,
+        });
+    });
+}
+
+/// Record a received message or send error, with the current blob.
+pub(crate) fn record_received(message: &Result<Message, SendError>) {
+    if !is_recording() {
+        return;
+    }
+    match message {
+        Ok(message) => record(
+            RecordedEvent::Received(message.clone()),
+            crate::get_blob().as_ref(),
+        ),
+        Err(error) => record(
+            RecordedEvent::SendFailed {
+                kind: error.kind.clone(),
+                message: error.message.clone(),
+                context: error.context.clone(),
+            },
+            error.lazy_load_blob.as_ref(),
+        ),
+    }
+}
+
+/// Read every entry of a recorder log, in the order they were recorded.
+pub fn read_log(path: &str) -> Result<Vec<RecordedMessage>, ProcessLibError> {
+    let log = vfs::open_file(path, false)?.read()?;
+    log.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| Ok(serde_json::from_slice(line)?))
+        .collect()
+}
+
+/// Feed every message a recorded process received through `handler`, in
+/// order, with its recorded blob, and return how many there were. Messages
+/// the process sent are skipped; read them with [`read_log()`] to compare
+/// against what the handler sends now.
+pub fn replay<F>(path: &str, mut handler: F) -> Result<usize, ProcessLibError>
+where
+    F: FnMut(Result<Message, SendError>, Option<LazyLoadBlob>),
+{
+    let mut count = 0;
+    for entry in read_log(path)? {
+        let blob = entry.blob.map(|bytes| LazyLoadBlob { mime: None, bytes });
+        let message = match entry.event {
+            RecordedEvent::Received(message) => Ok(message),
+            RecordedEvent::SendFailed {
+                kind,
+                message,
+                context,
+            } => Err(SendError {
+                kind,
+                message,
+                lazy_load_blob: blob.clone(),
+                context,
+            }),
+            RecordedEvent::Sent { .. } => continue,
+        };
+        handler(message, blob);
+        count += 1;
+    }
+    Ok(count)
+}
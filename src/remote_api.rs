@@ -0,0 +1,383 @@
+//! Typed request/response protocols between processes, usually on
+//! different nodes, declared once with [`crate::remote_api!`]. The macro
+//! generates the request and response enums, a client with one method per
+//! call, and a handler trait whose `handle_remote()` dispatches each
+//! request to its method and sends back the response.
+//!
+//! ```ignore
+//! remote_api! {
+//!     process: "chess:chess:template.os",
+//!     capability: "chess:play",
+//!     request: ChessRequest,
+//!     response: ChessResponse,
+//!     client: ChessClient,
+//!     handler: ChessHandler,
+//!     calls: {
+//!         NewGame => fn new_game(opponent: String) -> u64;
+//!         Move => fn make_move(game: u64, mv: String) -> Board;
+//!     }
+//! }
+//!
+//! // on the calling node
+//! let chess = ChessClient::new(&our, "bob.os");
+//! let game = chess.new_game(our.node().to_string())?;
+//!
+//! // on the serving node
+//! impl ChessHandler for Games {
+//!     fn new_game(&mut self, source: &Address, opponent: String) -> Result<u64, String> { ... }
+//!     fn make_move(&mut self, source: &Address, game: u64, mv: String) -> Result<Board, String> { ... }
+//! }
+//! if games.handle_remote(&our, &message) {
+//!     continue;
+//! }
+//! ```
+//!
+//! With a `capability`, callers must hold the one from [`capability()`],
+//! issued by the serving process, and the client attaches it to each call.
+//! Arguments and results must be serde types, and the crate using the macro
+//! must depend on `serde`.
+use crate::{Address, Capability, Message, ProcessLibError, RemoteError, Request, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+/// Timeout, in seconds, of a generated client's calls to its own node.
+pub const LOCAL_TIMEOUT: u64 = 5;
+/// Timeout, in seconds, of a generated client's calls to another node.
+pub const REMOTE_TIMEOUT: u64 = 30;
+
+/// Why a call failed on the serving side. Sent in place of the response.
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+pub enum RemoteCallError {
+    #[error("caller lacks the capability for this api")]
+    NoCapability,
+    #[error("{error}")]
+    Handler { error: String },
+}
+
+#[derive(Debug, Error)]
+pub enum RemoteApiError {
+    /// The server got the call and refused or failed it.
+    #[error("remote api: {0}")]
+    Call(#[from] RemoteCallError),
+    #[error("remote api: {0}")]
+    Request(#[from] ProcessLibError),
+}
+
+/// The capability a process needs to call the api named `name` on the
+/// process at `issuer`. Grant it to the processes that may call.
+pub fn capability(issuer: &Address, name: &str) -> Capability {
+    Capability::new(
+        issuer.clone(),
+        serde_json::json!({ "kind": "remote_api", "name": name }).to_string(),
+    )
+}
+
+/// Send a call and parse its response. Used by generated clients.
+#[doc(hidden)]
+pub fn call<Req: Serialize, Res: DeserializeOwned>(
+    target: &Address,
+    capability_name: Option<&str>,
+    timeout: u64,
+    request: &Req,
+) -> Result<Res, RemoteApiError> {
+    let mut outgoing = Request::new()
+        .target(target.clone())
+        .body(serde_json::to_vec(request).map_err(ProcessLibError::from)?);
+    if let Some(name) = capability_name {
+        outgoing = outgoing.capabilities(vec![capability(target, name)]);
+    }
+    let response = outgoing
+        .send_and_await_response(timeout)
+        .map_err(ProcessLibError::from)?
+        .map_err(|e| ProcessLibError::from(RemoteError::new(target.node(), e)))?;
+    let result: Result<Res, RemoteCallError> =
+        serde_json::from_slice(response.body()).map_err(ProcessLibError::from)?;
+    Ok(result?)
+}
+
+/// Parse a message as a call, or return `None` if it is not one. Used by
+/// generated handlers.
+#[doc(hidden)]
+pub fn parse_call<Req: DeserializeOwned>(message: &Message) -> Option<Req> {
+    if !message.is_request() {
+        return None;
+    }
+    serde_json::from_slice(message.body()).ok()
+}
+
+/// Whether a call carries the capability it needs. Used by generated handlers.
+#[doc(hidden)]
+pub fn check_capability(
+    our: &Address,
+    capability_name: Option<&str>,
+    message: &Message,
+) -> Result<(), RemoteCallError> {
+    match capability_name {
+        Some(name) if !message.capabilities().contains(&capability(our, name)) => {
+            Err(RemoteCallError::NoCapability)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Send the response to a call. Used by generated handlers.
+#[doc(hidden)]
+pub fn respond<Res: Serialize>(result: Result<Res, RemoteCallError>) {
+    let _ = Response::new()
+        .body(serde_json::to_vec(&result).unwrap())
+        .send();
+}
+
+/// Declare a typed api between processes; see the [`remote_api`](mod@crate::remote_api)
+/// module for an example. Generates:
+///
+/// - the request enum, with a struct variant per call holding its arguments;
+/// - the response enum, with a variant per call holding its result;
+/// - a client, built with `new(&our, node)`, with a method per call. Calls
+///   to our own node time out after [`LOCAL_TIMEOUT`] and others after
+///   [`REMOTE_TIMEOUT`], unless set with `timeout()`;
+/// - a handler trait, with a method per call, given the caller's address,
+///   and a provided `handle_remote(&mut self, our, message) -> bool` that
+///   answers the message if it is a call and returns whether it was.
+#[macro_export]
+macro_rules! remote_api {
+    (
+        process: $process:literal,
+        $(capability: $capability:literal,)?
+        request: $request:ident,
+        response: $response:ident,
+        client: $client:ident,
+        handler: $handler:ident,
+        calls: {
+            $($variant:ident => fn $method:ident($($arg:ident: $arg_ty:ty),* $(,)?) -> $ret:ty;)*
+        } $(,)?
+    ) => {
+        #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        pub enum $request {
+            $($variant { $($arg: $arg_ty),* },)*
+        }
+
+        #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        pub enum $response {
+            $($variant($ret),)*
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct $client {
+            target: $crate::Address,
+            timeout: u64,
+        }
+
+        impl $client {
+            const CAPABILITY: Option<&'static str> = {
+                #[allow(unused_variables)]
+                let capability: Option<&'static str> = None;
+                $(let capability = Some($capability);)?
+                capability
+            };
+
+            /// A client for the api's process on `node`, sent from `our`.
+            pub fn new(our: &$crate::Address, node: &str) -> Self {
+                let process: $crate::ProcessId = $process
+                    .parse()
+                    .expect(concat!("remote_api!: invalid process id ", $process));
+                $client {
+                    target: $crate::Address::new(node, process),
+                    timeout: if node == our.node() {
+                        $crate::remote_api::LOCAL_TIMEOUT
+                    } else {
+                        $crate::remote_api::REMOTE_TIMEOUT
+                    },
+                }
+            }
+
+            /// Set the timeout, in seconds, for every call.
+            pub fn timeout(mut self, timeout: u64) -> Self {
+                self.timeout = timeout;
+                self
+            }
+
+            pub fn target(&self) -> &$crate::Address {
+                &self.target
+            }
+
+            $(
+                pub fn $method(
+                    &self,
+                    $($arg: $arg_ty),*
+                ) -> Result<$ret, $crate::remote_api::RemoteApiError> {
+                    let request = $request::$variant { $($arg),* };
+                    let response: $response = $crate::remote_api::call(
+                        &self.target,
+                        Self::CAPABILITY,
+                        self.timeout,
+                        &request,
+                    )?;
+                    #[allow(unreachable_patterns)]
+                    match response {
+                        $response::$variant(value) => Ok(value),
+                        response => Err($crate::ProcessLibError::unexpected(response).into()),
+                    }
+                }
+            )*
+        }
+
+        pub trait $handler {
+            $(
+                fn $method(
+                    &mut self,
+                    source: &$crate::Address,
+                    $($arg: $arg_ty),*
+                ) -> Result<$ret, String>;
+            )*
+
+            /// If `message` is a call to this api, check its capability,
+            /// pass it to its method, send the response, and return `true`.
+            fn handle_remote(&mut self, our: &$crate::Address, message: &$crate::Message) -> bool {
+                let Some(request) = $crate::remote_api::parse_call::<$request>(message) else {
+                    return false;
+                };
+                if let Err(e) =
+                    $crate::remote_api::check_capability(our, $client::CAPABILITY, message)
+                {
+                    $crate::remote_api::respond::<$response>(Err(e));
+                    return true;
+                }
+                let result = match request {
+                    $(
+                        $request::$variant { $($arg),* } => self
+                            .$method(message.source(), $($arg),*)
+                            .map($response::$variant),
+                    )*
+                };
+                $crate::remote_api::respond(result.map_err(|error| {
+                    $crate::remote_api::RemoteCallError::Handler { error }
+                }));
+                true
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::remote_api! {
+        process: "chess:chess:template.os",
+        capability: "chess:play",
+        request: ChessRequest,
+        response: ChessResponse,
+        client: ChessClient,
+        handler: ChessHandler,
+        calls: {
+            NewGame => fn new_game(opponent: String) -> u64;
+            Move => fn make_move(game: u64, mv: String) -> Vec<String>;
+        }
+    }
+
+    crate::remote_api! {
+        process: "echo:echo:template.os",
+        request: EchoRequest,
+        response: EchoResponse,
+        client: EchoClient,
+        handler: EchoHandler,
+        calls: {
+            Echo => fn echo(text: String) -> String;
+        }
+    }
+
+    struct Games;
+
+    impl ChessHandler for Games {
+        fn new_game(&mut self, _source: &Address, _opponent: String) -> Result<u64, String> {
+            Ok(1)
+        }
+
+        fn make_move(
+            &mut self,
+            _source: &Address,
+            _game: u64,
+            mv: String,
+        ) -> Result<Vec<String>, String> {
+            Ok(vec![mv])
+        }
+    }
+
+    fn our() -> Address {
+        Address::new("alice.os", ("chess", "chess", "template.os"))
+    }
+
+    fn request(capabilities: Vec<Capability>) -> Message {
+        Message::Request {
+            source: Address::new("bob.os", ("chess", "chess", "template.os")),
+            expects_response: Some(5),
+            body: serde_json::to_vec(&ChessRequest::NewGame {
+                opponent: "alice.os".to_string(),
+            })
+            .unwrap(),
+            metadata: None,
+            capabilities,
+        }
+    }
+
+    #[test]
+    fn clients_target_the_process_on_the_given_node() {
+        let local = ChessClient::new(&our(), "alice.os");
+        assert_eq!(
+            local.target(),
+            &Address::new("alice.os", ("chess", "chess", "template.os"))
+        );
+        assert_eq!(local.timeout, LOCAL_TIMEOUT);
+        let remote = EchoClient::new(&our(), "bob.os").timeout(1);
+        assert_eq!(
+            remote.target(),
+            &Address::new("bob.os", ("echo", "echo", "template.os"))
+        );
+        assert_eq!(remote.timeout, 1);
+        assert_eq!(EchoClient::new(&our(), "bob.os").timeout, REMOTE_TIMEOUT);
+    }
+
+    #[test]
+    fn calls_are_sent_as_struct_variants() {
+        let call = ChessRequest::Move {
+            game: 1,
+            mv: "e4".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&call).unwrap(),
+            serde_json::json!({ "Move": { "game": 1, "mv": "e4" } })
+        );
+        let message = request(vec![]);
+        assert!(matches!(
+            parse_call::<ChessRequest>(&message),
+            Some(ChessRequest::NewGame { opponent }) if opponent == "alice.os"
+        ));
+    }
+
+    #[test]
+    fn only_calls_with_the_capability_are_allowed() {
+        assert!(matches!(
+            check_capability(&our(), ChessClient::CAPABILITY, &request(vec![])),
+            Err(RemoteCallError::NoCapability)
+        ));
+        let granted = request(vec![capability(&our(), "chess:play")]);
+        assert!(check_capability(&our(), ChessClient::CAPABILITY, &granted).is_ok());
+        assert!(check_capability(&our(), EchoClient::CAPABILITY, &request(vec![])).is_ok());
+    }
+
+    #[test]
+    fn handlers_ignore_messages_that_are_not_calls() {
+        let response = Message::Response {
+            source: our(),
+            body: serde_json::to_vec(&ChessRequest::NewGame {
+                opponent: "bob.os".to_string(),
+            })
+            .unwrap(),
+            metadata: None,
+            context: None,
+            capabilities: vec![],
+        };
+        assert!(!Games.handle_remote(&our(), &response));
+    }
+}
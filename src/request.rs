@@ -239,6 +239,20 @@ impl Request {
     /// fields have not been set.
     pub fn send(self) -> anyhow::Result<()> {
         if let (Some(target), Some(body)) = (self.target, self.body) {
+            #[cfg(feature = "vfs")]
+            if crate::recorder::is_recording() {
+                crate::recorder::record(
+                    crate::recorder::RecordedEvent::Sent {
+                        target: Some(target.clone()),
+                        expects_response: self.timeout,
+                        body: body.clone(),
+                        metadata: self.metadata.clone(),
+                        context: self.context.clone(),
+                        capabilities: self.capabilities.clone(),
+                    },
+                    self.blob.as_ref(),
+                );
+            }
             crate::send_request(
                 &target,
                 &crate::kinode::process::standard::Request {
@@ -263,7 +277,21 @@ impl Request {
         timeout: u64,
     ) -> anyhow::Result<Result<Message, SendError>> {
         if let (Some(target), Some(body)) = (self.target, self.body) {
-            match crate::send_and_await_response(
+            #[cfg(feature = "vfs")]
+            if crate::recorder::is_recording() {
+                crate::recorder::record(
+                    crate::recorder::RecordedEvent::Sent {
+                        target: Some(target.clone()),
+                        expects_response: Some(timeout),
+                        body: body.clone(),
+                        metadata: self.metadata.clone(),
+                        context: None,
+                        capabilities: self.capabilities.clone(),
+                    },
+                    self.blob.as_ref(),
+                );
+            }
+            let response = match crate::send_and_await_response(
                 &target,
                 &crate::kinode::process::standard::Request {
                     inherit: self.inherit,
@@ -274,8 +302,8 @@ impl Request {
                 },
                 self.blob.as_ref(),
             ) {
-                Ok((source, message)) => Ok(Ok(wit_message_to_message(source, message))),
-                Err(send_err) => Ok(Err(SendError {
+                Ok((source, message)) => Ok(wit_message_to_message(source, message)),
+                Err(send_err) => Err(SendError {
                     kind: match send_err.kind {
                         crate::kinode::process::standard::SendErrorKind::Offline => {
                             SendErrorKind::Offline
@@ -290,8 +318,11 @@ impl Request {
                     ),
                     lazy_load_blob: send_err.lazy_load_blob,
                     context: None,
-                })),
-            }
+                }),
+            };
+            #[cfg(feature = "vfs")]
+            crate::recorder::record_received(&response);
+            Ok(response)
         } else {
             Err(anyhow::anyhow!("missing fields"))
         }
@@ -153,6 +153,20 @@ impl Response {
     /// the response has not yet been set using `body()` or `try_body()`.
     pub fn send(self) -> anyhow::Result<()> {
         if let Some(body) = self.body {
+            #[cfg(feature = "vfs")]
+            if crate::recorder::is_recording() {
+                crate::recorder::record(
+                    crate::recorder::RecordedEvent::Sent {
+                        target: None,
+                        expects_response: None,
+                        body: body.clone(),
+                        metadata: self.metadata.clone(),
+                        context: None,
+                        capabilities: self.capabilities.clone(),
+                    },
+                    self.blob.as_ref(),
+                );
+            }
             crate::send_response(
                 &crate::kinode::process::standard::Response {
                     inherit: self.inherit,
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+/// How to retry a failed operation: up to `retries` times, waiting
+/// `base_delay`, then twice that, and so on up to `max_delay`. With
+/// `jitter`, each wait is instead a random time between half and all of
+/// that, so that many processes failing at once do not retry in lockstep.
+///
+/// Used by `http::client::JsonApi` for failed requests and by `saga::Step`
+/// for failed steps.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    /// Two retries, after about 250ms and 500ms.
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 2,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// How long to wait before retry number `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        let half = delay.as_millis() as u64 / 2;
+        Duration::from_millis(half + rand::random::<u64>() % (half + 1))
+    }
+}
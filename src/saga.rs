@@ -0,0 +1,298 @@
+//! Multi-step workflows that either complete every step or undo the ones
+//! they did. Progress is stored in kv after every step, so a process that
+//! crashes part-way through a saga resumes it, or finishes unwinding it,
+//! when it restarts.
+use crate::kv::{Kv, KvError};
+use crate::{timer, ModuleError, ProcessLibError, RetryPolicy};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+
+type Action<C> = Box<dyn Fn(&mut C) -> anyhow::Result<()>>;
+/// Each resumed run's id and outcome.
+type Resumed<C> = Vec<(String, Result<C, SagaError>)>;
+
+/// Retry delays set with [`Step::retry()`] stop doubling once they reach this.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Error)]
+pub enum SagaError {
+    /// A step failed, and every step before it was undone.
+    #[error("saga: step {step} failed and was rolled back: {error}")]
+    StepFailed { step: String, error: String },
+    /// A step failed, and then so did undoing an earlier step. The saga is
+    /// left stored, and [`Saga::resume()`] retries the rollback.
+    #[error("saga: compensating step {step} failed: {error} (after: {cause})")]
+    CompensationFailed {
+        step: String,
+        error: String,
+        cause: String,
+    },
+    #[error("saga: a saga with id {id} is already running")]
+    AlreadyRunning { id: String },
+    #[error("saga: {0}")]
+    Store(#[from] ProcessLibError),
+    #[error("saga: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// One step of a [`Saga`]: an action on the saga's context, and optionally a
+/// compensation that undoes it.
+///
+/// A process may crash after a step's action but before its progress is
+/// stored, in which case the action runs again on resume, so actions and
+/// compensations should be idempotent.
+pub struct Step<C> {
+    name: String,
+    action: Action<C>,
+    compensation: Option<Action<C>>,
+    retry: RetryPolicy,
+}
+
+impl<C> Step<C> {
+    pub fn new<F>(name: &str, action: F) -> Self
+    where
+        F: Fn(&mut C) -> anyhow::Result<()> + 'static,
+    {
+        Step {
+            name: name.to_string(),
+            action: Box::new(action),
+            compensation: None,
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Undo this step if a later one fails.
+    pub fn compensate<F>(mut self, compensation: F) -> Self
+    where
+        F: Fn(&mut C) -> anyhow::Result<()> + 'static,
+    {
+        self.compensation = Some(Box::new(compensation));
+        self
+    }
+
+    /// Try this step, and its compensation, up to `max_attempts` times,
+    /// waiting `backoff` before the first retry and twice as long before
+    /// each one after, up to five minutes.
+    pub fn retry(self, max_attempts: u32, backoff: Duration) -> Self {
+        self.retry_policy(RetryPolicy {
+            retries: max_attempts.saturating_sub(1),
+            base_delay: backoff,
+            max_delay: MAX_BACKOFF,
+            jitter: false,
+        })
+    }
+
+    /// Retry this step, and its compensation, as set by `retry`.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn run(action: &Action<C>, retry: RetryPolicy, context: &mut C) -> anyhow::Result<()> {
+        let mut attempt = 0;
+        loop {
+            match action(context) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= retry.retries => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    let delay = retry.delay(attempt);
+                    let _ = timer::set_and_await_timer(delay.as_millis() as u64);
+                }
+            }
+        }
+    }
+}
+
+/// A stored saga's progress.
+#[derive(Serialize, Deserialize)]
+struct Progress<C> {
+    context: C,
+    /// While running forward, the next step to run. While rolling back, the
+    /// number of steps still to compensate.
+    step: usize,
+    /// Set once a step has failed and the saga is rolling back.
+    failure: Option<Failure>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Failure {
+    step: String,
+    error: String,
+}
+
+/// A sequence of [`Step`]s run against a context `C`, such as the ids of the
+/// records a workflow creates. Each run has an id, under which its progress
+/// is stored in kv.
+///
+/// ```ignore
+/// let transfer = Saga::new("transfer")
+///     .step(Step::new("debit", |t: &mut Transfer| debit(&t.from, t.amount))
+///         .compensate(|t| credit(&t.from, t.amount)))
+///     .step(Step::new("credit remote", |t| credit_remote(&t.to, t.amount))
+///         .retry(3, Duration::from_secs(1)));
+///
+/// // on startup, finish whatever a crash interrupted
+/// for (id, result) in transfer.resume_all(&kv)? { ... }
+///
+/// transfer.run(&kv, &transfer_id, Transfer { from, to, amount })?;
+/// ```
+pub struct Saga<C> {
+    name: String,
+    steps: Vec<Step<C>>,
+}
+
+impl<C: Serialize + DeserializeOwned> Saga<C> {
+    pub fn new(name: &str) -> Self {
+        Saga {
+            name: name.to_string(),
+            steps: vec![],
+        }
+    }
+
+    pub fn step(mut self, step: Step<C>) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Run a new saga with id `id`, returning its final context. If a step
+    /// fails, the steps before it are compensated in reverse order.
+    pub fn run(&self, kv: &Kv, id: &str, context: C) -> Result<C, SagaError> {
+        if self.load(kv, id)?.is_some() {
+            return Err(SagaError::AlreadyRunning { id: id.to_string() });
+        }
+        // index first: a crash between the two writes leaves an id in the
+        // index with no progress, which resume skips, rather than progress
+        // that resume_all never finds and prunes. A rerun of such an id
+        // must not index it twice
+        let mut index = self.load_index(kv)?;
+        if !index.iter().any(|running| running == id) {
+            index.push(id.to_string());
+            self.save_index(kv, &index)?;
+        }
+        let progress = Progress {
+            context,
+            step: 0,
+            failure: None,
+        };
+        self.save(kv, id, &progress)?;
+        self.drive(kv, id, progress)
+    }
+
+    /// Continue the stored saga `id` from where it stopped, if there is one.
+    pub fn resume(&self, kv: &Kv, id: &str) -> Result<Option<C>, SagaError> {
+        match self.load(kv, id)? {
+            Some(progress) => Ok(Some(self.drive(kv, id, progress)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Continue every stored run of this saga, e.g. on process startup.
+    pub fn resume_all(&self, kv: &Kv) -> Result<Resumed<C>, SagaError> {
+        let index = self.load_index(kv)?;
+        let mut resumed = vec![];
+        let mut stale = vec![];
+        for id in index {
+            match self.resume(kv, &id) {
+                Ok(Some(context)) => resumed.push((id, Ok(context))),
+                // finished since the index was read, or indexed by a run that
+                // crashed before storing its progress
+                Ok(None) => stale.push(id),
+                Err(e) => resumed.push((id, Err(e))),
+            }
+        }
+        if !stale.is_empty() {
+            let mut index = self.load_index(kv)?;
+            index.retain(|running| !stale.contains(running));
+            self.save_index(kv, &index)?;
+        }
+        Ok(resumed)
+    }
+
+    fn drive(&self, kv: &Kv, id: &str, mut progress: Progress<C>) -> Result<C, SagaError> {
+        while progress.failure.is_none() && progress.step < self.steps.len() {
+            let step = &self.steps[progress.step];
+            match Step::run(&step.action, step.retry, &mut progress.context) {
+                Ok(()) => progress.step += 1,
+                Err(e) => {
+                    progress.failure = Some(Failure {
+                        step: step.name.clone(),
+                        error: e.to_string(),
+                    })
+                }
+            }
+            self.save(kv, id, &progress)?;
+        }
+
+        let Some(failure) = progress.failure.clone() else {
+            self.finish(kv, id)?;
+            return Ok(progress.context);
+        };
+
+        while progress.step > 0 {
+            let step = &self.steps[progress.step - 1];
+            if let Some(compensation) = &step.compensation {
+                if let Err(e) = Step::run(compensation, step.retry, &mut progress.context) {
+                    return Err(SagaError::CompensationFailed {
+                        step: step.name.clone(),
+                        error: e.to_string(),
+                        cause: failure.error,
+                    });
+                }
+            }
+            progress.step -= 1;
+            self.save(kv, id, &progress)?;
+        }
+        self.finish(kv, id)?;
+        Err(SagaError::StepFailed {
+            step: failure.step,
+            error: failure.error,
+        })
+    }
+
+    fn key(&self, id: &str) -> Vec<u8> {
+        format!("saga:{}:run:{}", self.name, id).into_bytes()
+    }
+
+    fn index_key(&self) -> Vec<u8> {
+        format!("saga:{}:index", self.name).into_bytes()
+    }
+
+    fn load(&self, kv: &Kv, id: &str) -> Result<Option<Progress<C>>, SagaError> {
+        match kv.get(self.key(id)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, kv: &Kv, id: &str, progress: &Progress<C>) -> Result<(), SagaError> {
+        kv.set(self.key(id), serde_json::to_vec(progress)?, None)?;
+        Ok(())
+    }
+
+    fn finish(&self, kv: &Kv, id: &str) -> Result<(), SagaError> {
+        // the reverse of run, for the same reason
+        kv.delete(self.key(id), None)?;
+        let mut index = self.load_index(kv)?;
+        index.retain(|running| running != id);
+        self.save_index(kv, &index)?;
+        Ok(())
+    }
+
+    fn load_index(&self, kv: &Kv) -> Result<Vec<String>, SagaError> {
+        match kv.get(self.index_key()) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {
+                Ok(vec![])
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_index(&self, kv: &Kv, index: &[String]) -> Result<(), SagaError> {
+        kv.set(self.index_key(), serde_json::to_vec(index)?, None)?;
+        Ok(())
+    }
+}
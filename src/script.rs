@@ -0,0 +1,472 @@
+//! Helpers for script processes: ones the terminal runs with an argument
+//! string, which do one thing and exit. Write the script's logic as a
+//! function from its arguments to its output and wrap it with [`crate::script!`],
+//! and describe its arguments with a [`Command`] to parse them.
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Define the entry point of a script process. The wrapped function gets
+/// this process's address and the argument string the script was run with,
+/// and returns its output: a response body if the caller expects a
+/// response, or otherwise text printed to the terminal.
+///
+/// ```ignore
+/// script!(init);
+/// fn init(our: Address, args: String) -> String {
+///     let command = Command::new("greet")
+///         .arg(Arg::positional("name").help("who to greet"))
+///         .arg(Arg::flag("loud").short('l'));
+///     let args = match command.parse_or_usage(&args) {
+///         Ok(args) => args,
+///         Err(usage) => return usage,
+///     };
+///     ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! script {
+    ($init_func:ident) => {
+        struct Component;
+        impl Guest for Component {
+            fn init(our: String) {
+                let our: $crate::Address = our.parse().unwrap();
+                let Ok($crate::Message::Request {
+                    body,
+                    expects_response,
+                    ..
+                }) = $crate::await_message()
+                else {
+                    return;
+                };
+                let args = String::from_utf8_lossy(&body).to_string();
+                let output: String = $init_func(our, args);
+                if expects_response.is_some() {
+                    $crate::Response::new()
+                        .body(output.into_bytes())
+                        .send()
+                        .unwrap();
+                } else {
+                    $crate::println!("{}", output);
+                }
+            }
+        }
+    };
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ArgsError {
+    #[error("unterminated quote in arguments")]
+    UnterminatedQuote,
+    #[error("unknown option --{0}")]
+    UnknownOption(String),
+    #[error("option --{0} needs a value")]
+    MissingValue(String),
+    #[error("missing required argument <{0}>")]
+    MissingArgument(String),
+    #[error("unexpected argument {0:?}")]
+    UnexpectedArgument(String),
+    #[error("invalid value {value:?} for {name}: {error}")]
+    InvalidValue {
+        name: String,
+        value: String,
+        error: String,
+    },
+}
+
+/// Split an argument string into words the way a shell would: on
+/// whitespace, except inside single or double quotes, with `\` escaping the
+/// next character outside single quotes.
+pub fn split_args(input: &str) -> Result<Vec<String>, ArgsError> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => {
+                word.push(chars.next().unwrap_or('\\'));
+                in_word = true;
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(ArgsError::UnterminatedQuote);
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArgKind {
+    Positional,
+    Option,
+    Flag,
+}
+
+/// One argument a [`Command`] accepts.
+#[derive(Debug, Clone)]
+pub struct Arg {
+    name: String,
+    kind: ArgKind,
+    short: Option<char>,
+    help: Option<String>,
+    default: Option<String>,
+    required: bool,
+}
+
+impl Arg {
+    fn new(name: &str, kind: ArgKind) -> Self {
+        Arg {
+            name: name.to_string(),
+            required: kind == ArgKind::Positional,
+            kind,
+            short: None,
+            help: None,
+            default: None,
+        }
+    }
+    /// A positional argument, required unless it has a default.
+    pub fn positional(name: &str) -> Self {
+        Self::new(name, ArgKind::Positional)
+    }
+    /// An option taking a value, given as `--name value` or `--name=value`.
+    pub fn option(name: &str) -> Self {
+        Self::new(name, ArgKind::Option)
+    }
+    /// A flag, given as `--name`, that is either present or not.
+    pub fn flag(name: &str) -> Self {
+        Self::new(name, ArgKind::Flag)
+    }
+    /// Also accept `-c` for this option or flag.
+    pub fn short(mut self, short: char) -> Self {
+        self.short = Some(short);
+        self
+    }
+    pub fn help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+    /// The value to use when the argument is not given. Makes it optional.
+    pub fn default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self.required = false;
+        self
+    }
+    /// Require an option to be given, or let a positional argument be left out.
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+}
+
+/// A description of a script's arguments, used to parse its argument
+/// string and to print usage when that fails.
+#[derive(Debug, Clone)]
+pub struct Command {
+    name: String,
+    about: Option<String>,
+    args: Vec<Arg>,
+}
+
+impl Command {
+    pub fn new(name: &str) -> Self {
+        Command {
+            name: name.to_string(),
+            about: None,
+            args: vec![],
+        }
+    }
+
+    /// A description of what the script does, for the usage text.
+    pub fn about(mut self, about: &str) -> Self {
+        self.about = Some(about.to_string());
+        self
+    }
+
+    pub fn arg(mut self, arg: Arg) -> Self {
+        self.args.push(arg);
+        self
+    }
+
+    /// Parse an argument string. `--help` or `-h` is reported as an error,
+    /// so that [`Command::parse_or_usage()`] prints usage for it.
+    pub fn parse(&self, input: &str) -> Result<Args, ArgsError> {
+        let words = split_args(input)?;
+        let mut values = HashMap::new();
+        let mut flags = vec![];
+        let mut positionals = self
+            .args
+            .iter()
+            .filter(|arg| arg.kind == ArgKind::Positional);
+
+        let mut words = words.into_iter();
+        while let Some(word) = words.next() {
+            let (arg, inline_value) = if let Some(long) = word.strip_prefix("--") {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let arg = self
+                    .args
+                    .iter()
+                    .find(|arg| arg.kind != ArgKind::Positional && arg.name == name)
+                    .ok_or_else(|| ArgsError::UnknownOption(name.to_string()))?;
+                (arg, value)
+            } else if let Some(short) = word.strip_prefix('-').filter(|s| s.chars().count() == 1) {
+                let short = short.chars().next().unwrap();
+                let arg = self
+                    .args
+                    .iter()
+                    .find(|arg| arg.short == Some(short))
+                    .ok_or_else(|| ArgsError::UnknownOption(short.to_string()))?;
+                (arg, None)
+            } else {
+                let arg = positionals
+                    .next()
+                    .ok_or_else(|| ArgsError::UnexpectedArgument(word.clone()))?;
+                values.insert(arg.name.clone(), word);
+                continue;
+            };
+            match arg.kind {
+                ArgKind::Flag => flags.push(arg.name.clone()),
+                _ => {
+                    let value = match inline_value {
+                        Some(value) => value,
+                        None => words
+                            .next()
+                            .ok_or_else(|| ArgsError::MissingValue(arg.name.clone()))?,
+                    };
+                    values.insert(arg.name.clone(), value);
+                }
+            }
+        }
+
+        for arg in &self.args {
+            if values.contains_key(&arg.name) || arg.kind == ArgKind::Flag {
+                continue;
+            }
+            match &arg.default {
+                Some(default) => {
+                    values.insert(arg.name.clone(), default.clone());
+                }
+                None if arg.required => {
+                    return Err(ArgsError::MissingArgument(arg.name.clone()));
+                }
+                None => {}
+            }
+        }
+        Ok(Args { values, flags })
+    }
+
+    /// Parse an argument string, or return the error and usage text to show
+    /// the user instead.
+    pub fn parse_or_usage(&self, input: &str) -> Result<Args, String> {
+        let wants_help = split_args(input)
+            .map(|words| words.iter().any(|word| word == "--help" || word == "-h"))
+            .unwrap_or(false);
+        if wants_help {
+            return Err(self.usage());
+        }
+        self.parse(input)
+            .map_err(|e| format!("error: {}\n\n{}", e, self.usage()))
+    }
+
+    /// Usage text listing every argument.
+    pub fn usage(&self) -> String {
+        let mut usage = String::new();
+        if let Some(about) = &self.about {
+            usage.push_str(about);
+            usage.push_str("\n\n");
+        }
+        usage.push_str(&format!("Usage: {}", self.name));
+        for arg in &self.args {
+            let shown = match arg.kind {
+                ArgKind::Positional => format!("<{}>", arg.name),
+                ArgKind::Option => format!("--{} <{}>", arg.name, arg.name),
+                ArgKind::Flag => format!("--{}", arg.name),
+            };
+            if arg.required {
+                usage.push_str(&format!(" {}", shown));
+            } else {
+                usage.push_str(&format!(" [{}]", shown));
+            }
+        }
+        usage.push('\n');
+        for arg in &self.args {
+            let name = match (&arg.kind, arg.short) {
+                (ArgKind::Positional, _) => format!("<{}>", arg.name),
+                (_, Some(short)) => format!("-{}, --{}", short, arg.name),
+                (_, None) => format!("    --{}", arg.name),
+            };
+            let mut line = format!("\n  {:<24} {}", name, arg.help.as_deref().unwrap_or(""));
+            if let Some(default) = &arg.default {
+                line.push_str(&format!(" [default: {}]", default));
+            }
+            usage.push_str(line.trim_end());
+        }
+        usage
+    }
+}
+
+/// Arguments parsed by a [`Command`].
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    values: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    /// The value of a positional argument or option, parsed as `T`, or
+    /// `None` if it was not given and has no default.
+    pub fn get<T>(&self, name: &str) -> Result<Option<T>, ArgsError>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.values
+            .get(name)
+            .map(|value| {
+                value.parse().map_err(|e: T::Err| ArgsError::InvalidValue {
+                    name: name.to_string(),
+                    value: value.clone(),
+                    error: e.to_string(),
+                })
+            })
+            .transpose()
+    }
+
+    /// The value of a required argument, parsed as `T`.
+    pub fn value<T>(&self, name: &str) -> Result<T, ArgsError>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.get(name)?
+            .ok_or_else(|| ArgsError::MissingArgument(name.to_string()))
+    }
+
+    /// Whether a flag was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn greet() -> Command {
+        Command::new("greet")
+            .arg(Arg::positional("name").help("who to greet"))
+            .arg(Arg::option("times").short('t').default("1"))
+            .arg(Arg::flag("loud").short('l'))
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(split_args(" a  b\tc ").unwrap(), ["a", "b", "c"]);
+        assert_eq!(split_args("").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn splits_quotes_and_escapes() {
+        assert_eq!(
+            split_args(r#""hello world" 'it''s'"#).unwrap(),
+            ["hello world", "its"]
+        );
+        assert_eq!(split_args(r"a\ b").unwrap(), ["a b"]);
+        assert_eq!(split_args(r#""a\"b""#).unwrap(), [r#"a"b"#]);
+        assert_eq!(split_args(r"'a\b'").unwrap(), [r"a\b"]);
+        assert_eq!(split_args(r#""" x"#).unwrap(), ["", "x"]);
+        assert_eq!(split_args(r#""abc"#), Err(ArgsError::UnterminatedQuote));
+        assert_eq!(split_args("'abc"), Err(ArgsError::UnterminatedQuote));
+    }
+
+    #[test]
+    fn parses_positionals_options_and_flags() {
+        let args = greet().parse("bob --times 3 -l").unwrap();
+        assert_eq!(args.value::<String>("name").unwrap(), "bob");
+        assert_eq!(args.value::<u32>("times").unwrap(), 3);
+        assert!(args.flag("loud"));
+
+        let args = greet().parse("--times=2 bob").unwrap();
+        assert_eq!(args.value::<u32>("times").unwrap(), 2);
+        assert!(!args.flag("loud"));
+
+        let args = greet().parse("-t 4 bob").unwrap();
+        assert_eq!(args.value::<u32>("times").unwrap(), 4);
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let args = greet().parse("bob").unwrap();
+        assert_eq!(args.value::<u32>("times").unwrap(), 1);
+        assert_eq!(args.get::<String>("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let command = greet();
+        assert_eq!(
+            command.parse("").unwrap_err(),
+            ArgsError::MissingArgument("name".into())
+        );
+        assert_eq!(
+            command.parse("bob --nope").unwrap_err(),
+            ArgsError::UnknownOption("nope".into())
+        );
+        assert_eq!(
+            command.parse("bob -x").unwrap_err(),
+            ArgsError::UnknownOption("x".into())
+        );
+        assert_eq!(
+            command.parse("bob --times").unwrap_err(),
+            ArgsError::MissingValue("times".into())
+        );
+        assert_eq!(
+            command.parse("bob extra").unwrap_err(),
+            ArgsError::UnexpectedArgument("extra".into())
+        );
+        let args = command.parse("bob --times many").unwrap();
+        assert!(matches!(
+            args.value::<u32>("times"),
+            Err(ArgsError::InvalidValue { name, value, .. }) if name == "times" && value == "many"
+        ));
+    }
+
+    #[test]
+    fn prints_usage() {
+        let command = greet();
+        let usage = command.usage();
+        assert!(usage.starts_with("Usage: greet <name> [--times <times>] [--loud]\n"));
+        assert!(usage.contains("-t, --times"));
+        assert!(usage.contains("[default: 1]"));
+        assert_eq!(command.parse_or_usage("bob --help").unwrap_err(), usage);
+        assert_eq!(command.parse_or_usage("-h").unwrap_err(), usage);
+        assert!(command
+            .parse_or_usage("")
+            .unwrap_err()
+            .starts_with("error: missing required argument <name>"));
+    }
+}
@@ -0,0 +1,245 @@
+//! Named secrets, such as API keys, stored encrypted in kv instead of in
+//! plaintext process state. Each secret keeps its past versions, so it can
+//! be rotated without losing the value in-flight work still uses, and other
+//! processes can be allowed to read individual secrets by capability.
+//!
+//! ```ignore
+//! let store = SecretStore::open(&our, master_key)?;
+//! store.rotate("stripe_api_key", b"sk_live_...")?;
+//! secrets::init(store);
+//! // anywhere else in the process
+//! let key = secrets::get("stripe_api_key")?;
+//! ```
+use crate::kv::{self, EncryptedKv, KvError};
+use crate::{Address, Capability, Message, ModuleError, ProcessLibError, Request, Response};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use thiserror::Error;
+
+/// The kv db secrets are stored in, in the process's own package.
+pub const SECRETS_DB: &str = "secrets";
+
+thread_local! {
+    static STORE: RefCell<Option<SecretStore>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Error)]
+pub enum SecretsError {
+    #[error("secrets: no secret named {name:?}")]
+    NotFound { name: String },
+    #[error("secrets: secret {name:?} has no version {version}")]
+    NoVersion { name: String, version: u32 },
+    #[error("secrets: secret {name:?} is not valid UTF-8")]
+    NotUtf8 { name: String },
+    #[error("secrets: no store set; call secrets::init() first")]
+    NoStore,
+    #[error("secrets: missing capability to read {name:?}")]
+    NoCapability { name: String },
+    /// The store [`fetch()`] asked refused or failed the request.
+    #[error("secrets: store answered: {error}")]
+    Remote { error: String },
+    #[error("secrets: {0}")]
+    Store(#[from] ProcessLibError),
+    #[error("secrets: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Requests another process sends to read a secret served by
+/// [`SecretStore::handle_request()`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SecretsRequest {
+    Get { name: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SecretsResponse {
+    Get { value: Vec<u8> },
+    Err { error: String },
+}
+
+/// Encrypted, versioned secrets in this package's [`SECRETS_DB`].
+pub struct SecretStore {
+    our: Address,
+    kv: EncryptedKv,
+}
+
+impl SecretStore {
+    /// Open the store, encrypting with a key derived from `master_key`. The
+    /// master key must come from outside the store, e.g. from the user at
+    /// install time, and the same key must be given every time.
+    pub fn open(our: &Address, master_key: &[u8]) -> Result<Self, ProcessLibError> {
+        let kv = kv::open(our.package_id(), SECRETS_DB)?;
+        Ok(SecretStore {
+            our: our.clone(),
+            kv: EncryptedKv::new(kv, master_key),
+        })
+    }
+
+    /// The current value of a secret.
+    pub fn get(&self, name: &str) -> Result<Vec<u8>, SecretsError> {
+        let version = self.current_version(name)?;
+        self.get_version(name, version)
+    }
+
+    /// The current value of a secret, as a string.
+    pub fn get_string(&self, name: &str) -> Result<String, SecretsError> {
+        String::from_utf8(self.get(name)?).map_err(|_| SecretsError::NotUtf8 {
+            name: name.to_string(),
+        })
+    }
+
+    /// A past or current version of a secret. Versions count up from 1.
+    pub fn get_version(&self, name: &str, version: u32) -> Result<Vec<u8>, SecretsError> {
+        match self.kv.get(version_key(name, version)) {
+            Ok(value) => Ok(value),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {
+                Err(SecretsError::NoVersion {
+                    name: name.to_string(),
+                    version,
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The current version number of a secret.
+    pub fn current_version(&self, name: &str) -> Result<u32, SecretsError> {
+        match self.kv.get(current_key(name)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {
+                Err(SecretsError::NotFound {
+                    name: name.to_string(),
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a new value for a secret, creating it if it does not exist, and
+    /// return the new version number. Past versions stay readable with
+    /// [`SecretStore::get_version()`] until pruned.
+    pub fn rotate(&self, name: &str, value: &[u8]) -> Result<u32, SecretsError> {
+        let version = match self.current_version(name) {
+            Ok(version) => version + 1,
+            Err(SecretsError::NotFound { .. }) => 1,
+            Err(e) => return Err(e),
+        };
+        let tx_id = self.kv.kv().begin_tx()?;
+        self.kv
+            .set(version_key(name, version), value, Some(tx_id))?;
+        self.kv.set(
+            current_key(name),
+            &serde_json::to_vec(&version)?,
+            Some(tx_id),
+        )?;
+        self.kv.kv().commit_tx(tx_id)?;
+        Ok(version)
+    }
+
+    /// Delete every version of a secret older than the newest `keep`.
+    pub fn prune(&self, name: &str, keep: u32) -> Result<(), SecretsError> {
+        let current = self.current_version(name)?;
+        let tx_id = self.kv.kv().begin_tx()?;
+        for version in 1..=current.saturating_sub(keep.max(1)) {
+            self.kv.delete(version_key(name, version), Some(tx_id))?;
+        }
+        self.kv.kv().commit_tx(tx_id)?;
+        Ok(())
+    }
+
+    /// Delete a secret and all of its versions.
+    pub fn remove(&self, name: &str) -> Result<(), SecretsError> {
+        let current = self.current_version(name)?;
+        let tx_id = self.kv.kv().begin_tx()?;
+        for version in 1..=current {
+            self.kv.delete(version_key(name, version), Some(tx_id))?;
+        }
+        self.kv.delete(current_key(name), Some(tx_id))?;
+        self.kv.kv().commit_tx(tx_id)?;
+        Ok(())
+    }
+
+    /// The capability a process needs to read the secret `name` through
+    /// [`SecretStore::handle_request()`]. Grant it to the processes that
+    /// should be able to read that secret.
+    pub fn read_capability(&self, name: &str) -> Capability {
+        read_capability(&self.our, name)
+    }
+
+    /// If `message` is a [`SecretsRequest`], answer it and return `true`.
+    /// A secret is only sent if the request carries the capability from
+    /// [`SecretStore::read_capability()`] for it.
+    pub fn handle_request(&self, message: &Message) -> bool {
+        if !message.is_request() {
+            return false;
+        }
+        let Ok(SecretsRequest::Get { name }) = serde_json::from_slice(message.body()) else {
+            return false;
+        };
+        let required = self.read_capability(&name);
+        let response = if !message.capabilities().contains(&required) {
+            SecretsResponse::Err {
+                error: SecretsError::NoCapability { name }.to_string(),
+            }
+        } else {
+            match self.get(&name) {
+                Ok(value) => SecretsResponse::Get { value },
+                Err(e) => SecretsResponse::Err {
+                    error: e.to_string(),
+                },
+            }
+        };
+        let _ = Response::new()
+            .body(serde_json::to_vec(&response).unwrap())
+            .send();
+        true
+    }
+}
+
+/// The capability to read the secret `name` from the process at `store`.
+pub fn read_capability(store: &Address, name: &str) -> Capability {
+    Capability::new(
+        store.clone(),
+        serde_json::json!({ "kind": "secrets:read", "name": name }).to_string(),
+    )
+}
+
+/// Read a secret from another process that serves its [`SecretStore`] with
+/// [`SecretStore::handle_request()`]. We must hold the capability from
+/// [`read_capability()`] for it.
+pub fn fetch(store: &Address, name: &str) -> Result<Vec<u8>, SecretsError> {
+    let response = Request::new()
+        .target(store.clone())
+        .body(serde_json::to_vec(&SecretsRequest::Get {
+            name: name.to_string(),
+        })?)
+        .capabilities(vec![read_capability(store, name)])
+        .send_and_await_response(5)
+        .map_err(ProcessLibError::from)?
+        .map_err(ProcessLibError::from)?;
+    match serde_json::from_slice(response.body())? {
+        SecretsResponse::Get { value } => Ok(value),
+        SecretsResponse::Err { error } => Err(SecretsError::Remote { error }),
+    }
+}
+
+/// Set the store that [`get()`] reads from.
+pub fn init(store: SecretStore) {
+    STORE.with(|cell| *cell.borrow_mut() = Some(store));
+}
+
+/// The current value of a secret, as a string, from the store set with [`init()`].
+pub fn get(name: &str) -> Result<String, SecretsError> {
+    STORE.with(|cell| match cell.borrow().as_ref() {
+        Some(store) => store.get_string(name),
+        None => Err(SecretsError::NoStore),
+    })
+}
+
+fn current_key(name: &str) -> Vec<u8> {
+    format!("secret:{}:current", name).into_bytes()
+}
+
+fn version_key(name: &str, version: u32) -> Vec<u8> {
+    format!("secret:{}:v{}", name, version).into_bytes()
+}
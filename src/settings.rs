@@ -0,0 +1,188 @@
+use crate::kv::{self, Kv, KvError};
+use crate::{Address, Capability, Message, ModuleError, ProcessLibError, Request, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// Requests other processes (or a UI backend) can send to a process that
+/// exposes its settings. Handle them with [`Settings::handle_request()`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SettingsRequest {
+    /// Read the current settings. Requires the read capability.
+    Get,
+    /// Replace the current settings. Requires the write capability.
+    Set(serde_json::Value),
+    /// Receive a [`SettingsUpdate`] request every time the settings change.
+    /// Requires the read capability.
+    Subscribe,
+    /// Stop receiving [`SettingsUpdate`] requests.
+    Unsubscribe,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SettingsResponse {
+    Ok,
+    Get(serde_json::Value),
+    Err(SettingsError),
+}
+
+/// Request sent to every subscriber when the settings change.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettingsUpdate {
+    pub settings: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Error)]
+pub enum SettingsError {
+    #[error("settings: No capability: {error}")]
+    NoCap { error: String },
+    #[error("settings: settings did not match schema: {error}")]
+    BadSettings { error: String },
+    #[error("settings: failed to persist settings: {error}")]
+    PersistError { error: String },
+}
+
+/// Typed settings for a process, persisted in a kv db.
+/// Load them with [`Settings::load()`], which will fall back to `T::default()`
+/// if nothing has been saved yet.
+///
+/// Processes in the same package on our node may always read and write the
+/// settings. Any other process must attach a capability from
+/// [`Settings::read_capability()`] or [`Settings::write_capability()`],
+/// which this process can grant as it sees fit.
+pub struct Settings<T> {
+    our: Address,
+    kv: Kv,
+    key: Vec<u8>,
+    current: T,
+    subscribers: HashSet<Address>,
+}
+
+impl<T> Settings<T>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    /// Load settings from the given kv db, opening or creating it.
+    pub fn load(our: &Address, db: &str) -> Result<Self, ProcessLibError> {
+        let kv = kv::open(our.package_id(), db)?;
+        let key = b"settings".to_vec();
+        let current = match kv.get(key.clone()) {
+            Ok(bytes) => serde_json::from_slice::<T>(&bytes)?,
+            Err(ProcessLibError::Module(ModuleError::Kv(KvError::KeyNotFound { .. }))) => {
+                T::default()
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Settings {
+            our: our.clone(),
+            kv,
+            key,
+            current,
+            subscribers: HashSet::new(),
+        })
+    }
+
+    /// Get the current settings.
+    pub fn get(&self) -> &T {
+        &self.current
+    }
+
+    /// Replace the current settings, persist them, and notify subscribers.
+    pub fn set(&mut self, settings: T) -> Result<(), ProcessLibError> {
+        let bytes = serde_json::to_vec(&settings)?;
+        self.kv.set(self.key.clone(), bytes, None)?;
+        self.current = settings;
+        self.notify()
+    }
+
+    /// The capability another process must attach to read these settings.
+    pub fn read_capability(&self) -> Capability {
+        Capability::new(&self.our, "{\"kind\":\"settings-read\"}")
+    }
+
+    /// The capability another process must attach to change these settings.
+    pub fn write_capability(&self) -> Capability {
+        Capability::new(&self.our, "{\"kind\":\"settings-write\"}")
+    }
+
+    /// Handle an incoming [`SettingsRequest`], sending a [`SettingsResponse`]
+    /// if the request expects one. Returns `true` if the settings changed,
+    /// so the process can react to its own new configuration.
+    ///
+    /// Returns an error if the message is not a request or its body is not
+    /// a [`SettingsRequest`], so it can be passed on to other handlers.
+    pub fn handle_request(&mut self, message: &Message) -> Result<bool, ProcessLibError> {
+        let Message::Request {
+            source,
+            expects_response,
+            body,
+            capabilities,
+            ..
+        } = message
+        else {
+            return Err(ProcessLibError::InvalidInput {
+                error: "settings: not a request".to_string(),
+            });
+        };
+        let request = serde_json::from_slice::<SettingsRequest>(body)?;
+
+        let can_read = self.is_local(source) || capabilities.contains(&self.read_capability());
+        let can_write = self.is_local(source) || capabilities.contains(&self.write_capability());
+
+        let mut changed = false;
+        let response = match request {
+            SettingsRequest::Get | SettingsRequest::Subscribe if !can_read => {
+                SettingsResponse::Err(SettingsError::NoCap {
+                    error: format!("{} may not read settings", source),
+                })
+            }
+            SettingsRequest::Set(_) if !can_write => SettingsResponse::Err(SettingsError::NoCap {
+                error: format!("{} may not write settings", source),
+            }),
+            SettingsRequest::Get => SettingsResponse::Get(serde_json::to_value(&self.current)?),
+            SettingsRequest::Set(value) => match serde_json::from_value::<T>(value) {
+                Ok(settings) => match self.set(settings) {
+                    Ok(()) => {
+                        changed = true;
+                        SettingsResponse::Ok
+                    }
+                    Err(e) => SettingsResponse::Err(SettingsError::PersistError {
+                        error: e.to_string(),
+                    }),
+                },
+                Err(e) => SettingsResponse::Err(SettingsError::BadSettings {
+                    error: e.to_string(),
+                }),
+            },
+            SettingsRequest::Subscribe => {
+                self.subscribers.insert(source.clone());
+                SettingsResponse::Ok
+            }
+            SettingsRequest::Unsubscribe => {
+                self.subscribers.remove(source);
+                SettingsResponse::Ok
+            }
+        };
+
+        if expects_response.is_some() {
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        Ok(changed)
+    }
+
+    fn is_local(&self, source: &Address) -> bool {
+        source.node == self.our.node && source.package_id() == self.our.package_id()
+    }
+
+    fn notify(&self) -> Result<(), ProcessLibError> {
+        let body = serde_json::to_vec(&SettingsUpdate {
+            settings: serde_json::to_value(&self.current)?,
+        })?;
+        for subscriber in &self.subscribers {
+            Request::to(subscriber).body(body.clone()).send()?;
+        }
+        Ok(())
+    }
+}
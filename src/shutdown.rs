@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+
+thread_local! {
+    static HANDLERS: RefCell<Vec<Box<dyn FnMut()>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Register a handler to run when this process exits, e.g. to commit an
+/// open kv transaction or persist state with [`crate::set_state()`].
+/// Handlers run once, in the order they were registered.
+///
+/// The runtime sends no signal before it kills a process, so handlers only
+/// run on exits the process itself goes through: when the guard returned
+/// by [`shutdown_guard()`] is dropped, when the process panics after that
+/// guard was taken, or when [`run_shutdown_handlers()`] is called.
+pub fn on_shutdown<F>(handler: F)
+where
+    F: FnMut() + 'static,
+{
+    HANDLERS.with(|handlers| handlers.borrow_mut().push(Box::new(handler)));
+}
+
+/// Run and clear every handler registered with [`on_shutdown()`].
+pub fn run_shutdown_handlers() {
+    // take the handlers out first, so a handler may register another, and
+    // skip them if a panic interrupted a registration
+    let Some(mut handlers) = HANDLERS.with(|handlers| {
+        handlers
+            .try_borrow_mut()
+            .ok()
+            .map(|mut handlers| std::mem::take(&mut *handlers))
+    }) else {
+        return;
+    };
+    for handler in handlers.iter_mut() {
+        handler();
+    }
+}
+
+/// Runs the [`on_shutdown()`] handlers when dropped. Take one at the top of
+/// the process's `init` function, so they run when it returns:
+///
+/// ```ignore
+/// fn init(our: Address) {
+///     let _shutdown = shutdown_guard();
+///     let state = Rc::new(RefCell::new(State::load()));
+///     let saved = state.clone();
+///     on_shutdown(move || set_state(&saved.borrow().to_bytes()));
+///     loop {
+///         ...
+///     }
+/// }
+/// ```
+///
+/// Taking a guard also installs a panic hook that runs the handlers after
+/// the previous hook, since a panicking process aborts without dropping it.
+#[must_use = "the handlers run when the guard is dropped"]
+pub struct ShutdownGuard(());
+
+/// Take a [`ShutdownGuard`].
+pub fn shutdown_guard() -> ShutdownGuard {
+    static HOOKED: std::sync::Once = std::sync::Once::new();
+    HOOKED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            run_shutdown_handlers();
+        }));
+    });
+    ShutdownGuard(())
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        run_shutdown_handlers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn guard_runs_handlers_once_in_order() {
+        let ran = Rc::new(RefCell::new(vec![]));
+        for i in 0..3 {
+            let ran = ran.clone();
+            on_shutdown(move || ran.borrow_mut().push(i));
+        }
+        drop(shutdown_guard());
+        assert_eq!(*ran.borrow(), [0, 1, 2]);
+        run_shutdown_handlers();
+        assert_eq!(ran.borrow().len(), 3);
+    }
+}
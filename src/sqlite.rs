@@ -1,4 +1,4 @@
-use crate::{get_blob, Message, PackageId, Request};
+use crate::{get_blob, Message, PackageId, ProcessLibError, Request};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use thiserror::Error;
@@ -29,6 +29,15 @@ pub enum SqliteAction {
         tx_id: u64,
     },
     Backup,
+    /// Write a consistent copy of the database to a file in the vfs.
+    BackupTo {
+        vfs_path: String,
+    },
+    /// Replace the database with a copy previously written by `BackupTo`.
+    RestoreFrom {
+        vfs_path: String,
+    },
+    IntegrityCheck,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,6 +45,7 @@ pub enum SqliteResponse {
     Ok,
     Read,
     BeginTx { tx_id: u64 },
+    IntegrityCheck { problems: Vec<String> },
     Err { error: SqliteError },
 }
 
@@ -76,6 +86,7 @@ pub enum SqliteError {
 /// Sqlite helper struct for a db.
 /// Opening or creating a db will give you a Result<sqlite>.
 /// You can call it's impl functions to interact with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Sqlite {
     pub package_id: PackageId,
     pub db: String,
@@ -87,7 +98,7 @@ impl Sqlite {
         &self,
         query: String,
         params: Vec<serde_json::Value>,
-    ) -> anyhow::Result<Vec<HashMap<String, serde_json::Value>>> {
+    ) -> Result<Vec<HashMap<String, serde_json::Value>>, ProcessLibError> {
         let res = Request::new()
             .target(("our", "sqlite", "distro", "sys"))
             .body(serde_json::to_vec(&SqliteRequest {
@@ -96,10 +107,10 @@ impl Sqlite {
                 action: SqliteAction::Read { query },
             })?)
             .blob_bytes(serde_json::to_vec(&params)?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<SqliteResponse>(&body)?;
 
                 match response {
@@ -116,13 +127,10 @@ impl Sqlite {
                         Ok(values)
                     }
                     SqliteResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!(
-                        "sqlite: unexpected response {:?}",
-                        response
-                    )),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("sqlite: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 
@@ -132,7 +140,7 @@ impl Sqlite {
         statement: String,
         params: Vec<serde_json::Value>,
         tx_id: Option<u64>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "sqlite", "distro", "sys"))
             .body(serde_json::to_vec(&SqliteRequest {
@@ -141,27 +149,24 @@ impl Sqlite {
                 action: SqliteAction::Write { statement, tx_id },
             })?)
             .blob_bytes(serde_json::to_vec(&params)?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<SqliteResponse>(&body)?;
 
                 match response {
                     SqliteResponse::Ok => Ok(()),
                     SqliteResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!(
-                        "sqlite: unexpected response {:?}",
-                        response
-                    )),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("sqlite: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 
     /// Begin a transaction.
-    pub fn begin_tx(&self) -> anyhow::Result<u64> {
+    pub fn begin_tx(&self) -> Result<u64, ProcessLibError> {
         let res = Request::new()
             .target(("our", "sqlite", "distro", "sys"))
             .body(serde_json::to_vec(&SqliteRequest {
@@ -169,27 +174,24 @@ impl Sqlite {
                 db: self.db.clone(),
                 action: SqliteAction::BeginTx,
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<SqliteResponse>(&body)?;
 
                 match response {
                     SqliteResponse::BeginTx { tx_id } => Ok(tx_id),
                     SqliteResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!(
-                        "sqlite: unexpected response {:?}",
-                        response
-                    )),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("sqlite: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 
     /// Commit a transaction.
-    pub fn commit_tx(&self, tx_id: u64) -> anyhow::Result<()> {
+    pub fn commit_tx(&self, tx_id: u64) -> Result<(), ProcessLibError> {
         let res = Request::new()
             .target(("our", "sqlite", "distro", "sys"))
             .body(serde_json::to_vec(&SqliteRequest {
@@ -197,28 +199,80 @@ impl Sqlite {
                 db: self.db.clone(),
                 action: SqliteAction::Commit { tx_id },
             })?)
-            .send_and_await_response(5)?;
+            .send_and_await_response(5)??;
 
         match res {
-            Ok(Message::Response { body, .. }) => {
+            Message::Response { body, .. } => {
                 let response = serde_json::from_slice::<SqliteResponse>(&body)?;
 
                 match response {
                     SqliteResponse::Ok => Ok(()),
                     SqliteResponse::Err { error } => Err(error.into()),
-                    _ => Err(anyhow::anyhow!(
-                        "sqlite: unexpected response {:?}",
-                        response
-                    )),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+
+    /// Write a consistent copy of the database to a file in the vfs.
+    /// Requires write access to the drive containing `vfs_path`.
+    /// Restore it later with [`restore_from()`].
+    pub fn backup_to(&self, vfs_path: &str) -> Result<(), ProcessLibError> {
+        let res = Request::new()
+            .target(("our", "sqlite", "distro", "sys"))
+            .body(serde_json::to_vec(&SqliteRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: SqliteAction::BackupTo {
+                    vfs_path: vfs_path.to_string(),
+                },
+            })?)
+            .send_and_await_response(30)??;
+
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<SqliteResponse>(&body)?;
+
+                match response {
+                    SqliteResponse::Ok => Ok(()),
+                    SqliteResponse::Err { error } => Err(error.into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
+                }
+            }
+            _ => Err(ProcessLibError::unexpected(res)),
+        }
+    }
+
+    /// Run sqlite's integrity check on the database.
+    /// Returns the problems found, which will be empty if the database is healthy.
+    pub fn integrity_check(&self) -> Result<Vec<String>, ProcessLibError> {
+        let res = Request::new()
+            .target(("our", "sqlite", "distro", "sys"))
+            .body(serde_json::to_vec(&SqliteRequest {
+                package_id: self.package_id.clone(),
+                db: self.db.clone(),
+                action: SqliteAction::IntegrityCheck,
+            })?)
+            .send_and_await_response(30)??;
+
+        match res {
+            Message::Response { body, .. } => {
+                let response = serde_json::from_slice::<SqliteResponse>(&body)?;
+
+                match response {
+                    SqliteResponse::IntegrityCheck { problems } => Ok(problems),
+                    SqliteResponse::Err { error } => Err(error.into()),
+                    _ => Err(ProcessLibError::unexpected(response)),
                 }
             }
-            _ => Err(anyhow::anyhow!("sqlite: unexpected message: {:?}", res)),
+            _ => Err(ProcessLibError::unexpected(res)),
         }
     }
 }
 
 /// Open or create sqlite database.
-pub fn open(package_id: PackageId, db: &str) -> anyhow::Result<Sqlite> {
+pub fn open(package_id: PackageId, db: &str) -> Result<Sqlite, ProcessLibError> {
     let res = Request::new()
         .target(("our", "sqlite", "distro", "sys"))
         .body(serde_json::to_vec(&SqliteRequest {
@@ -226,10 +280,10 @@ pub fn open(package_id: PackageId, db: &str) -> anyhow::Result<Sqlite> {
             db: db.to_string(),
             action: SqliteAction::Open,
         })?)
-        .send_and_await_response(5)?;
+        .send_and_await_response(5)??;
 
     match res {
-        Ok(Message::Response { body, .. }) => {
+        Message::Response { body, .. } => {
             let response = serde_json::from_slice::<SqliteResponse>(&body)?;
 
             match response {
@@ -238,18 +292,15 @@ pub fn open(package_id: PackageId, db: &str) -> anyhow::Result<Sqlite> {
                     db: db.to_string(),
                 }),
                 SqliteResponse::Err { error } => Err(error.into()),
-                _ => Err(anyhow::anyhow!(
-                    "sqlite: unexpected response {:?}",
-                    response
-                )),
+                _ => Err(ProcessLibError::unexpected(response)),
             }
         }
-        _ => Err(anyhow::anyhow!("sqlite: unexpected message: {:?}", res)),
+        _ => Err(ProcessLibError::unexpected(res)),
     }
 }
 
 /// Remove and delete sqlite database.
-pub fn remove_db(package_id: PackageId, db: &str) -> anyhow::Result<()> {
+pub fn remove_db(package_id: PackageId, db: &str) -> Result<(), ProcessLibError> {
     let res = Request::new()
         .target(("our", "sqlite", "distro", "sys"))
         .body(serde_json::to_vec(&SqliteRequest {
@@ -257,21 +308,53 @@ pub fn remove_db(package_id: PackageId, db: &str) -> anyhow::Result<()> {
             db: db.to_string(),
             action: SqliteAction::RemoveDb,
         })?)
-        .send_and_await_response(5)?;
+        .send_and_await_response(5)??;
 
     match res {
-        Ok(Message::Response { body, .. }) => {
+        Message::Response { body, .. } => {
             let response = serde_json::from_slice::<SqliteResponse>(&body)?;
 
             match response {
                 SqliteResponse::Ok => Ok(()),
                 SqliteResponse::Err { error } => Err(error.into()),
-                _ => Err(anyhow::anyhow!(
-                    "sqlite: unexpected response {:?}",
-                    response
-                )),
+                _ => Err(ProcessLibError::unexpected(response)),
+            }
+        }
+        _ => Err(ProcessLibError::unexpected(res)),
+    }
+}
+
+/// Create or replace a sqlite database from a file written by [`Sqlite::backup_to()`].
+/// Requires read access to the drive containing `vfs_path`.
+pub fn restore_from(
+    package_id: PackageId,
+    db: &str,
+    vfs_path: &str,
+) -> Result<Sqlite, ProcessLibError> {
+    let res = Request::new()
+        .target(("our", "sqlite", "distro", "sys"))
+        .body(serde_json::to_vec(&SqliteRequest {
+            package_id: package_id.clone(),
+            db: db.to_string(),
+            action: SqliteAction::RestoreFrom {
+                vfs_path: vfs_path.to_string(),
+            },
+        })?)
+        .send_and_await_response(30)??;
+
+    match res {
+        Message::Response { body, .. } => {
+            let response = serde_json::from_slice::<SqliteResponse>(&body)?;
+
+            match response {
+                SqliteResponse::Ok => Ok(Sqlite {
+                    package_id,
+                    db: db.to_string(),
+                }),
+                SqliteResponse::Err { error } => Err(error.into()),
+                _ => Err(ProcessLibError::unexpected(response)),
             }
         }
-        _ => Err(anyhow::anyhow!("sqlite: unexpected message: {:?}", res)),
+        _ => Err(ProcessLibError::unexpected(res)),
     }
 }
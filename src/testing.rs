@@ -0,0 +1,239 @@
+//! In-memory mock of the message-passing layer, enabled with the `testing` feature.
+//!
+//! With the feature on, [`Request`](crate::Request), [`Response`](crate::Response),
+//! [`await_message()`](crate::await_message) and [`get_blob()`](crate::get_blob) go
+//! through this module instead of the runtime, so process logic can be unit-tested
+//! without booting a node:
+//!
+//! ```ignore
+//! testing::reset();
+//! testing::mock_response(("our", "kv", "distro", "sys"), serde_json::to_vec(&KvResponse::Ok)?, None);
+//! testing::push_request(("fake.os", "app", "app", "fake.os"), b"{\"Ping\":null}".to_vec(), None);
+//!
+//! handle_message(&our, await_message()?)?;
+//!
+//! let sent = testing::take_sent();
+//! assert_eq!(sent[0].target.as_ref().unwrap().process(), "kv");
+//! ```
+//!
+//! Every function here acts on a thread-local mock, so tests running on
+//! separate threads do not interfere with each other.
+use crate::kinode::process::standard as wit;
+use crate::{Address, Capability, LazyLoadBlob};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+/// A message the process under test sent, recorded in the order it was sent.
+#[derive(Clone, Debug)]
+pub struct SentMessage {
+    /// The target of a request, or `None` for a response.
+    pub target: Option<Address>,
+    pub inherit: bool,
+    pub expects_response: Option<u64>,
+    pub body: Vec<u8>,
+    pub metadata: Option<String>,
+    pub context: Option<Vec<u8>>,
+    pub blob: Option<LazyLoadBlob>,
+    pub capabilities: Vec<Capability>,
+}
+
+impl SentMessage {
+    pub fn is_request(&self) -> bool {
+        self.target.is_some()
+    }
+    /// Attempt to deserialize the body of this message from JSON.
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+type Incoming = Result<(Address, wit::Message), (wit::SendError, Option<Vec<u8>>)>;
+/// A canned response body and blob.
+type Canned = (Vec<u8>, Option<LazyLoadBlob>);
+
+#[derive(Default)]
+struct MockTransport {
+    responses: HashMap<Address, VecDeque<Canned>>,
+    incoming: VecDeque<(Incoming, Option<LazyLoadBlob>)>,
+    sent: Vec<SentMessage>,
+    blob: Option<LazyLoadBlob>,
+}
+
+thread_local! {
+    static MOCK: RefCell<MockTransport> = RefCell::new(MockTransport::default());
+}
+
+/// Clear every canned response, queued message, and recorded send.
+pub fn reset() {
+    MOCK.with(|mock| *mock.borrow_mut() = MockTransport::default());
+}
+
+/// Queue a canned response to the next request sent to `target` that awaits one.
+/// Responses to the same target are returned in the order they were queued.
+/// A request with no canned response left fails with a timeout.
+pub fn mock_response<T>(target: T, body: Vec<u8>, blob: Option<LazyLoadBlob>)
+where
+    T: Into<Address>,
+{
+    MOCK.with(|mock| {
+        mock.borrow_mut()
+            .responses
+            .entry(target.into())
+            .or_default()
+            .push_back((body, blob))
+    });
+}
+
+/// Queue an incoming request, to be returned by a later call to `await_message()`.
+pub fn push_request<T>(source: T, body: Vec<u8>, blob: Option<LazyLoadBlob>)
+where
+    T: Into<Address>,
+{
+    let message = wit::Message::Request(wit::Request {
+        inherit: false,
+        expects_response: None,
+        body,
+        metadata: None,
+        capabilities: vec![],
+    });
+    push_incoming(Ok((source.into(), message)), blob);
+}
+
+/// Queue an incoming response carrying `context`, to be returned by a later
+/// call to `await_message()`.
+pub fn push_response<T>(
+    source: T,
+    body: Vec<u8>,
+    context: Option<Vec<u8>>,
+    blob: Option<LazyLoadBlob>,
+) where
+    T: Into<Address>,
+{
+    let message = wit::Message::Response((
+        wit::Response {
+            inherit: false,
+            body,
+            metadata: None,
+            capabilities: vec![],
+        },
+        context,
+    ));
+    push_incoming(Ok((source.into(), message)), blob);
+}
+
+/// Queue an incoming message exactly as the runtime would deliver it,
+/// including [`SendError`](wit::SendError)s for timed-out or undeliverable requests.
+pub fn push_incoming(incoming: Incoming, blob: Option<LazyLoadBlob>) {
+    MOCK.with(|mock| mock.borrow_mut().incoming.push_back((incoming, blob)));
+}
+
+/// Get every message sent so far, leaving the record in place.
+pub fn sent() -> Vec<SentMessage> {
+    MOCK.with(|mock| mock.borrow().sent.clone())
+}
+
+/// Take every message sent so far, clearing the record.
+pub fn take_sent() -> Vec<SentMessage> {
+    MOCK.with(|mock| std::mem::take(&mut mock.borrow_mut().sent))
+}
+
+//
+// replacements for the wit imports, with matching signatures
+//
+
+pub fn receive() -> Incoming {
+    MOCK.with(|mock| {
+        let mut mock = mock.borrow_mut();
+        let (incoming, blob) = mock
+            .incoming
+            .pop_front()
+            .expect("testing: await_message() called with no incoming message queued");
+        mock.blob = blob;
+        incoming
+    })
+}
+
+pub fn get_blob() -> Option<LazyLoadBlob> {
+    MOCK.with(|mock| mock.borrow().blob.clone())
+}
+
+pub fn send_request(
+    target: &Address,
+    request: &wit::Request,
+    context: Option<&Vec<u8>>,
+    blob: Option<&LazyLoadBlob>,
+) {
+    record_request(target, request, context, blob);
+}
+
+pub fn send_and_await_response(
+    target: &Address,
+    request: &wit::Request,
+    blob: Option<&LazyLoadBlob>,
+) -> Result<(Address, wit::Message), wit::SendError> {
+    record_request(target, request, None, blob);
+    MOCK.with(|mock| {
+        let mut mock = mock.borrow_mut();
+        match mock
+            .responses
+            .get_mut(target)
+            .and_then(|queue| queue.pop_front())
+        {
+            Some((body, blob)) => {
+                mock.blob = blob;
+                Ok((
+                    target.clone(),
+                    wit::Message::Response((
+                        wit::Response {
+                            inherit: false,
+                            body,
+                            metadata: None,
+                            capabilities: vec![],
+                        },
+                        None,
+                    )),
+                ))
+            }
+            None => Err(wit::SendError {
+                kind: wit::SendErrorKind::Timeout,
+                message: wit::Message::Request(request.clone()),
+                lazy_load_blob: blob.cloned(),
+            }),
+        }
+    })
+}
+
+pub fn send_response(response: &wit::Response, blob: Option<&LazyLoadBlob>) {
+    MOCK.with(|mock| {
+        mock.borrow_mut().sent.push(SentMessage {
+            target: None,
+            inherit: response.inherit,
+            expects_response: None,
+            body: response.body.clone(),
+            metadata: response.metadata.clone(),
+            context: None,
+            blob: blob.cloned(),
+            capabilities: response.capabilities.clone(),
+        })
+    });
+}
+
+fn record_request(
+    target: &Address,
+    request: &wit::Request,
+    context: Option<&Vec<u8>>,
+    blob: Option<&LazyLoadBlob>,
+) {
+    MOCK.with(|mock| {
+        mock.borrow_mut().sent.push(SentMessage {
+            target: Some(target.clone()),
+            inherit: request.inherit,
+            expects_response: request.expects_response,
+            body: request.body.clone(),
+            metadata: request.metadata.clone(),
+            context: context.cloned(),
+            blob: blob.cloned(),
+            capabilities: request.capabilities.clone(),
+        })
+    });
+}
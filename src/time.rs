@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, Sub};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The latest value [`now_millis()`] has returned in this process.
+static LAST_NOW_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+/// Milliseconds since the unix epoch, according to the runtime clock.
+///
+/// Monotonic within a process: if the clock steps back, this holds at the
+/// latest value it returned until the clock catches up. It is not monotonic
+/// across restarts, or between processes.
+pub fn now_millis() -> u64 {
+    let system = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let last = LAST_NOW_MILLIS.fetch_max(system, Ordering::Relaxed);
+    last.max(system)
+}
+
+/// Seconds since the unix epoch, according to the runtime clock.
+pub fn now_secs() -> u64 {
+    now_millis() / 1000
+}
+
+/// A point in time, stored as milliseconds since the unix epoch.
+/// Serializes as a bare integer, so it can be stored in kv or sent in a
+/// message body without a custom format.
+///
+/// Arithmetic with [`Duration`] saturates rather than panicking, so a
+/// timestamp can never go below the epoch.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// The current time.
+    pub fn now() -> Self {
+        Timestamp(now_millis())
+    }
+    pub fn from_millis(millis: u64) -> Self {
+        Timestamp(millis)
+    }
+    pub fn from_secs(secs: u64) -> Self {
+        Timestamp(secs.saturating_mul(1000))
+    }
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+    pub fn as_secs(&self) -> u64 {
+        self.0 / 1000
+    }
+    /// Time passed since this timestamp, or zero if it is in the future.
+    pub fn elapsed(&self) -> Duration {
+        Timestamp::now().duration_since(*self)
+    }
+    /// Time from `earlier` to this timestamp, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Timestamp) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+    /// Time left until this timestamp, or zero if it has passed.
+    pub fn until(&self) -> Duration {
+        self.duration_since(Timestamp::now())
+    }
+    /// Whether this timestamp is now or in the past.
+    pub fn has_passed(&self) -> bool {
+        self.0 <= now_millis()
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+    fn add(self, rhs: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_add(rhs.as_millis() as u64))
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+    fn sub(self, rhs: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_sub(rhs.as_millis() as u64))
+    }
+}
+
+impl From<Timestamp> for u64 {
+    fn from(timestamp: Timestamp) -> u64 {
+        timestamp.0
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -46,3 +46,123 @@ pub fn set_and_await_timer(duration: u64) -> Result<Message, SendError> {
         .send_and_await_response((duration / 1000) + 1)
         .unwrap()
 }
+
+/// Set a timer that will return a Response once `duration` has passed.
+/// Like [`set_timer()`], but takes a [`std::time::Duration`].
+pub fn set_timer_for(duration: std::time::Duration, context: Option<Context>) {
+    set_timer(duration.as_millis() as u64, context)
+}
+
+/// Set a timer that will return a Response at the given [`time::Timestamp`].
+/// If that time has already passed, the timer fires immediately.
+pub fn set_timer_at(at: time::Timestamp, context: Option<Context>) {
+    set_timer(at.until().as_millis() as u64, context)
+}
+
+/// Prefix of the context of every timer set with [`set_timer_with_id()`].
+const ID_TIMER_CONTEXT: &[u8] = b"process_lib:timer:";
+
+thread_local! {
+    /// The generation of each armed timer, by id. A timer that fires with
+    /// any other generation has been cancelled or reset.
+    static ARMED: std::cell::RefCell<std::collections::BTreeMap<String, u64>> =
+        const { std::cell::RefCell::new(std::collections::BTreeMap::new()) };
+    static NEXT_GENERATION: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct IdTimerContext {
+    id: String,
+    generation: u64,
+    context: Option<Context>,
+}
+
+/// A timer set with [`set_timer_with_id()`], as reported by [`handle_timer()`].
+#[derive(Debug)]
+pub enum TimerEvent {
+    /// The timer fired and had not been cancelled or reset.
+    Fired {
+        id: String,
+        context: Option<Context>,
+    },
+    /// The timer was cancelled or reset after being set; ignore it.
+    Stale { id: String },
+}
+
+/// Set a timer, in milliseconds, that can be cancelled or reset by `id`.
+/// Setting another timer with the same id replaces this one, so e.g. a
+/// debounce is just setting the same id on every change.
+///
+/// The runtime cannot cancel a timer, so it still sends its Response;
+/// pass every message to [`handle_timer()`] to tell fired timers from
+/// cancelled ones.
+pub fn set_timer_with_id(duration: u64, id: &str, context: Option<Context>) {
+    let generation = NEXT_GENERATION.with(|next| {
+        let generation = next.get();
+        next.set(generation + 1);
+        generation
+    });
+    ARMED.with(|armed| armed.borrow_mut().insert(id.to_string(), generation));
+    let context = IdTimerContext {
+        id: id.to_string(),
+        generation,
+        context,
+    };
+    // safe to unwrap: this struct always serializes
+    let context = [ID_TIMER_CONTEXT, &serde_json::to_vec(&context).unwrap()].concat();
+    set_timer(duration, Some(context));
+}
+
+/// Like [`set_timer_with_id()`], but fires at the given [`time::Timestamp`],
+/// or immediately if that time has already passed.
+pub fn set_timer_with_id_at(at: time::Timestamp, id: &str, context: Option<Context>) {
+    set_timer_with_id(at.until().as_millis() as u64, id, context)
+}
+
+/// Cancel the timer set with `id`. Returns whether one was armed.
+pub fn cancel_timer(id: &str) -> bool {
+    ARMED.with(|armed| armed.borrow_mut().remove(id).is_some())
+}
+
+/// Whether a timer with `id` is set and has not yet fired.
+pub fn is_timer_set(id: &str) -> bool {
+    ARMED.with(|armed| armed.borrow().contains_key(id))
+}
+
+/// The id of the timer set with [`set_timer_with_id()`] that `message` is
+/// the Response of, if it is one, without marking it as handled.
+pub fn timer_id(message: &Message) -> Option<String> {
+    parse_id_timer(message).map(|context| context.id)
+}
+
+/// If `message` is a timer set with [`set_timer_with_id()`] coming back,
+/// say whether it fired or is stale. Returns `None` for any other message.
+pub fn handle_timer(message: &Message) -> Option<TimerEvent> {
+    let IdTimerContext {
+        id,
+        generation,
+        context,
+    } = parse_id_timer(message)?;
+    let fired = ARMED.with(|armed| {
+        let mut armed = armed.borrow_mut();
+        if armed.get(&id) == Some(&generation) {
+            armed.remove(&id);
+            true
+        } else {
+            false
+        }
+    });
+    Some(if fired {
+        TimerEvent::Fired { id, context }
+    } else {
+        TimerEvent::Stale { id }
+    })
+}
+
+fn parse_id_timer(message: &Message) -> Option<IdTimerContext> {
+    if message.is_request() || !message.source().is_process("timer", "distro", "sys") {
+        return None;
+    }
+    let context = message.context()?.strip_prefix(ID_TIMER_CONTEXT)?;
+    serde_json::from_slice(context).ok()
+}
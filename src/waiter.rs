@@ -0,0 +1,95 @@
+use crate::{Message, Request, SendError};
+use std::collections::HashMap;
+
+/// Prefix on every context a [`ResponseWaiter`] hands out, so its contexts
+/// can be told apart from ones the process sets itself.
+const CONTEXT_PREFIX: &[u8] = b"process_lib:waiter:";
+
+/// A registry of outstanding requests for processes that must not block on
+/// [`Request::send_and_await_response()`]. Each request sent with
+/// [`Request::expects_response_with()`] stores a typed context here, and the
+/// matching response or [`SendError`] hands it back:
+///
+/// ```ignore
+/// let mut waiter = ResponseWaiter::<Pending>::new();
+/// Request::to(target)
+///     .body(body)
+///     .expects_response_with(5, &mut waiter, Pending::Lookup { user })
+///     .send()?;
+///
+/// // later, in the message loop
+/// let message = await_message();
+/// match waiter.resolve(&message) {
+///     Some(Pending::Lookup { user }) => match message {
+///         Ok(response) => handle_lookup(user, response),
+///         Err(_timeout_or_offline) => retry_lookup(user),
+///     },
+///     None => handle_other(message),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ResponseWaiter<C> {
+    next_id: u64,
+    pending: HashMap<u64, C>,
+}
+
+impl<C> ResponseWaiter<C> {
+    pub fn new() -> Self {
+        ResponseWaiter {
+            next_id: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Store `context` and get the raw context bytes to attach to a request.
+    /// Prefer [`Request::expects_response_with()`], which does this for you.
+    pub fn register(&mut self, context: C) -> Vec<u8> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.insert(id, context);
+        [CONTEXT_PREFIX, &id.to_le_bytes()].concat()
+    }
+
+    /// If `message` is a response or error for a request registered with this
+    /// waiter, remove and return its context. Otherwise return `None`, and the
+    /// message should be handled elsewhere.
+    pub fn resolve(&mut self, message: &Result<Message, SendError>) -> Option<C> {
+        let context = match message {
+            Ok(message) => message.context(),
+            Err(send_error) => send_error.context(),
+        }?;
+        let id = context.strip_prefix(CONTEXT_PREFIX)?;
+        let id = u64::from_le_bytes(id.try_into().ok()?);
+        self.pending.remove(&id)
+    }
+
+    /// The number of requests still waiting for a response or error.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<C> Default for ResponseWaiter<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Request {
+    /// Expect a response within `timeout` seconds, and register `context` with
+    /// `waiter` so the response (or timeout) can be matched back to it with
+    /// [`ResponseWaiter::resolve()`]. Replaces any context already set.
+    pub fn expects_response_with<C>(
+        self,
+        timeout: u64,
+        waiter: &mut ResponseWaiter<C>,
+        context: C,
+    ) -> Self {
+        self.expects_response(timeout)
+            .context(waiter.register(context))
+    }
+}
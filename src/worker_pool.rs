@@ -0,0 +1,250 @@
+use crate::{
+    spawn, Address, Message, OnExit, ProcessId, ProcessLibError, Request, SendError, SpawnError,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use thiserror::Error;
+
+/// Prefix on the context of every job a [`WorkerPool`] sends, so its
+/// responses can be told apart from the process's own.
+const CONTEXT_PREFIX: &[u8] = b"process_lib:worker_pool:";
+
+/// How a [`WorkerPool`] picks the worker for each job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Each worker in turn.
+    RoundRobin,
+    /// The worker with the fewest jobs outstanding.
+    LeastBusy,
+}
+
+#[derive(Debug, Error)]
+pub enum WorkerPoolError {
+    #[error("worker_pool: worker {worker} did not respond; it has been respawned")]
+    WorkerFailed { worker: ProcessId },
+    #[error("worker_pool: failed to respawn worker: {0:?}")]
+    Respawn(SpawnError),
+    #[error("worker_pool: bad job or result: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("worker_pool: pool has no workers")]
+    NoWorkers,
+    #[error("worker_pool: failed to send job: {0}")]
+    Send(ProcessLibError),
+}
+
+struct Worker {
+    process: ProcessId,
+    generation: u64,
+    busy: usize,
+}
+
+/// A pool of copies of one worker process, each spawned from the same wasm
+/// file, that typed jobs are sent to. A worker receives each job as a JSON
+/// request body of type `J` and must respond with a JSON body of type `R`.
+///
+/// Jobs are sent without blocking; pass every incoming message to
+/// [`WorkerPool::handle()`] to collect their results, or use
+/// [`WorkerPool::map()`] to run a batch of jobs to completion.
+///
+/// ```ignore
+/// let mut pool = WorkerPool::<Chunk, Summary>::spawn("summarizer", "/summarizer.wasm", 4)?
+///     .dispatch(Dispatch::LeastBusy);
+/// let summaries = pool.map(&chunks, |other| handle_other(other));
+/// ```
+///
+/// A worker that does not respond within the pool's timeout, or has exited,
+/// is replaced by a fresh spawn, and its job fails with
+/// [`WorkerPoolError::WorkerFailed`] so the caller can decide whether to retry.
+pub struct WorkerPool<J, R> {
+    name: String,
+    wasm_path: String,
+    workers: Vec<Worker>,
+    dispatch: Dispatch,
+    timeout: u64,
+    next_worker: usize,
+    next_job: u64,
+    /// Job id to the index and process of the worker running it.
+    pending: HashMap<u64, (usize, ProcessId)>,
+    _types: PhantomData<(J, R)>,
+}
+
+impl<J: Serialize, R: DeserializeOwned> WorkerPool<J, R> {
+    /// Spawn `size` workers from the wasm file at `wasm_path` in this
+    /// package, named `name-0`, `name-1`, and so on.
+    pub fn spawn(name: &str, wasm_path: &str, size: usize) -> Result<Self, SpawnError> {
+        let mut pool = WorkerPool {
+            name: name.to_string(),
+            wasm_path: wasm_path.to_string(),
+            workers: Vec::with_capacity(size),
+            dispatch: Dispatch::RoundRobin,
+            timeout: 30,
+            next_worker: 0,
+            next_job: 0,
+            pending: HashMap::new(),
+            _types: PhantomData,
+        };
+        for index in 0..size {
+            let process = pool.spawn_worker(index, 0)?;
+            pool.workers.push(Worker {
+                process,
+                generation: 0,
+                busy: 0,
+            });
+        }
+        Ok(pool)
+    }
+
+    /// Set how jobs are assigned to workers. Defaults to [`Dispatch::RoundRobin`].
+    pub fn dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.dispatch = dispatch;
+        self
+    }
+
+    /// Set how many seconds a worker has to finish a job before it is
+    /// considered dead. Defaults to 30.
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The processes currently in the pool.
+    pub fn workers(&self) -> Vec<&ProcessId> {
+        self.workers.iter().map(|worker| &worker.process).collect()
+    }
+
+    /// The number of jobs sent that have no result yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Send a job to a worker, returning an id that its result will carry.
+    pub fn submit(&mut self, job: &J) -> Result<u64, WorkerPoolError> {
+        if self.workers.is_empty() {
+            return Err(WorkerPoolError::NoWorkers);
+        }
+        let index = match self.dispatch {
+            Dispatch::RoundRobin => {
+                let index = self.next_worker % self.workers.len();
+                self.next_worker = index + 1;
+                index
+            }
+            Dispatch::LeastBusy => self
+                .workers
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, worker)| worker.busy)
+                .map(|(index, _)| index)
+                .unwrap(),
+        };
+        let job_id = self.next_job;
+        Request::new()
+            .target(Address::new("our", self.workers[index].process.clone()))
+            .body(serde_json::to_vec(job)?)
+            .expects_response(self.timeout)
+            .context([CONTEXT_PREFIX, &job_id.to_le_bytes()].concat())
+            .send()
+            .map_err(|e| WorkerPoolError::Send(e.into()))?;
+        self.next_job += 1;
+        self.workers[index].busy += 1;
+        self.pending
+            .insert(job_id, (index, self.workers[index].process.clone()));
+        Ok(job_id)
+    }
+
+    /// If `message` is the result of a job, return the job's id and result.
+    /// Otherwise return `None`, and the message should be handled elsewhere.
+    pub fn handle(
+        &mut self,
+        message: &Result<Message, SendError>,
+    ) -> Option<(u64, Result<R, WorkerPoolError>)> {
+        let context = match message {
+            Ok(message) => message.context(),
+            Err(send_error) => send_error.context(),
+        }?;
+        let job_id = context.strip_prefix(CONTEXT_PREFIX)?;
+        let job_id = u64::from_le_bytes(job_id.try_into().ok()?);
+        let (index, process) = self.pending.remove(&job_id)?;
+        // the worker may have been replaced since this job was sent
+        let current = self.workers[index].process == process;
+        if current {
+            let worker = &mut self.workers[index];
+            worker.busy = worker.busy.saturating_sub(1);
+        }
+        let result = match message {
+            Ok(message) => serde_json::from_slice(message.body()).map_err(Into::into),
+            Err(_) if current => Err(self.respawn(index)),
+            Err(_) => Err(WorkerPoolError::WorkerFailed { worker: process }),
+        };
+        Some((job_id, result))
+    }
+
+    /// Run every job in `jobs` and wait for all of their results, returned in
+    /// the same order. Messages that arrive meanwhile and are not results are
+    /// passed to `other`.
+    pub fn map<F>(
+        &mut self,
+        jobs: &[J],
+        mut other: F,
+    ) -> Result<Vec<Result<R, WorkerPoolError>>, WorkerPoolError>
+    where
+        F: FnMut(Result<Message, SendError>),
+    {
+        let mut ids = HashMap::new();
+        for (position, job) in jobs.iter().enumerate() {
+            ids.insert(self.submit(job)?, position);
+        }
+        let mut results: Vec<Option<Result<R, WorkerPoolError>>> =
+            jobs.iter().map(|_| None).collect();
+        while !ids.is_empty() {
+            let message = crate::await_message();
+            match self.handle(&message) {
+                Some((job_id, result)) => {
+                    // results for jobs submitted outside this call are dropped
+                    if let Some(position) = ids.remove(&job_id) {
+                        results[position] = Some(result);
+                    }
+                }
+                None => other(message),
+            }
+        }
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Kill the worker at `index`, in case it is hung rather than gone, and
+    /// replace it with a fresh spawn. Its other outstanding jobs fail when
+    /// their own timeouts pass.
+    fn respawn(&mut self, index: usize) -> WorkerPoolError {
+        let failed = self.workers[index].process.clone();
+        // fails if the worker has already exited, which is as good
+        let _ = crate::kernel::kill_process(&failed);
+        let generation = self.workers[index].generation + 1;
+        match self.spawn_worker(index, generation) {
+            Ok(process) => {
+                self.workers[index] = Worker {
+                    process,
+                    generation,
+                    busy: 0,
+                };
+                WorkerPoolError::WorkerFailed { worker: failed }
+            }
+            Err(e) => WorkerPoolError::Respawn(e),
+        }
+    }
+
+    fn spawn_worker(&self, index: usize, generation: u64) -> Result<ProcessId, SpawnError> {
+        // a dead worker may still hold its name, so respawns get a new one
+        let name = match generation {
+            0 => format!("{}-{}", self.name, index),
+            generation => format!("{}-{}-{}", self.name, index, generation),
+        };
+        spawn(
+            Some(&name),
+            &self.wasm_path,
+            OnExit::None,
+            vec![],
+            vec![],
+            false,
+        )
+    }
+}